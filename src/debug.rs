@@ -0,0 +1,37 @@
+use std::time::Instant;
+
+pub struct DebugTimer {
+    name: String,
+    start: Instant,
+    debug_mode: bool,
+}
+
+impl DebugTimer {
+    #[inline(always)]
+    pub fn new(name: &str, debug_mode: bool) -> Self {
+        let timer = Self {
+            name: name.to_string(),
+            start: Instant::now(),
+            debug_mode,
+        };
+
+        if debug_mode {
+            println!("Starting: {}", name);
+        }
+
+        timer
+    }
+
+    #[inline(always)]
+    pub fn elapsed(&self) -> String {
+        format!("{:.4?}", self.start.elapsed())
+    }
+}
+
+impl Drop for DebugTimer {
+    fn drop(&mut self) {
+        if self.debug_mode {
+            println!("Completed: {} in {}", self.name, self.elapsed());
+        }
+    }
+}
@@ -3,6 +3,7 @@ use smallvec::SmallVec;
 use std::collections::HashMap;
 use std::hash::Hash;
 use std::sync::Arc;
+use thiserror::Error;
 
 #[derive(Default)]
 pub struct Interner {
@@ -62,7 +63,11 @@ pub enum Expr {
     Reference(StrId),
     Number(StrId),
     StringLit(StrId),
+    CharLit(char),
     Bool(bool),
+    Null,
+    // Zero-based: `$1` is `Parameter(0)`.
+    Parameter(usize),
     FieldAccess {
         base: NodeId,
         field: StrId,
@@ -95,10 +100,31 @@ pub enum Expr {
         value: NodeId,
         body: NodeId,
     },
+    If {
+        cond: NodeId,
+        then_branch: NodeId,
+        else_branch: NodeId,
+    },
 }
+
+// Far more than any real query needs, and well short of where node ids would run into
+// `NodeId::INVALID`.
+pub const DEFAULT_MAX_NODES: usize = 1 << 20;
+
+#[derive(Debug, Clone, Copy, Error)]
+#[error("Query is too complex: it needs more than {0} syntax nodes")]
+pub struct ArenaFull(pub usize);
+
 pub struct Arena {
     nodes: Vec<Expr>,
     interner: Interner,
+    max_nodes: usize,
+}
+
+impl Default for Arena {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Arena {
@@ -106,6 +132,7 @@ impl Arena {
         Self {
             nodes: Vec::new(),
             interner: Interner::new(),
+            max_nodes: DEFAULT_MAX_NODES,
         }
     }
 
@@ -113,19 +140,28 @@ impl Arena {
         Self {
             nodes: Vec::with_capacity(node_capacity),
             interner: Interner::with_capacity(string_capacity),
+            max_nodes: DEFAULT_MAX_NODES,
         }
     }
 
+    pub fn with_max_nodes(mut self, max_nodes: usize) -> Self {
+        self.max_nodes = max_nodes.min(NodeId::INVALID.index());
+        self
+    }
+
     pub fn clear(&mut self) {
         self.nodes.clear();
         self.interner = Interner::new();
     }
 
     #[inline]
-    pub fn alloc(&mut self, expr: Expr) -> NodeId {
+    pub fn alloc(&mut self, expr: Expr) -> Result<NodeId, ArenaFull> {
+        if self.nodes.len() >= self.max_nodes {
+            return Err(ArenaFull(self.max_nodes));
+        }
         let id = NodeId(self.nodes.len() as u32);
         self.nodes.push(expr);
-        id
+        Ok(id)
     }
 
     #[inline]
@@ -146,30 +182,55 @@ impl Arena {
         self.interner.resolve(id)
     }
 
-    pub fn create_reference(&mut self, name: &str) -> NodeId {
+    pub fn create_reference(&mut self, name: &str) -> Result<NodeId, ArenaFull> {
         let str_id = self.intern_str(name);
         self.alloc(Expr::Reference(str_id))
     }
 
-    pub fn create_number(&mut self, value: &str) -> NodeId {
+    pub fn create_number(&mut self, value: &str) -> Result<NodeId, ArenaFull> {
         let str_id = self.intern_str(value);
         self.alloc(Expr::Number(str_id))
     }
 
-    pub fn create_string_lit(&mut self, value: &str) -> NodeId {
+    pub fn create_string_lit(&mut self, value: &str) -> Result<NodeId, ArenaFull> {
         let str_id = self.intern_str(value);
         self.alloc(Expr::StringLit(str_id))
     }
 
-    pub fn create_bool(&mut self, value: bool) -> NodeId {
+    pub fn create_char_lit(&mut self, value: char) -> Result<NodeId, ArenaFull> {
+        self.alloc(Expr::CharLit(value))
+    }
+
+    pub fn create_bool(&mut self, value: bool) -> Result<NodeId, ArenaFull> {
         self.alloc(Expr::Bool(value))
     }
 
-    pub fn create_binary_op(&mut self, op: TokenKind, left: NodeId, right: NodeId) -> NodeId {
+    pub fn create_null(&mut self) -> Result<NodeId, ArenaFull> {
+        self.alloc(Expr::Null)
+    }
+
+    pub fn create_parameter(&mut self, index: usize) -> Result<NodeId, ArenaFull> {
+        self.alloc(Expr::Parameter(index))
+    }
+
+    pub fn create_unary_op(&mut self, op: TokenKind, operand: NodeId) -> Result<NodeId, ArenaFull> {
+        self.alloc(Expr::UnaryOp { op, operand })
+    }
+
+    pub fn create_binary_op(
+        &mut self,
+        op: TokenKind,
+        left: NodeId,
+        right: NodeId,
+    ) -> Result<NodeId, ArenaFull> {
         self.alloc(Expr::BinaryOp { op, left, right })
     }
 
-    pub fn create_function_call(&mut self, func: NodeId, args: &[NodeId]) -> NodeId {
+    pub fn create_function_call(
+        &mut self,
+        func: NodeId,
+        args: &[NodeId],
+    ) -> Result<NodeId, ArenaFull> {
         let mut args_vec = SmallVec::with_capacity(args.len());
         for &arg in args {
             args_vec.push(arg);
@@ -180,7 +241,7 @@ impl Arena {
         })
     }
 
-    pub fn create_tuple(&mut self, items: &[NodeId]) -> NodeId {
+    pub fn create_tuple(&mut self, items: &[NodeId]) -> Result<NodeId, ArenaFull> {
         let mut tuple = SmallVec::with_capacity(items.len());
         for &item in items {
             tuple.push(item);
@@ -188,7 +249,7 @@ impl Arena {
         self.alloc(Expr::Tuple(tuple))
     }
 
-    pub fn create_array(&mut self, items: &[NodeId]) -> NodeId {
+    pub fn create_array(&mut self, items: &[NodeId]) -> Result<NodeId, ArenaFull> {
         let mut array = SmallVec::with_capacity(items.len());
         for &item in items {
             array.push(item);
@@ -196,7 +257,7 @@ impl Arena {
         self.alloc(Expr::Array(array))
     }
 
-    pub fn create_block(&mut self, exprs: &[NodeId]) -> NodeId {
+    pub fn create_block(&mut self, exprs: &[NodeId]) -> Result<NodeId, ArenaFull> {
         let mut block = SmallVec::with_capacity(exprs.len());
         for &expr in exprs {
             block.push(expr);
@@ -204,7 +265,11 @@ impl Arena {
         self.alloc(Expr::Block(block))
     }
 
-    pub fn create_lambda(&mut self, param_names: &[&str], body: NodeId) -> NodeId {
+    pub fn create_lambda(
+        &mut self,
+        param_names: &[&str],
+        body: NodeId,
+    ) -> Result<NodeId, ArenaFull> {
         let params = param_names
             .iter()
             .map(|&name| self.intern_str(name))
@@ -212,7 +277,7 @@ impl Arena {
         self.alloc(Expr::Lambda { params, body })
     }
 
-    pub fn create_instance(&mut self, fields: &[(&str, NodeId)]) -> NodeId {
+    pub fn create_instance(&mut self, fields: &[(&str, NodeId)]) -> Result<NodeId, ArenaFull> {
         let instance = fields
             .iter()
             .map(|&(name, value)| {
@@ -223,7 +288,7 @@ impl Arena {
         self.alloc(Expr::Instance(instance))
     }
 
-    pub fn create_field_access(&mut self, base: NodeId, field: &str) -> NodeId {
+    pub fn create_field_access(&mut self, base: NodeId, field: &str) -> Result<NodeId, ArenaFull> {
         let field_id = self.intern_str(field);
         self.alloc(Expr::FieldAccess {
             base,
@@ -231,7 +296,12 @@ impl Arena {
         })
     }
 
-    pub fn create_let(&mut self, name: &str, value: NodeId, body: NodeId) -> NodeId {
+    pub fn create_let(
+        &mut self,
+        name: &str,
+        value: NodeId,
+        body: NodeId,
+    ) -> Result<NodeId, ArenaFull> {
         let name_id = self.intern_str(name);
         self.alloc(Expr::Let {
             name: name_id,
@@ -240,6 +310,19 @@ impl Arena {
         })
     }
 
+    pub fn create_if(
+        &mut self,
+        cond: NodeId,
+        then_branch: NodeId,
+        else_branch: NodeId,
+    ) -> Result<NodeId, ArenaFull> {
+        self.alloc(Expr::If {
+            cond,
+            then_branch,
+            else_branch,
+        })
+    }
+
     pub fn extract_function_call(&self, id: NodeId) -> Option<(NodeId, Vec<NodeId>)> {
         match self.get(id) {
             Expr::FunctionCall { func, args } => Some((*func, args.to_vec())),
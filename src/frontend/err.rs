@@ -6,4 +6,14 @@ pub enum QueryParsingError {
     UnexpectedCharacter(char),
     #[error("The string starting at position {0} is not terminated")]
     UnterminatedString(usize),
+    #[error("The character literal starting at position {0} is not terminated")]
+    UnterminatedChar(usize),
+    #[error("The character literal at position {0} must contain exactly one character")]
+    InvalidCharLiteral(usize),
+    #[error("The comment starting at position {0} is not terminated")]
+    UnterminatedComment(usize),
+    #[error("The number starting at position {0} is malformed")]
+    MalformedNumber(usize),
+    #[error("Invalid escape sequence `\\{0}` at position {1}")]
+    InvalidEscape(char, usize),
 }
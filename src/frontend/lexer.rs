@@ -1,4 +1,5 @@
 use crate::frontend::err::QueryParsingError;
+use std::borrow::Cow;
 use std::fmt::Display;
 
 pub type LexResult<'src> = Result<Vec<Token<'src>>, QueryParsingError>;
@@ -15,6 +16,8 @@ pub struct Token<'src> {
 pub struct Span {
     pub start: usize,
     pub end: usize,
+    pub line: usize,
+    pub column: usize,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -22,11 +25,14 @@ pub enum TokenKind {
     Number,
     Identifier,
     String,
+    Char,
     Plus,
+    PlusPlus,
     Minus,
     Asterisk,
     Slash,
     Percent,
+    Caret,
     Application,
     LeftBraces,
     RightBraces,
@@ -49,13 +55,19 @@ pub enum TokenKind {
     Let,
     In,
     Do,
+    If,
+    Then,
+    Else,
     Dollar,
+    Parameter,
     Newline,
     True,
     False,
     Pipe,
     EqualsEquals,
     NotEquals,
+    Null,
+    Is,
 }
 
 pub struct Lexer<'src> {
@@ -65,6 +77,10 @@ pub struct Lexer<'src> {
     position: usize,
     line_start: bool,
     current_indent: usize,
+    line: usize,
+    column: usize,
+    token_line: usize,
+    token_column: usize,
 }
 
 impl<'src> Lexer<'src> {
@@ -78,16 +94,42 @@ impl<'src> Lexer<'src> {
             position: 0,
             line_start: true,
             current_indent: 0,
+            line: 1,
+            column: 1,
+            token_line: 1,
+            token_column: 1,
         }
     }
 
     fn advance(&mut self) {
+        match self.current_char {
+            Some((_, '\n')) => {
+                self.line += 1;
+                self.column = 1;
+            }
+            Some(_) => self.column += 1,
+            None => {}
+        }
         self.current_char = self.chars.next();
         if let Some((pos, _)) = self.current_char {
             self.position = pos;
         }
     }
 
+    fn mark_token_start(&mut self) {
+        self.token_line = self.line;
+        self.token_column = self.column;
+    }
+
+    fn span(&self, start: usize, end: usize) -> Span {
+        Span {
+            start,
+            end,
+            line: self.token_line,
+            column: self.token_column,
+        }
+    }
+
     fn peek(&self) -> Option<char> {
         self.current_char.map(|(_, c)| c)
     }
@@ -122,6 +164,28 @@ impl<'src> Lexer<'src> {
         }
     }
 
+    fn skip_block_comment(&mut self) -> Result<(), QueryParsingError> {
+        let start_pos = self.peek_pos();
+        self.advance(); // Consume '{'
+        self.advance(); // Consume '-'
+        let mut depth = 1;
+        while let Some((_, c)) = self.current_char {
+            if c == '{' && self.peek_next() == Some('-') {
+                depth += 1;
+                self.advance();
+            } else if c == '-' && self.peek_next() == Some('}') {
+                depth -= 1;
+                self.advance();
+                if depth == 0 {
+                    self.advance();
+                    return Ok(());
+                }
+            }
+            self.advance();
+        }
+        Err(QueryParsingError::UnterminatedComment(start_pos))
+    }
+
     fn is_identifier_start(c: char) -> bool {
         c.is_alphabetic() || c == '_'
     }
@@ -150,64 +214,111 @@ impl<'src> Lexer<'src> {
             "let" => TokenKind::Let,
             "in" => TokenKind::In,
             "do" => TokenKind::Do,
+            "if" => TokenKind::If,
+            "then" => TokenKind::Then,
+            "else" => TokenKind::Else,
             "true" => TokenKind::True,
             "false" => TokenKind::False,
+            "null" => TokenKind::Null,
+            "is" => TokenKind::Is,
+            "not" => TokenKind::Not,
             _ => TokenKind::Identifier,
         };
         Token {
             kind,
             value: text,
             indent: self.current_indent,
-            span: Span {
-                start: start_pos,
-                end: end_pos,
-            },
+            span: self.span(start_pos, end_pos),
         }
     }
 
-    fn read_number(&mut self) -> Token<'src> {
+    // `$1`, `$2`, ...; a `$` with no digits after it is the application operator instead.
+    fn read_parameter(&mut self) -> Token<'src> {
         let start_pos = self.peek_pos();
-        if self.peek() == Some('-') {
-            self.advance();
+        self.advance();
+        while let Some((_, c)) = self.current_char {
+            if Self::is_digit(c) {
+                self.advance();
+            } else {
+                break;
+            }
+        }
+        let end_pos = self.peek_pos();
+        Token {
+            kind: TokenKind::Parameter,
+            value: &self.source[start_pos..end_pos],
+            indent: self.current_indent,
+            span: self.span(start_pos, end_pos),
         }
+    }
+
+    /// Reads a run of digits that may use `_` as a separator between them, returning how many
+    /// digits were read.
+    fn read_digits(&mut self, number_start: usize) -> Result<usize, QueryParsingError> {
+        let mut count = 0;
+        let mut after_separator = false;
         while let Some((_, c)) = self.current_char {
             if Self::is_digit(c) {
+                count += 1;
+                after_separator = false;
+                self.advance();
+            } else if c == '_' {
+                if count == 0 || after_separator {
+                    return Err(QueryParsingError::MalformedNumber(number_start));
+                }
+                after_separator = true;
                 self.advance();
             } else {
                 break;
             }
         }
+        if after_separator {
+            return Err(QueryParsingError::MalformedNumber(number_start));
+        }
+        Ok(count)
+    }
+
+    fn read_number(&mut self) -> Result<Token<'src>, QueryParsingError> {
+        let start_pos = self.peek_pos();
+        if self.peek() == Some('-') {
+            self.advance();
+        }
+        self.read_digits(start_pos)?;
         if self.peek() == Some('.') {
             self.advance();
-            if let Some((_, c)) = self.current_char {
-                if Self::is_digit(c) {
-                    self.advance();
-                    while let Some((_, c)) = self.current_char {
-                        if Self::is_digit(c) {
-                            self.advance();
-                        } else {
-                            break;
-                        }
-                    }
-                }
+            self.read_digits(start_pos)?;
+        }
+        if let Some('e' | 'E') = self.peek() {
+            self.advance();
+            if let Some('+' | '-') = self.peek() {
+                self.advance();
+            }
+            if self.read_digits(start_pos)? == 0 {
+                return Err(QueryParsingError::MalformedNumber(start_pos));
             }
         }
         let end_pos = self.peek_pos();
-        Token {
+        Ok(Token {
             kind: TokenKind::Number,
             value: &self.source[start_pos..end_pos],
             indent: self.current_indent,
-            span: Span {
-                start: start_pos,
-                end: end_pos,
-            },
-        }
+            span: self.span(start_pos, end_pos),
+        })
     }
 
     fn read_string(&mut self) -> Result<Token<'src>, QueryParsingError> {
         let start_pos = self.peek_pos();
         self.advance();
-        while let Some((_, c)) = self.current_char {
+        while let Some((pos, c)) = self.current_char {
+            if c == '\\' {
+                self.advance();
+                match self.peek() {
+                    Some(escaped) if escape_char(escaped).is_some() => self.advance(),
+                    Some(escaped) => return Err(QueryParsingError::InvalidEscape(escaped, pos)),
+                    None => break,
+                }
+                continue;
+            }
             if c == '"' {
                 let end_pos = self.peek_pos() + 1;
                 self.advance();
@@ -215,10 +326,7 @@ impl<'src> Lexer<'src> {
                     kind: TokenKind::String,
                     value: &self.source[start_pos..end_pos],
                     indent: self.current_indent,
-                    span: Span {
-                        start: start_pos,
-                        end: end_pos,
-                    },
+                    span: self.span(start_pos, end_pos),
                 });
             }
             self.advance();
@@ -226,6 +334,38 @@ impl<'src> Lexer<'src> {
         Err(QueryParsingError::UnterminatedString(start_pos))
     }
 
+    fn read_char(&mut self) -> Result<Token<'src>, QueryParsingError> {
+        let start_pos = self.peek_pos();
+        self.advance();
+        match self.current_char {
+            None | Some((_, '\n')) => return Err(QueryParsingError::UnterminatedChar(start_pos)),
+            Some((_, '\'')) => return Err(QueryParsingError::InvalidCharLiteral(start_pos)),
+            Some((pos, '\\')) => {
+                self.advance();
+                match self.peek() {
+                    Some(escaped) if escape_char(escaped).is_some() => self.advance(),
+                    Some(escaped) => return Err(QueryParsingError::InvalidEscape(escaped, pos)),
+                    None => return Err(QueryParsingError::UnterminatedChar(start_pos)),
+                }
+            }
+            Some(_) => self.advance(),
+        }
+        match self.peek() {
+            Some('\'') => {
+                let end_pos = self.peek_pos() + 1;
+                self.advance();
+                Ok(Token {
+                    kind: TokenKind::Char,
+                    value: &self.source[start_pos..end_pos],
+                    indent: self.current_indent,
+                    span: self.span(start_pos, end_pos),
+                })
+            }
+            None | Some('\n') => Err(QueryParsingError::UnterminatedChar(start_pos)),
+            Some(_) => Err(QueryParsingError::InvalidCharLiteral(start_pos)),
+        }
+    }
+
     fn read_arrow(&mut self) -> Token<'src> {
         let start_pos = self.peek_pos();
         self.advance(); // Consume '-'
@@ -235,10 +375,7 @@ impl<'src> Lexer<'src> {
             kind: TokenKind::RightArrow,
             value: &self.source[start_pos..end_pos],
             indent: self.current_indent,
-            span: Span {
-                start: start_pos,
-                end: end_pos,
-            },
+            span: self.span(start_pos, end_pos),
         }
     }
 
@@ -251,10 +388,20 @@ impl<'src> Lexer<'src> {
             kind: TokenKind::Application,
             value: &self.source[start_pos..end_pos],
             indent: self.current_indent,
-            span: Span {
-                start: start_pos,
-                end: end_pos,
-            },
+            span: self.span(start_pos, end_pos),
+        }
+    }
+
+    fn read_two_char_token(&mut self, kind: TokenKind) -> Token<'src> {
+        let start_pos = self.peek_pos();
+        self.advance();
+        self.advance();
+        let end_pos = self.peek_pos();
+        Token {
+            kind,
+            value: &self.source[start_pos..end_pos],
+            indent: self.current_indent,
+            span: self.span(start_pos, end_pos),
         }
     }
 
@@ -267,6 +414,8 @@ impl<'src> Lexer<'src> {
             '-' => TokenKind::Minus,
             '*' => TokenKind::Asterisk,
             '/' => TokenKind::Slash,
+            '%' => TokenKind::Percent,
+            '^' => TokenKind::Caret,
             '{' => TokenKind::LeftBraces,
             '}' => TokenKind::RightBraces,
             '(' => TokenKind::LeftParenthesis,
@@ -279,6 +428,7 @@ impl<'src> Lexer<'src> {
             '|' => TokenKind::Pipe,
             '\\' => TokenKind::Lambda,
             '=' => TokenKind::Equals,
+            '!' => TokenKind::Not,
             '<' => TokenKind::LessThan,
             '>' => TokenKind::GreaterThan,
             '\n' => TokenKind::Newline,
@@ -290,10 +440,7 @@ impl<'src> Lexer<'src> {
                         kind: TokenKind::Newline,
                         value: &self.source[start_pos..end_pos],
                         indent: self.current_indent,
-                        span: Span {
-                            start: start_pos,
-                            end: end_pos,
-                        },
+                        span: self.span(start_pos, end_pos),
                     });
                 }
                 TokenKind::Newline
@@ -304,21 +451,23 @@ impl<'src> Lexer<'src> {
             kind,
             value: &self.source[start_pos..end_pos],
             indent: self.current_indent,
-            span: Span {
-                start: start_pos,
-                end: end_pos,
-            },
+            span: self.span(start_pos, end_pos),
         })
     }
 
     pub fn tokenize(&mut self) -> Result<Vec<Token<'src>>, QueryParsingError> {
-        let mut tokens = Vec::new();
+        self.collect()
+    }
+
+    // Reads tokens one at a time. `None` means the source is exhausted.
+    pub fn next_token(&mut self) -> Result<Option<Token<'src>>, QueryParsingError> {
         while let Some((_, c)) = self.current_char {
             if self.line_start {
                 self.current_indent = self.skip_whitespace();
                 self.line_start = false;
                 continue;
             }
+            self.mark_token_start();
             match c {
                 ' ' => {
                     self.advance();
@@ -329,52 +478,164 @@ impl<'src> Lexer<'src> {
                     if token.kind == TokenKind::Newline {
                         self.line_start = true;
                     }
-                    tokens.push(token);
+                    return Ok(Some(token));
+                }
+                '_' if self.peek_next().is_some_and(Self::is_digit) => {
+                    return Ok(Some(self.read_number()?));
                 }
                 c if Self::is_identifier_start(c) => {
-                    tokens.push(self.read_identifier());
+                    return Ok(Some(self.read_identifier()));
                 }
                 c if Self::is_digit(c) => {
-                    tokens.push(self.read_number());
+                    return Ok(Some(self.read_number()?));
                 }
                 '"' => {
-                    tokens.push(self.read_string()?);
+                    return Ok(Some(self.read_string()?));
+                }
+                '\'' => {
+                    return Ok(Some(self.read_char()?));
                 }
                 '-' => {
                     if let Some(next_c) = self.peek_next() {
                         if next_c == '>' {
-                            tokens.push(self.read_arrow());
+                            return Ok(Some(self.read_arrow()));
                         } else if next_c == '-' {
                             self.skip_comment();
                             continue;
-                        } else if next_c.is_digit(10) {
-                            tokens.push(self.read_number());
+                        } else if next_c.is_ascii_digit() {
+                            return Ok(Some(self.read_number()?));
                         } else {
-                            tokens.push(self.read_single_char_token(c)?);
+                            return Ok(Some(self.read_single_char_token(c)?));
                         }
                     } else {
-                        tokens.push(self.read_single_char_token(c)?);
+                        return Ok(Some(self.read_single_char_token(c)?));
                     }
                 }
                 '|' => {
                     if let Some(next_c) = self.peek_next() {
                         if next_c == '>' {
-                            tokens.push(self.read_application());
+                            return Ok(Some(self.read_application()));
+                        } else if next_c == '|' {
+                            return Ok(Some(self.read_two_char_token(TokenKind::Or)));
                         } else {
-                            tokens.push(self.read_single_char_token(c)?);
+                            return Ok(Some(self.read_single_char_token(c)?));
                         }
                     } else {
-                        tokens.push(self.read_single_char_token(c)?);
+                        return Ok(Some(self.read_single_char_token(c)?));
+                    }
+                }
+                '<' | '>' => {
+                    if self.peek_next() == Some('=') {
+                        let kind = if c == '>' {
+                            TokenKind::GreaterThanEquals
+                        } else {
+                            TokenKind::LessThanEquals
+                        };
+                        return Ok(Some(self.read_two_char_token(kind)));
+                    } else {
+                        return Ok(Some(self.read_single_char_token(c)?));
                     }
                 }
-                '+' | '*' | '/' | '(' | ')' | '{' | '}' | '[' | ']' | ',' | '.' | '$' | '='
-                | '\\' | '<' | '>' => {
-                    tokens.push(self.read_single_char_token(c)?);
+                '+' if self.peek_next() == Some('+') => {
+                    return Ok(Some(self.read_two_char_token(TokenKind::PlusPlus)));
+                }
+                '=' => {
+                    if self.peek_next() == Some('=') {
+                        return Ok(Some(self.read_two_char_token(TokenKind::EqualsEquals)));
+                    } else {
+                        return Ok(Some(self.read_single_char_token(c)?));
+                    }
+                }
+                '!' => {
+                    if self.peek_next() == Some('=') {
+                        return Ok(Some(self.read_two_char_token(TokenKind::NotEquals)));
+                    } else {
+                        return Ok(Some(self.read_single_char_token(c)?));
+                    }
+                }
+                '{' => {
+                    if self.peek_next() == Some('-') {
+                        self.skip_block_comment()?;
+                        continue;
+                    }
+                    return Ok(Some(self.read_single_char_token(c)?));
+                }
+                '$' if self.peek_next().is_some_and(Self::is_digit) => {
+                    return Ok(Some(self.read_parameter()));
+                }
+                '&' => {
+                    if self.peek_next() == Some('&') {
+                        return Ok(Some(self.read_two_char_token(TokenKind::And)));
+                    } else {
+                        return Err(QueryParsingError::UnexpectedCharacter(c));
+                    }
+                }
+                '+' | '*' | '/' | '%' | '^' | '(' | ')' | '}' | '[' | ']' | ',' | '.' | '$'
+                | '\\' => {
+                    return Ok(Some(self.read_single_char_token(c)?));
                 }
                 _ => return Err(QueryParsingError::UnexpectedCharacter(c)),
             }
         }
-        Ok(tokens)
+        Ok(None)
+    }
+}
+
+// Stops after the first error, since the lexer doesn't move past the offending character.
+impl<'src> Iterator for Lexer<'src> {
+    type Item = Result<Token<'src>, QueryParsingError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let result = self.next_token().transpose();
+        if matches!(result, Some(Err(_))) {
+            self.current_char = None;
+        }
+        result
+    }
+}
+
+fn escape_char(c: char) -> Option<char> {
+    match c {
+        'n' => Some('\n'),
+        't' => Some('\t'),
+        'r' => Some('\r'),
+        '\\' => Some('\\'),
+        '"' => Some('"'),
+        '\'' => Some('\''),
+        '0' => Some('\0'),
+        _ => None,
+    }
+}
+
+/// Strips the quotes off a lexed string token and resolves its escape sequences, only
+/// allocating when the literal actually contains one.
+pub fn unescape_string(lexeme: &str) -> Cow<'_, str> {
+    let contents = &lexeme[1..lexeme.len() - 1];
+    if !contents.contains('\\') {
+        return Cow::Borrowed(contents);
+    }
+
+    let mut unescaped = String::with_capacity(contents.len());
+    let mut chars = contents.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            if let Some(escaped) = chars.next().and_then(escape_char) {
+                unescaped.push(escaped);
+            }
+        } else {
+            unescaped.push(c);
+        }
+    }
+    Cow::Owned(unescaped)
+}
+
+/// Resolves the single, possibly escaped, character of a lexed character token.
+pub fn unescape_char(lexeme: &str) -> char {
+    let mut chars = lexeme[1..lexeme.len() - 1].chars();
+    match chars.next() {
+        Some('\\') => chars.next().and_then(escape_char).unwrap_or('\\'),
+        Some(c) => c,
+        None => '\0',
     }
 }
 
@@ -384,7 +645,9 @@ impl Display for TokenKind {
             TokenKind::Number => "Number",
             TokenKind::Identifier => "Identifier",
             TokenKind::String => "String",
+            TokenKind::Char => "Char",
             TokenKind::Plus => "Plus",
+            TokenKind::PlusPlus => "PlusPlus",
             TokenKind::Minus => "Minus",
             TokenKind::Asterisk => "Asterisk",
             TokenKind::Slash => "Slash",
@@ -405,7 +668,11 @@ impl Display for TokenKind {
             TokenKind::Let => "Let",
             TokenKind::In => "In",
             TokenKind::Do => "Do",
+            TokenKind::If => "If",
+            TokenKind::Then => "Then",
+            TokenKind::Else => "Else",
             TokenKind::Dollar => "Dollar",
+            TokenKind::Parameter => "Parameter",
             TokenKind::Newline => "Newline",
             TokenKind::True => "True",
             TokenKind::False => "False",
@@ -418,7 +685,229 @@ impl Display for TokenKind {
             TokenKind::GreaterThanEquals => "GreaterThanEquals",
             TokenKind::LessThanEquals => "LessThanEquals",
             TokenKind::Percent => "Percent",
+            TokenKind::Caret => "Caret",
+            TokenKind::Null => "Null",
+            TokenKind::Is => "Is",
         };
         write!(f, "{}", ref_name)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(source: &str) -> Vec<Token<'_>> {
+        Lexer::new(source).tokenize().unwrap()
+    }
+
+    fn kinds(source: &str) -> Vec<TokenKind> {
+        tokens(source).iter().map(|token| token.kind).collect()
+    }
+
+    #[test]
+    fn comparisons_with_equals_are_one_token() {
+        use TokenKind::*;
+        assert_eq!(kinds("a >= b"), [Identifier, GreaterThanEquals, Identifier]);
+        assert_eq!(kinds("a <= b"), [Identifier, LessThanEquals, Identifier]);
+        assert_eq!(kinds("a > b"), [Identifier, GreaterThan, Identifier]);
+
+        let operator = tokens("a >= b")[1];
+        assert_eq!(operator.value, ">=");
+        assert_eq!((operator.span.start, operator.span.end), (2, 4));
+    }
+
+    #[test]
+    fn double_equals_compares_and_single_equals_assigns() {
+        use TokenKind::*;
+        assert_eq!(kinds("x == 1"), [Identifier, EqualsEquals, Number]);
+        assert_eq!(kinds("x != 1"), [Identifier, NotEquals, Number]);
+        assert_eq!(
+            kinds("{ a = 1 }"),
+            [LeftBraces, Identifier, Equals, Number, RightBraces]
+        );
+    }
+
+    #[test]
+    fn logical_operators_and_modulus() {
+        use TokenKind::*;
+        assert_eq!(kinds("a && b"), [Identifier, And, Identifier]);
+        assert_eq!(kinds("a || b"), [Identifier, Or, Identifier]);
+        assert_eq!(kinds("x % 2"), [Identifier, Percent, Number]);
+        assert!(matches!(
+            Lexer::new("a & b").tokenize(),
+            Err(QueryParsingError::UnexpectedCharacter('&'))
+        ));
+    }
+
+    #[test]
+    fn string_escapes_are_resolved() {
+        let source = r#""say \"hi\"\n\tbye\\""#;
+        let string = tokens(source)[0];
+        assert_eq!(string.kind, TokenKind::String);
+        assert_eq!(string.value, source);
+        assert_eq!(unescape_string(string.value), "say \"hi\"\n\tbye\\");
+        assert!(matches!(
+            unescape_string(r#""plain""#),
+            Cow::Borrowed("plain")
+        ));
+    }
+
+    #[test]
+    fn bad_string_escapes_are_errors() {
+        assert!(matches!(
+            Lexer::new(r#""a\qb""#).tokenize(),
+            Err(QueryParsingError::InvalidEscape('q', 2))
+        ));
+        assert!(matches!(
+            Lexer::new(r#"x "abc\"#).tokenize(),
+            Err(QueryParsingError::UnterminatedString(2))
+        ));
+    }
+
+    #[test]
+    fn exponents_belong_to_the_number() {
+        for source in ["1e10", "2.5e-3", "1.0E+5"] {
+            let lexed = tokens(source);
+            assert_eq!(lexed.len(), 1, "{}", source);
+            assert_eq!((lexed[0].kind, lexed[0].value), (TokenKind::Number, source));
+        }
+        for source in ["1e", "2.5e-", "3E+x"] {
+            assert!(
+                matches!(
+                    Lexer::new(source).tokenize(),
+                    Err(QueryParsingError::MalformedNumber(0))
+                ),
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn block_comments_nest() {
+        use TokenKind::*;
+        assert_eq!(
+            kinds("a {- outer {- inner -} still comment -} b"),
+            [Identifier, Identifier]
+        );
+        assert_eq!(kinds("{ a = 1 }")[0], LeftBraces);
+        assert_eq!(
+            kinds("x -- to the end\ny"),
+            [Identifier, Newline, Identifier]
+        );
+    }
+
+    #[test]
+    fn unterminated_block_comment_is_an_error() {
+        assert!(matches!(
+            Lexer::new("a {- outer {- inner -} b").tokenize(),
+            Err(QueryParsingError::UnterminatedComment(2))
+        ));
+        assert!(matches!(
+            Lexer::new("{-").tokenize(),
+            Err(QueryParsingError::UnterminatedComment(0))
+        ));
+    }
+
+    #[test]
+    fn spans_know_their_line_and_column() {
+        let lexed = tokens("scan users\n  |> filter\n    é x");
+        let last = lexed.last().unwrap();
+        assert_eq!(last.value, "x");
+        assert_eq!((last.span.line, last.span.column), (3, 7));
+        assert_eq!((lexed[0].span.line, lexed[0].span.column), (1, 1));
+    }
+
+    #[test]
+    fn char_literals() {
+        let lexed = tokens(r"'x' '\n' '\''");
+        assert!(lexed.iter().all(|token| token.kind == TokenKind::Char));
+        let chars: Vec<char> = lexed
+            .iter()
+            .map(|token| unescape_char(token.value))
+            .collect();
+        assert_eq!(chars, ['x', '\n', '\'']);
+    }
+
+    #[test]
+    fn bad_char_literals_are_errors() {
+        let lex = |source| Lexer::new(source).tokenize();
+        assert!(matches!(
+            lex("''"),
+            Err(QueryParsingError::InvalidCharLiteral(0))
+        ));
+        assert!(matches!(
+            lex("'ab'"),
+            Err(QueryParsingError::InvalidCharLiteral(0))
+        ));
+        assert!(matches!(
+            lex("a 'b"),
+            Err(QueryParsingError::UnterminatedChar(2))
+        ));
+        assert!(matches!(
+            lex("'\n'"),
+            Err(QueryParsingError::UnterminatedChar(0))
+        ));
+        assert!(matches!(
+            lex(r"'\q'"),
+            Err(QueryParsingError::InvalidEscape('q', 1))
+        ));
+    }
+
+    #[test]
+    fn digit_separators_go_between_digits() {
+        assert_eq!(tokens("1_000_000")[0].value, "1_000_000");
+        for source in ["1__0", "_1", "1_", "1._5"] {
+            assert!(
+                matches!(
+                    Lexer::new(source).tokenize(),
+                    Err(QueryParsingError::MalformedNumber(0))
+                ),
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn streaming_yields_what_tokenize_collects() {
+        let source = "scan users\n  |> filter (\\u -> u.age >= 18) -- adults\n  |> take 1";
+        let mut lexer = Lexer::new(source);
+        let mut streamed = Vec::new();
+        while let Some(token) = lexer.next_token().unwrap() {
+            streamed.push(token);
+        }
+        assert_eq!(streamed, tokens(source));
+        assert_eq!(lexer.next_token().unwrap(), None);
+
+        let first = Lexer::new(source).next().unwrap().unwrap();
+        assert_eq!((first.kind, first.value), (TokenKind::Identifier, "scan"));
+    }
+
+    #[test]
+    fn streaming_stops_after_an_error() {
+        let mut lexer = Lexer::new("a # b");
+        assert_eq!(lexer.next().unwrap().unwrap().kind, TokenKind::Identifier);
+        assert!(matches!(
+            lexer.next(),
+            Some(Err(QueryParsingError::UnexpectedCharacter('#')))
+        ));
+        assert!(lexer.next().is_none());
+    }
+
+    #[test]
+    fn double_plus_is_one_token() {
+        use TokenKind::*;
+        assert_eq!(kinds("a ++ b"), [Identifier, PlusPlus, Identifier]);
+        assert_eq!(kinds("a + +b"), [Identifier, Plus, Plus, Identifier]);
+        assert_eq!(kinds("a+++b"), [Identifier, PlusPlus, Plus, Identifier]);
+    }
+
+    #[test]
+    fn columns_count_characters_along_the_line() {
+        let lexed = tokens("é >= \"ü\" ++ bb");
+        let columns: Vec<usize> = lexed.iter().map(|token| token.span.column).collect();
+        assert_eq!(columns, [1, 3, 6, 10, 13]);
+    }
+}
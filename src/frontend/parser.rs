@@ -1,17 +1,64 @@
-use crate::frontend::ast::{Arena, NodeId};
-use crate::frontend::lexer::{Token, TokenKind};
-
-#[derive(Debug, Clone)]
+use crate::frontend::ast::{Arena, ArenaFull, NodeId};
+use crate::frontend::lexer::{Token, TokenKind, unescape_char, unescape_string};
+use thiserror::Error;
+
+// The tokens `atom` can begin with. An application only takes another argument when one of
+// these comes next, so any operator, delimiter or keyword ends it.
+const ATOM_START: &[TokenKind] = &[
+    TokenKind::Number,
+    TokenKind::Identifier,
+    TokenKind::String,
+    TokenKind::Char,
+    TokenKind::True,
+    TokenKind::False,
+    TokenKind::Null,
+    TokenKind::Parameter,
+    TokenKind::LeftBraces,
+    TokenKind::LeftParenthesis,
+    TokenKind::LeftBracket,
+    TokenKind::Let,
+    TokenKind::Dollar,
+    TokenKind::Do,
+    TokenKind::If,
+    TokenKind::Lambda,
+];
+
+#[derive(Debug, Clone, Error)]
 pub enum ParseError<'src> {
+    #[error(
+        "`{}` is not an expression at line {}, column {}",
+        .0.value, .0.span.line, .0.span.column
+    )]
     NotAnExpression(Token<'src>),
+    #[error(
+        "Expected an expression at line {}, column {}, but found `{}`",
+        .0.span.line, .0.span.column, .0.value
+    )]
     ExpectedAnExpression(Token<'src>),
+    #[error(
+        "Expected {kind} at line {line}, column {column}, but found `{found}`",
+        kind = .0, line = .1.span.line, column = .1.span.column, found = .1.value
+    )]
     ExpectedToken(TokenKind, Token<'src>),
+    #[error(
+        "Expected indentation {expected} at line {}, column {}, but found {actual}",
+        .token.span.line, .token.span.column
+    )]
     ExpectedDifferentIndentation {
         token: Token<'src>,
         expected: usize,
         actual: usize,
     },
+    #[error(
+        "Unexpected `{}` at line {}, column {} after the end of the query",
+        .0.value, .0.span.line, .0.span.column
+    )]
+    TrailingInput(Token<'src>),
+    #[error(transparent)]
+    TooComplex(#[from] ArenaFull),
+    #[error("Unexpected end of input")]
     UnexpectedEndOfInput,
+    #[error("{0}")]
     Custom(String),
 }
 
@@ -19,6 +66,9 @@ pub struct Parser<'src> {
     tokens: &'src [Token<'src>],
     pos: usize,
     arena: &'src mut Arena,
+    // The furthest-reaching error an application swallowed when it stopped collecting
+    // arguments. If the parse then stops short of it, it's the real mistake.
+    abandoned: Option<(usize, ParseError<'src>)>,
 }
 
 impl<'src> Parser<'src> {
@@ -27,6 +77,7 @@ impl<'src> Parser<'src> {
             tokens,
             pos: 0,
             arena,
+            abandoned: None,
         }
     }
 
@@ -34,6 +85,92 @@ impl<'src> Parser<'src> {
         self.expression()
     }
 
+    // Like `parse_expression`, but a failed expression doesn't end the parse: the parser skips
+    // to the next top-level line and carries on, so every independent mistake in a file is
+    // reported in one go. Once something failed, whatever parses afterwards only serves to
+    // get back in sync.
+    pub fn parse_expression_all(&mut self) -> Result<NodeId, Vec<ParseError<'src>>> {
+        let mut root = None;
+        let mut errors = Vec::new();
+
+        loop {
+            self.skip_newlines();
+            let Ok(token) = self.peek() else {
+                break;
+            };
+            let start = self.pos;
+
+            if root.is_some() && errors.is_empty() {
+                let (position, err) = match self.abandoned.take() {
+                    Some((position, err)) if position >= start => (position, err),
+                    _ => (start, ParseError::TrailingInput(token)),
+                };
+                errors.push(err);
+                self.synchronize(start, position);
+                continue;
+            }
+
+            self.abandoned = None;
+            match self.expression() {
+                Ok(node) => root = Some(node),
+                // Past the node limit every further attempt would fail the same way.
+                Err(err @ ParseError::TooComplex(_)) => {
+                    errors.push(err);
+                    break;
+                }
+                Err(err) => {
+                    let failed_at = self.error_position(&err).max(self.pos);
+                    errors.push(err);
+                    self.synchronize(start, failed_at);
+                }
+            }
+        }
+
+        match root {
+            Some(root) if errors.is_empty() => Ok(root),
+            None if errors.is_empty() => Err(vec![ParseError::UnexpectedEndOfInput]),
+            _ => Err(errors),
+        }
+    }
+
+    fn error_position(&self, err: &ParseError<'src>) -> usize {
+        let token = match err {
+            ParseError::NotAnExpression(token)
+            | ParseError::ExpectedAnExpression(token)
+            | ParseError::ExpectedToken(_, token)
+            | ParseError::ExpectedDifferentIndentation { token, .. }
+            | ParseError::TrailingInput(token) => token,
+            ParseError::UnexpectedEndOfInput => return self.tokens.len(),
+            ParseError::Custom(_) | ParseError::TooComplex(_) => return self.pos,
+        };
+        self.tokens
+            .iter()
+            .position(|candidate| candidate.span == token.span)
+            .unwrap_or(self.pos)
+    }
+
+    // Moves past the failed expression to the next unindented line. Lines opening with a
+    // closing delimiter or a pipe still belong to the expression above, like the `} (name)`
+    // that ends a multi-line insert.
+    fn synchronize(&mut self, start: usize, failed_at: usize) {
+        self.pos = failed_at.max(start + 1);
+        while let Some(token) = self.tokens.get(self.pos) {
+            let starts_line = self.tokens[self.pos - 1].kind == TokenKind::Newline;
+            let continues_expression = matches!(
+                token.kind,
+                TokenKind::Newline
+                    | TokenKind::RightParenthesis
+                    | TokenKind::RightBracket
+                    | TokenKind::RightBraces
+                    | TokenKind::Application
+            );
+            if starts_line && token.indent == 0 && !continues_expression {
+                break;
+            }
+            self.pos += 1;
+        }
+    }
+
     fn peek(&self) -> Result<Token<'src>, ParseError<'src>> {
         self.tokens
             .get(self.pos)
@@ -119,72 +256,132 @@ impl<'src> Parser<'src> {
     }
 
     fn pipe_expression(&mut self) -> Result<NodeId, ParseError<'src>> {
-        let mut left = self.comparison_expression()?;
+        let mut left = self.or_expression()?;
 
         // Use peek_is_any_relevant to look past newlines for pipe operators
         while self.peek_is_any_relevant(&[TokenKind::Application]) {
             // Skip newlines before consuming the pipe operator
             self.skip_newlines();
             self.consume()?; // consume the pipe operator
-            let right = self.comparison_expression()?;
+            let right = self.or_expression()?;
 
+            // The piped value becomes the last argument, which is where every built-in takes
+            // its input, so `a |> f x |> g y` is `g y (f x a)` whatever the arities.
             if let Some((func, mut args)) = self.arena.extract_function_call(right) {
                 args.push(left);
-                left = self.arena.create_function_call(func, &args);
+                left = self.arena.create_function_call(func, &args)?;
             } else {
-                left = self.arena.create_function_call(right, &[left]);
+                left = self.arena.create_function_call(right, &[left])?;
             }
         }
         Ok(left)
     }
 
+    fn or_expression(&mut self) -> Result<NodeId, ParseError<'src>> {
+        let mut left = self.and_expression()?;
+
+        while self.peek_is_any(&[TokenKind::Or]) {
+            let op_token = self.consume()?;
+            let right = self.and_expression()?;
+            left = self.arena.create_binary_op(op_token.kind, left, right)?;
+        }
+
+        Ok(left)
+    }
+
+    fn and_expression(&mut self) -> Result<NodeId, ParseError<'src>> {
+        let mut left = self.not_expression()?;
+
+        while self.peek_is_any(&[TokenKind::And]) {
+            let op_token = self.consume()?;
+            let right = self.not_expression()?;
+            left = self.arena.create_binary_op(op_token.kind, left, right)?;
+        }
+
+        Ok(left)
+    }
+
+    // Negates a whole comparison, so `!u.age > 18` reads as `!(u.age > 18)`.
+    fn not_expression(&mut self) -> Result<NodeId, ParseError<'src>> {
+        if self.peek_is_any(&[TokenKind::Not]) {
+            let op_token = self.consume()?;
+            let operand = self.not_expression()?;
+            return Ok(self.arena.create_unary_op(op_token.kind, operand)?);
+        }
+        self.comparison_expression()
+    }
+
+    // Looser than arithmetic, so `a ++ b + c` is `a ++ (b + c)`.
+    fn concat_expression(&mut self) -> Result<NodeId, ParseError<'src>> {
+        let mut left = self.numeric_expression()?;
+
+        while self.peek_is_any(&[TokenKind::PlusPlus]) {
+            let op_token = self.consume()?;
+            let right = self.numeric_expression()?;
+            left = self.arena.create_binary_op(op_token.kind, left, right)?;
+        }
+
+        Ok(left)
+    }
+
     fn numeric_expression(&mut self) -> Result<NodeId, ParseError<'src>> {
         let mut left = self.term()?;
 
         while self.peek_is_any(&[TokenKind::Plus, TokenKind::Minus]) {
             let op_token = self.consume()?;
             let right = self.term()?;
-            left = self.arena.create_binary_op(op_token.kind, left, right);
+            left = self.arena.create_binary_op(op_token.kind, left, right)?;
         }
 
         Ok(left)
     }
 
     fn term(&mut self) -> Result<NodeId, ParseError<'src>> {
-        let mut left = self.application()?;
+        let mut left = self.power()?;
 
-        while self.peek_is_any(&[TokenKind::Asterisk, TokenKind::Slash]) {
+        while self.peek_is_any(&[TokenKind::Asterisk, TokenKind::Slash, TokenKind::Percent]) {
             let op_token = self.consume()?;
-            let right = self.application()?;
-            left = self.arena.create_binary_op(op_token.kind, left, right);
+            let right = self.power()?;
+            left = self.arena.create_binary_op(op_token.kind, left, right)?;
         }
 
         Ok(left)
     }
 
+    // Right-associative, so `2 ^ 3 ^ 2` is `2 ^ (3 ^ 2)`.
+    fn power(&mut self) -> Result<NodeId, ParseError<'src>> {
+        let base = self.application()?;
+
+        if self.peek_is_any(&[TokenKind::Caret]) {
+            let op_token = self.consume()?;
+            let exponent = self.power()?;
+            return Ok(self.arena.create_binary_op(op_token.kind, base, exponent)?);
+        }
+
+        Ok(base)
+    }
+
     fn application(&mut self) -> Result<NodeId, ParseError<'src>> {
         let first = self.field_access()?;
 
         let mut items = vec![first];
         while let Ok(current_pos) = self.save_position() {
-            if self.peek_is_any(&[
-                TokenKind::Plus,
-                TokenKind::Minus,
-                TokenKind::Asterisk,
-                TokenKind::Slash,
-                TokenKind::RightParenthesis,
-                TokenKind::RightBracket,
-                TokenKind::Comma,
-                TokenKind::In,
-                TokenKind::Newline,
-                TokenKind::Application,
-            ]) {
+            if !self.peek_is_any(ATOM_START) {
                 break;
             }
 
             match self.field_access() {
                 Ok(item) => items.push(item),
-                Err(_) => {
+                Err(err @ ParseError::TooComplex(_)) => return Err(err),
+                Err(err) => {
+                    let position = self.error_position(&err);
+                    if self
+                        .abandoned
+                        .as_ref()
+                        .is_none_or(|(furthest, _)| position > *furthest)
+                    {
+                        self.abandoned = Some((position, err));
+                    }
                     self.restore_position(current_pos);
                     break;
                 }
@@ -195,7 +392,7 @@ impl<'src> Parser<'src> {
             return Ok(items[0]);
         }
 
-        let result = self.arena.create_function_call(items[0], &items[1..]);
+        let result = self.arena.create_function_call(items[0], &items[1..])?;
         Ok(result)
     }
 
@@ -205,23 +402,43 @@ impl<'src> Parser<'src> {
         match token.kind {
             TokenKind::Number => {
                 self.consume()?;
-                Ok(self.arena.create_number(token.value))
+                Ok(self.arena.create_number(token.value)?)
             }
             TokenKind::Identifier => {
                 self.consume()?;
-                Ok(self.arena.create_reference(token.value))
+                Ok(self.arena.create_reference(token.value)?)
             }
             TokenKind::String => {
                 self.consume()?;
-                Ok(self.arena.create_string_lit(token.value))
+                Ok(self
+                    .arena
+                    .create_string_lit(&unescape_string(token.value))?)
+            }
+            TokenKind::Char => {
+                self.consume()?;
+                Ok(self.arena.create_char_lit(unescape_char(token.value))?)
             }
             TokenKind::True => {
                 self.consume()?;
-                Ok(self.arena.create_bool(true))
+                Ok(self.arena.create_bool(true)?)
             }
             TokenKind::False => {
                 self.consume()?;
-                Ok(self.arena.create_bool(false))
+                Ok(self.arena.create_bool(false)?)
+            }
+            TokenKind::Null => {
+                self.consume()?;
+                Ok(self.arena.create_null()?)
+            }
+            TokenKind::Parameter => {
+                self.consume()?;
+                match token.value[1..].parse::<usize>() {
+                    Ok(number) if number > 0 => Ok(self.arena.create_parameter(number - 1)?),
+                    _ => Err(ParseError::Custom(format!(
+                        "Invalid parameter `{}` at line {}, column {}: parameters are numbered from $1",
+                        token.value, token.span.line, token.span.column
+                    ))),
+                }
             }
             TokenKind::LeftBraces => self.instance_expr(),
             TokenKind::LeftParenthesis => self.paren_expr(),
@@ -232,7 +449,8 @@ impl<'src> Parser<'src> {
                 self.expression()
             }
             TokenKind::Do => self.do_expr(),
-            TokenKind::Lambda => self.lambda_short_form(),
+            TokenKind::If => self.if_expr(),
+            TokenKind::Lambda => self.lambda_expr(),
             _ => Err(ParseError::NotAnExpression(token)),
         }
     }
@@ -243,28 +461,39 @@ impl<'src> Parser<'src> {
         while self.peek_is_any(&[TokenKind::Dot]) {
             self.consume()?;
             let field_token = self.expect(TokenKind::Identifier)?;
-            expr = self.arena.create_field_access(expr, field_token.value);
+            expr = self.arena.create_field_access(expr, field_token.value)?;
         }
 
         Ok(expr)
     }
 
-    fn lambda_short_form(&mut self) -> Result<NodeId, ParseError<'src>> {
+    fn lambda_expr(&mut self) -> Result<NodeId, ParseError<'src>> {
         self.expect(TokenKind::Lambda)?;
+        let param_names = self.lambda_params()?;
 
-        let mut param_names = Vec::new();
+        self.expect(TokenKind::RightArrow)?;
+        let body = self.expression()?;
 
-        loop {
-            if self.peek_is_any(&[TokenKind::RightArrow]) {
-                break;
+        Ok(self.arena.create_lambda(&param_names, body)?)
+    }
+
+    // Both lambda forms take `\a b`, `\a.b` and `\a, b` alike.
+    fn lambda_params(&mut self) -> Result<Vec<&'src str>, ParseError<'src>> {
+        let mut param_names: Vec<&'src str> = Vec::new();
+
+        while !self.peek_is_any(&[TokenKind::RightArrow]) {
+            if !param_names.is_empty() && self.peek_is_any(&[TokenKind::Comma, TokenKind::Dot]) {
+                self.consume()?;
             }
 
             let id_token = self.expect(TokenKind::Identifier)?;
-            param_names.push(id_token.value);
-
-            if self.peek_is_any(&[TokenKind::RightArrow]) {
-                break;
+            if param_names.contains(&id_token.value) {
+                return Err(ParseError::Custom(format!(
+                    "Lambda parameter `{}` at line {}, column {} is already declared",
+                    id_token.value, id_token.span.line, id_token.span.column
+                )));
             }
+            param_names.push(id_token.value);
         }
 
         if param_names.is_empty() {
@@ -273,10 +502,7 @@ impl<'src> Parser<'src> {
             ));
         }
 
-        self.expect(TokenKind::RightArrow)?;
-        let body = self.expression()?;
-
-        Ok(self.arena.create_lambda(&*param_names, body))
+        Ok(param_names)
     }
 
     fn paren_expr(&mut self) -> Result<NodeId, ParseError<'src>> {
@@ -291,7 +517,7 @@ impl<'src> Parser<'src> {
             if items.len() == 1 {
                 Ok(items[0])
             } else {
-                Ok(self.arena.create_tuple(&items))
+                Ok(self.arena.create_tuple(&items)?)
             }
         }
     }
@@ -299,7 +525,7 @@ impl<'src> Parser<'src> {
     fn array_expr(&mut self) -> Result<NodeId, ParseError<'src>> {
         self.expect(TokenKind::LeftBracket)?;
         let items = self.comma_separated_expressions(TokenKind::RightBracket)?;
-        Ok(self.arena.create_array(&items))
+        Ok(self.arena.create_array(&items)?)
     }
 
     fn instance_expr(&mut self) -> Result<NodeId, ParseError<'src>> {
@@ -322,48 +548,24 @@ impl<'src> Parser<'src> {
             self.consume()?;
         }
         self.expect_relevant(TokenKind::RightBraces)?;
-        Ok(self.arena.create_instance(&items))
-    }
-
-    fn lambda_expr(&mut self) -> Result<NodeId, ParseError<'src>> {
-        self.expect(TokenKind::Lambda)?;
-
-        let mut param_names = Vec::new();
-
-        let id_token = self.expect(TokenKind::Identifier)?;
-        param_names.push(id_token.value);
-
-        loop {
-            if self.peek_is_any(&[TokenKind::RightArrow]) {
-                break;
-            }
-
-            self.expect(TokenKind::Dot)?;
-            let id_token = self.expect(TokenKind::Identifier)?;
-            param_names.push(id_token.value);
-        }
-
-        self.expect(TokenKind::RightArrow)?;
-        let body = self.expression()?;
-
-        Ok(self.arena.create_lambda(&*param_names, body))
+        Ok(self.arena.create_instance(&items)?)
     }
 
     fn let_expr(&mut self) -> Result<NodeId, ParseError<'src>> {
         self.expect(TokenKind::Let)?;
 
         let start_pos = self.save_position()?;
-        if let Ok(token) = self.peek() {
-            if token.kind == TokenKind::Newline {
-                self.consume()?;
-                let let_indent = token.indent;
-                let bindings = self.parse_indented_bindings(let_indent)?;
-                self.skip_newlines();
-                self.expect(TokenKind::In)?;
-                let body = self.expression()?;
+        if let Ok(token) = self.peek()
+            && token.kind == TokenKind::Newline
+        {
+            self.consume()?;
+            let let_indent = token.indent;
+            let bindings = self.parse_indented_bindings(let_indent)?;
+            self.skip_newlines();
+            self.expect(TokenKind::In)?;
+            let body = self.expression()?;
 
-                return self.create_nested_lets(bindings, body);
-            }
+            return self.create_nested_lets(bindings, body);
         }
 
         self.restore_position(start_pos);
@@ -374,7 +576,18 @@ impl<'src> Parser<'src> {
         self.expect(TokenKind::In)?;
         let body = self.expression()?;
 
-        Ok(self.arena.create_let(id_token.value, value, body))
+        Ok(self.arena.create_let(id_token.value, value, body)?)
+    }
+
+    fn if_expr(&mut self) -> Result<NodeId, ParseError<'src>> {
+        self.expect(TokenKind::If)?;
+        let cond = self.expression()?;
+        self.expect_relevant(TokenKind::Then)?;
+        let then_branch = self.expression()?;
+        self.expect_relevant(TokenKind::Else)?;
+        let else_branch = self.expression()?;
+
+        Ok(self.arena.create_if(cond, then_branch, else_branch)?)
     }
 
     fn parse_indented_bindings(
@@ -383,22 +596,18 @@ impl<'src> Parser<'src> {
     ) -> Result<Vec<(&'src str, NodeId)>, ParseError<'src>> {
         let mut bindings = Vec::new();
 
-        loop {
-            if let Ok(token) = self.peek_relevant() {
-                if token.indent < indent || token.kind == TokenKind::In {
-                    break;
-                }
+        while let Ok(token) = self.peek_relevant() {
+            if token.indent < indent || token.kind == TokenKind::In {
+                break;
+            }
 
-                let id_token = self.expect(TokenKind::Identifier)?;
-                self.expect(TokenKind::Equals)?;
-                let value = self.expression()?;
+            let id_token = self.expect(TokenKind::Identifier)?;
+            self.expect(TokenKind::Equals)?;
+            let value = self.expression()?;
 
-                bindings.push((id_token.value, value));
+            bindings.push((id_token.value, value));
 
-                self.skip_newlines();
-            } else {
-                break;
-            }
+            self.skip_newlines();
         }
 
         if bindings.is_empty() {
@@ -411,17 +620,46 @@ impl<'src> Parser<'src> {
     }
 
     fn comparison_expression(&mut self) -> Result<NodeId, ParseError<'src>> {
-        let mut left = self.numeric_expression()?;
+        let mut left = self.concat_expression()?;
 
         while self.peek_is_any(&[
             TokenKind::GreaterThan,
+            TokenKind::GreaterThanEquals,
             TokenKind::LessThan,
+            TokenKind::LessThanEquals,
             TokenKind::EqualsEquals,
             TokenKind::NotEquals,
         ]) {
             let op_token = self.consume()?;
-            let right = self.numeric_expression()?;
-            left = self.arena.create_binary_op(op_token.kind, left, right);
+            let right = self.concat_expression()?;
+            left = self.arena.create_binary_op(op_token.kind, left, right)?;
+        }
+
+        // `x is not null` reads as `!(x is null)`.
+        if self.peek_is_any(&[TokenKind::Is]) {
+            let op_token = self.consume()?;
+            let negated = self.peek_is_any(&[TokenKind::Not]);
+            if negated {
+                self.consume()?;
+            }
+            self.expect(TokenKind::Null)?;
+            let null = self.arena.create_null()?;
+            left = self.arena.create_binary_op(op_token.kind, left, null)?;
+            if negated {
+                left = self.arena.create_unary_op(TokenKind::Not, left)?;
+            }
+        }
+
+        // `in` also closes a `let` binding, so it only tests membership when a list follows.
+        if self.peek_is_any(&[TokenKind::In])
+            && self
+                .tokens
+                .get(self.pos + 1)
+                .is_some_and(|token| token.kind == TokenKind::LeftBracket)
+        {
+            let op_token = self.consume()?;
+            let right = self.array_expr()?;
+            left = self.arena.create_binary_op(op_token.kind, left, right)?;
         }
 
         Ok(left)
@@ -435,7 +673,7 @@ impl<'src> Parser<'src> {
         let mut result = body;
 
         for (name, value) in bindings.into_iter().rev() {
-            result = self.arena.create_let(name, value, result);
+            result = self.arena.create_let(name, value, result)?;
         }
 
         Ok(result)
@@ -444,20 +682,22 @@ impl<'src> Parser<'src> {
     fn do_expr(&mut self) -> Result<NodeId, ParseError<'src>> {
         let do_token = self.expect(TokenKind::Do)?;
         let indent = do_token.indent;
+        // The statements can start on the line of the `do` or on the ones below it.
+        self.skip_newlines();
 
         let exprs = self.indented_block(indent)?;
-        Ok(self.arena.create_block(&exprs))
+        Ok(self.arena.create_block(&exprs)?)
     }
 
     fn comma_separated_expressions(
         &mut self,
         end_token: TokenKind,
     ) -> Result<Vec<NodeId>, ParseError<'src>> {
-        if let Ok(token) = self.peek() {
-            if token.kind == end_token {
-                self.consume()?;
-                return Ok(Vec::new());
-            }
+        if let Ok(token) = self.peek()
+            && token.kind == end_token
+        {
+            self.consume()?;
+            return Ok(Vec::new());
         }
 
         let mut exprs = Vec::new();
@@ -471,11 +711,11 @@ impl<'src> Parser<'src> {
                     break;
                 } else if token.kind == TokenKind::Comma {
                     self.consume()?;
-                    if let Ok(next) = self.peek() {
-                        if next.kind == end_token {
-                            self.consume()?;
-                            break;
-                        }
+                    if let Ok(next) = self.peek()
+                        && next.kind == end_token
+                    {
+                        self.consume()?;
+                        break;
                     }
                     exprs.push(self.expression()?);
                 } else {
@@ -494,22 +734,23 @@ impl<'src> Parser<'src> {
 
         exprs.push(self.expression()?);
 
+        // The block can run to the end of the input, so there may be nothing left to peek.
         loop {
-            let current_pos = self.save_position()?;
-
-            if let Ok(token) = self.peek() {
-                if token.kind == TokenKind::Newline {
-                    self.consume()?;
+            let current_pos = self.pos;
 
-                    if let Ok(token) = self.peek() {
-                        if token.indent < indent {
-                            self.restore_position(current_pos);
-                            break;
-                        }
+            if let Ok(token) = self.peek()
+                && token.kind == TokenKind::Newline
+            {
+                self.consume()?;
 
-                        exprs.push(self.expression()?);
-                        continue;
+                if let Ok(token) = self.peek() {
+                    if token.indent < indent {
+                        self.restore_position(current_pos);
+                        break;
                     }
+
+                    exprs.push(self.expression()?);
+                    continue;
                 }
             }
 
@@ -528,3 +769,188 @@ pub fn parse_expression<'src>(
     let mut parser = Parser::new(tokens, arena);
     parser.parse_expression()
 }
+
+pub fn parse_expression_all<'src>(
+    tokens: &'src [Token<'src>],
+    arena: &'src mut Arena,
+) -> Result<NodeId, Vec<ParseError<'src>>> {
+    let mut parser = Parser::new(tokens, arena);
+    parser.parse_expression_all()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frontend::ast::Expr;
+    use crate::frontend::lexer::Lexer;
+    use crate::frontend::print::Unparser;
+
+    /// Parses `source` and prints it back with only the parentheses its structure needs.
+    fn reparsed(source: &str) -> String {
+        let tokens = Lexer::new(source).tokenize().unwrap();
+        let mut arena = Arena::with_capacity(100, 100);
+        let root = parse_expression_all(&tokens, &mut arena).unwrap();
+        Unparser::unparse(&arena, root)
+    }
+
+    fn errors(source: &str) -> Vec<String> {
+        let tokens = Lexer::new(source).tokenize().unwrap();
+        let mut arena = Arena::with_capacity(100, 100);
+        match parse_expression_all(&tokens, &mut arena) {
+            Ok(_) => vec![],
+            Err(errors) => errors.iter().map(|err| err.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn errors_point_at_line_and_column() {
+        assert_eq!(
+            errors("scan users\n  |> filter\n  )"),
+            ["Unexpected `)` at line 3, column 3 after the end of the query"]
+        );
+    }
+
+    #[test]
+    fn if_nests_in_either_branch() {
+        assert_eq!(
+            reparsed("if a then if b then 1 else 2 else 3"),
+            "if a then (if b then 1 else 2) else 3"
+        );
+        assert_eq!(
+            reparsed("if a then 1 else if b then 2 else 3"),
+            "if a then 1 else if b then 2 else 3"
+        );
+        assert_eq!(
+            reparsed("if if a then b else c then 1 else 2"),
+            "if (if a then b else c) then 1 else 2"
+        );
+    }
+
+    #[test]
+    fn if_without_else_is_an_error() {
+        assert!(!errors("if a then 1").is_empty());
+    }
+
+    #[test]
+    fn in_takes_a_list_on_its_right() {
+        assert_eq!(
+            reparsed(r#"u.status in ["active", "pending"] && u.age > 1"#),
+            r#"u.status in ["active", "pending"] && u.age > 1"#
+        );
+        assert_eq!(reparsed("x in []"), "x in []");
+        // Without a list, `in` is left for a `let` to close.
+        assert_eq!(reparsed("let x = 1 in x"), "let x = 1 in x");
+        assert!(!errors(r"scan users |> filter (\u -> u.age in u.retirement)").is_empty());
+    }
+
+    #[test]
+    fn is_not_null_negates_the_null_test() {
+        assert_eq!(
+            reparsed("x is not null && y is null"),
+            "!x is null && y is null"
+        );
+        assert!(!errors("x is not 1").is_empty());
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert_eq!(reparsed("a || b && c"), "a || b && c");
+        assert_eq!(reparsed("a && b || c"), "a && b || c");
+        assert_eq!(reparsed("(a || b) && c"), "(a || b) && c");
+        assert_eq!(reparsed("!a && b || !(c || d)"), "!a && b || !(c || d)");
+    }
+
+    #[test]
+    fn lambda_params_can_be_separated_by_commas() {
+        assert_eq!(reparsed(r"\a, b, c -> a + b + c"), r"\a b c -> a + b + c");
+        assert_eq!(reparsed(r"(\a, b -> a == b)"), r"\a b -> a == b");
+        assert_eq!(reparsed(r"\a b -> a"), r"\a b -> a");
+    }
+
+    #[test]
+    fn lambda_params_must_be_distinct_and_present() {
+        assert_eq!(
+            errors(r"\a, b, a -> a"),
+            ["Lambda parameter `a` at line 1, column 8 is already declared"]
+        );
+        assert_eq!(
+            errors(r"\ -> 1"),
+            ["Lambda expression needs at least one parameter"]
+        );
+    }
+
+    #[test]
+    fn every_broken_line_is_reported() {
+        let source = "let x = in x\nlet y = 2 in )";
+        assert_eq!(
+            errors(source),
+            [
+                "`in` is not an expression at line 1, column 9",
+                "`)` is not an expression at line 2, column 14",
+            ]
+        );
+
+        let tokens = Lexer::new(source).tokenize().unwrap();
+        let mut arena = Arena::with_capacity(100, 100);
+        assert!(matches!(
+            parse_expression(&tokens, &mut arena),
+            Err(ParseError::NotAnExpression(token)) if token.span.line == 1
+        ));
+    }
+
+    #[test]
+    fn query_past_the_node_limit_is_too_complex() {
+        let tokens = Lexer::new("1 + 2 + 3 + 4").tokenize().unwrap();
+        let mut arena = Arena::new().with_max_nodes(4);
+        let errors = parse_expression_all(&tokens, &mut arena).unwrap_err();
+        assert!(matches!(errors[..], [ParseError::TooComplex(ArenaFull(4))]));
+
+        let tokens = Lexer::new("1 + 2").tokenize().unwrap();
+        let mut arena = Arena::new().with_max_nodes(3);
+        assert!(parse_expression(&tokens, &mut arena).is_ok());
+        assert!(matches!(arena.alloc(Expr::Null), Err(ArenaFull(3))));
+    }
+
+    #[test]
+    fn null_is_an_expression() {
+        assert_eq!(
+            reparsed(r#"insert_ users { name = "x", retirement = null }"#),
+            r#"insert_ users { name = "x", retirement = null }"#
+        );
+        assert_eq!(
+            reparsed(r"scan users |> filter (\u -> u.retirement == null)"),
+            r"scan users |> filter (\u -> u.retirement == null)"
+        );
+    }
+
+    #[test]
+    fn application_stops_at_operators_and_delimiters() {
+        assert_eq!(reparsed("f (g x) y"), "f (g x) y");
+        assert_eq!(reparsed("f x + 1"), "f x + 1");
+        assert_eq!(reparsed("f (x + 1)"), "f (x + 1)");
+        assert_eq!(reparsed("f x == g y && h"), "f x == g y && h");
+        assert_eq!(reparsed("[f x, g (h y)]"), "[f x, h y |> g]");
+    }
+
+    #[test]
+    fn power_is_right_associative_and_binds_tightest() {
+        assert_eq!(reparsed("2 ^ 3 ^ 2"), "2 ^ 3 ^ 2");
+        assert_eq!(reparsed("(2 ^ 3) ^ 2"), "(2 ^ 3) ^ 2");
+        assert_eq!(reparsed("2 * 3 ^ 2"), "2 * 3 ^ 2");
+        assert_eq!(reparsed("(2 * 3) ^ 2"), "(2 * 3) ^ 2");
+    }
+
+    #[test]
+    fn modulus_binds_like_multiplication() {
+        assert_eq!(reparsed("1 + 10 % 3 * 2"), "1 + 10 % 3 * 2");
+        assert_eq!(reparsed("(1 + 10) % 3"), "(1 + 10) % 3");
+        assert_eq!(reparsed("10 % (3 * 2)"), "10 % (3 * 2)");
+    }
+
+    #[test]
+    fn concat_is_looser_than_arithmetic() {
+        assert_eq!(reparsed(r#""n" ++ 1 + 2"#), r#""n" ++ 1 + 2"#);
+        assert_eq!(reparsed(r#"("n" ++ 1) + 2"#), r#"("n" ++ 1) + 2"#);
+        assert_eq!(reparsed(r#"a ++ b == c"#), r#"a ++ b == c"#);
+    }
+}
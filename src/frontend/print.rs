@@ -1,155 +1,203 @@
 use crate::frontend::ast::{Arena, Expr, NodeId, Visitor};
+use crate::frontend::lexer::TokenKind;
+use std::fmt::{self, Write};
 
 pub struct PrettyPrinter {
     indent: usize,
+    out: String,
 }
 
 impl PrettyPrinter {
     pub fn new() -> Self {
-        Self { indent: 0 }
+        Self {
+            indent: 0,
+            out: String::new(),
+        }
+    }
+
+    pub fn render(arena: &Arena, node_id: NodeId) -> String {
+        let mut printer = PrettyPrinter::new();
+        // Writing into a `String` can't fail.
+        let _ = printer.visit(arena, node_id);
+        printer.out
     }
 
-    fn write_indent(&self) {
+    fn write_indent(&mut self) -> fmt::Result {
         for _ in 0..self.indent {
-            print!("  ");
+            write!(self.out, "  ")?;
         }
+        Ok(())
     }
 }
 
 impl<'a> Visitor<'a> for PrettyPrinter {
-    type Result = ();
+    type Result = fmt::Result;
 
     fn visit(&mut self, arena: &'a Arena, node_id: NodeId) -> Self::Result {
         let expr = arena.get(node_id);
 
-        self.write_indent();
+        self.write_indent()?;
         match expr {
             Expr::Reference(name) => {
-                println!("Reference({})", arena.resolve_str(*name));
+                writeln!(self.out, "Reference({})", arena.resolve_str(*name))?;
             }
             Expr::Number(num) => {
-                println!("Number({})", arena.resolve_str(*num));
+                writeln!(self.out, "Number({})", arena.resolve_str(*num))?;
             }
             Expr::StringLit(s) => {
-                println!("StringLit({:?})", arena.resolve_str(*s));
+                writeln!(self.out, "StringLit({:?})", arena.resolve_str(*s))?;
+            }
+            Expr::CharLit(c) => {
+                writeln!(self.out, "CharLit({:?})", c)?;
             }
             Expr::Bool(b) => {
-                println!("Bool({})", b);
+                writeln!(self.out, "Bool({})", b)?;
+            }
+            Expr::Null => {
+                writeln!(self.out, "Null")?;
+            }
+            Expr::Parameter(index) => {
+                writeln!(self.out, "Parameter(${})", index + 1)?;
             }
             Expr::FieldAccess { base, field } => {
-                println!("FieldAccess:");
+                writeln!(self.out, "FieldAccess:")?;
                 self.indent += 1;
-                self.visit(arena, *base);
-                self.write_indent();
-                println!("Field: {}", arena.resolve_str(*field));
+                self.visit(arena, *base)?;
+                self.write_indent()?;
+                writeln!(self.out, "Field: {}", arena.resolve_str(*field))?;
                 self.indent -= 1;
             }
             Expr::UnaryOp { op, operand } => {
-                println!("UnaryOp({:?}):", op);
+                writeln!(self.out, "UnaryOp({:?}):", op)?;
                 self.indent += 1;
-                self.visit(arena, *operand);
+                self.visit(arena, *operand)?;
                 self.indent -= 1;
             }
             Expr::Instance(fields) => {
-                println!("Instance:");
+                writeln!(self.out, "Instance:")?;
                 self.indent += 1;
                 for (field, value) in fields.iter() {
-                    self.write_indent();
-                    println!("Field: {}", arena.resolve_str(*field));
+                    self.write_indent()?;
+                    writeln!(self.out, "Field: {}", arena.resolve_str(*field))?;
                     self.indent += 1;
-                    self.visit(arena, *value);
+                    self.visit(arena, *value)?;
                     self.indent -= 1;
                 }
                 self.indent -= 1;
             }
             Expr::BinaryOp { op, left, right } => {
-                println!("BinaryOp({:?}):", op);
+                writeln!(self.out, "BinaryOp({:?}):", op)?;
                 self.indent += 1;
-                self.write_indent();
-                println!("Left:");
+                self.write_indent()?;
+                writeln!(self.out, "Left:")?;
                 self.indent += 1;
-                self.visit(arena, *left);
+                self.visit(arena, *left)?;
                 self.indent -= 1;
-                self.write_indent();
-                println!("Right:");
+                self.write_indent()?;
+                writeln!(self.out, "Right:")?;
                 self.indent += 1;
-                self.visit(arena, *right);
+                self.visit(arena, *right)?;
                 self.indent -= 1;
                 self.indent -= 1;
             }
             Expr::FunctionCall { func, args } => {
-                println!("FunctionCall:");
+                writeln!(self.out, "FunctionCall:")?;
                 self.indent += 1;
-                self.write_indent();
-                println!("Function:");
+                self.write_indent()?;
+                writeln!(self.out, "Function:")?;
                 self.indent += 1;
-                self.visit(arena, *func);
+                self.visit(arena, *func)?;
                 self.indent -= 1;
-                self.write_indent();
-                println!("Args:");
+                self.write_indent()?;
+                writeln!(self.out, "Args:")?;
                 self.indent += 1;
                 for arg in args.iter() {
-                    self.visit(arena, *arg);
+                    self.visit(arena, *arg)?;
                 }
                 self.indent -= 1;
                 self.indent -= 1;
             }
             Expr::Tuple(items) => {
-                println!("Tuple:");
+                writeln!(self.out, "Tuple:")?;
                 self.indent += 1;
                 for item in items.iter() {
-                    self.visit(arena, *item);
+                    self.visit(arena, *item)?;
                 }
                 self.indent -= 1;
             }
             Expr::Array(items) => {
-                println!("Array:");
+                writeln!(self.out, "Array:")?;
                 self.indent += 1;
                 for item in items.iter() {
-                    self.visit(arena, *item);
+                    self.visit(arena, *item)?;
                 }
                 self.indent -= 1;
             }
             Expr::Block(items) => {
-                println!("Block:");
+                writeln!(self.out, "Block:")?;
                 self.indent += 1;
                 for item in items.iter() {
-                    self.visit(arena, *item);
+                    self.visit(arena, *item)?;
                 }
                 self.indent -= 1;
             }
             Expr::Lambda { params, body } => {
-                println!("Lambda:");
+                writeln!(self.out, "Lambda:")?;
                 self.indent += 1;
-                self.write_indent();
-                print!("Params: ");
+                self.write_indent()?;
+                write!(self.out, "Params: ")?;
                 for param in params {
-                    print!("{} ", arena.resolve_str(*param));
+                    write!(self.out, "{} ", arena.resolve_str(*param))?;
                 }
-                println!();
-                self.write_indent();
-                println!("Body:");
+                writeln!(self.out)?;
+                self.write_indent()?;
+                writeln!(self.out, "Body:")?;
+                self.indent += 1;
+                self.visit(arena, *body)?;
+                self.indent -= 1;
+                self.indent -= 1;
+            }
+            Expr::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => {
+                writeln!(self.out, "If:")?;
+                self.indent += 1;
+                self.write_indent()?;
+                writeln!(self.out, "Condition:")?;
+                self.indent += 1;
+                self.visit(arena, *cond)?;
+                self.indent -= 1;
+                self.write_indent()?;
+                writeln!(self.out, "Then:")?;
                 self.indent += 1;
-                self.visit(arena, *body);
+                self.visit(arena, *then_branch)?;
+                self.indent -= 1;
+                self.write_indent()?;
+                writeln!(self.out, "Else:")?;
+                self.indent += 1;
+                self.visit(arena, *else_branch)?;
                 self.indent -= 1;
                 self.indent -= 1;
             }
             Expr::Let { name, value, body } => {
-                println!("Let {} =", arena.resolve_str(*name));
+                writeln!(self.out, "Let {} =", arena.resolve_str(*name))?;
                 self.indent += 1;
-                self.write_indent();
-                println!("Value:");
+                self.write_indent()?;
+                writeln!(self.out, "Value:")?;
                 self.indent += 1;
-                self.visit(arena, *value);
+                self.visit(arena, *value)?;
                 self.indent -= 1;
-                self.write_indent();
-                println!("Body:");
+                self.write_indent()?;
+                writeln!(self.out, "Body:")?;
                 self.indent += 1;
-                self.visit(arena, *body);
+                self.visit(arena, *body)?;
                 self.indent -= 1;
                 self.indent -= 1;
             }
         }
+        Ok(())
     }
 }
 
@@ -158,3 +206,344 @@ impl Default for PrettyPrinter {
         PrettyPrinter::new()
     }
 }
+
+// How tightly each construct binds, loosest first. A node rendered where something tighter
+// is expected gets parenthesized.
+const LOOSEST: u8 = 0; // lambdas, `let`, `if`, and `do` blocks run as far right as they can
+const PIPE: u8 = 1;
+const OR: u8 = 2;
+const AND: u8 = 3;
+const NOT: u8 = 4;
+const IN: u8 = 5;
+const IS: u8 = 6;
+const COMPARISON: u8 = 7;
+const CONCAT: u8 = 8;
+const SUM: u8 = 9;
+const PRODUCT: u8 = 10;
+const POWER: u8 = 11;
+const APPLICATION: u8 = 12;
+const ATOM: u8 = 13;
+
+// Turns an AST back into source that parses to the same tree. Pipes aren't kept in the AST,
+// so a call whose last argument is another call is written as a pipe, which keeps chains of
+// built-ins readable.
+pub struct Unparser {
+    indent: usize,
+    out: String,
+}
+
+impl Unparser {
+    pub fn new() -> Self {
+        Self {
+            indent: 0,
+            out: String::new(),
+        }
+    }
+
+    pub fn unparse(arena: &Arena, node_id: NodeId) -> String {
+        let mut unparser = Unparser::new();
+        // Writing into a `String` can't fail.
+        let _ = unparser.visit(arena, node_id);
+        unparser.out
+    }
+
+    fn write_at(&mut self, arena: &Arena, node_id: NodeId, min_precedence: u8) -> fmt::Result {
+        let parenthesize = precedence(arena, node_id) < min_precedence;
+        if parenthesize {
+            write!(self.out, "(")?;
+        }
+        self.write_node(arena, node_id)?;
+        if parenthesize {
+            write!(self.out, ")")?;
+        }
+        Ok(())
+    }
+
+    fn write_node(&mut self, arena: &Arena, node_id: NodeId) -> fmt::Result {
+        match arena.get(node_id) {
+            Expr::Reference(name) | Expr::Number(name) => {
+                write!(self.out, "{}", arena.resolve_str(*name))
+            }
+            Expr::StringLit(s) => {
+                write!(self.out, "\"")?;
+                for c in arena.resolve_str(*s).chars() {
+                    write_escaped(&mut self.out, c, '"')?;
+                }
+                write!(self.out, "\"")
+            }
+            Expr::CharLit(c) => {
+                write!(self.out, "'")?;
+                write_escaped(&mut self.out, *c, '\'')?;
+                write!(self.out, "'")
+            }
+            Expr::Bool(b) => write!(self.out, "{}", b),
+            Expr::Null => write!(self.out, "null"),
+            Expr::Parameter(index) => write!(self.out, "${}", index + 1),
+            Expr::FieldAccess { base, field } => {
+                self.write_at(arena, *base, ATOM)?;
+                write!(self.out, ".{}", arena.resolve_str(*field))
+            }
+            Expr::UnaryOp { op, operand } => {
+                write!(self.out, "{}", operator_symbol(*op))?;
+                self.write_at(arena, *operand, NOT)
+            }
+            Expr::BinaryOp { op, left, right } => {
+                let (left_precedence, right_precedence) = match op {
+                    TokenKind::Or => (OR, AND),
+                    TokenKind::And => (AND, NOT),
+                    TokenKind::In => (IS, ATOM),
+                    TokenKind::Is => (COMPARISON, ATOM),
+                    TokenKind::PlusPlus => (CONCAT, SUM),
+                    TokenKind::Plus | TokenKind::Minus => (SUM, PRODUCT),
+                    TokenKind::Asterisk | TokenKind::Slash | TokenKind::Percent => (PRODUCT, POWER),
+                    TokenKind::Caret => (APPLICATION, POWER),
+                    _ => (COMPARISON, CONCAT),
+                };
+                self.write_at(arena, *left, left_precedence)?;
+                write!(self.out, " {} ", operator_symbol(*op))?;
+                self.write_at(arena, *right, right_precedence)
+            }
+            Expr::FunctionCall { func, args } => {
+                let (last, rest) = args.split_last().expect("calls have arguments");
+                if is_piped(arena, node_id) {
+                    self.write_at(arena, *last, PIPE)?;
+                    write!(self.out, " |> ")?;
+                    self.write_application(arena, *func, rest)
+                } else {
+                    self.write_application(arena, *func, args)
+                }
+            }
+            Expr::Tuple(items) => {
+                write!(self.out, "(")?;
+                self.write_list(arena, items)?;
+                write!(self.out, ")")
+            }
+            Expr::Array(items) => {
+                write!(self.out, "[")?;
+                self.write_list(arena, items)?;
+                write!(self.out, "]")
+            }
+            Expr::Instance(fields) => {
+                write!(self.out, "{{")?;
+                for (i, (field, value)) in fields.iter().enumerate() {
+                    let separator = if i == 0 { " " } else { ", " };
+                    write!(self.out, "{}{} = ", separator, arena.resolve_str(*field))?;
+                    self.write_at(arena, *value, LOOSEST)?;
+                }
+                if !fields.is_empty() {
+                    write!(self.out, " ")?;
+                }
+                write!(self.out, "}}")
+            }
+            // Always parenthesized: the block would otherwise swallow any line after it
+            // indented at least as deep as its own.
+            Expr::Block(items) => {
+                write!(self.out, "(do ")?;
+                self.indent += 1;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(self.out, "\n{}", "    ".repeat(self.indent))?;
+                    }
+                    self.write_at(arena, *item, LOOSEST)?;
+                }
+                self.indent -= 1;
+                write!(self.out, ")")
+            }
+            Expr::Lambda { params, body } => {
+                write!(self.out, "\\")?;
+                for (i, param) in params.iter().enumerate() {
+                    let separator = if i == 0 { "" } else { " " };
+                    write!(self.out, "{}{}", separator, arena.resolve_str(*param))?;
+                }
+                write!(self.out, " -> ")?;
+                self.write_at(arena, *body, LOOSEST)
+            }
+            Expr::Let { name, value, body } => {
+                write!(self.out, "let {} = ", arena.resolve_str(*name))?;
+                self.write_at(arena, *value, PIPE)?;
+                write!(self.out, " in ")?;
+                // `in [` right after the value would read as a membership test on it.
+                let body_text = Unparser::unparse(arena, *body);
+                if body_text.starts_with('[') && precedence(arena, *body) >= ATOM {
+                    write!(self.out, "({})", body_text)
+                } else {
+                    self.write_at(arena, *body, LOOSEST)
+                }
+            }
+            Expr::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => {
+                write!(self.out, "if ")?;
+                self.write_at(arena, *cond, PIPE)?;
+                write!(self.out, " then ")?;
+                self.write_at(arena, *then_branch, PIPE)?;
+                write!(self.out, " else ")?;
+                self.write_at(arena, *else_branch, LOOSEST)
+            }
+        }
+    }
+
+    fn write_application(&mut self, arena: &Arena, func: NodeId, args: &[NodeId]) -> fmt::Result {
+        self.write_at(arena, func, ATOM)?;
+        for arg in args {
+            write!(self.out, " ")?;
+            self.write_at(arena, *arg, ATOM)?;
+        }
+        Ok(())
+    }
+
+    fn write_list(&mut self, arena: &Arena, items: &[NodeId]) -> fmt::Result {
+        for (i, item) in items.iter().enumerate() {
+            if i > 0 {
+                write!(self.out, ", ")?;
+            }
+            self.write_at(arena, *item, LOOSEST)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Visitor<'a> for Unparser {
+    type Result = fmt::Result;
+
+    fn visit(&mut self, arena: &'a Arena, node_id: NodeId) -> Self::Result {
+        self.write_at(arena, node_id, LOOSEST)
+    }
+}
+
+impl Default for Unparser {
+    fn default() -> Self {
+        Unparser::new()
+    }
+}
+
+// A pipe hands its left side to the call on its right as an extra argument, so a function
+// that's a call itself would absorb it into its own arguments instead.
+fn is_piped(arena: &Arena, node_id: NodeId) -> bool {
+    let Expr::FunctionCall { func, args } = arena.get(node_id) else {
+        return false;
+    };
+    let last_is_call = args
+        .last()
+        .is_some_and(|last| matches!(arena.get(*last), Expr::FunctionCall { .. }));
+    last_is_call && !matches!(arena.get(*func), Expr::FunctionCall { .. })
+}
+
+fn precedence(arena: &Arena, node_id: NodeId) -> u8 {
+    match arena.get(node_id) {
+        Expr::Lambda { .. } | Expr::Let { .. } | Expr::If { .. } => LOOSEST,
+        Expr::FunctionCall { .. } if is_piped(arena, node_id) => PIPE,
+        Expr::FunctionCall { .. } => APPLICATION,
+        Expr::UnaryOp { .. } => NOT,
+        Expr::BinaryOp { op, .. } => match op {
+            TokenKind::Or => OR,
+            TokenKind::And => AND,
+            TokenKind::In => IN,
+            TokenKind::Is => IS,
+            TokenKind::PlusPlus => CONCAT,
+            TokenKind::Plus | TokenKind::Minus => SUM,
+            TokenKind::Asterisk | TokenKind::Slash | TokenKind::Percent => PRODUCT,
+            TokenKind::Caret => POWER,
+            _ => COMPARISON,
+        },
+        _ => ATOM,
+    }
+}
+
+fn operator_symbol(op: TokenKind) -> &'static str {
+    match op {
+        TokenKind::Plus => "+",
+        TokenKind::PlusPlus => "++",
+        TokenKind::Minus => "-",
+        TokenKind::Asterisk => "*",
+        TokenKind::Slash => "/",
+        TokenKind::Percent => "%",
+        TokenKind::Caret => "^",
+        TokenKind::EqualsEquals => "==",
+        TokenKind::NotEquals => "!=",
+        TokenKind::GreaterThan => ">",
+        TokenKind::GreaterThanEquals => ">=",
+        TokenKind::LessThan => "<",
+        TokenKind::LessThanEquals => "<=",
+        TokenKind::And => "&&",
+        TokenKind::Or => "||",
+        TokenKind::Not => "!",
+        TokenKind::Is => "is",
+        TokenKind::In => "in",
+        other => unreachable!("{} is not an operator", other),
+    }
+}
+
+fn write_escaped(out: &mut String, c: char, quote: char) -> fmt::Result {
+    match c {
+        '\n' => write!(out, "\\n"),
+        '\t' => write!(out, "\\t"),
+        '\r' => write!(out, "\\r"),
+        '\0' => write!(out, "\\0"),
+        '\\' => write!(out, "\\\\"),
+        c if c == quote => write!(out, "\\{}", c),
+        c => write!(out, "{}", c),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frontend::lexer::Lexer;
+    use crate::frontend::parser::parse_expression_all;
+
+    fn parsed(source: &str) -> (Arena, NodeId) {
+        let tokens = Lexer::new(source).tokenize().unwrap();
+        let mut arena = Arena::with_capacity(100, 100);
+        let root = parse_expression_all(&tokens, &mut arena).unwrap();
+        (arena, root)
+    }
+
+    #[test]
+    fn let_renders_its_value_and_body() {
+        let (arena, root) = parsed("let x = 1 in x + 1");
+        assert_eq!(
+            PrettyPrinter::render(&arena, root),
+            concat!(
+                "Let x =\n",
+                "  Value:\n",
+                "    Number(1)\n",
+                "  Body:\n",
+                "    BinaryOp(Plus):\n",
+                "      Left:\n",
+                "        Reference(x)\n",
+                "      Right:\n",
+                "        Number(1)\n",
+            )
+        );
+    }
+
+    #[test]
+    fn unparsed_queries_parse_back_to_the_same_tree() {
+        let samples = [
+            "scan users",
+            r#"scan users |> filter (\u -> u.age > 18 && u.name != "bob") |> sort age"#,
+            r"scan users |> filter (\u -> (u.age + 1) * 2 > 10 || !u.active)",
+            r#"scan users |> map (\u -> { name = u.name ++ "!", old = u.age - 1 - 2 })"#,
+            r"scan users |> filter (\u -> u.id in [1, 2, 3] && u.retirement is not null)",
+            r"let limit = 2 ^ 3 ^ 2 in scan users |> take limit",
+            r"if 1 < 2 then scan users else scan orders",
+            r"scan accounts |> join (scan orders) (\a o -> a.id == o.account)",
+            r"scan users |> project (name, age) |> sort_desc (\u -> 0 - u.age)",
+            "insert_ users [{ name = \"a\\\"b\", age = 1, initial = 'c' }]",
+            r"\a b -> a - (b - a) / (a / b)",
+        ];
+        for source in samples {
+            let (arena, root) = parsed(source);
+            let unparsed = Unparser::unparse(&arena, root);
+            let (reparsed_arena, reparsed) = parsed(&unparsed);
+            assert_eq!(
+                PrettyPrinter::render(&reparsed_arena, reparsed),
+                PrettyPrinter::render(&arena, root),
+                "{source} was unparsed as {unparsed}"
+            );
+        }
+    }
+}
@@ -0,0 +1,208 @@
+use crate::frontend::ast::Arena;
+use crate::frontend::err::QueryParsingError;
+use crate::frontend::lexer::Lexer;
+use crate::frontend::parser::parse_expression_all;
+use crate::frontend::print::PrettyPrinter;
+use crate::page::err::DbInternalError;
+use crate::page::io::{FileSystemManager, IoManager};
+use crate::page::pool::BufferPool;
+use crate::page::tuple::{DataType, Tuple};
+use crate::query::Transaction;
+use crate::query::compiler::PlanCompiler;
+use crate::query::err::{QueryError, TransformError};
+use crate::query::exec::{QueryExecutor, ResultSet, TupleStream};
+use crate::query::optimizer::ConstantFoldingOptimizer;
+use crate::query::transformer::AstToQueryTransformer;
+use crate::table::{SharedCatalog, TableCatalog};
+use futures::TryStreamExt;
+use std::fmt::Write;
+use std::sync::Arc;
+use std::time::Instant;
+use thiserror::Error;
+
+pub mod debug;
+pub mod frontend;
+pub mod page;
+pub mod query;
+pub mod table;
+#[cfg(test)]
+mod testing;
+
+#[derive(Debug, Error)]
+pub enum DatabaseError {
+    #[error(transparent)]
+    Storage(#[from] DbInternalError),
+    #[error(transparent)]
+    Lexing(#[from] QueryParsingError),
+    // Every error the parser found, one per line.
+    #[error("{0}")]
+    Parsing(String),
+    #[error(transparent)]
+    Transform(#[from] TransformError),
+    #[error(transparent)]
+    Query(QueryError),
+}
+
+// A storage error that surfaced while the rows were streamed is still a storage error.
+impl From<QueryError> for DatabaseError {
+    fn from(err: QueryError) -> Self {
+        match err {
+            QueryError::Storage(err) => DatabaseError::Storage(err),
+            err => DatabaseError::Query(err),
+        }
+    }
+}
+
+// Runs query text against the tables in one directory: lexing, parsing, lowering, compiling
+// and executing, in that order.
+pub struct Database {
+    catalog: SharedCatalog,
+    compiler: PlanCompiler,
+    executor: QueryExecutor,
+    arena: Arena,
+    created: bool,
+}
+
+impl Database {
+    // A directory without a catalog yet gets the internal tables and nothing else. One whose
+    // catalog can't be read is left alone, since starting over would lose its tables.
+    pub async fn open(path: &str) -> Result<Self, DatabaseError> {
+        let file_io = Arc::new(FileSystemManager::new(path.to_string()));
+        file_io.create_home().await.map_err(DbInternalError::from)?;
+        let created = !TableCatalog::exists(&file_io).await?;
+
+        let io = Arc::new(IoManager::new(Arc::clone(&file_io)));
+        let buffer_pool = BufferPool::new(Arc::clone(&io));
+
+        let catalog = if created {
+            TableCatalog::init_then_load(io, buffer_pool).await
+        } else {
+            TableCatalog::load(io, buffer_pool).await?
+        };
+        let catalog = catalog.shared();
+
+        Ok(Self {
+            compiler: PlanCompiler::new(Arc::clone(&catalog)),
+            executor: QueryExecutor::new(Arc::clone(&catalog)),
+            arena: Arena::with_capacity(10000, 1000),
+            catalog,
+            created,
+        })
+    }
+
+    // Whether `open` found no catalog and started a new one.
+    pub fn was_created(&self) -> bool {
+        self.created
+    }
+
+    pub fn catalog(&self) -> &SharedCatalog {
+        &self.catalog
+    }
+
+    // Returns the plan along with the name and type of each column its rows come out with.
+    pub async fn compile(
+        &mut self,
+        query: &str,
+    ) -> Result<(Transaction, Vec<(String, DataType)>), DatabaseError> {
+        self.compile_stages(query, None).await
+    }
+
+    // Like `compile`, but also returns how long each stage took and what it produced: the
+    // parsed tree, the lowered query and the plan. Nothing is printed.
+    pub async fn compile_traced(
+        &mut self,
+        query: &str,
+    ) -> Result<(Transaction, Vec<(String, DataType)>, String), DatabaseError> {
+        let mut trace = String::new();
+        let (transaction, columns) = self.compile_stages(query, Some(&mut trace)).await?;
+        Ok((transaction, columns, trace))
+    }
+
+    async fn compile_stages(
+        &mut self,
+        query: &str,
+        mut trace: Option<&mut String>,
+    ) -> Result<(Transaction, Vec<(String, DataType)>), DatabaseError> {
+        let started = Instant::now();
+        let mut lexer = Lexer::new(query);
+        let lexed = lexer.tokenize()?;
+        trace_stage(trace.as_deref_mut(), "Lexical analysis", started, String::new);
+
+        self.arena.clear();
+
+        let started = Instant::now();
+        let root_id = parse_expression_all(&lexed, &mut self.arena).map_err(|errors| {
+            DatabaseError::Parsing(
+                errors
+                    .iter()
+                    .map(|err| err.to_string())
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            )
+        })?;
+        trace_stage(trace.as_deref_mut(), "Parsing", started, || {
+            PrettyPrinter::render(&self.arena, root_id)
+        });
+
+        let started = Instant::now();
+        let mut transformer =
+            AstToQueryTransformer::new(&self.arena, Box::new(ConstantFoldingOptimizer));
+        let transformed = transformer.transform(root_id)?;
+        trace_stage(trace.as_deref_mut(), "AST transformation", started, || {
+            format!("{:#?}", transformed)
+        });
+
+        let started = Instant::now();
+        let compiled = self.compiler.compile_with_columns(&transformed).await?;
+        trace_stage(trace, "Query compilation", started, || {
+            format!("{:#?}", compiled)
+        });
+        Ok(compiled)
+    }
+
+    pub async fn execute(&self, transaction: Transaction) -> Result<TupleStream, DatabaseError> {
+        Ok(self.executor.execute(transaction).await?)
+    }
+
+    pub async fn execute_stream(&mut self, query: &str) -> Result<TupleStream, DatabaseError> {
+        let (transaction, _) = self.compile(query).await?;
+        self.execute(transaction).await
+    }
+
+    pub async fn execute_with_schema(&mut self, query: &str) -> Result<ResultSet, DatabaseError> {
+        let (transaction, columns) = self.compile(query).await?;
+        Ok(self
+            .executor
+            .execute_with_schema(transaction, columns)
+            .await?)
+    }
+
+    // Puts every page still sitting in the buffer pool on disk. Dropping the database instead
+    // loses whatever the pool hadn't written back yet.
+    pub async fn close(self) -> Result<(), DatabaseError> {
+        let buffer_pool = Arc::clone(&self.catalog.read().await.buffer_pool);
+        buffer_pool.close().await?;
+        Ok(())
+    }
+
+    pub async fn execute_str(&mut self, query: &str) -> Result<Vec<Tuple>, DatabaseError> {
+        Ok(self.execute_stream(query).await?.try_collect().await?)
+    }
+}
+
+// Adds how long a stage took to the trace, if one is kept, followed by what it produced.
+fn trace_stage(
+    trace: Option<&mut String>,
+    stage: &str,
+    started: Instant,
+    output: impl FnOnce() -> String,
+) {
+    let Some(trace) = trace else {
+        return;
+    };
+    let _ = writeln!(trace, "{} took {:.4?}", stage, started.elapsed());
+    let output = output();
+    if !output.is_empty() {
+        let _ = writeln!(trace, "{}", output.trim_end());
+    }
+}
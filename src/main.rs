@@ -1,129 +1,63 @@
-#![feature(let_chains)]
-
-use crate::frontend::ast::{Arena, Visitor};
-use crate::frontend::lexer::Lexer;
-use crate::frontend::parser::parse_expression;
-use crate::frontend::print::PrettyPrinter;
-use crate::page::io::{FileSystemManager, IoManager};
-use crate::page::pool::BufferPool;
-use crate::page::tuple::Tuple;
-use crate::query::compiler::PlanCompiler;
-use crate::query::exec::QueryExecutor;
-use crate::query::optimizer::IdentityOptimizer;
-use crate::query::transformer::AstToQueryTransformer;
-use crate::table::{ColumnInfo, TableCatalog, TableInfo};
-use futures::StreamExt;
+use akasha::Database;
+use akasha::debug::DebugTimer;
+use akasha::page::tuple::{DataType, Tuple};
+use akasha::query::explain::explain;
+use akasha::query::json::rows_to_json;
+use akasha::table::csv::import_csv;
+use akasha::table::{ColumnInfo, TableInfo};
+use futures::TryStreamExt;
 use std::collections::HashMap;
 use std::env;
 use std::io::{self, Write};
 use std::path::Path;
-use std::sync::Arc;
-use std::time::Instant;
 use tokio::io::AsyncReadExt;
 
-pub mod frontend;
-pub mod page;
-pub mod query;
-pub mod table;
-
-struct DebugTimer {
-    name: String,
-    start: Instant,
-    debug_mode: bool,
-}
-
-impl DebugTimer {
-    #[inline(always)]
-    fn new(name: &str, debug_mode: bool) -> Self {
-        let timer = Self {
-            name: name.to_string(),
-            start: Instant::now(),
-            debug_mode,
-        };
-
-        if debug_mode {
-            println!("Starting: {}", name);
-        }
-
-        timer
-    }
-
-    #[inline(always)]
-    fn elapsed(&self) -> String {
-        format!("{:.4?}", self.start.elapsed())
-    }
-}
-
-impl Drop for DebugTimer {
-    fn drop(&mut self) {
-        if self.debug_mode {
-            println!("Completed: {} in {}", self.name, self.elapsed());
-        }
-    }
-}
-
 struct QueryEngine {
-    compiler: PlanCompiler,
-    arena: Arena,
-    executor: QueryExecutor,
+    database: Database,
     debug_mode: bool,
 }
 
 impl QueryEngine {
     async fn new(debug_mode: bool) -> Result<Self, Box<dyn std::error::Error>> {
         let init_timer = DebugTimer::new("Database initialization", debug_mode);
-
-        let file_io = Arc::new(FileSystemManager::new("database".to_string()));
-        file_io.create_home().await?;
-
-        let io = Arc::new(IoManager::new(Arc::clone(&file_io)));
-        let buffer_pool = BufferPool::new(Arc::clone(&io));
-
-        let catalog = match TableCatalog::load(Arc::clone(&io), Arc::clone(&buffer_pool)).await {
-            Ok(cat) => {
-                println!("Loaded catalog with {} tables", cat.tables.len());
-                cat
-            }
-            Err(err) => {
-                eprintln!("Error loading catalog: {}", err);
-                let mut cat = TableCatalog::init_then_load(io, buffer_pool).await;
+        let database = Database::open("database").await?;
+        {
+            let mut catalog = database.catalog().write().await;
+            // A fresh database starts out with a table to play with.
+            if database.was_created() {
                 let mut columns = HashMap::new();
                 columns.insert("name".to_string(), ColumnInfo {
                     id: 0,
                     name: "name".to_string(),
-                    data_type: page::tuple::DataType::Text,
+                    data_type: DataType::Text,
                     default: None,
                     nullable: false,
                 });
                 columns.insert("age".to_string(), ColumnInfo {
                     id: 1,
                     name: "age".to_string(),
-                    data_type: page::tuple::DataType::Int,
+                    data_type: DataType::Int,
                     default: None,
                     nullable: false,
                 });
-                cat.create_table("users".to_string(), TableInfo { columns })
+                catalog
+                    .create_table("users".to_string(), TableInfo {
+                        columns,
+                        primary_key: None,
+                    })
                     .await?;
-                cat
             }
-        };
+            println!("Loaded catalog with {} tables", catalog.tables.len());
+        }
         drop(init_timer);
-        let catalog = Arc::new(catalog);
 
         Ok(Self {
-            compiler: PlanCompiler::new(Arc::clone(&catalog)),
-            arena: Arena::with_capacity(10000, 1000),
-            executor: QueryExecutor::new(catalog),
+            database,
             debug_mode,
         })
     }
 
-    async fn execute_query_file(
-        &mut self,
-        file_path: &str,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        println!("Executing query from file: {}", file_path);
-
+    async fn read_query_file(&self, file_path: &str) -> Result<String, Box<dyn std::error::Error>> {
         let file_timer = DebugTimer::new("File loading", self.debug_mode);
         let query_file = tokio::fs::OpenOptions::new()
             .read(true)
@@ -134,43 +68,28 @@ impl QueryEngine {
         let mut text = String::new();
         buffer.read_to_string(&mut text).await?;
         drop(file_timer);
+        Ok(text)
+    }
 
-        let total_timer = DebugTimer::new("Total query execution", self.debug_mode);
-
-        let lexer_timer = DebugTimer::new("Lexical analysis", self.debug_mode);
-        let mut lexer = Lexer::new(text.as_str());
-        let lexed = lexer.tokenize()?;
-        drop(lexer_timer);
-
-        self.arena.clear();
-
-        let parse_timer = DebugTimer::new("Parsing", self.debug_mode);
-        let root_id = parse_expression(&*lexed, &mut self.arena).unwrap();
-        if self.debug_mode {
-            let mut printer = PrettyPrinter::new();
-            printer.visit(&self.arena, root_id);
-        }
-        drop(parse_timer);
-
-        let transform_timer = DebugTimer::new("AST transformation", self.debug_mode);
-        let mut transformer = AstToQueryTransformer::new(&self.arena, Box::new(IdentityOptimizer));
-        let transformed = transformer.transform(root_id)?;
-        if self.debug_mode {
-            println!("\nTransformed query: {:#?}", transformed);
-        }
-        drop(transform_timer);
+    async fn execute_query_file(
+        &mut self,
+        file_path: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        println!("Executing query from file: {}", file_path);
+        let text = self.read_query_file(file_path).await?;
 
-        let compile_timer = DebugTimer::new("Query compilation", self.debug_mode);
-        let compiled = self.compiler.compile(&transformed).unwrap();
-        if self.debug_mode {
-            println!("\nCompiled query: {:#?}", compiled);
-        }
-        drop(transformed);
-        drop(compile_timer);
+        let total_timer = DebugTimer::new("Total query execution", self.debug_mode);
+        let compiled = if self.debug_mode {
+            let (compiled, _, trace) = self.database.compile_traced(&text).await?;
+            print!("{}", trace);
+            compiled
+        } else {
+            self.database.compile(&text).await?.0
+        };
 
         let execute_timer = DebugTimer::new("Query execution", self.debug_mode);
-        let plan = self.executor.execute(compiled).await?;
-        let tuples = plan.collect::<Vec<Tuple>>().await;
+        let plan = self.database.execute(compiled).await?;
+        let tuples = plan.try_collect::<Vec<Tuple>>().await?;
 
         let execution_elapsed = execute_timer.elapsed();
         let total_elapsed = total_timer.elapsed();
@@ -186,11 +105,56 @@ impl QueryEngine {
             println!("No results found.");
         } else {
             for tuple in tuples {
-                println!("{:?}", tuple);
+                println!("{}", tuple);
             }
         }
         Ok(())
     }
+
+    async fn explain_query_file(
+        &mut self,
+        file_path: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let text = self.read_query_file(file_path).await?;
+        let (compiled, _) = self.database.compile(&text).await?;
+        print!(
+            "{}",
+            explain(&compiled, &*self.database.catalog().read().await)
+        );
+        Ok(())
+    }
+
+    async fn import_file(
+        &self,
+        file_path: &str,
+        table: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let import_timer = DebugTimer::new("CSV import", self.debug_mode);
+        let file = tokio::fs::File::open(file_path).await?;
+        let catalog = self.database.catalog().read().await;
+        let summary = import_csv(&catalog, table, tokio::io::BufReader::new(file)).await?;
+        drop(import_timer);
+        println!(
+            "Imported {} rows with {} columns into '{}'",
+            summary.rows, summary.columns, table
+        );
+        Ok(())
+    }
+
+    async fn json_query_file(&mut self, file_path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let text = self.read_query_file(file_path).await?;
+        let result = self.database.execute_with_schema(&text).await?;
+        println!("{}", rows_to_json(result).await?);
+        Ok(())
+    }
+}
+
+fn query_file_path(name: &str) -> String {
+    if name.ends_with(".aka") {
+        format!("queries/{}", name)
+    } else {
+        format!("queries/{}.aka", name)
+    }
 }
 
 #[tokio::main]
@@ -211,9 +175,12 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     println!("Query CLI started");
     println!("Available commands:");
-    println!("  <filename>  - Execute a query file from /queries");
-    println!("  :list       - List all available query files");
-    println!("  :q          - Quit the program");
+    println!("  <filename>             - Execute a query file from /queries");
+    println!("  :explain <filename>    - Show the compiled plan of a query file");
+    println!("  :json <filename>       - Execute a query file and print its rows as JSON");
+    println!("  :import <file> <table> - Insert the rows of a CSV file into a table");
+    println!("  :list                  - List all available query files");
+    println!("  :q                     - Quit the program");
 
     let mut input = String::with_capacity(100);
 
@@ -236,11 +203,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     let mut found = false;
 
                     for entry in entries.filter_map(Result::ok) {
-                        if let Some(file_name) = entry.file_name().to_str() {
-                            if file_name.ends_with(".aka") {
-                                println!("  {}", file_name);
-                                found = true;
-                            }
+                        if let Some(file_name) = entry.file_name().to_str()
+                            && file_name.ends_with(".aka")
+                        {
+                            println!("  {}", file_name);
+                            found = true;
                         }
                     }
 
@@ -250,13 +217,43 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 }
                 drop(list_timer);
             }
-            _ => {
-                let file_path = if input_str.ends_with(".aka") {
-                    format!("queries/{}", input_str)
-                } else {
-                    format!("queries/{}.aka", input_str)
+            _ if input_str.starts_with(":explain ") => {
+                let file_path = query_file_path(input_str[":explain ".len()..].trim());
+                if !Path::new(&file_path).exists() {
+                    println!("Error: File '{}' not found", file_path);
+                    continue;
+                }
+
+                if let Err(e) = engine.explain_query_file(&file_path).await {
+                    println!("Error: {}", e);
+                }
+            }
+            _ if input_str.starts_with(":import ") => {
+                let mut args = input_str[":import ".len()..].split_whitespace();
+                let (Some(file_path), Some(table), None) = (args.next(), args.next(), args.next())
+                else {
+                    println!("Usage: :import <file> <table>");
+                    continue;
                 };
 
+                if let Err(e) = engine.import_file(file_path, table).await {
+                    println!("Error: {}", e);
+                }
+            }
+            _ if input_str.starts_with(":json ") => {
+                let file_path = query_file_path(input_str[":json ".len()..].trim());
+                if !Path::new(&file_path).exists() {
+                    println!("Error: File '{}' not found", file_path);
+                    continue;
+                }
+
+                if let Err(e) = engine.json_query_file(&file_path).await {
+                    println!("Error: {}", e);
+                }
+            }
+            _ => {
+                let file_path = query_file_path(input_str);
+
                 if !Path::new(&file_path).exists() {
                     println!("Error: File '{}' not found", file_path);
                     continue;
@@ -268,5 +265,6 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             }
         }
     }
+    engine.database.close().await?;
     Ok(())
 }
@@ -0,0 +1,141 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+// An exact base-10 number: `mantissa * 10^-scale`, so 19.99 is stored as (1999, 2).
+#[derive(Clone, Copy)]
+pub struct Decimal {
+    pub mantissa: i128,
+    pub scale: u8,
+}
+
+impl Decimal {
+    pub fn new(mantissa: i128, scale: u8) -> Self {
+        Self { mantissa, scale }
+    }
+
+    pub fn from_integer(value: i64) -> Self {
+        Self::new(value as i128, 0)
+    }
+
+    // Plain `-12.345` notation only; no exponents.
+    pub fn parse(text: &str) -> Option<Self> {
+        let (negative, digits) = match text.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, text.strip_prefix('+').unwrap_or(text)),
+        };
+        let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+        if whole.is_empty() && fraction.is_empty()
+            || !whole
+                .chars()
+                .chain(fraction.chars())
+                .all(|c| c.is_ascii_digit())
+        {
+            return None;
+        }
+        let scale = u8::try_from(fraction.len()).ok()?;
+        let mut mantissa: i128 = 0;
+        for c in whole.chars().chain(fraction.chars()) {
+            mantissa = mantissa
+                .checked_mul(10)?
+                .checked_add(c.to_digit(10)? as i128)?;
+        }
+        Some(Self::new(
+            if negative { -mantissa } else { mantissa },
+            scale,
+        ))
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        self.mantissa as f64 / 10f64.powi(self.scale as i32)
+    }
+
+    pub fn compare(&self, other: &Decimal) -> Ordering {
+        let scale = self.scale.max(other.scale);
+        match (self.rescaled(scale), other.rescaled(scale)) {
+            (Some(a), Some(b)) => a.cmp(&b),
+            // Only the side with the smaller scale gets multiplied, so if that overflows its
+            // magnitude is beyond anything the other side can hold and the sign decides.
+            (None, _) if self.mantissa > 0 => Ordering::Greater,
+            (None, _) => Ordering::Less,
+            (_, None) if other.mantissa > 0 => Ordering::Less,
+            (_, None) => Ordering::Greater,
+        }
+    }
+
+    pub fn checked_add(&self, other: &Decimal) -> Option<Decimal> {
+        let (a, b, scale) = self.aligned(other)?;
+        Some(Self::new(a.checked_add(b)?, scale))
+    }
+
+    pub fn checked_sub(&self, other: &Decimal) -> Option<Decimal> {
+        let (a, b, scale) = self.aligned(other)?;
+        Some(Self::new(a.checked_sub(b)?, scale))
+    }
+
+    pub fn checked_mul(&self, other: &Decimal) -> Option<Decimal> {
+        let mantissa = self.mantissa.checked_mul(other.mantissa)?;
+        Some(Self::new(mantissa, self.scale.checked_add(other.scale)?))
+    }
+
+    // `None` for a zero divisor as well as for an overflow.
+    pub fn checked_rem(&self, other: &Decimal) -> Option<Decimal> {
+        let (a, b, scale) = self.aligned(other)?;
+        Some(Self::new(a.checked_rem(b)?, scale))
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.mantissa == 0
+    }
+
+    // Both mantissas at the larger of the two scales, so they line up digit for digit.
+    fn aligned(&self, other: &Decimal) -> Option<(i128, i128, u8)> {
+        let scale = self.scale.max(other.scale);
+        Some((self.rescaled(scale)?, other.rescaled(scale)?, scale))
+    }
+
+    fn rescaled(&self, scale: u8) -> Option<i128> {
+        if self.mantissa == 0 {
+            return Some(0);
+        }
+        10i128
+            .checked_pow((scale - self.scale) as u32)
+            .and_then(|factor| self.mantissa.checked_mul(factor))
+    }
+}
+
+impl PartialEq for Decimal {
+    fn eq(&self, other: &Self) -> bool {
+        self.compare(other) == Ordering::Equal
+    }
+}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.compare(other))
+    }
+}
+
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scale = self.scale as usize;
+        let digits = format!(
+            "{:0>width$}",
+            self.mantissa.unsigned_abs(),
+            width = scale + 1
+        );
+        let (whole, fraction) = digits.split_at(digits.len() - scale);
+        let sign = if self.mantissa < 0 { "-" } else { "" };
+
+        if fraction.is_empty() {
+            write!(f, "{}{}", sign, whole)
+        } else {
+            write!(f, "{}{}.{}", sign, whole, fraction)
+        }
+    }
+}
+
+impl fmt::Debug for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
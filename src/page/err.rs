@@ -1,3 +1,4 @@
+use crate::page::tuple::DataType;
 use thiserror::Error;
 
 pub type DbResult<T> = Result<T, DbInternalError>;
@@ -8,6 +9,79 @@ pub enum DbInternalError {
     IoError(std::io::Error),
     #[error("Table already exists: {0}")]
     TableAlreadyExists(String),
+    #[error("Table not found: {0}")]
+    TableNotFound(String),
+    #[error("Column '{column}' not found in table '{table}'")]
+    ColumnNotFound { table: String, column: String },
+    #[error("Internal table {0} can't be dropped")]
+    CannotDropInternalTable(String),
+    #[error("Checksum mismatch in page {page_id} of file {file_id}")]
+    ChecksumMismatch { file_id: u32, page_id: u32 },
+    #[error(
+        "Page {page_id} of file {file_id} uses page format version {version}, expected {}",
+        crate::page::PAGE_FORMAT_VERSION
+    )]
+    UnsupportedPageFormat {
+        file_id: u32,
+        page_id: u32,
+        version: u16,
+    },
+    #[error("Expected {expected} values to match the schema, found {found}")]
+    ColumnCountMismatch { expected: usize, found: usize },
+    #[error("Column {column} is {expected:?}, but the value is {found:?}")]
+    SchemaMismatch {
+        column: usize,
+        expected: DataType,
+        found: DataType,
+    },
+    #[error(
+        "No free frame for page {page_id} of file {file_id}: every frame in its shard is pinned"
+    )]
+    NoFreeFrames { file_id: u32, page_id: u32 },
+    #[error("Broken overflow chain at page {page_id} of file {file_id}")]
+    BrokenOverflowChain { file_id: u32, page_id: u32 },
+    #[error("Cannot read {text:?} as {data_type:?}")]
+    InvalidValue { text: String, data_type: DataType },
+    #[error("Line {line} of the CSV: {reason}")]
+    InvalidCsv { line: usize, reason: String },
+    #[error("Insert failed: {0}")]
+    InsertFailed(String),
+    #[error("Update failed: {0}")]
+    UpdateFailed(String),
+    #[error("Delete failed: {0}")]
+    DeleteFailed(String),
+    #[error("Field {field} of a row in {table} is missing or has the wrong type")]
+    CorruptCatalog { table: String, field: usize },
+    #[error("The catalog has lost its {0} table")]
+    MissingInternalTable(String),
+    #[error("{0} page writes failed; the write-ahead log still holds them")]
+    UnwrittenPages(usize),
+    #[error("The page writer has stopped")]
+    WriterStopped,
+    #[error("Corrupt tuple: {0}")]
+    CorruptTuple(TupleDecodeError),
+}
+
+#[derive(Debug, Error)]
+pub enum TupleDecodeError {
+    #[error("unknown value type {0:#04x}")]
+    UnknownType(u8),
+    #[error("a value is missing its type tag")]
+    MissingType,
+    #[error("value of type {type_id:#04x} needs {needed} bytes, only {available} are left")]
+    Truncated {
+        type_id: u8,
+        needed: usize,
+        available: usize,
+    },
+    #[error("slot points at {length} bytes from offset {offset}, outside the record area")]
+    SlotOutOfBounds { offset: usize, length: usize },
+    #[error("the null bitmap needs {needed} bytes, only {available} are there")]
+    MissingNullBitmap { needed: usize, available: usize },
+    #[error("invalid date {year}-{month}-{day}")]
+    InvalidDate { year: i32, month: u32, day: u32 },
+    #[error("invalid timestamp {timestamp}s + {nanos}ns")]
+    InvalidDateTime { timestamp: i64, nanos: u32 },
 }
 
 impl From<std::io::Error> for DbInternalError {
@@ -15,3 +89,9 @@ impl From<std::io::Error> for DbInternalError {
         DbInternalError::IoError(err)
     }
 }
+
+impl From<TupleDecodeError> for DbInternalError {
+    fn from(err: TupleDecodeError) -> Self {
+        DbInternalError::CorruptTuple(err)
+    }
+}
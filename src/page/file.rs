@@ -1,5 +1,5 @@
 use crate::page::err::DbResult;
-use crate::page::{PAGE_SIZE, Page};
+use crate::page::{PAGE_SIZE, Page, sealed_copy};
 use std::io::SeekFrom;
 use tokio::fs::{File, OpenOptions};
 use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
@@ -7,7 +7,6 @@ use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
 pub const EXTENSION: &str = "record";
 
 pub struct RelationFile {
-    #[allow(dead_code)]
     id: u32,
     pub(crate) file: File,
 }
@@ -18,6 +17,7 @@ impl RelationFile {
             .read(true)
             .write(true)
             .create(true)
+            .truncate(false)
             .open(path)
             .await?;
         Ok(RelationFile { id, file })
@@ -34,9 +34,15 @@ impl RelationFile {
         buffer: &'a mut [u8; PAGE_SIZE],
     ) -> DbResult<Page<'a>> {
         let offset = (page_index as u64) * (PAGE_SIZE as u64);
-        self.file.seek(SeekFrom::Start(offset)).await?;
-        self.file.read_exact(buffer).await?;
-        Ok(Page::from_bytes(page_index, buffer))
+        // Pages past the end of the file haven't been written yet and start out empty; a page
+        // that's only partly there is a failed write, and reading it errors.
+        if offset >= self.file.metadata().await?.len() {
+            buffer.fill(0);
+        } else {
+            self.file.seek(SeekFrom::Start(offset)).await?;
+            self.file.read_exact(buffer).await?;
+        }
+        Page::from_bytes(self.id, page_index, buffer)
     }
 
     pub async fn write_page(&mut self, page: &Page<'_>) -> DbResult<()> {
@@ -49,6 +55,7 @@ impl RelationFile {
 
     pub async fn write_page_data(&mut self, page_id: u32, data: Vec<u8>) -> DbResult<()> {
         assert_eq!(data.len(), PAGE_SIZE, "data must be exactly one page");
+        let data = sealed_copy(data.as_slice().try_into().unwrap());
         let offset = (page_id as u64) * (PAGE_SIZE as u64);
         self.file.seek(SeekFrom::Start(offset)).await?;
         self.file.write_all(&data).await?;
@@ -1,28 +1,99 @@
+pub mod decimal;
 pub mod err;
 pub mod file;
 pub mod io;
 pub mod pool;
 pub mod tuple;
+pub mod wal;
 
-use crate::page::tuple::Tuple;
+use crate::page::err::{DbInternalError, DbResult, TupleDecodeError};
+use crate::page::tuple::{DataType, Tuple};
 use std::mem::size_of;
+use std::ops::Range;
 
 pub const PAGE_SIZE: usize = 4096;
+// Bumped whenever the on-disk layout of pages or values changes. Version 2 widened the
+// Text/Blob length prefix from u16 to u32; pages from earlier versions are refused on read.
+pub const PAGE_FORMAT_VERSION: u16 = 2;
+// Every page ends with a trailer: the format version it was written with, followed by a
+// CRC32 of everything before the checksum, version included.
+const VERSION_SIZE: usize = size_of::<u16>();
+const CHECKSUM_SIZE: usize = size_of::<u32>();
+const CHECKSUM_START: usize = PAGE_SIZE - CHECKSUM_SIZE;
+const DATA_END: usize = CHECKSUM_START - VERSION_SIZE;
 const HEADER_SIZE: usize = size_of::<u16>() /* slot_count */ + size_of::<u16>() /* free_space_pointer */;
 const SLOT_META_SIZE: usize = size_of::<SlotMeta>();
+const DEAD_SLOT_FLAG: u16 = 0x8000;
+// Largest record that still fits in an empty page next to its slot; anything bigger is
+// spilled to overflow pages and the slot keeps an `OverflowPointer` instead.
+pub const MAX_INLINE_RECORD_SIZE: usize = DATA_END - HEADER_SIZE - SLOT_META_SIZE;
 
+// Overflow pages reuse the slot count field as a marker, which no slotted page can reach.
+// After it come the chunk length (u16) and the next page of the chain (u32, MAX if last).
+const OVERFLOW_PAGE_MARKER: u16 = u16::MAX;
+const OVERFLOW_HEADER_SIZE: usize = size_of::<u16>() + size_of::<u16>() + size_of::<u32>();
+pub const OVERFLOW_CHUNK_SIZE: usize = DATA_END - OVERFLOW_HEADER_SIZE;
+const NO_NEXT_PAGE: u32 = u32::MAX;
+
+// Tuple records start with a value type id or the packed record tag, none of which is this.
+const OVERFLOW_RECORD_TAG: u8 = 0xFF;
+const OVERFLOW_RECORD_SIZE: usize = 1 + 3 * size_of::<u32>();
+
+// Deleted tuples keep their slot: the high bit of `length` marks it dead and the low bits
+// still hold the size of the region it used, so a later insert that fits can take it over.
+// Tuples never get near 32K within a 4K page, so the bit is free in live slots.
 #[repr(C)]
 struct SlotMeta {
     offset: u16,
     length: u16,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OverflowPointer {
+    pub file_id: u32,
+    pub page_id: u32,
+    pub length: u32,
+}
+
+impl OverflowPointer {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(OVERFLOW_RECORD_SIZE);
+        bytes.push(OVERFLOW_RECORD_TAG);
+        bytes.extend_from_slice(&self.file_id.to_le_bytes());
+        bytes.extend_from_slice(&self.page_id.to_le_bytes());
+        bytes.extend_from_slice(&self.length.to_le_bytes());
+        bytes
+    }
+
+    fn from_bytes(data: &[u8]) -> Option<Self> {
+        if data.len() != OVERFLOW_RECORD_SIZE || data[0] != OVERFLOW_RECORD_TAG {
+            return None;
+        }
+
+        let read = |at: usize| u32::from_le_bytes(data[at..at + 4].try_into().unwrap());
+        Some(Self {
+            file_id: read(1),
+            page_id: read(5),
+            length: read(9),
+        })
+    }
+}
+
+pub enum Record {
+    Inline(Tuple),
+    Overflow(OverflowPointer),
+}
+
 pub struct Page<'a> {
     pub index: u32,
     pub data: &'a mut [u8; PAGE_SIZE],
 }
 
 impl<'a> Page<'a> {
+    /// # Safety
+    ///
+    /// `ptr` must point to a pinned buffer-pool frame of exactly `PAGE_SIZE` bytes that stays
+    /// valid, and is not aliased mutably elsewhere, for the lifetime of the returned page.
     pub unsafe fn from_raw(index: u32, ptr: *mut u8) -> Self {
         let data = unsafe { &mut *(ptr as *mut [u8; PAGE_SIZE]) };
         Page { index, data }
@@ -32,35 +103,48 @@ impl<'a> Page<'a> {
         self.data.fill(0);
         self.data[0..2].copy_from_slice(&0u16.to_le_bytes());
 
-        let init_free_ptr = std::cmp::min(PAGE_SIZE, u16::MAX as usize) as u16;
-        self.data[2..4].copy_from_slice(&init_free_ptr.to_le_bytes());
+        self.data[2..4].copy_from_slice(&(DATA_END as u16).to_le_bytes());
     }
 
     pub fn insert_tuple(&mut self, tuple: &Tuple) -> Result<usize, String> {
-        let bytes = tuple.to_bytes();
+        self.insert_record(&tuple.to_bytes())
+    }
+
+    pub fn insert_record(&mut self, bytes: &[u8]) -> Result<usize, String> {
+        if self.is_overflow() {
+            return Err("not a slotted page: page is part of an overflow chain".into());
+        }
+        if bytes.len() > MAX_INLINE_RECORD_SIZE {
+            return Err("page full: tuple too large".into());
+        }
         let len = bytes.len() as u16;
-        let d = &mut self.data;
 
-        let slot_count = u16::from_le_bytes([d[0], d[1]]) as usize;
-        let free_ptr = u16::from_le_bytes([d[2], d[3]]) as usize;
+        let slot_count = self.slot_count();
+        let free_ptr = u16::from_le_bytes([self.data[2], self.data[3]]) as usize;
 
-        if free_ptr == 0 || free_ptr > PAGE_SIZE {
+        if free_ptr == 0 || free_ptr > DATA_END {
             return Err("invalid page state: corrupted free pointer".into());
         }
 
-        if len as usize > free_ptr {
-            return Err("page full: tuple too large".into());
+        if let Some((idx, offset)) = self.find_reusable_slot(len) {
+            let start = offset as usize;
+            self.data[start..start + bytes.len()].copy_from_slice(bytes);
+            self.write_slot_meta(idx, &SlotMeta {
+                offset,
+                length: len,
+            });
+            return Ok(idx);
         }
 
-        let new_slot_end = HEADER_SIZE + (slot_count + 1) * SLOT_META_SIZE;
-        let new_data_start = free_ptr - (len as usize);
-
-        if new_data_start <= new_slot_end {
+        if !record_fits(self.available_space(), bytes.len()) {
             return Err("page full: not enough space".into());
         }
 
+        let d = &mut self.data;
+        let new_data_start = free_ptr - (len as usize);
+
         let start = new_data_start;
-        d[start..start + (len as usize)].copy_from_slice(&bytes);
+        d[start..start + (len as usize)].copy_from_slice(bytes);
 
         let meta = SlotMeta {
             offset: start as u16,
@@ -77,50 +161,530 @@ impl<'a> Page<'a> {
         Ok(slot_count)
     }
 
-    pub fn get_tuple(&self, idx: usize) -> Option<Tuple> {
-        let d = &self.data;
-        let slot_count = u16::from_le_bytes([d[0], d[1]]) as usize;
+    // `None` for a slot that's empty or dead; a live one whose bytes don't decode is an error.
+    // Without the column types, only self-describing records can be read.
+    pub fn get_record(
+        &self,
+        idx: usize,
+        schema: Option<&[DataType]>,
+    ) -> Result<Option<Record>, TupleDecodeError> {
+        let Some(meta) = self.live_slot(idx) else {
+            return Ok(None);
+        };
+        let region = self
+            .slot_region(&meta)
+            .ok_or(TupleDecodeError::SlotOutOfBounds {
+                offset: meta.offset as usize,
+                length: meta.length as usize,
+            })?;
+
+        let slice = &self.data[region];
+        match OverflowPointer::from_bytes(slice) {
+            Some(pointer) => Ok(Some(Record::Overflow(pointer))),
+            None => match schema {
+                Some(columns) => Tuple::from_bytes_with_schema(slice, columns),
+                None => Tuple::from_bytes(slice),
+            }
+            .map(|tuple| Some(Record::Inline(tuple))),
+        }
+    }
+
+    // Where the row in a live slot was spilled to, if it was.
+    pub fn overflow_pointer(&self, idx: usize) -> Option<OverflowPointer> {
+        let meta = self.live_slot(idx)?;
+        let region = self.slot_region(&meta)?;
+        OverflowPointer::from_bytes(&self.data[region])
+    }
 
-        if idx >= slot_count {
+    pub fn slot_count(&self) -> usize {
+        if self.is_overflow() {
+            return 0;
+        }
+        u16::from_le_bytes([self.data[0], self.data[1]]) as usize
+    }
+
+    // Rows in the page, inline or spilled, read from the slot headers alone.
+    pub fn live_slot_count(&self) -> usize {
+        (0..self.slot_count())
+            .filter(|&idx| self.live_slot(idx).is_some())
+            .count()
+    }
+
+    pub fn is_overflow(&self) -> bool {
+        u16::from_le_bytes([self.data[0], self.data[1]]) == OVERFLOW_PAGE_MARKER
+    }
+
+    pub fn init_overflow(&mut self, chunk: &[u8], next: Option<u32>) {
+        self.data.fill(0);
+        self.data[0..2].copy_from_slice(&OVERFLOW_PAGE_MARKER.to_le_bytes());
+        self.data[2..4].copy_from_slice(&(chunk.len() as u16).to_le_bytes());
+        self.data[4..8].copy_from_slice(&next.unwrap_or(NO_NEXT_PAGE).to_le_bytes());
+        self.data[OVERFLOW_HEADER_SIZE..OVERFLOW_HEADER_SIZE + chunk.len()].copy_from_slice(chunk);
+    }
+
+    // The payload stored in an overflow page, along with the page the chain continues on.
+    pub fn overflow_chunk(&self) -> Option<(&[u8], Option<u32>)> {
+        if !self.is_overflow() {
             return None;
         }
 
+        let length = u16::from_le_bytes([self.data[2], self.data[3]]) as usize;
+        let next = u32::from_le_bytes(self.data[4..8].try_into().unwrap());
+        let chunk = self
+            .data
+            .get(OVERFLOW_HEADER_SIZE..OVERFLOW_HEADER_SIZE + length)?;
+        Some((chunk, (next != NO_NEXT_PAGE).then_some(next)))
+    }
+
+    pub fn update_record(&mut self, idx: usize, bytes: &[u8]) -> Result<(), String> {
+        let mut meta = self
+            .live_slot(idx)
+            .filter(|meta| self.slot_region(meta).is_some())
+            .ok_or_else(|| format!("invalid slot {}", idx))?;
+
+        if bytes.len() > meta.length as usize {
+            let free_ptr = u16::from_le_bytes([self.data[2], self.data[3]]) as usize;
+            let slot_end = HEADER_SIZE + self.slot_count() * SLOT_META_SIZE;
+            if !record_fits(free_ptr.saturating_sub(slot_end), bytes.len()) {
+                return Err("page full: not enough space".into());
+            }
+
+            meta.offset = (free_ptr - bytes.len()) as u16;
+            self.data[2..4].copy_from_slice(&meta.offset.to_le_bytes());
+        }
+
+        let start = meta.offset as usize;
+        self.data[start..start + bytes.len()].copy_from_slice(bytes);
+        meta.length = bytes.len() as u16;
+        self.write_slot_meta(idx, &meta);
+        Ok(())
+    }
+
+    pub fn delete_tuple(&mut self, idx: usize) -> Result<(), String> {
+        let mut meta = self
+            .live_slot(idx)
+            .ok_or_else(|| format!("invalid slot {}", idx))?;
+        meta.length |= DEAD_SLOT_FLAG;
+        self.write_slot_meta(idx, &meta);
+        Ok(())
+    }
+
+    // Slot ids are how rows are addressed, so they stay put; only the payloads move. Dead
+    // slots at the tail are dropped, the ones in between are kept with an empty region.
+    pub fn compact(&mut self) {
+        if self.is_overflow() {
+            return;
+        }
+
+        let live: Vec<(usize, Vec<u8>)> = (0..self.slot_count())
+            .filter_map(|idx| {
+                let region = self.slot_region(&self.live_slot(idx)?)?;
+                Some((idx, self.data[region].to_vec()))
+            })
+            .collect();
+        let slot_count = live.last().map_or(0, |(idx, _)| idx + 1);
+
+        for idx in 0..slot_count {
+            self.write_slot_meta(idx, &SlotMeta {
+                offset: 0,
+                length: DEAD_SLOT_FLAG,
+            });
+        }
+
+        let mut free_ptr = DATA_END;
+        for (idx, bytes) in live {
+            free_ptr -= bytes.len();
+            self.data[free_ptr..free_ptr + bytes.len()].copy_from_slice(&bytes);
+            self.write_slot_meta(idx, &SlotMeta {
+                offset: free_ptr as u16,
+                length: bytes.len() as u16,
+            });
+        }
+
+        self.data[0..2].copy_from_slice(&(slot_count as u16).to_le_bytes());
+        self.data[2..4].copy_from_slice(&(free_ptr as u16).to_le_bytes());
+    }
+
+    fn find_reusable_slot(&self, len: u16) -> Option<(usize, u16)> {
+        (0..self.slot_count()).find_map(|idx| {
+            let meta = self.slot_meta(idx);
+            let dead = meta.length & DEAD_SLOT_FLAG != 0;
+            let region = SlotMeta {
+                offset: meta.offset,
+                length: len,
+            };
+            (dead && meta.length & !DEAD_SLOT_FLAG >= len && self.slot_region(&region).is_some())
+                .then_some((idx, meta.offset))
+        })
+    }
+
+    fn slot_meta(&self, idx: usize) -> SlotMeta {
         let slot_pos = HEADER_SIZE + idx * SLOT_META_SIZE;
-        let offset = u16::from_le_bytes([d[slot_pos], d[slot_pos + 1]]) as usize;
-        let length = u16::from_le_bytes([d[slot_pos + 2], d[slot_pos + 3]]) as usize;
+        let d = &self.data;
+        SlotMeta {
+            offset: u16::from_le_bytes([d[slot_pos], d[slot_pos + 1]]),
+            length: u16::from_le_bytes([d[slot_pos + 2], d[slot_pos + 3]]),
+        }
+    }
 
-        let slice = &d[offset..offset + length];
-        Some(Tuple::from_bytes(slice))
+    fn live_slot(&self, idx: usize) -> Option<SlotMeta> {
+        if idx >= self.slot_count() || HEADER_SIZE + (idx + 1) * SLOT_META_SIZE > DATA_END {
+            return None;
+        }
+
+        let meta = self.slot_meta(idx);
+        if meta.length & DEAD_SLOT_FLAG != 0 {
+            None
+        } else {
+            Some(meta)
+        }
+    }
+
+    // Where a slot's payload sits, as long as it lies between the slot directory and the
+    // trailer. A slot pointing anywhere else is corrupt.
+    fn slot_region(&self, meta: &SlotMeta) -> Option<Range<usize>> {
+        let (offset, length) = (meta.offset as usize, meta.length as usize);
+        let slot_end = HEADER_SIZE + self.slot_count() * SLOT_META_SIZE;
+        (offset >= slot_end && offset + length <= DATA_END).then_some(offset..offset + length)
+    }
+
+    fn write_slot_meta(&mut self, idx: usize, meta: &SlotMeta) {
+        let slot_pos = HEADER_SIZE + idx * SLOT_META_SIZE;
+        self.data[slot_pos..slot_pos + 2].copy_from_slice(&meta.offset.to_le_bytes());
+        self.data[slot_pos + 2..slot_pos + 4].copy_from_slice(&meta.length.to_le_bytes());
     }
-    pub fn from_bytes(index: u32, data: &'a mut [u8; PAGE_SIZE]) -> Self {
+
+    // Pages are verified once, when they come from disk; frames in the buffer pool are
+    // trusted from then on, and stamped again whenever they're written back.
+    pub fn from_bytes(file_id: u32, index: u32, data: &'a mut [u8; PAGE_SIZE]) -> DbResult<Self> {
+        check_trailer(file_id, index, data)?;
+
         let page = Page { index, data };
         let free_ptr = u16::from_le_bytes([page.data[2], page.data[3]]) as usize;
 
-        if free_ptr == 0 || free_ptr > PAGE_SIZE {
-            let init_free_ptr = std::cmp::min(PAGE_SIZE, u16::MAX as usize) as u16;
-            page.data[2..4].copy_from_slice(&init_free_ptr.to_le_bytes());
+        if !page.is_overflow() && (free_ptr == 0 || free_ptr > DATA_END) {
+            page.data[2..4].copy_from_slice(&(DATA_END as u16).to_le_bytes());
         }
 
-        page
+        Ok(page)
     }
 
     pub fn to_bytes(&self) -> [u8; PAGE_SIZE] {
-        let mut bytes = [0; PAGE_SIZE];
-        bytes.copy_from_slice(self.data);
-        bytes
+        sealed_copy(self.data)
     }
 
+    // The payload room left for one more record, once the slot it needs is set aside.
     pub fn available_space(&self) -> usize {
+        if self.is_overflow() {
+            return 0;
+        }
+
         let d = &self.data;
         let slot_count = u16::from_le_bytes([d[0], d[1]]) as usize;
         let free_ptr = u16::from_le_bytes([d[2], d[3]]) as usize;
 
-        let slot_end = HEADER_SIZE + slot_count * SLOT_META_SIZE;
+        let slot_end = HEADER_SIZE + (slot_count + 1) * SLOT_META_SIZE;
 
-        if free_ptr > slot_end {
-            free_ptr - slot_end
-        } else {
-            0
+        free_ptr.saturating_sub(slot_end)
+    }
+
+    // Whether `insert_record` would take a record of this length, by the same checks.
+    pub fn can_fit(&self, tuple_len: usize) -> bool {
+        if self.is_overflow() || tuple_len > MAX_INLINE_RECORD_SIZE {
+            return false;
+        }
+        let free_ptr = u16::from_le_bytes([self.data[2], self.data[3]]) as usize;
+        if free_ptr == 0 || free_ptr > DATA_END {
+            return false;
+        }
+        self.find_reusable_slot(tuple_len as u16).is_some()
+            || record_fits(self.available_space(), tuple_len)
+    }
+}
+
+// Whether a record of `len` bytes fits in `available` bytes between the slot directory and
+// the records below it; it may end right where the directory does. Inserts, updates that grow
+// a record and the heap's free space map all decide by this.
+pub fn record_fits(available: usize, len: usize) -> bool {
+    available >= len
+}
+
+pub fn sealed_copy(data: &[u8; PAGE_SIZE]) -> [u8; PAGE_SIZE] {
+    let mut bytes = *data;
+    bytes[DATA_END..CHECKSUM_START].copy_from_slice(&PAGE_FORMAT_VERSION.to_le_bytes());
+    let checksum = crc32(&bytes[..CHECKSUM_START]);
+    bytes[CHECKSUM_START..].copy_from_slice(&checksum.to_le_bytes());
+    bytes
+}
+
+// A page that was never written is all zeroes, which is fine to read back as empty.
+fn check_trailer(file_id: u32, page_id: u32, data: &[u8; PAGE_SIZE]) -> DbResult<()> {
+    if data.iter().all(|&b| b == 0) {
+        return Ok(());
+    }
+
+    if !checksum_matches(data) {
+        return Err(DbInternalError::ChecksumMismatch { file_id, page_id });
+    }
+
+    let version = format_version(data);
+    if version != PAGE_FORMAT_VERSION {
+        return Err(DbInternalError::UnsupportedPageFormat {
+            file_id,
+            page_id,
+            version,
+        });
+    }
+    Ok(())
+}
+
+// Whether `data` is a complete image sealed by this build, as opposed to a blank page or one
+// that was only partly written.
+pub fn is_intact(data: &[u8; PAGE_SIZE]) -> bool {
+    checksum_matches(data)
+        && format_version(data) == PAGE_FORMAT_VERSION
+        && data.iter().any(|&b| b != 0)
+}
+
+fn checksum_matches(data: &[u8; PAGE_SIZE]) -> bool {
+    let stored = u32::from_le_bytes(data[CHECKSUM_START..].try_into().unwrap());
+    stored == crc32(&data[..CHECKSUM_START])
+}
+
+fn format_version(data: &[u8; PAGE_SIZE]) -> u16 {
+    u16::from_le_bytes([data[DATA_END], data[DATA_END + 1]])
+}
+
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc = CRC32_TABLE[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::page::tuple::Value;
+
+    fn sealed_page(tuples: &[Tuple]) -> Box<[u8; PAGE_SIZE]> {
+        let mut buf = Box::new([0u8; PAGE_SIZE]);
+        let mut page = Page {
+            index: 0,
+            data: &mut buf,
+        };
+        page.init_new();
+        for tuple in tuples {
+            page.insert_tuple(tuple).unwrap();
+        }
+        Box::new(page.to_bytes())
+    }
+
+    #[test]
+    fn flipped_byte_fails_the_checksum() {
+        let mut bytes = sealed_page(&[Tuple(vec![Value::Int(5)])]);
+        assert!(Page::from_bytes(1, 0, &mut bytes.clone()).is_ok());
+
+        bytes[DATA_END - 1] ^= 1;
+        assert!(matches!(
+            Page::from_bytes(1, 3, &mut bytes),
+            Err(DbInternalError::ChecksumMismatch {
+                file_id: 1,
+                page_id: 3
+            })
+        ));
+    }
+
+    fn empty_page() -> Box<[u8; PAGE_SIZE]> {
+        let mut buf = Box::new([0u8; PAGE_SIZE]);
+        Page {
+            index: 0,
+            data: &mut buf,
+        }
+        .init_new();
+        buf
+    }
+
+    // Fills an empty page until exactly `room` bytes are left for the next record.
+    fn fill_to(page: &mut Page, room: usize) {
+        while page.available_space() > MAX_INLINE_RECORD_SIZE {
+            page.insert_record(&[1; MAX_INLINE_RECORD_SIZE / 2])
+                .unwrap();
+        }
+        while page.available_space() > room {
+            let len =
+                (page.available_space() - room - SLOT_META_SIZE).min(MAX_INLINE_RECORD_SIZE / 2);
+            page.insert_record(&vec![2; len]).unwrap();
+        }
+        assert_eq!(page.available_space(), room);
+    }
+
+    #[test]
+    fn largest_inline_record_fits_an_empty_page() {
+        let mut buf = empty_page();
+        let mut page = Page {
+            index: 0,
+            data: &mut buf,
+        };
+        assert!(page.can_fit(MAX_INLINE_RECORD_SIZE));
+        assert!(page.insert_record(&[3; MAX_INLINE_RECORD_SIZE]).is_ok());
+        assert_eq!(page.available_space(), 0);
+        assert!(!page.can_fit(1));
+    }
+
+    #[test]
+    fn can_fit_agrees_with_insert_around_the_boundary() {
+        for len in 38..=42 {
+            let mut buf = empty_page();
+            let mut page = Page {
+                index: 0,
+                data: &mut buf,
+            };
+            fill_to(&mut page, 40);
+            let fits = page.can_fit(len);
+            assert_eq!(fits, len <= 40, "length {}", len);
+            let inserted = page.insert_record(&vec![4; len]);
+            assert_eq!(inserted.is_ok(), fits, "length {}", len);
+        }
+    }
+
+    #[test]
+    fn growing_a_record_stops_at_the_slot_directory() {
+        for grow_by in 0..3 {
+            let mut buf = empty_page();
+            let mut page = Page {
+                index: 0,
+                data: &mut buf,
+            };
+            fill_to(&mut page, 60);
+            let slot = page.insert_record(&[5; 10]).unwrap();
+            // The record keeps its slot, so it also gets the room a new one would have taken.
+            let room = page.available_space() + SLOT_META_SIZE;
+            let len = room - 1 + grow_by;
+            let grown = page.update_record(slot, &vec![6; len]);
+            assert_eq!(grown.is_ok(), len <= room, "length {}", len);
+        }
+    }
+
+    fn values(page: &Page, idx: usize) -> Option<Vec<Value>> {
+        match page.get_record(idx, None).unwrap()? {
+            Record::Inline(tuple) => Some(tuple.0),
+            Record::Overflow(_) => panic!("slot {} was spilled", idx),
+        }
+    }
+
+    #[test]
+    fn deleted_slot_is_reused_by_a_record_that_fits_it() {
+        let mut buf = empty_page();
+        let mut page = Page {
+            index: 0,
+            data: &mut buf,
+        };
+        for i in 0..3 {
+            page.insert_record(&[i; 20]).unwrap();
+        }
+        page.delete_tuple(1).unwrap();
+        assert!(page.get_record(1, None).unwrap().is_none());
+        assert!(page.delete_tuple(1).is_err());
+        assert_eq!(page.live_slot_count(), 2);
+
+        let available = page.available_space();
+        assert_eq!(page.insert_record(&[7; 12]).unwrap(), 1);
+        assert_eq!(page.slot_count(), 3);
+        assert_eq!(page.available_space(), available);
+
+        page.delete_tuple(1).unwrap();
+        assert_eq!(page.insert_record(&[8; 30]).unwrap(), 3);
+        assert_eq!(page.available_space(), available - 30 - SLOT_META_SIZE);
+    }
+
+    #[test]
+    fn compacting_reclaims_deleted_records() {
+        let mut buf = empty_page();
+        let mut page = Page {
+            index: 0,
+            data: &mut buf,
+        };
+        let mut count = 0;
+        while page.insert_tuple(&Tuple(vec![Value::Int(count)])).is_ok() {
+            count += 1;
+        }
+        for idx in (0..count as usize).step_by(2) {
+            page.delete_tuple(idx).unwrap();
+        }
+        let available = page.available_space();
+
+        page.compact();
+
+        assert!(page.available_space() > available);
+        for idx in 0..count as usize {
+            let expected = (idx % 2 == 1).then(|| vec![Value::Int(idx as i32)]);
+            assert_eq!(values(&page, idx), expected, "slot {}", idx);
+        }
+        assert!(page.insert_tuple(&Tuple(vec![Value::Int(-1)])).is_ok());
+    }
+
+    #[test]
+    fn page_from_an_older_format_is_rejected() {
+        let mut bytes = sealed_page(&[Tuple(vec![Value::Text("ana".to_string())])]);
+        bytes[DATA_END..CHECKSUM_START].copy_from_slice(&1u16.to_le_bytes());
+        let checksum = crc32(&bytes[..CHECKSUM_START]);
+        bytes[CHECKSUM_START..].copy_from_slice(&checksum.to_le_bytes());
+        assert!(matches!(
+            Page::from_bytes(1, 2, &mut bytes),
+            Err(DbInternalError::UnsupportedPageFormat {
+                file_id: 1,
+                page_id: 2,
+                version: 1
+            })
+        ));
+    }
+
+    #[test]
+    fn slot_pointing_outside_the_record_area_is_an_error() {
+        let mut buf = empty_page();
+        let mut page = Page {
+            index: 0,
+            data: &mut buf,
+        };
+        page.insert_tuple(&Tuple(vec![Value::Int(1)])).unwrap();
+        page.insert_tuple(&Tuple(vec![Value::Int(2)])).unwrap();
+        let meta = page.slot_meta(1);
+
+        // Past the end of the page, into the trailer, and over the slot directory.
+        for (offset, length) in [
+            (PAGE_SIZE as u16 - 2, 40),
+            (DATA_END as u16 - 2, meta.length),
+            (HEADER_SIZE as u16, meta.length),
+        ] {
+            page.write_slot_meta(1, &SlotMeta { offset, length });
+            assert!(matches!(
+                page.get_record(1, None),
+                Err(TupleDecodeError::SlotOutOfBounds { .. })
+            ));
+            assert!(page.overflow_pointer(1).is_none());
         }
+        assert_eq!(values(&page, 0), Some(vec![Value::Int(1)]));
     }
 }
@@ -1,14 +1,35 @@
+use crate::page::PAGE_SIZE;
+use crate::page::err::{DbInternalError, DbResult};
 use crate::page::io::IoManager;
-use crate::page::{PAGE_SIZE, Page};
 use std::cell::UnsafeCell;
-use std::sync::Arc;
 use std::sync::atomic::{
     AtomicBool, AtomicU64, AtomicUsize,
     Ordering::{AcqRel, Acquire, Relaxed, Release},
 };
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 const SHARD_COUNT: usize = 4;
 const SLOTS_PER_SHARD: usize = 1024;
+const MAX_EVICTION_SWEEPS: usize = 16;
+const MAX_SWEEP_BACKOFF: Duration = Duration::from_millis(8);
+
+#[derive(Debug, Clone, Copy)]
+pub struct BufferPoolConfig {
+    pub slots_per_shard: usize,
+    // Full passes of the clock hand over a shard that find every frame pinned before a
+    // request gives up with `NoFreeFrames`.
+    pub max_eviction_sweeps: usize,
+}
+
+impl Default for BufferPoolConfig {
+    fn default() -> Self {
+        Self {
+            slots_per_shard: SLOTS_PER_SHARD,
+            max_eviction_sweeps: MAX_EVICTION_SWEEPS,
+        }
+    }
+}
 
 struct Slot {
     key: AtomicU64,
@@ -31,100 +52,195 @@ impl Slot {
             buf: UnsafeCell::new([0u8; PAGE_SIZE]),
         }
     }
+
+    // Pins the frame if it still holds the page once pinned, so it can't be handed to another
+    // page while it's copied. One that's being evicted is already on its way to the IO
+    // manager, and is left alone.
+    fn pin_holding(&self, key: u64) -> bool {
+        loop {
+            let pin = self.pin.load(Acquire);
+            if pin == usize::MAX {
+                return false;
+            }
+            if self
+                .pin
+                .compare_exchange(pin, pin + 1, AcqRel, Relaxed)
+                .is_ok()
+            {
+                break;
+            }
+        }
+        if self.key.load(Acquire) != key {
+            self.pin.fetch_sub(1, Release);
+            return false;
+        }
+        true
+    }
 }
 
 struct Shard {
     slots: Box<[Slot]>,
     hand: AtomicUsize,
+    // Held while a miss checks that no other frame has the page and takes its frame for it.
+    claim: Mutex<()>,
     io: Arc<IoManager>,
+    max_eviction_sweeps: usize,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+    writebacks: AtomicU64,
+}
+
+// Totals since the pool was created. A miss is any lookup that had to load the page into a
+// frame; an eviction is a miss that pushed another page out; writebacks count every dirty
+// page handed to the IO manager, whether on eviction or on flush.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BufferPoolStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub writebacks: u64,
 }
 
 impl Shard {
-    fn new(io: Arc<IoManager>) -> Self {
-        let mut v = Vec::with_capacity(SLOTS_PER_SHARD);
-        for _ in 0..SLOTS_PER_SHARD {
+    fn new(io: Arc<IoManager>, config: &BufferPoolConfig) -> Self {
+        let mut v = Vec::with_capacity(config.slots_per_shard);
+        for _ in 0..config.slots_per_shard {
             v.push(Slot::new());
         }
         Shard {
             slots: v.into_boxed_slice(),
             hand: AtomicUsize::new(0),
+            claim: Mutex::new(()),
             io,
+            max_eviction_sweeps: config.max_eviction_sweeps,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+            writebacks: AtomicU64::new(0),
         }
     }
 
-    async fn get_page(&self, file_id: u32, page_id: u32) -> *mut u8 {
+    async fn get_page(&self, file_id: u32, page_id: u32) -> DbResult<*mut u8> {
         let key_to_find = make_key(file_id, page_id);
 
-        'search_loop: for slot_idx in 0..SLOTS_PER_SHARD {
-            let current_slot = &self.slots[slot_idx];
-            if current_slot.key.load(Acquire) == key_to_find {
-                loop {
-                    let pin_val = current_slot.pin.load(Acquire);
-                    if pin_val == usize::MAX {
-                        break 'search_loop;
-                    }
-                    match current_slot
+        let slot_count = self.slots.len();
+        let mut attempts = 0;
+        'search_loop: loop {
+            for current_slot in self.slots.iter() {
+                if current_slot.key.load(Acquire) != key_to_find {
+                    continue;
+                }
+                let pin_val = current_slot.pin.load(Acquire);
+                // The frame is still being filled or written back; wait for it rather than
+                // loading a second copy of the page.
+                if pin_val == usize::MAX
+                    || current_slot
                         .pin
                         .compare_exchange(pin_val, pin_val + 1, AcqRel, Relaxed)
-                    {
-                        Ok(_) => {
-                            if current_slot.key.load(Acquire) == key_to_find {
-                                current_slot.ref_bit.store(true, Release);
-                                return current_slot.buf.get().cast();
-                            } else {
-                                current_slot.pin.fetch_sub(1, Release);
-                                break 'search_loop;
-                            }
-                        }
-                        Err(_) => {
-                            // CAS failed, pin_val changed, loop will reload and retry
-                        }
-                    }
+                        .is_err()
+                {
+                    tokio::task::yield_now().await;
+                    continue 'search_loop;
                 }
+                if current_slot.key.load(Acquire) == key_to_find {
+                    current_slot.ref_bit.store(true, Release);
+                    self.hits.fetch_add(1, Relaxed);
+                    return Ok(current_slot.buf.get().cast());
+                }
+                current_slot.pin.fetch_sub(1, Release);
+                continue 'search_loop;
             }
-        }
 
-        loop {
-            let victim_idx = self.hand.fetch_add(1, Relaxed) % SLOTS_PER_SHARD;
-            let victim_slot = &self.slots[victim_idx];
+            loop {
+                if attempts == slot_count * self.max_eviction_sweeps {
+                    return Err(DbInternalError::NoFreeFrames { file_id, page_id });
+                }
+                // Back off a little more after every sweep that found nothing, so whoever holds
+                // the pins gets a chance to release them.
+                if attempts > 0 && attempts % slot_count == 0 {
+                    let sweeps = (attempts / slot_count) as u32;
+                    let backoff = Duration::from_micros(50 << sweeps.min(16));
+                    tokio::time::sleep(backoff.min(MAX_SWEEP_BACKOFF)).await;
+                }
+                attempts += 1;
 
-            if victim_slot.pin.load(Acquire) == 0 {
-                if victim_slot
-                    .pin
-                    .compare_exchange(0, usize::MAX, AcqRel, Relaxed)
-                    .is_ok()
+                let victim_idx = self.hand.fetch_add(1, Relaxed) % slot_count;
+                let victim_slot = &self.slots[victim_idx];
+
+                if victim_slot.pin.load(Acquire) != 0
+                    || victim_slot
+                        .pin
+                        .compare_exchange(0, usize::MAX, AcqRel, Relaxed)
+                        .is_err()
                 {
-                    if victim_slot.ref_bit.swap(false, AcqRel) {
+                    tokio::task::yield_now().await;
+                    continue;
+                }
+                if victim_slot.ref_bit.swap(false, AcqRel) {
+                    victim_slot.pin.store(0, Release);
+                    tokio::task::yield_now().await;
+                    continue;
+                }
+
+                let old_key = victim_slot.key.load(Acquire);
+                if old_key != u64::MAX && victim_slot.dirty.swap(false, AcqRel) {
+                    let old_file_id = (old_key >> 32) as u32;
+                    let old_page_id = old_key as u32;
+                    let page_data_to_write = unsafe { (*victim_slot.buf.get()).to_vec() };
+                    let written = self
+                        .io
+                        .schedule_write(old_file_id, old_page_id, page_data_to_write)
+                        .await;
+                    // The old page stays in its frame, still dirty, if it can't be logged.
+                    if let Err(err) = written {
+                        victim_slot.dirty.store(true, Release);
                         victim_slot.pin.store(0, Release);
-                        tokio::task::yield_now().await;
-                        continue;
+                        return Err(err);
                     }
+                    self.writebacks.fetch_add(1, Relaxed);
+                }
 
-                    let old_key = victim_slot.key.load(Acquire);
-                    if old_key != u64::MAX && victim_slot.dirty.swap(false, AcqRel) {
-                        let old_file_id = (old_key >> 32) as u32;
-                        let old_page_id = old_key as u32;
-                        let page_data_to_write = unsafe { (*victim_slot.buf.get()).to_vec() };
-                        self.io
-                            .schedule_write(old_file_id, old_page_id, page_data_to_write);
+                {
+                    // Another miss on the same page may have claimed a frame while this one
+                    // was writing back; if so, give this frame up and pin that one instead.
+                    // Two frames holding one page would leave `unpin` releasing the wrong one.
+                    let _claim = self.claim.lock().unwrap();
+                    if self
+                        .slots
+                        .iter()
+                        .any(|slot| slot.key.load(Acquire) == key_to_find)
+                    {
+                        victim_slot.key.store(u64::MAX, Release);
+                        victim_slot.pin.store(0, Release);
+                        continue 'search_loop;
                     }
-
                     victim_slot.key.store(key_to_find, Release);
+                }
 
-                    let page_buffer_for_io = unsafe { &mut *victim_slot.buf.get() };
-                    let _res = self
-                        .io
-                        .read_into_buf(file_id, page_id, page_buffer_for_io)
-                        .await;
+                self.misses.fetch_add(1, Relaxed);
+                if old_key != u64::MAX {
+                    self.evictions.fetch_add(1, Relaxed);
+                }
 
-                    let final_raw_ptr = victim_slot.buf.get();
+                let page_buffer_for_io = unsafe { &mut *victim_slot.buf.get() };
+                let res = self
+                    .io
+                    .read_into_buf(file_id, page_id, page_buffer_for_io)
+                    .await;
 
-                    victim_slot.pin.store(1, Release);
-                    victim_slot.ref_bit.store(true, Release);
-                    return final_raw_ptr.cast();
+                if let Err(err) = res {
+                    victim_slot.key.store(u64::MAX, Release);
+                    victim_slot.pin.store(0, Release);
+                    return Err(err);
                 }
+
+                let final_raw_ptr = victim_slot.buf.get();
+
+                victim_slot.pin.store(1, Release);
+                victim_slot.ref_bit.store(true, Release);
+                return Ok(final_raw_ptr.cast());
             }
-            tokio::task::yield_now().await;
         }
     }
 
@@ -132,95 +248,380 @@ impl Shard {
         let key = make_key(file_id, page_id);
         for slot in self.slots.iter() {
             if slot.key.load(Acquire) == key {
-                let prev = slot.pin.fetch_sub(1, Release);
-                if prev == 1 && is_dirty {
+                // Marked before the pin drops, whoever else holds the frame, so the write can't
+                // be evicted away unflushed.
+                if is_dirty {
                     slot.dirty.store(true, Release);
                 }
+                slot.pin.fetch_sub(1, Release);
                 return;
             }
         }
     }
 
-    pub async fn flush_page(&self, file_id: u32, page_id: u32) {
+    fn discard_file(&self, file_id: u32) {
+        for slot in self.slots.iter() {
+            let key = slot.key.load(Acquire);
+            if key != u64::MAX
+                && (key >> 32) as u32 == file_id
+                && slot
+                    .pin
+                    .compare_exchange(0, usize::MAX, AcqRel, Relaxed)
+                    .is_ok()
+            {
+                slot.dirty.store(false, Release);
+                slot.ref_bit.store(false, Release);
+                slot.key.store(u64::MAX, Release);
+                slot.pin.store(0, Release);
+            }
+        }
+    }
+
+    pub async fn flush_page(&self, file_id: u32, page_id: u32) -> DbResult<()> {
         let key = make_key(file_id, page_id);
         for slot in self.slots.iter() {
-            if slot.key.load(Acquire) == key {
-                if slot.dirty.swap(false, AcqRel) {
-                    let bytes = unsafe { (*slot.buf.get())[..].to_vec() };
-                    self.io.schedule_write(file_id, page_id, bytes);
-                }
-                return;
+            if slot.key.load(Acquire) != key || !slot.pin_holding(key) {
+                continue;
             }
+            return self.flush_pinned(slot, file_id, page_id).await;
         }
+        Ok(())
     }
 
-    pub async fn flush_all_dirty_pages_in_shard(&self) {
-        for slot_idx in 0..self.slots.len() {
-            let s = &self.slots[slot_idx];
-            if s.dirty.load(Acquire) {
-                let key = s.key.load(Acquire);
-                if key == u64::MAX {
-                    continue;
-                }
-                if s.dirty.swap(false, AcqRel) {
-                    let fid = (key >> 32) as u32;
-                    let pid = key as u32;
-                    let bytes = unsafe { (*s.buf.get())[..].to_vec() };
-                    self.io.schedule_write(fid, pid, bytes);
-                }
+    pub async fn flush_all_dirty_pages_in_shard(&self) -> DbResult<()> {
+        for slot in self.slots.iter() {
+            if !slot.dirty.load(Acquire) {
+                continue;
+            }
+            let key = slot.key.load(Acquire);
+            if key == u64::MAX || !slot.pin_holding(key) {
+                continue;
             }
+            self.flush_pinned(slot, (key >> 32) as u32, key as u32)
+                .await?;
+        }
+        Ok(())
+    }
+
+    // Writes back a frame pinned with `pin_holding`, then lets go of it.
+    async fn flush_pinned(&self, slot: &Slot, file_id: u32, page_id: u32) -> DbResult<()> {
+        let flushed = if slot.dirty.swap(false, AcqRel) {
+            self.write_back(slot, file_id, page_id).await
+        } else {
+            Ok(())
+        };
+        slot.pin.fetch_sub(1, Release);
+        flushed
+    }
+
+    async fn write_back(&self, slot: &Slot, file_id: u32, page_id: u32) -> DbResult<()> {
+        let bytes = unsafe { (*slot.buf.get()).to_vec() };
+        if let Err(err) = self.io.schedule_write(file_id, page_id, bytes).await {
+            slot.dirty.store(true, Release);
+            return Err(err);
         }
+        self.writebacks.fetch_add(1, Relaxed);
+        Ok(())
     }
 }
 
 pub struct BufferPool {
     shards: Vec<Arc<Shard>>,
+    io: Arc<IoManager>,
 }
 
 impl BufferPool {
     pub fn new(io: Arc<IoManager>) -> Arc<Self> {
+        Self::with_config(io, BufferPoolConfig::default())
+    }
+
+    pub fn with_config(io: Arc<IoManager>, config: BufferPoolConfig) -> Arc<Self> {
         let mut shards = Vec::with_capacity(SHARD_COUNT);
         for _ in 0..SHARD_COUNT {
-            shards.push(Arc::new(Shard::new(Arc::clone(&io))));
+            shards.push(Arc::new(Shard::new(Arc::clone(&io), &config)));
         }
-        Arc::new(BufferPool { shards })
+        Arc::new(BufferPool { shards, io })
     }
 
     fn pick_shard(&self, file_id: u32, page_id: u32) -> usize {
         (file_id as usize ^ page_id as usize) & (SHARD_COUNT - 1)
     }
 
-    pub async fn get_page_ptr(&self, file_id: u32, page_id: u32) -> *mut u8 {
+    pub async fn get_page_ptr(&self, file_id: u32, page_id: u32) -> DbResult<*mut u8> {
         let s = self.pick_shard(file_id, page_id);
         self.shards[s].get_page(file_id, page_id).await
     }
 
-    pub async fn get_page_raw(&self, file_id: u32, page_id: u32) -> Page {
-        let ptr = self.get_page_ptr(file_id, page_id).await;
-        unsafe { Page::from_raw(page_id, ptr) }
-    }
-
     pub fn unpin(&self, file_id: u32, page_id: u32, is_dirty: bool) {
         let s = self.pick_shard(file_id, page_id);
         self.shards[s].unpin(file_id, page_id, is_dirty);
     }
 
-    pub async fn unpin_and_flush(&self, file_id: u32, page_id: u32, is_dirty: bool) {
+    pub async fn unpin_and_flush(
+        &self,
+        file_id: u32,
+        page_id: u32,
+        is_dirty: bool,
+    ) -> DbResult<()> {
         let s = self.pick_shard(file_id, page_id);
         let shard = &self.shards[s];
         shard.unpin(file_id, page_id, is_dirty);
-        shard.flush_page(file_id, page_id).await;
+        shard.flush_page(file_id, page_id).await
     }
 
-    pub async fn flush(&self) {
+    pub async fn flush_page(&self, file_id: u32, page_id: u32) -> DbResult<()> {
+        let s = self.pick_shard(file_id, page_id);
+        self.shards[s].flush_page(file_id, page_id).await
+    }
+
+    pub fn stats(&self) -> BufferPoolStats {
+        self.shards
+            .iter()
+            .fold(BufferPoolStats::default(), |stats, shard| BufferPoolStats {
+                hits: stats.hits + shard.hits.load(Relaxed),
+                misses: stats.misses + shard.misses.load(Relaxed),
+                evictions: stats.evictions + shard.evictions.load(Relaxed),
+                writebacks: stats.writebacks + shard.writebacks.load(Relaxed),
+            })
+    }
+
+    // Forgets every cached page of the file, dirty or not, so none of them is written back
+    // after the file is gone.
+    pub fn discard_file(&self, file_id: u32) {
+        for shard in &self.shards {
+            shard.discard_file(file_id);
+        }
+    }
+
+    pub async fn flush(&self) -> DbResult<()> {
         let mut futures = Vec::new();
         for shard_arc in &self.shards {
             futures.push(shard_arc.flush_all_dirty_pages_in_shard());
         }
-        futures::future::join_all(futures).await;
+        futures::future::join_all(futures)
+            .await
+            .into_iter()
+            .collect()
+    }
+
+    // Like `flush`, but only returns once the pages have actually reached their files, so
+    // reading them back from disk afterwards sees the new contents. A page that couldn't be
+    // written fails the call.
+    pub async fn flush_sync(&self) -> DbResult<()> {
+        self.flush().await?;
+        self.io.flush_and_sync().await
+    }
+
+    // Everything dirty goes through the log and into the data files, after which the log
+    // can start over.
+    pub async fn checkpoint(&self) -> DbResult<()> {
+        self.flush().await?;
+        self.io.checkpoint().await
+    }
+
+    // Dropping the pool doesn't write anything back, so frames still dirty at that point are
+    // lost. Whoever owns the pool calls this before the process exits.
+    pub async fn close(&self) -> DbResult<()> {
+        self.checkpoint().await
     }
 }
 
 fn make_key(file_id: u32, page_id: u32) -> u64 {
     ((file_id as u64) << 32) | page_id as u64
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::page::Record;
+    use crate::page::file::{EXTENSION, RelationFile};
+    use crate::page::io::FileSystemManager;
+    use crate::page::tuple::{Tuple, Value};
+    use crate::page::{MAX_INLINE_RECORD_SIZE, PAGE_SIZE, Page};
+    use crate::testing::{TempDir, rows, users_database};
+
+    #[tokio::test]
+    async fn flush_sync_puts_rows_in_the_file() {
+        let dir = TempDir::new();
+        let mut database = users_database(&dir).await;
+        rows(&mut database, r#"insert_ users { name = "ana", age = 30 }"#).await;
+        let (file_id, schema) = {
+            let catalog = database.catalog().read().await;
+            catalog.buffer_pool.flush_sync().await.unwrap();
+            let users = catalog.get_table("users").unwrap();
+            (users.file_id, users.info.column_types())
+        };
+
+        let path = dir.file(&format!("ak{}.{}", file_id, EXTENSION));
+        let mut file = RelationFile::open_existing(file_id, &path).await.unwrap();
+        let mut buf = [0u8; PAGE_SIZE];
+        let page: Page = file.read_page_into_buffer(0, &mut buf).await.unwrap();
+        let Ok(Some(Record::Inline(tuple))) = page.get_record(0, Some(&schema)) else {
+            panic!("the row isn't in the file");
+        };
+        assert_eq!(tuple.0[0], Value::Text("ana".to_string()));
+    }
+
+    // Pages 0 and 4 of a file land in the same shard.
+    async fn one_frame_pool(dir: &TempDir) -> Arc<BufferPool> {
+        let fs = Arc::new(FileSystemManager::new(dir.path().to_string()));
+        fs.create_home().await.unwrap();
+        BufferPool::with_config(
+            Arc::new(IoManager::new(fs)),
+            BufferPoolConfig {
+                slots_per_shard: 1,
+                max_eviction_sweeps: 2,
+            },
+        )
+    }
+
+    #[tokio::test]
+    async fn repeated_read_is_one_miss_then_hits() {
+        let dir = TempDir::new();
+        let pool = one_frame_pool(&dir).await;
+        for _ in 0..3 {
+            pool.get_page_ptr(1, 0).await.unwrap();
+            pool.unpin(1, 0, false);
+        }
+        let stats = pool.stats();
+        assert_eq!((stats.misses, stats.hits, stats.evictions), (1, 2, 0));
+
+        pool.get_page_ptr(1, 0).await.unwrap();
+        pool.unpin(1, 0, true);
+        pool.get_page_ptr(1, 4).await.unwrap();
+        pool.unpin(1, 4, false);
+        let stats = pool.stats();
+        assert_eq!((stats.misses, stats.evictions, stats.writebacks), (2, 1, 1));
+    }
+
+    #[tokio::test]
+    async fn full_shard_of_pinned_frames_gives_up() {
+        let dir = TempDir::new();
+        let pool = one_frame_pool(&dir).await;
+        pool.get_page_ptr(1, 0).await.unwrap();
+        assert!(matches!(
+            pool.get_page_ptr(1, 4).await,
+            Err(DbInternalError::NoFreeFrames {
+                file_id: 1,
+                page_id: 4
+            })
+        ));
+        // Other shards still have room, and page 4 loads once page 0 lets go of the frame.
+        pool.get_page_ptr(1, 1).await.unwrap();
+        pool.unpin(1, 1, false);
+        pool.unpin(1, 0, false);
+        pool.get_page_ptr(1, 4).await.unwrap();
+        pool.unpin(1, 4, false);
+    }
+
+    #[tokio::test]
+    async fn failed_read_is_returned_and_frees_its_frame() {
+        let dir = TempDir::new();
+        let pool = one_frame_pool(&dir).await;
+        // Half a page: a write that never finished.
+        std::fs::write(dir.file("ak1.record"), [7u8; PAGE_SIZE / 2]).unwrap();
+
+        for _ in 0..2 {
+            assert!(matches!(
+                pool.get_page_ptr(1, 0).await,
+                Err(DbInternalError::IoError(_))
+            ));
+        }
+
+        // Past the end of the file is a page nobody wrote yet, which reads as empty.
+        let ptr = pool.get_page_ptr(1, 4).await.unwrap();
+        let page = unsafe { Page::from_raw(4, ptr) };
+        assert_eq!(page.slot_count(), 0);
+        assert!(page.can_fit(MAX_INLINE_RECORD_SIZE));
+        pool.unpin(1, 4, false);
+    }
+
+    #[tokio::test]
+    async fn rows_written_through_page_pointers_read_back_after_reopening() {
+        let dir = TempDir::new();
+        let fs = Arc::new(FileSystemManager::new(dir.path().to_string()));
+        fs.create_home().await.unwrap();
+        let io = Arc::new(IoManager::new(fs));
+        let row = Tuple(vec![Value::Int(7), Value::Text("ana".to_string())]);
+
+        let pool = BufferPool::new(Arc::clone(&io));
+        let ptr = pool.get_page_ptr(3, 0).await.unwrap();
+        let mut page = unsafe { Page::from_raw(0, ptr) };
+        page.init_new();
+        let slot = page.insert_record(&row.to_bytes()).unwrap();
+        pool.unpin_and_flush(3, 0, true).await.unwrap();
+        pool.close().await.unwrap();
+
+        let pool = BufferPool::new(io);
+        let ptr = pool.get_page_ptr(3, 0).await.unwrap();
+        let page = unsafe { Page::from_raw(0, ptr) };
+        let record = page.get_record(slot, None);
+        pool.unpin(3, 0, false);
+        let Ok(Some(Record::Inline(tuple))) = record else {
+            panic!("the row didn't come back");
+        };
+        assert_eq!(tuple.0, row.0);
+    }
+
+    // Pages 0, 4, 8 and 12 of file 1 share a shard of one frame, so every write evicts the
+    // page before it while another task keeps flushing. Each page has to come back with its
+    // own row, never one that moved into the frame after it.
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn flushing_alongside_evictions_writes_each_page_under_its_own_id() {
+        let dir = TempDir::new();
+        let fs = Arc::new(FileSystemManager::new(dir.path().to_string()));
+        fs.create_home().await.unwrap();
+        // The flushes pin the frame too, so evictions get more sweeps to wait them out.
+        let pool = BufferPool::with_config(
+            Arc::new(IoManager::new(fs)),
+            BufferPoolConfig {
+                slots_per_shard: 1,
+                max_eviction_sweeps: 64,
+            },
+        );
+        let pages = [0u32, 4, 8, 12];
+
+        let writer = {
+            let pool = Arc::clone(&pool);
+            tokio::spawn(async move {
+                for round in 0..200 {
+                    for page_id in pages {
+                        let ptr = pool.get_page_ptr(1, page_id).await.unwrap();
+                        {
+                            let mut page = unsafe { Page::from_raw(page_id, ptr) };
+                            if round > 0 {
+                                let Ok(Some(Record::Inline(tuple))) = page.get_record(0, None)
+                                else {
+                                    panic!("page {} lost its row", page_id);
+                                };
+                                assert_eq!(tuple.0[0], Value::Long(page_id as i64));
+                            }
+                            page.init_new();
+                            let row = Tuple(vec![Value::Long(page_id as i64), Value::Int(round)]);
+                            page.insert_record(&row.to_bytes()).unwrap();
+                        }
+                        pool.unpin(1, page_id, true);
+                    }
+                }
+            })
+        };
+        while !writer.is_finished() {
+            pool.flush().await.unwrap();
+        }
+        writer.await.unwrap();
+        pool.flush_sync().await.unwrap();
+
+        for page_id in pages {
+            let path = dir.file(&format!("ak1.{}", EXTENSION));
+            let mut file = RelationFile::open_existing(1, &path).await.unwrap();
+            let mut buf = [0u8; PAGE_SIZE];
+            file.read_page_into_buffer(page_id, &mut buf).await.unwrap();
+            let page = unsafe { Page::from_raw(page_id, buf.as_mut_ptr()) };
+            let Ok(Some(Record::Inline(tuple))) = page.get_record(0, None) else {
+                panic!("page {} has no row", page_id);
+            };
+            assert_eq!(tuple.0[0], Value::Long(page_id as i64));
+            assert_eq!(tuple.0[1], Value::Int(199));
+        }
+    }
+}
@@ -1,8 +1,16 @@
+use crate::page::decimal::Decimal;
+use crate::page::err::{DbInternalError, DbResult, TupleDecodeError};
 use chrono::Datelike;
+use std::cmp::Ordering;
+use std::fmt;
 
 #[derive(Debug)]
 pub struct Tuple(pub Vec<Value>);
 
+// Records encoded against a schema start with this. Self-describing ones start with a type
+// id, and overflow pointers with their own tag, neither of which gets this high.
+pub const PACKED_RECORD_TAG: u8 = 0x80;
+
 impl Tuple {
     pub fn to_bytes(&self) -> Vec<u8> {
         let total_size: usize = self.0.iter().map(Value::get_size).sum();
@@ -13,19 +21,84 @@ impl Tuple {
         bytes
     }
 
-    pub fn from_bytes(data: &[u8]) -> Self {
+    pub fn from_bytes(data: &[u8]) -> Result<Self, TupleDecodeError> {
         let mut values = Vec::new();
         let mut offset = 0;
         while offset < data.len() {
-            let (val, size) = Value::read_from_bytes(&data[offset..]);
+            let (val, size) = Value::read_from_bytes(&data[offset..])?;
             values.push(val);
             offset += size;
         }
-        Self(values)
+        Ok(Self(values))
+    }
+
+    // With the schema at hand, types are implied by the columns: after the tag comes a null
+    // bitmap (one bit per column, in column id order) and values follow without their type
+    // tags. Columns typed `Null` don't pin a type down, so their values keep the tag.
+    pub fn to_bytes_with_schema(&self, columns: &[DataType]) -> DbResult<Vec<u8>> {
+        if columns.len() != self.0.len() {
+            return Err(DbInternalError::ColumnCountMismatch {
+                expected: columns.len(),
+                found: self.0.len(),
+            });
+        }
+
+        let mut bytes = vec![0u8; 1 + columns.len().div_ceil(8)];
+        bytes[0] = PACKED_RECORD_TAG;
+        for (i, (data_type, value)) in columns.iter().zip(&self.0).enumerate() {
+            match (data_type, value) {
+                (_, Value::Null) => bytes[1 + i / 8] |= 1 << (i % 8),
+                (DataType::Null, value) => value.to_bytes_into(&mut bytes),
+                (data_type, value) if data_type.id() == value.id() => {
+                    value.payload_into(&mut bytes)
+                }
+                (data_type, value) => {
+                    return Err(DbInternalError::SchemaMismatch {
+                        column: i,
+                        expected: data_type.clone(),
+                        found: value.data_type(),
+                    });
+                }
+            }
+        }
+        Ok(bytes)
+    }
+
+    // Records without the tag were written self-describing, and are read that way.
+    pub fn from_bytes_with_schema(
+        data: &[u8],
+        columns: &[DataType],
+    ) -> Result<Self, TupleDecodeError> {
+        let Some((&PACKED_RECORD_TAG, data)) = data.split_first() else {
+            return Self::from_bytes(data);
+        };
+        let mut offset = columns.len().div_ceil(8);
+        if data.len() < offset {
+            return Err(TupleDecodeError::MissingNullBitmap {
+                needed: offset,
+                available: data.len(),
+            });
+        }
+        let mut values = Vec::with_capacity(columns.len());
+
+        for (i, data_type) in columns.iter().enumerate() {
+            if data[i / 8] & (1 << (i % 8)) != 0 {
+                values.push(Value::Null);
+                continue;
+            }
+
+            let (val, size) = match data_type {
+                DataType::Null => Value::read_from_bytes(&data[offset..])?,
+                data_type => Value::read_payload(data_type.id(), &data[offset..])?,
+            };
+            values.push(val);
+            offset += size;
+        }
+        Ok(Self(values))
     }
 }
 
-#[derive(Clone, Debug, PartialEq, PartialOrd)]
+#[derive(Clone, Debug)]
 pub enum Value {
     Null,
     Int(i32),
@@ -38,108 +111,271 @@ pub enum Value {
     DateTime(chrono::NaiveDateTime),
     Blob(Vec<u8>),
     Byte(u8),
+    Decimal(Decimal),
 }
 
-impl Value {
-    fn to_bytes_into(&self, buf: &mut Vec<u8>) {
-        match self {
-            Value::Null => buf.push(self.id()),
-            Value::Int(i) => {
-                buf.push(self.id());
-                buf.extend_from_slice(&i.to_le_bytes());
-            }
-            Value::Long(l) => {
-                buf.push(self.id());
-                buf.extend_from_slice(&l.to_le_bytes());
+impl fmt::Display for Tuple {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, value) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, " | ")?;
             }
-            Value::Float(f) => {
-                buf.push(self.id());
-                buf.extend_from_slice(&f.to_le_bytes());
+            write!(f, "{}", value)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Null => write!(f, "NULL"),
+            Value::Int(i) => write!(f, "{}", i),
+            Value::Long(l) => write!(f, "{}", l),
+            Value::Float(x) => write!(f, "{}", x),
+            Value::Double(d) => write!(f, "{}", d),
+            Value::Text(s) => write!(f, "{}", s),
+            Value::Boolean(b) => write!(f, "{}", b),
+            Value::Date(date) => write!(f, "{}", date),
+            Value::DateTime(dt) => write!(f, "{}", dt),
+            Value::Blob(bytes) => {
+                write!(f, "0x")?;
+                bytes.iter().try_for_each(|b| write!(f, "{:02x}", b))
             }
-            Value::Double(d) => {
-                buf.push(self.id());
-                buf.extend_from_slice(&d.to_le_bytes());
+            Value::Byte(b) => write!(f, "{}", b),
+            Value::Decimal(d) => write!(f, "{}", d),
+        }
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        self.partial_cmp(other) == Some(Ordering::Equal)
+    }
+}
+
+// Values of different types order by type id, except that decimals compare numerically
+// against the other numeric types.
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        let by_type = || self.id().partial_cmp(&other.id());
+        match (self, other) {
+            (Value::Decimal(a), b) => compare_decimal(a, b).or_else(by_type),
+            (a, Value::Decimal(b)) => compare_decimal(b, a)
+                .map(Ordering::reverse)
+                .or_else(by_type),
+            (Value::Null, Value::Null) => Some(Ordering::Equal),
+            (Value::Int(a), Value::Int(b)) => a.partial_cmp(b),
+            (Value::Long(a), Value::Long(b)) => a.partial_cmp(b),
+            (Value::Float(a), Value::Float(b)) => a.partial_cmp(b),
+            (Value::Double(a), Value::Double(b)) => a.partial_cmp(b),
+            (Value::Text(a), Value::Text(b)) => a.partial_cmp(b),
+            (Value::Boolean(a), Value::Boolean(b)) => a.partial_cmp(b),
+            (Value::Date(a), Value::Date(b)) => a.partial_cmp(b),
+            (Value::DateTime(a), Value::DateTime(b)) => a.partial_cmp(b),
+            (Value::Blob(a), Value::Blob(b)) => a.partial_cmp(b),
+            (Value::Byte(a), Value::Byte(b)) => a.partial_cmp(b),
+            _ => by_type(),
+        }
+    }
+}
+
+fn compare_decimal(decimal: &Decimal, other: &Value) -> Option<Ordering> {
+    match other {
+        Value::Decimal(other) => Some(decimal.compare(other)),
+        Value::Int(i) => Some(decimal.compare(&Decimal::from_integer(*i as i64))),
+        Value::Long(l) => Some(decimal.compare(&Decimal::from_integer(*l))),
+        Value::Byte(b) => Some(decimal.compare(&Decimal::from_integer(*b as i64))),
+        Value::Float(f) => decimal.to_f64().partial_cmp(&(*f as f64)),
+        Value::Double(d) => decimal.to_f64().partial_cmp(d),
+        _ => None,
+    }
+}
+
+impl Value {
+    // Unlike `partial_cmp`, which orders values of different types by type id so that any
+    // column can be sorted, this only compares values that mean the same kind of thing:
+    // numbers of any width against each other, everything else against its own type.
+    pub fn compare(&self, other: &Value) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Decimal(a), b) => compare_decimal(a, b),
+            (a, Value::Decimal(b)) => compare_decimal(b, a).map(Ordering::reverse),
+            _ => match (self.as_i64(), other.as_i64()) {
+                (Some(a), Some(b)) => Some(a.cmp(&b)),
+                _ => match (self.as_f64(), other.as_f64()) {
+                    (Some(a), Some(b)) => a.partial_cmp(&b),
+                    _ if self.id() == other.id() => self.partial_cmp(other),
+                    _ => None,
+                },
+            },
+        }
+    }
+
+    // Number literals only come as `Int`, `Long` and `Double`, so a number written into a
+    // column of another numeric type is converted on the way in. Integers are never narrowed,
+    // and everything else is stored as it comes.
+    pub fn for_column(self, data_type: &DataType) -> Value {
+        match (data_type, &self) {
+            (DataType::Decimal, Value::Float(_) | Value::Double(_)) => {
+                let parsed = self.as_f64().and_then(|f| Decimal::parse(&f.to_string()));
+                parsed.map_or(self, Value::Decimal)
             }
+            (DataType::Decimal, _) => match self.as_i64() {
+                Some(i) => Value::Decimal(Decimal::from_integer(i)),
+                None => self,
+            },
+            (DataType::Long, Value::Int(_) | Value::Byte(_)) => Value::Long(self.as_i64().unwrap()),
+            (DataType::Int, Value::Byte(b)) => Value::Int(*b as i32),
+            (
+                DataType::Double,
+                Value::Int(_) | Value::Long(_) | Value::Byte(_) | Value::Float(_),
+            ) => Value::Double(self.as_f64().unwrap()),
+            (
+                DataType::Float,
+                Value::Int(_) | Value::Long(_) | Value::Byte(_) | Value::Double(_),
+            ) => Value::Float(self.as_f64().unwrap() as f32),
+            _ => self,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Value::Int(i) => Some(*i as i64),
+            Value::Long(l) => Some(*l),
+            Value::Byte(b) => Some(*b as i64),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Float(f) => Some(*f as f64),
+            Value::Double(d) => Some(*d),
+            other => other.as_i64().map(|i| i as f64),
+        }
+    }
+
+    fn to_bytes_into(&self, buf: &mut Vec<u8>) {
+        let start = buf.len();
+        buf.push(self.id());
+        self.payload_into(buf);
+        // Page space is reserved by `get_size`, so the two can't drift apart.
+        debug_assert_eq!(
+            buf.len() - start,
+            self.get_size(),
+            "get_size is off for {:?}",
+            self
+        );
+    }
+
+    // Everything but the type tag, for callers that already know the type.
+    fn payload_into(&self, buf: &mut Vec<u8>) {
+        match self {
+            Value::Null => {}
+            Value::Int(i) => buf.extend_from_slice(&i.to_le_bytes()),
+            Value::Long(l) => buf.extend_from_slice(&l.to_le_bytes()),
+            Value::Float(f) => buf.extend_from_slice(&f.to_le_bytes()),
+            Value::Double(d) => buf.extend_from_slice(&d.to_le_bytes()),
             Value::Text(s) => {
                 let bytes = s.as_bytes();
-                buf.push(self.id());
-                buf.extend_from_slice(&(bytes.len() as u16).to_le_bytes());
+                buf.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
                 buf.extend_from_slice(bytes);
             }
-            Value::Boolean(b) => {
-                buf.push(self.id());
-                buf.push(*b as u8);
-            }
+            Value::Boolean(b) => buf.push(*b as u8),
             Value::Date(date) => {
-                buf.push(self.id());
                 buf.extend_from_slice(&date.year().to_le_bytes());
                 buf.extend_from_slice(&(date.month() as u16).to_le_bytes());
                 buf.extend_from_slice(&(date.day() as u16).to_le_bytes());
             }
             Value::DateTime(dt) => {
-                buf.push(self.id());
                 buf.extend_from_slice(&dt.and_utc().timestamp().to_le_bytes());
                 buf.extend_from_slice(&dt.and_utc().timestamp_subsec_nanos().to_le_bytes());
             }
             Value::Blob(b) => {
-                buf.push(self.id());
-                buf.extend_from_slice(&(b.len() as u16).to_le_bytes());
+                buf.extend_from_slice(&(b.len() as u32).to_le_bytes());
                 buf.extend_from_slice(b);
             }
-            Value::Byte(b) => {
-                buf.push(self.id());
-                buf.push(*b);
+            Value::Byte(b) => buf.push(*b),
+            Value::Decimal(d) => {
+                buf.extend_from_slice(&d.mantissa.to_le_bytes());
+                buf.push(d.scale);
             }
         }
     }
 
-    pub fn read_from_bytes(data: &[u8]) -> (Self, usize) {
-        match data[0] {
-            0x00 => (Value::Null, 1),
+    pub fn read_from_bytes(data: &[u8]) -> Result<(Self, usize), TupleDecodeError> {
+        let (&type_id, payload) = data.split_first().ok_or(TupleDecodeError::MissingType)?;
+        let (value, size) = Self::read_payload(type_id, payload)?;
+        Ok((value, size + 1))
+    }
+
+    fn read_payload(type_id: u8, data: &[u8]) -> Result<(Self, usize), TupleDecodeError> {
+        let take = |len: usize| {
+            data.get(..len).ok_or(TupleDecodeError::Truncated {
+                type_id,
+                needed: len,
+                available: data.len(),
+            })
+        };
+        let length_prefix =
+            || take(4).map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()) as usize);
+
+        let decoded = match type_id {
+            0x00 => (Value::Null, 0),
             0x01 => (
-                Value::Int(i32::from_le_bytes(data[1..5].try_into().unwrap())),
-                5,
+                Value::Int(i32::from_le_bytes(take(4)?.try_into().unwrap())),
+                4,
             ),
             0x02 => (
-                Value::Long(i64::from_le_bytes(data[1..9].try_into().unwrap())),
-                9,
+                Value::Long(i64::from_le_bytes(take(8)?.try_into().unwrap())),
+                8,
             ),
             0x03 => (
-                Value::Float(f32::from_le_bytes(data[1..5].try_into().unwrap())),
-                5,
+                Value::Float(f32::from_le_bytes(take(4)?.try_into().unwrap())),
+                4,
             ),
             0x04 => (
-                Value::Double(f64::from_le_bytes(data[1..9].try_into().unwrap())),
-                9,
+                Value::Double(f64::from_le_bytes(take(8)?.try_into().unwrap())),
+                8,
             ),
             0x05 => {
-                let len = u16::from_le_bytes(data[1..3].try_into().unwrap()) as usize;
-                let s = String::from_utf8_lossy(&data[3..3 + len]).to_string();
-                (Value::Text(s), 3 + len)
+                let len = length_prefix()?;
+                let bytes = take(4 + len)?;
+                let s = String::from_utf8_lossy(&bytes[4..]).to_string();
+                (Value::Text(s), 4 + len)
             }
-            0x06 => (Value::Boolean(data[1] != 0), 2),
+            0x06 => (Value::Boolean(take(1)?[0] != 0), 1),
             0x07 => {
-                let year = i32::from_le_bytes(data[1..5].try_into().unwrap());
-                let month = u16::from_le_bytes(data[5..7].try_into().unwrap()) as u32;
-                let day = u16::from_le_bytes(data[7..9].try_into().unwrap()) as u32;
-                let date = chrono::NaiveDate::from_ymd_opt(year, month, day).unwrap();
-                (Value::Date(date), 9)
+                let bytes = take(8)?;
+                let year = i32::from_le_bytes(bytes[0..4].try_into().unwrap());
+                let month = u16::from_le_bytes(bytes[4..6].try_into().unwrap()) as u32;
+                let day = u16::from_le_bytes(bytes[6..8].try_into().unwrap()) as u32;
+                let date = chrono::NaiveDate::from_ymd_opt(year, month, day)
+                    .ok_or(TupleDecodeError::InvalidDate { year, month, day })?;
+                (Value::Date(date), 8)
             }
             0x08 => {
-                let timestamp = i64::from_le_bytes(data[1..9].try_into().unwrap());
-                let nanos = u32::from_le_bytes(data[9..13].try_into().unwrap());
-                #[allow(deprecated)]
-                let dt = chrono::NaiveDateTime::from_timestamp(timestamp, nanos);
-                (Value::DateTime(dt), 13)
+                let bytes = take(12)?;
+                let timestamp = i64::from_le_bytes(bytes[0..8].try_into().unwrap());
+                let nanos = u32::from_le_bytes(bytes[8..12].try_into().unwrap());
+                let dt = chrono::DateTime::from_timestamp(timestamp, nanos)
+                    .ok_or(TupleDecodeError::InvalidDateTime { timestamp, nanos })?;
+                (Value::DateTime(dt.naive_utc()), 12)
             }
             0x09 => {
-                let len = u16::from_le_bytes(data[1..3].try_into().unwrap()) as usize;
-                let b = data[3..3 + len].to_vec();
-                (Value::Blob(b), 3 + len)
+                let len = length_prefix()?;
+                let b = take(4 + len)?[4..].to_vec();
+                (Value::Blob(b), 4 + len)
             }
-            0x0A => (Value::Byte(data[1]), 2),
-            _ => panic!("Unknown value type: {}", data[0]),
-        }
+            0x0A => (Value::Byte(take(1)?[0]), 1),
+            0x0B => {
+                let bytes = take(17)?;
+                let mantissa = i128::from_le_bytes(bytes[0..16].try_into().unwrap());
+                (Value::Decimal(Decimal::new(mantissa, bytes[16])), 17)
+            }
+            _ => return Err(TupleDecodeError::UnknownType(type_id)),
+        };
+        Ok(decoded)
     }
 
     pub fn get_size(&self) -> usize {
@@ -147,17 +383,22 @@ impl Value {
             Value::Int(_) => 5,
             Value::Float(_) => 5,
             Value::Boolean(_) => 2,
-            Value::Text(s) => 3 + s.len(),
-            Value::Blob(b) => 3 + b.len(),
+            Value::Text(s) => 5 + s.len(),
+            Value::Blob(b) => 5 + b.len(),
             Value::Date(_) => 9,
             Value::DateTime(_) => 13,
             Value::Null => 1,
             Value::Long(_) => 9,
             Value::Double(_) => 9,
             Value::Byte(_) => 2,
+            Value::Decimal(_) => 18,
         }
     }
 
+    pub fn data_type(&self) -> DataType {
+        DataType::from_id(self.id()).expect("every value id has a data type")
+    }
+
     pub fn id(&self) -> u8 {
         match self {
             Value::Null => 0x00,
@@ -171,6 +412,7 @@ impl Value {
             Value::DateTime(_) => 0x08,
             Value::Blob(_) => 0x09,
             Value::Byte(_) => 0x0A,
+            Value::Decimal(_) => 0x0B,
         }
     }
 
@@ -205,6 +447,96 @@ impl Value {
             None
         }
     }
+
+    pub fn as_long(&self) -> Option<i64> {
+        if let Value::Long(l) = self {
+            Some(*l)
+        } else {
+            None
+        }
+    }
+
+    pub fn as_float(&self) -> Option<f32> {
+        if let Value::Float(f) = self {
+            Some(*f)
+        } else {
+            None
+        }
+    }
+
+    pub fn as_double(&self) -> Option<f64> {
+        if let Value::Double(d) = self {
+            Some(*d)
+        } else {
+            None
+        }
+    }
+
+    pub fn as_date(&self) -> Option<chrono::NaiveDate> {
+        if let Value::Date(date) = self {
+            Some(*date)
+        } else {
+            None
+        }
+    }
+
+    pub fn as_datetime(&self) -> Option<chrono::NaiveDateTime> {
+        if let Value::DateTime(datetime) = self {
+            Some(*datetime)
+        } else {
+            None
+        }
+    }
+
+    pub fn as_blob(&self) -> Option<&[u8]> {
+        if let Value::Blob(bytes) = self {
+            Some(bytes)
+        } else {
+            None
+        }
+    }
+
+    // Reads text the way `Display` writes it, so a value survives a round trip through a
+    // file. Dates and times are also accepted in their ISO form.
+    pub fn parse_as(text: &str, data_type: &DataType) -> DbResult<Value> {
+        let trimmed = text.trim();
+        let value = match data_type {
+            DataType::Null => trimmed.eq_ignore_ascii_case("null").then_some(Value::Null),
+            DataType::Int => trimmed.parse().ok().map(Value::Int),
+            DataType::Long => trimmed.parse().ok().map(Value::Long),
+            DataType::Float => trimmed.parse().ok().map(Value::Float),
+            DataType::Double => trimmed.parse().ok().map(Value::Double),
+            DataType::Text => Some(Value::Text(text.to_string())),
+            DataType::Boolean => match trimmed.to_ascii_lowercase().as_str() {
+                "true" => Some(Value::Boolean(true)),
+                "false" => Some(Value::Boolean(false)),
+                _ => None,
+            },
+            DataType::Date => chrono::NaiveDate::parse_from_str(trimmed, "%Y-%m-%d")
+                .ok()
+                .map(Value::Date),
+            DataType::DateTime => ["%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M:%S%.f"]
+                .iter()
+                .find_map(|format| chrono::NaiveDateTime::parse_from_str(trimmed, format).ok())
+                .map(Value::DateTime),
+            DataType::Blob => trimmed
+                .strip_prefix("0x")
+                .filter(|hex| hex.len() % 2 == 0 && hex.is_ascii())
+                .and_then(|hex| {
+                    (0..hex.len())
+                        .step_by(2)
+                        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+                        .collect::<Option<Vec<u8>>>()
+                })
+                .map(Value::Blob),
+            DataType::Byte => trimmed.parse().ok().map(Value::Byte),
+            DataType::Decimal => Decimal::parse(trimmed).map(Value::Decimal),
+        };
+        value.ok_or_else(|| DbInternalError::InvalidValue {
+            text: text.to_string(),
+            data_type: data_type.clone(),
+        })
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -220,9 +552,29 @@ pub enum DataType {
     DateTime, // 0x08
     Blob,     // 0x09
     Byte,     // 0x0A
+    Decimal,  // 0x0B
 }
 
 impl DataType {
+    // The names a query spells column types with. `Null` is left out: it's the untyped
+    // column the catalog uses internally, not something a table is declared with.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "Int" => Some(DataType::Int),
+            "Long" => Some(DataType::Long),
+            "Float" => Some(DataType::Float),
+            "Double" => Some(DataType::Double),
+            "Text" => Some(DataType::Text),
+            "Boolean" => Some(DataType::Boolean),
+            "Date" => Some(DataType::Date),
+            "DateTime" => Some(DataType::DateTime),
+            "Blob" => Some(DataType::Blob),
+            "Byte" => Some(DataType::Byte),
+            "Decimal" => Some(DataType::Decimal),
+            _ => None,
+        }
+    }
+
     pub fn from_id(id: u8) -> Option<Self> {
         match id {
             0x00 => Some(DataType::Null),
@@ -236,6 +588,7 @@ impl DataType {
             0x08 => Some(DataType::DateTime),
             0x09 => Some(DataType::Blob),
             0x0A => Some(DataType::Byte),
+            0x0B => Some(DataType::Decimal),
             _ => None,
         }
     }
@@ -253,6 +606,586 @@ impl DataType {
             DataType::DateTime => 0x08,
             DataType::Blob => 0x09,
             DataType::Byte => 0x0A,
+            DataType::Decimal => 0x0B,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_type_tag_is_an_error() {
+        let mut bytes = Tuple(vec![Value::Int(1)]).to_bytes();
+        bytes.extend_from_slice(&[0xFF, 0, 0, 0, 0]);
+        assert!(matches!(
+            Tuple::from_bytes(&bytes),
+            Err(TupleDecodeError::UnknownType(0xFF))
+        ));
+    }
+
+    #[test]
+    fn schema_encoding_is_smaller() {
+        let columns = vec![DataType::Int; 12];
+        let sparse = Tuple(
+            (0..12)
+                .map(|i| {
+                    if i % 3 == 0 {
+                        Value::Int(i)
+                    } else {
+                        Value::Null
+                    }
+                })
+                .collect(),
+        );
+        // A tag per value, against the record tag and a two byte bitmap.
+        assert_eq!(sparse.to_bytes().len(), 4 * 5 + 8);
+        assert_eq!(
+            sparse.to_bytes_with_schema(&columns).unwrap().len(),
+            3 + 4 * 4
+        );
+
+        let full = Tuple((0..12).map(Value::Int).collect());
+        assert!(full.to_bytes_with_schema(&columns).unwrap().len() < full.to_bytes().len());
+    }
+
+    #[test]
+    fn mixed_nulls_round_trip_with_schema() {
+        let columns = vec![
+            DataType::Text,
+            DataType::Int,
+            DataType::Null,
+            DataType::DateTime,
+            DataType::Blob,
+            DataType::Long,
+            DataType::Boolean,
+            DataType::Double,
+            DataType::Decimal,
+        ];
+        let values = vec![
+            Value::Text("ana".to_string()),
+            Value::Null,
+            Value::Byte(7),
+            Value::Null,
+            Value::Blob(vec![0xFF; 3]),
+            Value::Null,
+            Value::Boolean(true),
+            Value::Null,
+            Value::Decimal(Decimal::new(-12345, 2)),
+        ];
+        for nulls in 0..1u32 << values.len() {
+            let tuple = Tuple(
+                values
+                    .iter()
+                    .enumerate()
+                    .map(|(i, value)| match nulls & (1 << i) {
+                        0 => value.clone(),
+                        _ => Value::Null,
+                    })
+                    .collect(),
+            );
+            let bytes = tuple.to_bytes_with_schema(&columns).unwrap();
+            assert_eq!(bytes[0], PACKED_RECORD_TAG);
+            let decoded = Tuple::from_bytes_with_schema(&bytes, &columns).unwrap();
+            assert_eq!(decoded.0, tuple.0);
+        }
+    }
+
+    #[test]
+    fn schema_decoding_reads_self_describing_records() {
+        let columns = vec![DataType::Text, DataType::Int];
+        let tuple = Tuple(vec![Value::Text("ana".to_string()), Value::Null]);
+        let decoded = Tuple::from_bytes_with_schema(&tuple.to_bytes(), &columns).unwrap();
+        assert_eq!(decoded.0, tuple.0);
+    }
+
+    #[test]
+    fn value_of_the_wrong_type_is_a_schema_mismatch() {
+        let tuple = Tuple(vec![Value::Long(1)]);
+        assert!(matches!(
+            tuple.to_bytes_with_schema(&[DataType::Int]),
+            Err(DbInternalError::SchemaMismatch { column: 0, .. })
+        ));
+    }
+
+    fn encoded(value: &Value) -> Vec<u8> {
+        let mut bytes = vec![];
+        value.to_bytes_into(&mut bytes);
+        bytes
+    }
+
+    // One of each variant. The match has no catch-all, so a new variant doesn't compile
+    // until it's added here too.
+    fn one_of_each() -> Vec<Value> {
+        let values = vec![
+            Value::Null,
+            Value::Int(-7),
+            Value::Long(1 << 40),
+            Value::Float(1.5),
+            Value::Double(-2.25),
+            Value::Text("héllo".to_string()),
+            Value::Boolean(true),
+            Value::Date(chrono::NaiveDate::from_ymd_opt(2024, 2, 29).unwrap()),
+            Value::DateTime(chrono::NaiveDateTime::MIN),
+            Value::Blob(vec![0, 0xFF, 3]),
+            Value::Byte(9),
+            Value::Decimal(Decimal::new(-12345, 2)),
+        ];
+        for value in &values {
+            match value {
+                Value::Null
+                | Value::Int(_)
+                | Value::Long(_)
+                | Value::Float(_)
+                | Value::Double(_)
+                | Value::Text(_)
+                | Value::Boolean(_)
+                | Value::Date(_)
+                | Value::DateTime(_)
+                | Value::Blob(_)
+                | Value::Byte(_)
+                | Value::Decimal(_) => {}
+            }
+        }
+        values
+    }
+
+    #[test]
+    fn every_value_takes_exactly_get_size_bytes() {
+        for value in one_of_each() {
+            let mut bytes = encoded(&value);
+            assert_eq!(bytes.len(), value.get_size(), "{:?}", value);
+            // Trailing bytes of a next value must be left alone.
+            bytes.extend_from_slice(&[0xAB; 4]);
+            let (read, consumed) = Value::read_from_bytes(&bytes).unwrap();
+            assert_eq!(consumed, value.get_size(), "{:?}", value);
+            assert_eq!(read, value);
+        }
+    }
+
+    #[test]
+    fn edge_timestamps_round_trip() {
+        let date = |y, m, d| chrono::NaiveDate::from_ymd_opt(y, m, d).unwrap();
+        let times = [
+            chrono::NaiveDateTime::MIN,
+            chrono::NaiveDateTime::MAX,
+            // Just before the epoch, where the seconds are negative but the nanos aren't.
+            date(1969, 12, 31)
+                .and_hms_nano_opt(23, 59, 59, 999_999_999)
+                .unwrap(),
+            // A leap second, which keeps its nanos past one second.
+            date(2016, 12, 31)
+                .and_hms_nano_opt(23, 59, 59, 1_500_000_000)
+                .unwrap(),
+        ];
+        for time in times {
+            let bytes = encoded(&Value::DateTime(time));
+            let (value, read) = Value::read_from_bytes(&bytes).unwrap();
+            assert_eq!(value, Value::DateTime(time));
+            assert_eq!(read, bytes.len());
+        }
+    }
+
+    #[test]
+    fn corrupt_date_is_an_error() {
+        let mut bytes = encoded(&Value::Date(
+            chrono::NaiveDate::from_ymd_opt(2024, 2, 29).unwrap(),
+        ));
+        bytes[5..7].copy_from_slice(&13u16.to_le_bytes());
+        assert!(matches!(
+            Value::read_from_bytes(&bytes),
+            Err(TupleDecodeError::InvalidDate {
+                year: 2024,
+                month: 13,
+                day: 29
+            })
+        ));
+
+        bytes[5..7].copy_from_slice(&2u16.to_le_bytes());
+        bytes[1..5].copy_from_slice(&2023i32.to_le_bytes());
+        assert!(matches!(
+            Value::read_from_bytes(&bytes),
+            Err(TupleDecodeError::InvalidDate { day: 29, .. })
+        ));
+    }
+
+    #[test]
+    fn corrupt_timestamp_is_an_error() {
+        let mut bytes = encoded(&Value::DateTime(chrono::NaiveDateTime::MAX));
+        bytes[1..9].copy_from_slice(&i64::MAX.to_le_bytes());
+        assert!(matches!(
+            Value::read_from_bytes(&bytes),
+            Err(TupleDecodeError::InvalidDateTime { .. })
+        ));
+    }
+
+    // xorshift64*, so a failure can be replayed from the seed it reports.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 >> 12;
+            self.0 ^= self.0 << 25;
+            self.0 ^= self.0 >> 27;
+            self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+        }
+
+        fn below(&mut self, n: u64) -> u64 {
+            self.next() % n
+        }
+
+        fn pick<T: Clone>(&mut self, items: &[T]) -> T {
+            items[self.below(items.len() as u64) as usize].clone()
+        }
+    }
+
+    fn datetime(date: chrono::NaiveDate, secs: u32, nanos: u32) -> chrono::NaiveDateTime {
+        let time = chrono::NaiveTime::from_num_seconds_from_midnight_opt(secs, nanos).unwrap();
+        date.and_time(time)
+    }
+
+    // Each variant in turn, with its extremes coming up as often as ordinary values.
+    fn random_value(rng: &mut Rng) -> Value {
+        let edge = rng.below(2) == 0;
+        match rng.below(12) {
+            0 => Value::Null,
+            1 if edge => Value::Int(rng.pick(&[i32::MIN, -1, 0, i32::MAX])),
+            1 => Value::Int(rng.next() as i32),
+            2 if edge => Value::Long(rng.pick(&[i64::MIN, 0, i64::MAX])),
+            2 => Value::Long(rng.next() as i64),
+            3 if edge => Value::Float(rng.pick(&[
+                f32::NAN,
+                f32::INFINITY,
+                f32::NEG_INFINITY,
+                -0.0,
+                f32::MIN_POSITIVE,
+            ])),
+            3 => Value::Float(f32::from_bits(rng.next() as u32)),
+            4 if edge => Value::Double(rng.pick(&[f64::NAN, f64::NEG_INFINITY, -0.0, f64::MAX])),
+            4 => Value::Double(f64::from_bits(rng.next())),
+            5 => {
+                // Past what a u16 length prefix could hold, now and then.
+                let len = match rng.below(4) {
+                    0 => 0,
+                    1 => u16::MAX as u64 + 1 + rng.below(100),
+                    _ => rng.below(40),
+                };
+                let text = (0..len)
+                    .map(|_| rng.pick(&['a', 'Z', ' ', '"', '\n', 'é', '字', '🦀']))
+                    .collect();
+                Value::Text(text)
+            }
+            6 => Value::Boolean(rng.below(2) == 1),
+            7 if edge => Value::Date(rng.pick(&[
+                chrono::NaiveDate::MIN,
+                chrono::NaiveDate::MAX,
+                chrono::NaiveDate::from_ymd_opt(2024, 2, 29).unwrap(),
+            ])),
+            7 => Value::Date(
+                chrono::NaiveDate::from_num_days_from_ce_opt(rng.below(800_000) as i32 - 100_000)
+                    .unwrap(),
+            ),
+            8 if edge => {
+                let day = chrono::NaiveDate::from_ymd_opt(2016, 12, 31).unwrap();
+                Value::DateTime(rng.pick(&[
+                    chrono::NaiveDateTime::MIN,
+                    chrono::NaiveDateTime::MAX,
+                    chrono::NaiveDateTime::UNIX_EPOCH,
+                    // A leap second, and a moment before the epoch with a fraction to it.
+                    datetime(day, 86_399, 1_500_000_000),
+                    datetime(
+                        chrono::NaiveDate::from_ymd_opt(1969, 12, 31).unwrap(),
+                        86_399,
+                        1,
+                    ),
+                ]))
+            }
+            8 => {
+                let date = chrono::NaiveDate::from_num_days_from_ce_opt(
+                    rng.below(800_000) as i32 - 100_000,
+                )
+                .unwrap();
+                Value::DateTime(datetime(
+                    date,
+                    rng.below(86_400) as u32,
+                    rng.below(1_000_000_000) as u32,
+                ))
+            }
+            9 => {
+                let len = match rng.below(4) {
+                    0 => 0,
+                    1 => u16::MAX as u64 + 1,
+                    _ => rng.below(40),
+                };
+                Value::Blob((0..len).map(|_| rng.next() as u8).collect())
+            }
+            10 => Value::Byte(rng.next() as u8),
+            _ if edge => Value::Decimal(Decimal::new(rng.pick(&[i128::MIN, 0, i128::MAX]), 38)),
+            _ => Value::Decimal(Decimal::new(
+                ((rng.next() as i128) << 64) | rng.next() as i128,
+                rng.below(39) as u8,
+            )),
+        }
+    }
+
+    // Exact equality: NaNs match themselves, and decimals keep their scale.
+    fn same(a: &Value, b: &Value) -> bool {
+        match (a, b) {
+            (Value::Float(a), Value::Float(b)) => a.to_bits() == b.to_bits(),
+            (Value::Double(a), Value::Double(b)) => a.to_bits() == b.to_bits(),
+            (Value::Decimal(a), Value::Decimal(b)) => {
+                a.mantissa == b.mantissa && a.scale == b.scale
+            }
+            (a, b) => a.id() == b.id() && a == b,
+        }
+    }
+
+    fn round_trips(values: &[Value]) -> bool {
+        let tuple = Tuple(values.to_vec());
+        match Tuple::from_bytes(&tuple.to_bytes()) {
+            Ok(decoded) => {
+                decoded.0.len() == values.len()
+                    && decoded.0.iter().zip(values).all(|(a, b)| same(a, b))
+            }
+            Err(_) => false,
+        }
+    }
+
+    // Smaller values of the same type to try in place of one that fails.
+    fn simpler(value: &Value) -> Vec<Value> {
+        match value {
+            Value::Null => vec![],
+            Value::Text(text) if !text.is_empty() => {
+                let half: String = text.chars().take(text.chars().count() / 2).collect();
+                vec![Value::Text(String::new()), Value::Text(half)]
+            }
+            Value::Blob(bytes) if !bytes.is_empty() => {
+                vec![
+                    Value::Blob(vec![]),
+                    Value::Blob(bytes[..bytes.len() / 2].to_vec()),
+                ]
+            }
+            Value::Int(i) if *i != 0 => vec![Value::Int(0), Value::Int(i / 2)],
+            Value::Long(l) if *l != 0 => vec![Value::Long(0), Value::Long(l / 2)],
+            Value::Decimal(d) if d.mantissa != 0 || d.scale != 0 => vec![
+                Value::Decimal(Decimal::new(0, 0)),
+                Value::Decimal(Decimal::new(d.mantissa / 2, d.scale)),
+                Value::Decimal(Decimal::new(d.mantissa, d.scale / 2)),
+            ],
+            Value::DateTime(dt) if *dt != chrono::NaiveDateTime::UNIX_EPOCH => {
+                vec![
+                    Value::DateTime(chrono::NaiveDateTime::UNIX_EPOCH),
+                    Value::DateTime(dt.date().and_hms_opt(0, 0, 0).unwrap()),
+                ]
+            }
+            _ => vec![Value::Null],
+        }
+    }
+
+    // Drops values and simplifies the rest for as long as the tuple keeps failing.
+    fn shrink(mut values: Vec<Value>) -> Vec<Value> {
+        'smaller: loop {
+            for i in 0..values.len() {
+                let mut fewer = values.clone();
+                fewer.remove(i);
+                if !round_trips(&fewer) {
+                    values = fewer;
+                    continue 'smaller;
+                }
+            }
+            for i in 0..values.len() {
+                for candidate in simpler(&values[i]) {
+                    let mut simplified = values.clone();
+                    simplified[i] = candidate;
+                    if !round_trips(&simplified) {
+                        values = simplified;
+                        continue 'smaller;
+                    }
+                }
+            }
+            return values;
         }
     }
+
+    // Long texts and blobs by their length, so a failure stays readable.
+    fn describe(values: &[Value]) -> String {
+        let described: Vec<String> = values
+            .iter()
+            .map(|value| match value {
+                Value::Text(text) if text.len() > 64 => format!("Text({} bytes)", text.len()),
+                Value::Blob(bytes) if bytes.len() > 64 => format!("Blob({} bytes)", bytes.len()),
+                value => format!("{:?}", value),
+            })
+            .collect();
+        format!("[{}]", described.join(", "))
+    }
+
+    #[test]
+    fn random_tuples_round_trip() {
+        for seed in 1..=2_000u64 {
+            let mut rng = Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+            let len = rng.below(9);
+            let values: Vec<Value> = (0..len).map(|_| random_value(&mut rng)).collect();
+            if !round_trips(&values) {
+                panic!(
+                    "seed {} fails; shrunk to {}",
+                    seed,
+                    describe(&shrink(values))
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn values_longer_than_a_u16_length_round_trip() {
+        let text = Value::Text("ab".repeat(50 * 1024));
+        let blob = Value::Blob((0..100 * 1024).map(|i| i as u8).collect());
+        let tuple = Tuple(vec![text, blob]);
+        assert_eq!(Tuple::from_bytes(&tuple.to_bytes()).unwrap().0, tuple.0);
+
+        let columns = [DataType::Text, DataType::Blob];
+        let packed = tuple.to_bytes_with_schema(&columns).unwrap();
+        assert_eq!(
+            Tuple::from_bytes_with_schema(&packed, &columns).unwrap().0,
+            tuple.0
+        );
+    }
+
+    #[test]
+    fn decimal_round_trips_and_compares_with_other_numbers() {
+        let price = Value::Decimal(Decimal::new(-1999, 2));
+        let tuple = Tuple(vec![price.clone()]);
+        assert_eq!(Tuple::from_bytes(&tuple.to_bytes()).unwrap().0, tuple.0);
+        let packed = tuple.to_bytes_with_schema(&[DataType::Decimal]).unwrap();
+        assert_eq!(
+            Tuple::from_bytes_with_schema(&packed, &[DataType::Decimal])
+                .unwrap()
+                .0,
+            tuple.0
+        );
+
+        assert!(price < Value::Int(-19));
+        assert!(price > Value::Long(-20));
+        assert_eq!(
+            Value::Decimal(Decimal::new(500, 2)),
+            Value::Decimal(Decimal::from_integer(5))
+        );
+    }
+
+    #[test]
+    fn numbers_written_into_a_decimal_column_become_decimals() {
+        assert_eq!(
+            Value::Double(19.99).for_column(&DataType::Decimal),
+            Value::Decimal(Decimal::new(1999, 2))
+        );
+        assert_eq!(
+            Value::Long(7).for_column(&DataType::Decimal),
+            Value::Decimal(Decimal::from_integer(7))
+        );
+        assert_eq!(
+            Value::Double(f64::INFINITY).for_column(&DataType::Decimal),
+            Value::Double(f64::INFINITY)
+        );
+    }
+
+    #[test]
+    fn numbers_written_into_a_column_take_its_type() {
+        assert!(matches!(
+            Value::Int(7).for_column(&DataType::Long),
+            Value::Long(7)
+        ));
+        assert!(matches!(
+            Value::Byte(7).for_column(&DataType::Int),
+            Value::Int(7)
+        ));
+        assert!(matches!(
+            Value::Long(7).for_column(&DataType::Double),
+            Value::Double(7.0)
+        ));
+        assert!(matches!(
+            Value::Double(1.5).for_column(&DataType::Float),
+            Value::Float(1.5)
+        ));
+        assert!(matches!(
+            Value::Long(1 << 40).for_column(&DataType::Int),
+            Value::Long(1099511627776)
+        ));
+        assert_eq!(
+            Value::Text("7".to_string()).for_column(&DataType::Long),
+            Value::Text("7".to_string())
+        );
+    }
+
+    #[test]
+    fn numbers_compare_across_widths() {
+        assert_eq!(
+            Value::Long(19).compare(&Value::Int(18)),
+            Some(Ordering::Greater)
+        );
+        assert_eq!(
+            Value::Int(18).compare(&Value::Double(18.5)),
+            Some(Ordering::Less)
+        );
+        assert_eq!(
+            Value::Byte(3).compare(&Value::Float(3.0)),
+            Some(Ordering::Equal)
+        );
+        assert_eq!(
+            Value::Long(i64::MAX).compare(&Value::Long(i64::MAX - 1)),
+            Some(Ordering::Greater)
+        );
+        assert_eq!(Value::Text("1".to_string()).compare(&Value::Int(1)), None);
+        assert_eq!(
+            Value::Text("a".to_string()).compare(&Value::Text("b".to_string())),
+            Some(Ordering::Less)
+        );
+    }
+
+    #[test]
+    fn mixed_tuple_displays_plainly() {
+        let tuple = Tuple(vec![
+            Value::Text("bob".to_string()),
+            Value::Int(42),
+            Value::Null,
+            Value::Date(chrono::NaiveDate::from_ymd_opt(2024, 1, 1).unwrap()),
+            Value::Blob(vec![0x0a, 0xff]),
+            Value::Boolean(true),
+            Value::Decimal(Decimal::new(-5, 2)),
+            Value::Double(2.5),
+        ]);
+        assert_eq!(
+            tuple.to_string(),
+            "bob | 42 | NULL | 2024-01-01 | 0x0aff | true | -0.05 | 2.5"
+        );
+        assert_eq!(Tuple(vec![]).to_string(), "");
+    }
+
+    #[test]
+    fn accessors_only_read_their_own_variant() {
+        let day = chrono::NaiveDate::from_ymd_opt(2024, 2, 29).unwrap();
+        let moment = day.and_hms_opt(8, 0, 0).unwrap();
+        assert_eq!(Value::Long(5).as_long(), Some(5));
+        assert_eq!(Value::Int(5).as_long(), None);
+        assert_eq!(Value::Float(1.5).as_float(), Some(1.5));
+        assert_eq!(Value::Double(1.5).as_float(), None);
+        assert_eq!(Value::Double(1.5).as_double(), Some(1.5));
+        assert_eq!(Value::Float(1.5).as_double(), None);
+        assert_eq!(Value::Date(day).as_date(), Some(day));
+        assert_eq!(Value::DateTime(moment).as_date(), None);
+        assert_eq!(Value::DateTime(moment).as_datetime(), Some(moment));
+        assert_eq!(Value::Date(day).as_datetime(), None);
+        assert_eq!(Value::Blob(vec![1, 2]).as_blob(), Some(&[1u8, 2][..]));
+        assert_eq!(Value::Text("ab".to_string()).as_blob(), None);
+        assert_eq!(Value::Null.as_long(), None);
+    }
+
+    #[test]
+    fn as_i64_promotes_every_integer_width() {
+        assert_eq!(Value::Byte(7).as_i64(), Some(7));
+        assert_eq!(Value::Int(-7).as_i64(), Some(-7));
+        assert_eq!(Value::Long(1 << 40).as_i64(), Some(1 << 40));
+        assert_eq!(Value::Double(7.0).as_i64(), None);
+        assert_eq!(Value::Text("7".to_string()).as_i64(), None);
+    }
 }
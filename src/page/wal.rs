@@ -0,0 +1,115 @@
+use crate::page::err::DbResult;
+use crate::page::io::FileSystemManager;
+use crate::page::{PAGE_SIZE, is_intact};
+use std::io::SeekFrom;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+pub const WAL_FILE_NAME: &str = "wal.log";
+const RECORD_HEADER_SIZE: usize = size_of::<u32>() /* file_id */ + size_of::<u32>() /* page_id */;
+pub const RECORD_SIZE: usize = RECORD_HEADER_SIZE + PAGE_SIZE;
+// How many records the log holds before the next write checkpoints it, about a megabyte.
+pub const CHECKPOINT_RECORDS: usize = 256;
+
+pub struct LogRecord {
+    pub file_id: u32,
+    pub page_id: u32,
+    pub image: Box<[u8; PAGE_SIZE]>,
+}
+
+// Every page write is preceded by a full image of the page appended here and synced, so the
+// data files can always be brought back to the last logged state. Images carry their own
+// checksum, which is how a record torn by a crash is told apart from a complete one.
+pub struct WriteAheadLog {
+    path: String,
+    file: Option<File>,
+    // Records in the log, counted from its length when the file is first opened.
+    len: usize,
+}
+
+impl WriteAheadLog {
+    pub fn new(path: String) -> Self {
+        Self {
+            path,
+            file: None,
+            len: 0,
+        }
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len >= CHECKPOINT_RECORDS
+    }
+
+    async fn file(&mut self) -> DbResult<&mut File> {
+        if self.file.is_none() {
+            let file = OpenOptions::new()
+                .read(true)
+                .append(true)
+                .create(true)
+                .open(&self.path)
+                .await?;
+            self.len = file.metadata().await?.len() as usize / RECORD_SIZE;
+            self.file = Some(file);
+        }
+        Ok(self.file.as_mut().unwrap())
+    }
+
+    pub async fn append(
+        &mut self,
+        file_id: u32,
+        page_id: u32,
+        image: &[u8; PAGE_SIZE],
+    ) -> DbResult<()> {
+        let mut record = Vec::with_capacity(RECORD_SIZE);
+        record.extend_from_slice(&file_id.to_le_bytes());
+        record.extend_from_slice(&page_id.to_le_bytes());
+        record.extend_from_slice(image);
+
+        let file = self.file().await?;
+        file.write_all(&record).await?;
+        file.sync_data().await?;
+        self.len += 1;
+        Ok(())
+    }
+
+    pub async fn records(&mut self) -> DbResult<Vec<LogRecord>> {
+        let file = self.file().await?;
+        let mut bytes = Vec::new();
+        file.seek(SeekFrom::Start(0)).await?;
+        file.read_to_end(&mut bytes).await?;
+
+        let mut records = Vec::new();
+        for chunk in bytes.chunks_exact(RECORD_SIZE) {
+            let image: Box<[u8; PAGE_SIZE]> =
+                Box::new(chunk[RECORD_HEADER_SIZE..].try_into().unwrap());
+            if !is_intact(&image) {
+                break;
+            }
+            records.push(LogRecord {
+                file_id: u32::from_le_bytes(chunk[0..4].try_into().unwrap()),
+                page_id: u32::from_le_bytes(chunk[4..8].try_into().unwrap()),
+                image,
+            });
+        }
+        Ok(records)
+    }
+
+    // Writes every logged image back into its data file, oldest first, so the newest one wins.
+    pub async fn replay(&mut self, fs: &FileSystemManager) -> DbResult<usize> {
+        let records = self.records().await?;
+        for record in &records {
+            let mut file = fs.open_page_file(record.file_id).await?;
+            file.write_page_data(record.page_id, record.image.to_vec())
+                .await?;
+        }
+        Ok(records.len())
+    }
+
+    pub async fn truncate(&mut self) -> DbResult<()> {
+        let file = self.file().await?;
+        file.set_len(0).await?;
+        file.sync_all().await?;
+        self.len = 0;
+        Ok(())
+    }
+}
@@ -0,0 +1,156 @@
+use crate::page::decimal::Decimal;
+use crate::page::tuple::{DataType, Value};
+use std::cmp::Ordering;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AggregateFunction {
+    Count,
+    Sum,
+    Avg,
+    Min,
+    Max,
+}
+
+impl AggregateFunction {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "count" => Some(AggregateFunction::Count),
+            "sum" => Some(AggregateFunction::Sum),
+            "avg" => Some(AggregateFunction::Avg),
+            "min" => Some(AggregateFunction::Min),
+            "max" => Some(AggregateFunction::Max),
+            _ => None,
+        }
+    }
+
+    pub fn result_type(&self, input: Option<&DataType>) -> DataType {
+        match (self, input) {
+            (AggregateFunction::Count, _) => DataType::Long,
+            (AggregateFunction::Sum, Some(DataType::Float | DataType::Double)) => DataType::Double,
+            (AggregateFunction::Sum, Some(DataType::Decimal)) => DataType::Decimal,
+            (AggregateFunction::Sum, _) => DataType::Long,
+            (AggregateFunction::Avg, _) => DataType::Double,
+            (AggregateFunction::Min | AggregateFunction::Max, input) => {
+                input.cloned().unwrap_or(DataType::Null)
+            }
+        }
+    }
+
+    pub fn accepts(&self, data_type: &DataType) -> bool {
+        match self {
+            AggregateFunction::Count | AggregateFunction::Min | AggregateFunction::Max => true,
+            AggregateFunction::Sum | AggregateFunction::Avg => matches!(
+                data_type,
+                DataType::Int
+                    | DataType::Long
+                    | DataType::Float
+                    | DataType::Double
+                    | DataType::Byte
+                    | DataType::Decimal
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Accumulator {
+    function: AggregateFunction,
+    count: i64,
+    int_sum: Option<i64>,
+    decimal_sum: Option<Decimal>,
+    float_sum: f64,
+    is_float: bool,
+    is_decimal: bool,
+    extreme: Option<Value>,
+}
+
+impl Accumulator {
+    pub fn new(function: AggregateFunction) -> Self {
+        Self {
+            function,
+            count: 0,
+            int_sum: Some(0),
+            decimal_sum: Some(Decimal::from_integer(0)),
+            float_sum: 0.0,
+            is_float: false,
+            is_decimal: false,
+            extreme: None,
+        }
+    }
+
+    // `None` stands for a bare row, which only `count` without a column cares about.
+    pub fn update(&mut self, value: Option<&Value>) {
+        let value = match value {
+            Some(Value::Null) => return,
+            Some(value) => value,
+            None => {
+                self.count += 1;
+                return;
+            }
+        };
+        self.count += 1;
+
+        match self.function {
+            AggregateFunction::Count => {}
+            AggregateFunction::Sum | AggregateFunction::Avg => {
+                if let Value::Decimal(decimal) = value {
+                    self.add_decimal(decimal);
+                } else if let Some(integer) = value.as_i64() {
+                    self.add_integer(integer);
+                } else if let Some(float) = value.as_f64() {
+                    self.add_float(float);
+                }
+            }
+            AggregateFunction::Min | AggregateFunction::Max => {
+                let wanted = if self.function == AggregateFunction::Min {
+                    Ordering::Less
+                } else {
+                    Ordering::Greater
+                };
+                let replace = match &self.extreme {
+                    Some(current) => value.partial_cmp(current) == Some(wanted),
+                    None => true,
+                };
+                if replace {
+                    self.extreme = Some(value.clone());
+                }
+            }
+        }
+    }
+
+    pub fn finish(&self) -> Value {
+        match self.function {
+            AggregateFunction::Count => Value::Long(self.count),
+            AggregateFunction::Sum if self.count == 0 => Value::Null,
+            AggregateFunction::Sum if self.is_decimal => self
+                .decimal_sum
+                .map_or(Value::Double(self.float_sum), Value::Decimal),
+            AggregateFunction::Sum => match self.int_sum {
+                Some(sum) if !self.is_float => Value::Long(sum),
+                _ => Value::Double(self.float_sum),
+            },
+            AggregateFunction::Avg if self.count == 0 => Value::Null,
+            AggregateFunction::Avg => Value::Double(self.float_sum / self.count as f64),
+            AggregateFunction::Min | AggregateFunction::Max => {
+                self.extreme.clone().unwrap_or(Value::Null)
+            }
+        }
+    }
+
+    fn add_integer(&mut self, value: i64) {
+        self.int_sum = self.int_sum.and_then(|sum| sum.checked_add(value));
+        self.float_sum += value as f64;
+    }
+
+    // A decimal sum that overflows falls back to the double one, like an integer sum does.
+    fn add_decimal(&mut self, value: &Decimal) {
+        self.is_decimal = true;
+        self.decimal_sum = self.decimal_sum.and_then(|sum| sum.checked_add(value));
+        self.float_sum += value.to_f64();
+    }
+
+    fn add_float(&mut self, value: f64) {
+        self.is_float = true;
+        self.float_sum += value;
+    }
+}
@@ -1,7 +1,7 @@
-use crate::page::tuple::Value;
+use crate::page::tuple::{DataType, Value};
 use crate::query::err::TransformError;
 use crate::query::transformer::AstToQueryTransformer;
-use crate::query::{QueryExpr, TransactionOp, TransactionType};
+use crate::query::{JoinType, QueryExpr, Returning, SortDirection, TransactionOp, TransactionType};
 use std::rc::Rc;
 
 pub struct BuiltInTransactionFunction {
@@ -42,10 +42,7 @@ pub fn filter_impl(
             transformer.push_scope();
             transformer.set_row_variable(&params[0]);
 
-            let predicate = match transformer.transform_to_predicate(*body) {
-                Ok(pred) => pred,
-                Err(e) => return Err(e),
-            };
+            let predicate = transformer.transform_to_predicate(*body)?;
 
             transformer.pop_scope();
             transformer.clear_row_variable();
@@ -70,6 +67,41 @@ pub fn filter_impl(
     }
 }
 
+pub fn map_impl(
+    transformer: &mut AstToQueryTransformer,
+    mut args: Vec<QueryExpr>,
+) -> Result<QueryExpr, TransformError> {
+    let QueryExpr::Lambda { params, body } = &args[0] else {
+        return Err(TransformError::ExpectedLambda);
+    };
+    if params.len() != 1 {
+        return Err(TransformError::InvalidLambdaParams);
+    }
+    transformer.push_scope();
+    transformer.set_row_variable(&params[0]);
+
+    let row = transformer.transform_node(*body);
+
+    transformer.pop_scope();
+    transformer.clear_row_variable();
+
+    let fields = match row? {
+        QueryExpr::Instance(fields) => fields,
+        _ => return Err(TransformError::InvalidArgument("map".to_string())),
+    };
+
+    let input = args
+        .get_mut(1)
+        .ok_or_else(|| TransformError::InvalidArgument("map".to_string()))?;
+    match input {
+        QueryExpr::Transaction { operations, .. } => {
+            operations.push(TransactionOp::Map { fields });
+        }
+        _ => return Err(TransformError::InvalidArgument("map".to_string())),
+    }
+    Ok(input.clone())
+}
+
 pub fn insert_impl(
     _transformer: &mut AstToQueryTransformer,
     args: Vec<QueryExpr>,
@@ -96,9 +128,10 @@ pub fn insert_r_impl(
 ) -> Result<QueryExpr, TransformError> {
     if let QueryExpr::Reference(table_name) = &args[0] {
         let value = args[1].clone();
-        let columns = match &args[2] {
-            QueryExpr::Tuple(cols) => cols.clone(),
-            QueryExpr::Reference(name) => vec![name.clone()],
+        let returning = match &args[2] {
+            QueryExpr::Reference(name) if name == "all" => Returning::All,
+            QueryExpr::Tuple(cols) => Returning::Columns(cols.clone()),
+            QueryExpr::Reference(name) => Returning::Columns(vec![name.clone()]),
             _ => return Err(TransformError::InvalidArgument("insertR".to_string())),
         };
 
@@ -106,7 +139,7 @@ pub fn insert_r_impl(
             typ: TransactionType::Insert {
                 table_name: table_name.clone(),
                 value: Rc::new(value),
-                returning: Some(columns),
+                returning: Some(returning),
             },
             operations: vec![],
         })
@@ -119,9 +152,17 @@ pub fn project_impl(
     _transformer: &mut AstToQueryTransformer,
     mut args: Vec<QueryExpr>,
 ) -> Result<QueryExpr, TransformError> {
-    let columns = match args.get(0) {
-        Some(QueryExpr::Tuple(cols)) => cols.clone(),
-        Some(QueryExpr::Reference(name)) => vec![name.clone()],
+    let columns = match args.first() {
+        Some(QueryExpr::Tuple(cols)) => cols.iter().map(|col| (col.clone(), None)).collect(),
+        Some(QueryExpr::Reference(name)) => vec![(name.clone(), None)],
+        // `project { total = price, name = name }` renames as it projects.
+        Some(QueryExpr::Instance(fields)) => fields
+            .iter()
+            .map(|(alias, value)| match value {
+                QueryExpr::Reference(col) => Ok((col.clone(), Some(alias.clone()))),
+                _ => Err(TransformError::InvalidColumnName),
+            })
+            .collect::<Result<Vec<_>, _>>()?,
         _ => return Err(TransformError::ExpectedLambda),
     };
 
@@ -141,48 +182,444 @@ pub fn project_impl(
 
 pub fn limit_impl(
     _transformer: &mut AstToQueryTransformer,
+    args: Vec<QueryExpr>,
+) -> Result<QueryExpr, TransformError> {
+    push_row_count_op(args, "limit", |count| TransactionOp::Limit { count })
+}
+
+pub fn offset_impl(
+    _transformer: &mut AstToQueryTransformer,
+    args: Vec<QueryExpr>,
+) -> Result<QueryExpr, TransformError> {
+    push_row_count_op(args, "offset", |offset| TransactionOp::Offset { offset })
+}
+
+pub fn take_impl(
+    _transformer: &mut AstToQueryTransformer,
+    args: Vec<QueryExpr>,
+) -> Result<QueryExpr, TransformError> {
+    push_row_count_op(args, "take", |count| TransactionOp::Limit { count })
+}
+
+pub fn drop_impl(
+    _transformer: &mut AstToQueryTransformer,
+    args: Vec<QueryExpr>,
+) -> Result<QueryExpr, TransformError> {
+    push_row_count_op(args, "drop", |offset| TransactionOp::Offset { offset })
+}
+
+// `name` is the built-in as the user spelled it, so errors point at `take` rather than `limit`.
+fn push_row_count_op(
     mut args: Vec<QueryExpr>,
+    name: &str,
+    op: fn(usize) -> TransactionOp,
 ) -> Result<QueryExpr, TransformError> {
-    let limit_value = match args.get(0) {
-        Some(QueryExpr::Literal(Value::Int(limit))) => *limit,
-        _ => return Err(TransformError::ExpectedNumber),
-    };
+    let count = row_count(args.first(), name)?;
 
     let input = args
         .get_mut(1)
-        .ok_or_else(|| TransformError::InvalidArgument("limit".to_string()))?;
+        .ok_or_else(|| TransformError::InvalidArgument(name.to_string()))?;
+
+    match input {
+        QueryExpr::Transaction { operations, .. } => operations.push(op(count)),
+        _ => return Err(TransformError::InvalidArgument(name.to_string())),
+    }
+
+    Ok(input.clone())
+}
+
+fn row_count(arg: Option<&QueryExpr>, name: &str) -> Result<usize, TransformError> {
+    match arg {
+        Some(QueryExpr::Literal(Value::Int(count))) => {
+            usize::try_from(*count).map_err(|_| TransformError::NegativeLimit {
+                name: name.to_string(),
+                count: *count,
+            })
+        }
+        _ => Err(TransformError::ExpectedNumber),
+    }
+}
+
+pub fn sort_impl(
+    transformer: &mut AstToQueryTransformer,
+    args: Vec<QueryExpr>,
+) -> Result<QueryExpr, TransformError> {
+    sort_with_direction(transformer, args, SortDirection::Ascending)
+}
+
+pub fn sort_desc_impl(
+    transformer: &mut AstToQueryTransformer,
+    args: Vec<QueryExpr>,
+) -> Result<QueryExpr, TransformError> {
+    sort_with_direction(transformer, args, SortDirection::Descending)
+}
+
+fn sort_with_direction(
+    transformer: &mut AstToQueryTransformer,
+    mut args: Vec<QueryExpr>,
+    direction: SortDirection,
+) -> Result<QueryExpr, TransformError> {
+    let columns = key_columns(transformer, args.first(), "sort")?;
+
+    let input = args
+        .get_mut(1)
+        .ok_or_else(|| TransformError::InvalidArgument("sort".to_string()))?;
 
     match input {
         QueryExpr::Transaction { operations, .. } => {
-            operations.push(TransactionOp::Limit { count: limit_value });
+            operations.push(TransactionOp::Sort {
+                keys: columns
+                    .into_iter()
+                    .map(|column| (column, direction.clone()))
+                    .collect(),
+            });
         }
-        _ => return Err(TransformError::InvalidArgument("limit".to_string())),
+        _ => return Err(TransformError::InvalidArgument("sort".to_string())),
     }
 
     Ok(input.clone())
 }
 
-pub fn offset_impl(
+pub fn distinct_impl(
+    _transformer: &mut AstToQueryTransformer,
+    mut args: Vec<QueryExpr>,
+) -> Result<QueryExpr, TransformError> {
+    let input = args
+        .get_mut(0)
+        .ok_or_else(|| TransformError::InvalidArgument("distinct".to_string()))?;
+
+    match input {
+        QueryExpr::Transaction { operations, .. } => {
+            operations.push(TransactionOp::Distinct);
+        }
+        _ => return Err(TransformError::InvalidArgument("distinct".to_string())),
+    }
+
+    Ok(input.clone())
+}
+
+fn key_columns(
+    transformer: &mut AstToQueryTransformer,
+    arg: Option<&QueryExpr>,
+    function_name: &str,
+) -> Result<Vec<String>, TransformError> {
+    match arg {
+        Some(QueryExpr::Tuple(cols)) => Ok(cols.clone()),
+        Some(QueryExpr::Reference(name)) => Ok(vec![name.clone()]),
+        Some(QueryExpr::Lambda { params, body }) => {
+            if params.len() != 1 {
+                return Err(TransformError::InvalidLambdaParams);
+            }
+            transformer.push_scope();
+            transformer.set_row_variable(&params[0]);
+
+            let key = transformer.transform_node(*body);
+
+            transformer.pop_scope();
+            transformer.clear_row_variable();
+
+            match key? {
+                QueryExpr::Column(name) => Ok(vec![name]),
+                _ => Err(TransformError::InvalidArgument(function_name.to_string())),
+            }
+        }
+        _ => Err(TransformError::InvalidArgument(function_name.to_string())),
+    }
+}
+
+pub fn count_impl(
     _transformer: &mut AstToQueryTransformer,
     mut args: Vec<QueryExpr>,
 ) -> Result<QueryExpr, TransformError> {
-    let offset_value = match args.get(0) {
-        Some(QueryExpr::Literal(Value::Int(offset))) => *offset,
-        _ => return Err(TransformError::ExpectedNumber),
+    push_aggregate(args.get_mut(0), "count", None)
+}
+
+pub fn sum_impl(
+    transformer: &mut AstToQueryTransformer,
+    args: Vec<QueryExpr>,
+) -> Result<QueryExpr, TransformError> {
+    column_aggregate(transformer, args, "sum")
+}
+
+pub fn avg_impl(
+    transformer: &mut AstToQueryTransformer,
+    args: Vec<QueryExpr>,
+) -> Result<QueryExpr, TransformError> {
+    column_aggregate(transformer, args, "avg")
+}
+
+pub fn min_impl(
+    transformer: &mut AstToQueryTransformer,
+    args: Vec<QueryExpr>,
+) -> Result<QueryExpr, TransformError> {
+    column_aggregate(transformer, args, "min")
+}
+
+pub fn max_impl(
+    transformer: &mut AstToQueryTransformer,
+    args: Vec<QueryExpr>,
+) -> Result<QueryExpr, TransformError> {
+    column_aggregate(transformer, args, "max")
+}
+
+fn column_aggregate(
+    transformer: &mut AstToQueryTransformer,
+    mut args: Vec<QueryExpr>,
+    func: &str,
+) -> Result<QueryExpr, TransformError> {
+    let mut columns = key_columns(transformer, args.first(), func)?;
+    if columns.len() != 1 {
+        return Err(TransformError::InvalidArgument(func.to_string()));
+    }
+    push_aggregate(args.get_mut(1), func, columns.pop())
+}
+
+fn push_aggregate(
+    input: Option<&mut QueryExpr>,
+    func: &str,
+    column: Option<String>,
+) -> Result<QueryExpr, TransformError> {
+    let input = input.ok_or_else(|| TransformError::InvalidArgument(func.to_string()))?;
+    let alias = match &column {
+        Some(column) => format!("{}_{}", func, column),
+        None => func.to_string(),
+    };
+
+    match input {
+        QueryExpr::Transaction { operations, .. } => {
+            operations.push(TransactionOp::Aggregate {
+                func: func.to_string(),
+                column,
+                alias,
+            });
+        }
+        _ => return Err(TransformError::InvalidArgument(func.to_string())),
+    }
+
+    Ok(input.clone())
+}
+
+pub fn group_by_impl(
+    transformer: &mut AstToQueryTransformer,
+    mut args: Vec<QueryExpr>,
+) -> Result<QueryExpr, TransformError> {
+    let keys = key_columns(transformer, args.first(), "group_by")?;
+    let aggregates = match args.get(1) {
+        Some(QueryExpr::Lambda { params, body }) => {
+            if params.len() != 1 {
+                return Err(TransformError::InvalidLambdaParams);
+            }
+            transformer.push_scope();
+            transformer.set_row_variable(&params[0]);
+
+            let aggregates = transformer.transform_to_aggregates(*body);
+
+            transformer.pop_scope();
+            transformer.clear_row_variable();
+            aggregates?
+        }
+        _ => return Err(TransformError::ExpectedLambda),
     };
 
     let input = args
-        .get_mut(1)
-        .ok_or_else(|| TransformError::InvalidArgument("offset".to_string()))?;
+        .get_mut(2)
+        .ok_or_else(|| TransformError::InvalidArgument("group_by".to_string()))?;
 
     match input {
         QueryExpr::Transaction { operations, .. } => {
-            operations.push(TransactionOp::Offset {
-                offset: offset_value,
-            });
+            operations.push(TransactionOp::GroupBy { keys, aggregates });
         }
-        _ => return Err(TransformError::InvalidArgument("offset".to_string())),
+        _ => return Err(TransformError::InvalidArgument("group_by".to_string())),
     }
 
     Ok(input.clone())
 }
+
+pub fn join_impl(
+    transformer: &mut AstToQueryTransformer,
+    args: Vec<QueryExpr>,
+) -> Result<QueryExpr, TransformError> {
+    join_with_type(transformer, args, JoinType::Inner)
+}
+
+pub fn left_join_impl(
+    transformer: &mut AstToQueryTransformer,
+    args: Vec<QueryExpr>,
+) -> Result<QueryExpr, TransformError> {
+    join_with_type(transformer, args, JoinType::Left)
+}
+
+pub fn right_join_impl(
+    transformer: &mut AstToQueryTransformer,
+    args: Vec<QueryExpr>,
+) -> Result<QueryExpr, TransformError> {
+    join_with_type(transformer, args, JoinType::Right)
+}
+
+pub fn full_join_impl(
+    transformer: &mut AstToQueryTransformer,
+    args: Vec<QueryExpr>,
+) -> Result<QueryExpr, TransformError> {
+    join_with_type(transformer, args, JoinType::Full)
+}
+
+// Takes the right side first so `left |> join right keys` reads naturally.
+fn join_with_type(
+    transformer: &mut AstToQueryTransformer,
+    mut args: Vec<QueryExpr>,
+    join_type: JoinType,
+) -> Result<QueryExpr, TransformError> {
+    let (left_key, right_key) = match &args[1] {
+        QueryExpr::Tuple(cols) if cols.len() == 2 => (cols[0].clone(), cols[1].clone()),
+        QueryExpr::Reference(name) => (name.clone(), name.clone()),
+        QueryExpr::Lambda { params, body } => transformer.transform_to_join_keys(params, *body)?,
+        _ => return Err(TransformError::InvalidArgument("join".to_string())),
+    };
+
+    let left = args.pop().expect("arity is checked by the transformer");
+    let right = args.swap_remove(0);
+    for side in [&left, &right] {
+        if !matches!(side, QueryExpr::Transaction { .. }) {
+            return Err(TransformError::InvalidArgument("join".to_string()));
+        }
+    }
+
+    Ok(QueryExpr::Transaction {
+        typ: TransactionType::Join {
+            left: Rc::new(left),
+            right: Rc::new(right),
+            left_key,
+            right_key,
+            join_type,
+        },
+        operations: vec![],
+    })
+}
+
+pub fn update_impl(
+    transformer: &mut AstToQueryTransformer,
+    mut args: Vec<QueryExpr>,
+) -> Result<QueryExpr, TransformError> {
+    let assignments = match &args[0] {
+        QueryExpr::Instance(fields) => fields.clone(),
+        QueryExpr::Lambda { params, body } => {
+            if params.len() != 1 {
+                return Err(TransformError::InvalidLambdaParams);
+            }
+            transformer.push_scope();
+            transformer.set_row_variable(&params[0]);
+
+            let row = transformer.transform_node(*body);
+
+            transformer.pop_scope();
+            transformer.clear_row_variable();
+
+            match row? {
+                QueryExpr::Instance(fields) => fields,
+                _ => return Err(TransformError::InvalidArgument("update".to_string())),
+            }
+        }
+        _ => return Err(TransformError::InvalidArgument("update".to_string())),
+    };
+
+    match args.swap_remove(1) {
+        QueryExpr::Transaction {
+            typ: TransactionType::Scan { table_name },
+            operations,
+        } => Ok(QueryExpr::Transaction {
+            typ: TransactionType::Update {
+                table_name,
+                assignments,
+            },
+            operations,
+        }),
+        _ => Err(TransformError::InvalidArgument("update".to_string())),
+    }
+}
+
+// `create_table products { id = Int, name = Text }`: the fields name the columns, in the
+// order they're declared, and the values are type names rather than expressions.
+pub fn create_table_impl(
+    _transformer: &mut AstToQueryTransformer,
+    args: Vec<QueryExpr>,
+) -> Result<QueryExpr, TransformError> {
+    let table_name = match &args[0] {
+        QueryExpr::Literal(Value::Text(name)) | QueryExpr::Reference(name) => name.clone(),
+        _ => return Err(TransformError::InvalidArgument("create_table".to_string())),
+    };
+    let QueryExpr::Instance(fields) = &args[1] else {
+        return Err(TransformError::InvalidArgument("create_table".to_string()));
+    };
+
+    let mut columns: Vec<(String, DataType)> = Vec::with_capacity(fields.len());
+    for (name, typ) in fields {
+        let QueryExpr::Reference(type_name) = typ else {
+            return Err(TransformError::InvalidArgument("create_table".to_string()));
+        };
+        let data_type = DataType::from_name(type_name)
+            .ok_or_else(|| TransformError::UnknownType(type_name.clone()))?;
+        if columns.iter().any(|(existing, _)| existing == name) {
+            return Err(TransformError::DuplicateColumn(name.clone()));
+        }
+        columns.push((name.clone(), data_type));
+    }
+
+    Ok(QueryExpr::Transaction {
+        typ: TransactionType::CreateTable {
+            table_name,
+            columns,
+        },
+        operations: vec![],
+    })
+}
+
+// `create_index users age`: later equality filters on the column are answered from the index
+// instead of scanning the table.
+pub fn create_index_impl(
+    _transformer: &mut AstToQueryTransformer,
+    args: Vec<QueryExpr>,
+) -> Result<QueryExpr, TransformError> {
+    let name = |arg: &QueryExpr| match arg {
+        QueryExpr::Literal(Value::Text(name)) | QueryExpr::Reference(name) => Ok(name.clone()),
+        _ => Err(TransformError::InvalidArgument("create_index".to_string())),
+    };
+
+    Ok(QueryExpr::Transaction {
+        typ: TransactionType::CreateIndex {
+            table_name: name(&args[0])?,
+            column: name(&args[1])?,
+        },
+        operations: vec![],
+    })
+}
+
+pub fn date_impl(
+    _transformer: &mut AstToQueryTransformer,
+    args: Vec<QueryExpr>,
+) -> Result<QueryExpr, TransformError> {
+    typed_literal(&args[0], DataType::Date, "date")
+}
+
+pub fn datetime_impl(
+    _transformer: &mut AstToQueryTransformer,
+    args: Vec<QueryExpr>,
+) -> Result<QueryExpr, TransformError> {
+    typed_literal(&args[0], DataType::DateTime, "datetime")
+}
+
+// Reads the text the same way a CSV field of that type is read, so both accept the same formats.
+fn typed_literal(
+    arg: &QueryExpr,
+    data_type: DataType,
+    kind: &str,
+) -> Result<QueryExpr, TransformError> {
+    let QueryExpr::Literal(Value::Text(text)) = arg else {
+        return Err(TransformError::InvalidArgument(kind.to_string()));
+    };
+    Value::parse_as(text, &data_type)
+        .map(QueryExpr::Literal)
+        .map_err(|_| TransformError::InvalidLiteral {
+            kind: kind.to_string(),
+            text: text.clone(),
+        })
+}
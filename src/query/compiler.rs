@@ -1,40 +1,87 @@
-use crate::page::tuple::{Tuple, Value};
+use crate::page::tuple::{DataType, Value};
+use crate::query::aggregate::AggregateFunction;
 use crate::query::err::{QueryError, QueryResult};
-use crate::query::op::TableOp;
+use crate::query::eval::{CompiledPredicate, ScalarExpr, apply_arithmetic};
+use crate::query::op::{AggregateCall, TableOp};
+use crate::query::schema::{Schema, SchemaColumn};
 use crate::query::{
-    PredicateExpr, QueryExpr, SymbolInfo, Transaction, TransactionOp, TransactionType,
+    BinaryOperator, ComparisonOperator, JoinType, PredicateExpr, PreparedStatement, QueryExpr,
+    Returning, SymbolInfo, Transaction, TransactionOp, TransactionType,
 };
-use crate::table::TableCatalog;
+use crate::table::{SharedCatalog, TableCatalog};
 use std::collections::HashMap;
 use std::rc::Rc;
 use std::sync::Arc;
+use tokio::sync::OwnedRwLockReadGuard;
 
 #[derive(Debug)]
 pub enum TransactionValue {
-    Row(Vec<(String, Value)>),
+    Row(Vec<(String, ScalarExpr)>),
     Literal(Value),
+    Parameter(usize),
 }
 
 pub struct PlanCompiler {
-    table_catalog: Arc<TableCatalog>,
+    table_catalog: SharedCatalog,
+    // Held for as long as one query is being compiled, so it sees a single version of the
+    // catalog throughout.
+    catalog_guard: Option<OwnedRwLockReadGuard<TableCatalog>>,
     symbol_table_stack: Vec<HashMap<String, SymbolInfo>>,
+    // The type each parameter of the query being compiled is used as, by position.
+    parameters: Vec<Option<DataType>>,
 }
 
 impl PlanCompiler {
-    pub fn new(table_catalog: Arc<TableCatalog>) -> Self {
+    pub fn new(table_catalog: SharedCatalog) -> Self {
         Self {
             table_catalog,
+            catalog_guard: None,
             symbol_table_stack: vec![HashMap::new()],
+            parameters: vec![],
         }
     }
 
-    pub fn compile(&mut self, expr: &QueryExpr) -> QueryResult<Transaction> {
+    pub async fn compile(&mut self, expr: &QueryExpr) -> QueryResult<Transaction> {
+        self.compile_with_columns(expr)
+            .await
+            .map(|(transaction, _)| transaction)
+    }
+
+    pub async fn compile_with_columns(
+        &mut self,
+        expr: &QueryExpr,
+    ) -> QueryResult<(Transaction, Vec<(String, DataType)>)> {
+        let prepared = self.prepare(expr).await?;
+        if !prepared.parameters.is_empty() {
+            return Err(QueryError::UnboundParameters);
+        }
+        Ok((prepared.transaction, prepared.columns))
+    }
+
+    pub async fn prepare(&mut self, expr: &QueryExpr) -> QueryResult<PreparedStatement> {
+        self.parameters.clear();
+        self.catalog_guard = Some(Arc::clone(&self.table_catalog).read_owned().await);
+        let compiled = self.compile_with_schema(expr);
+        self.catalog_guard = None;
+        let (transaction, schema) = compiled?;
+        Ok(PreparedStatement {
+            transaction,
+            parameters: std::mem::take(&mut self.parameters),
+            columns: schema
+                .columns
+                .into_iter()
+                .map(|col| (col.name, col.data_type))
+                .collect(),
+        })
+    }
+
+    fn compile_with_schema(&mut self, expr: &QueryExpr) -> QueryResult<(Transaction, Schema)> {
         match expr {
             QueryExpr::Binding { name, value, body } => {
                 self.push_scope();
 
                 self.add_symbol(name.clone(), Rc::new((**value).clone()));
-                let result = self.compile(body)?;
+                let result = self.compile_with_schema(body)?;
                 self.pop_scope();
 
                 Ok(result)
@@ -42,68 +89,273 @@ impl PlanCompiler {
             QueryExpr::Reference(name) => {
                 if let Some(info) = self.lookup_symbol(name) {
                     let info = info.clone();
-                    self.compile(&info)
+                    self.compile_with_schema(&info)
                 } else {
                     Err(QueryError::SymbolNotFound(name.clone()))
                 }
             }
+            QueryExpr::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => {
+                if self.evaluate_condition(cond)? {
+                    self.compile_with_schema(then_branch)
+                } else {
+                    self.compile_with_schema(else_branch)
+                }
+            }
+            // Every statement is compiled before any of them runs, so they all see the tables
+            // as they were when the block started. Using a table that an earlier statement
+            // creates is rejected as such instead of failing as if the table didn't exist.
+            QueryExpr::Block(statements) => {
+                let mut steps = vec![];
+                let mut schema = None;
+                for statement in statements {
+                    let (step, step_schema) =
+                        self.compile_with_schema(statement)
+                            .map_err(|err| match err {
+                                QueryError::TableNotFound(table)
+                                    if creates_table(&steps, &table) =>
+                                {
+                                    QueryError::TableCreatedInBlock(table)
+                                }
+                                err => err,
+                            })?;
+                    steps.push(step);
+                    schema = Some(step_schema);
+                }
+                let schema = schema.ok_or(QueryError::NotATransaction)?;
+                Ok((Transaction::Block(steps), schema))
+            }
             QueryExpr::Transaction { operations, typ } => match &typ {
                 TransactionType::Scan { table_name } => {
-                    let ops = self.build_ops(table_name, operations)?;
-                    Ok(Transaction::Select {
-                        table: table_name.clone(),
-                        ops,
-                    })
+                    let mut schema = self.table_schema(table_name)?;
+                    let mut ops = self.build_ops(&mut schema, operations)?;
+                    if let Some((column_id, value)) = self.index_lookup(table_name, &ops) {
+                        return Ok((
+                            Transaction::IndexScan {
+                                table: table_name.clone(),
+                                column_id,
+                                value,
+                                ops,
+                            },
+                            schema,
+                        ));
+                    }
+                    let pushed = ops
+                        .iter()
+                        .take_while(|op| {
+                            matches!(op, TableOp::Filter { .. } | TableOp::PredicativeFilter(_))
+                        })
+                        .count();
+                    let filter = ops.drain(..pushed).collect();
+                    let limit = match ops.first() {
+                        Some(TableOp::Limit(count)) => Some(*count),
+                        _ => None,
+                    };
+                    if limit.is_some() {
+                        ops.remove(0);
+                    }
+                    Ok((
+                        Transaction::Select {
+                            table: table_name.clone(),
+                            filter,
+                            limit,
+                            ops,
+                        },
+                        schema,
+                    ))
                 }
                 TransactionType::Insert {
                     table_name,
                     value,
                     returning,
                 } => {
-                    let ops = self.build_ops(table_name, operations)?;
-                    let value = self.compile_expr(value)?;
+                    let table_schema = self.table_schema(table_name)?;
                     let returning_indices = returning
                         .as_ref()
-                        .map(|returning_columns| {
-                            returning_columns
+                        .map(|returning| match returning {
+                            Returning::All => Ok((0..table_schema.columns.len()).collect()),
+                            Returning::Columns(columns) => columns
                                 .iter()
-                                .map(|col| self.resolve_column_index(table_name, col))
-                                .collect::<QueryResult<Vec<usize>>>()
+                                .map(|col| table_schema.resolve_index(col))
+                                .collect::<QueryResult<Vec<usize>>>(),
                         })
                         .transpose()?;
+                    // Without `returning`, the insert yields how many rows it wrote.
+                    let mut schema = match &returning_indices {
+                        Some(indices) => table_schema.project(indices),
+                        None => Schema {
+                            source: table_name.clone(),
+                            columns: vec![SchemaColumn {
+                                name: "inserted".to_string(),
+                                data_type: DataType::Long,
+                            }],
+                            projected_out: vec![],
+                        },
+                    };
+                    let ops = self.build_ops(&mut schema, operations)?;
+                    let values = match &**value {
+                        QueryExpr::List(items) => items.iter().collect(),
+                        value => vec![value],
+                    };
 
-                    match value {
-                        TransactionValue::Row(mut values) => {
-                            let indexed_values = values
-                                .drain(..)
-                                .map(|(name, value)| {
-                                    self.resolve_column_index(table_name, &name)
-                                        .map(|index| (index as u32, value))
-                                })
-                                .collect::<QueryResult<Vec<_>>>()?;
-
-                            Ok(Transaction::Insert {
-                                table: table_name.clone(),
-                                values: indexed_values,
-                                ops,
-                                returning: returning_indices,
+                    let mut rows: Vec<Vec<(u32, ScalarExpr)>> = vec![];
+                    for (row, value) in values.into_iter().enumerate() {
+                        let TransactionValue::Row(values) = self.compile_expr(value)? else {
+                            return Err(QueryError::ExpectedRow);
+                        };
+                        let indexed_values = values
+                            .into_iter()
+                            .map(|(name, value)| {
+                                let index = self.resolve_column_index(table_name, &name)?;
+                                self.expect_parameter(
+                                    &value,
+                                    &table_schema.columns[index].data_type,
+                                );
+                                Ok((index as u32, value))
                             })
+                            .collect::<QueryResult<Vec<_>>>()?;
+                        if let Some(first) = rows.first()
+                            && !sets_same_columns(first, &indexed_values)
+                        {
+                            return Err(QueryError::MismatchedInsertRow { row: row + 1 });
                         }
-                        _ => Err(QueryError::ExpectedRow),
+                        rows.push(indexed_values);
+                    }
+
+                    Ok((
+                        Transaction::Insert {
+                            table: table_name.clone(),
+                            rows,
+                            ops,
+                            returning: returning_indices,
+                        },
+                        schema,
+                    ))
+                }
+                TransactionType::Update {
+                    table_name,
+                    assignments,
+                } => {
+                    let mut schema = self.table_schema(table_name)?;
+                    let ops = self.build_ops(&mut schema, operations)?;
+                    if let Some(op) = ops.iter().find(|op| {
+                        !matches!(op, TableOp::Filter { .. } | TableOp::PredicativeFilter(_))
+                    }) {
+                        return Err(QueryError::UnsupportedUpdateOp(format!("{:?}", op)));
                     }
+
+                    let assignments = assignments
+                        .iter()
+                        .map(|(column, value)| {
+                            let index = self.resolve_column_index(table_name, column)?;
+                            let value = self.compile_scalar(&schema, value)?;
+                            self.expect_parameter(&value, &schema.columns[index].data_type);
+                            Ok((index as u32, value))
+                        })
+                        .collect::<QueryResult<Vec<_>>>()?;
+
+                    let count_schema = Schema {
+                        source: table_name.clone(),
+                        columns: vec![SchemaColumn {
+                            name: "updated".to_string(),
+                            data_type: DataType::Long,
+                        }],
+                        projected_out: vec![],
+                    };
+                    Ok((
+                        Transaction::Update {
+                            table: table_name.clone(),
+                            assignments,
+                            ops,
+                        },
+                        count_schema,
+                    ))
+                }
+                TransactionType::Join {
+                    left,
+                    right,
+                    left_key,
+                    right_key,
+                    join_type,
+                } => {
+                    if !matches!(join_type, JoinType::Inner) {
+                        return Err(QueryError::NotImplemented(format!("{:?} joins", join_type)));
+                    }
+
+                    let (left, left_schema) = self.compile_with_schema(left)?;
+                    let (right, right_schema) = self.compile_with_schema(right)?;
+                    let left_key = left_schema.resolve_index(left_key)?;
+                    let right_key = right_schema.resolve_index(right_key)?;
+
+                    let mut schema = Schema::join(&left_schema, &right_schema);
+                    let ops = self.build_ops(&mut schema, operations)?;
+                    Ok((
+                        Transaction::Join {
+                            left: Box::new(left),
+                            right: Box::new(right),
+                            left_key,
+                            right_key,
+                            ops,
+                        },
+                        schema,
+                    ))
+                }
+                TransactionType::CreateTable {
+                    table_name,
+                    columns,
+                } => {
+                    if !operations.is_empty() {
+                        return Err(QueryError::NotImplemented(
+                            "operations after create_table".to_string(),
+                        ));
+                    }
+                    Ok((
+                        Transaction::CreateTable {
+                            table: table_name.clone(),
+                            columns: columns.clone(),
+                        },
+                        Schema {
+                            source: table_name.clone(),
+                            columns: vec![],
+                            projected_out: vec![],
+                        },
+                    ))
+                }
+                TransactionType::CreateIndex { table_name, column } => {
+                    if !operations.is_empty() {
+                        return Err(QueryError::NotImplemented(
+                            "operations after create_index".to_string(),
+                        ));
+                    }
+                    self.resolve_column_index(table_name, column)?;
+                    Ok((
+                        Transaction::CreateIndex {
+                            table: table_name.clone(),
+                            column: column.clone(),
+                        },
+                        Schema {
+                            source: table_name.clone(),
+                            columns: vec![],
+                            projected_out: vec![],
+                        },
+                    ))
                 }
             },
             _ => Err(QueryError::NotATransaction),
         }
     }
 
-    pub fn compile_expr(&mut self, expr: &QueryExpr) -> QueryResult<TransactionValue> {
+    fn compile_expr(&mut self, expr: &QueryExpr) -> QueryResult<TransactionValue> {
         match expr {
             QueryExpr::Instance(values) => {
                 let mut compiled_values = vec![];
                 for (name, value) in values {
                     let compiled_value = match self.compile_expr(value)? {
-                        TransactionValue::Literal(value) => Ok(value),
+                        TransactionValue::Literal(value) => Ok(ScalarExpr::Literal(value)),
+                        TransactionValue::Parameter(index) => Ok(ScalarExpr::Parameter(index)),
                         TransactionValue::Row(_) => {
                             Err(QueryError::RowCannotBeEmbeddedIntoAnotherRow)
                         }
@@ -113,53 +365,265 @@ impl PlanCompiler {
                 Ok(TransactionValue::Row(compiled_values))
             }
             QueryExpr::Literal(value) => Ok(TransactionValue::Literal(value.clone())),
-            u => todo!("Unimplemented expression: {:?}", u),
+            QueryExpr::Parameter(index) => {
+                self.declare_parameter(*index);
+                Ok(TransactionValue::Parameter(*index))
+            }
+            // Constants the optimizer left alone, so working them out again surfaces its error.
+            QueryExpr::BinaryOp { left, op, right } => {
+                match (self.compile_expr(left)?, self.compile_expr(right)?) {
+                    (TransactionValue::Literal(left), TransactionValue::Literal(right)) => {
+                        apply_arithmetic(left, op, right).map(TransactionValue::Literal)
+                    }
+                    _ => Err(QueryError::NotImplemented(
+                        "operators on parameters outside filters and maps".to_string(),
+                    )),
+                }
+            }
+            QueryExpr::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => {
+                if self.evaluate_condition(cond)? {
+                    self.compile_expr(then_branch)
+                } else {
+                    self.compile_expr(else_branch)
+                }
+            }
+            other => Err(QueryError::UnsupportedValueExpression(format!(
+                "{:?}",
+                other
+            ))),
         }
     }
 
-    pub fn compile_transaction_ops(
+    fn compile_transaction_ops(
         &mut self,
-        table: &str,
+        schema: &mut Schema,
         transaction: &TransactionOp,
     ) -> QueryResult<Vec<TableOp>> {
         match transaction {
             TransactionOp::Filter { predicate } => match &**predicate {
                 PredicateExpr::Comparison { left, op, right } => {
-                    if let (QueryExpr::Column(col_name), QueryExpr::Literal(value)) =
-                        (&left, &right)
-                    {
-                        let col_idx = self.resolve_column_index(table, col_name)?;
-
-                        return Ok(vec![TableOp::Filter {
-                            column_index: col_idx,
-                            operator: op.clone(),
-                            value: value.clone(),
-                        }]);
+                    match (left, right) {
+                        (QueryExpr::Column(col_name), QueryExpr::Literal(value)) => {
+                            let col_idx = schema.resolve_index(col_name)?;
+
+                            return Ok(vec![TableOp::Filter {
+                                column_index: col_idx,
+                                operator: op.clone(),
+                                value: value.clone(),
+                            }]);
+                        }
+                        (QueryExpr::Literal(value), QueryExpr::Column(col_name)) => {
+                            if let Some(operator) = op.flipped() {
+                                let col_idx = schema.resolve_index(col_name)?;
+
+                                return Ok(vec![TableOp::Filter {
+                                    column_index: col_idx,
+                                    operator,
+                                    value: value.clone(),
+                                }]);
+                            }
+                        }
+                        (QueryExpr::Column(left_name), QueryExpr::Column(right_name)) => {
+                            let left = ScalarExpr::Column(schema.resolve_index(left_name)?);
+                            let right = ScalarExpr::Column(schema.resolve_index(right_name)?);
+                            return Ok(vec![TableOp::PredicativeFilter(
+                                CompiledPredicate::Comparison {
+                                    left,
+                                    op: op.clone(),
+                                    right,
+                                },
+                            )]);
+                        }
+                        _ => {}
                     }
 
-                    let filter_fn = self.create_predicate_function(&*predicate)?;
-                    Ok(vec![TableOp::PredicativeFilter(filter_fn)])
+                    let predicate = self.compile_predicate(schema, predicate)?;
+                    Ok(vec![TableOp::PredicativeFilter(predicate)])
                 }
                 _ => {
-                    let filter_fn = self.create_predicate_function(&*predicate)?;
-                    Ok(vec![TableOp::PredicativeFilter(filter_fn)])
+                    let predicate = self.compile_predicate(schema, predicate)?;
+                    Ok(vec![TableOp::PredicativeFilter(predicate)])
                 }
             },
             TransactionOp::Limit { count } => Ok(vec![TableOp::Limit(*count)]),
             TransactionOp::Offset { offset } => Ok(vec![TableOp::Offset(*offset)]),
             TransactionOp::Project { columns } => {
                 let mut indices = vec![];
-                for column in columns {
-                    let index = self.resolve_column_index(table, column)?;
+                let mut output = vec![];
+                for (column, alias) in columns {
+                    let (index, info) = schema.resolve(column)?;
+                    let name = alias.as_ref().unwrap_or(column);
+                    if output.iter().any(|col: &SchemaColumn| col.name == *name) {
+                        return Err(QueryError::DuplicateColumn(name.clone()));
+                    }
                     indices.push(index);
+                    output.push(SchemaColumn {
+                        name: name.clone(),
+                        data_type: info.data_type.clone(),
+                    });
                 }
+                schema.replace_columns(output);
                 Ok(vec![TableOp::Project(indices)])
             }
+            TransactionOp::Sort { keys } => {
+                let mut resolved = vec![];
+                for (column, direction) in keys {
+                    let index = schema.resolve_index(column)?;
+                    resolved.push((index, direction.clone()));
+                }
+                Ok(vec![TableOp::Sort(resolved)])
+            }
+            TransactionOp::Distinct => Ok(vec![TableOp::Distinct]),
+            TransactionOp::Map { fields } => {
+                let mut exprs = vec![];
+                let mut output = vec![];
+                for (name, value) in fields {
+                    if output.iter().any(|col: &SchemaColumn| col.name == *name) {
+                        return Err(QueryError::DuplicateColumn(name.clone()));
+                    }
+                    let expr = self.compile_scalar(schema, value)?;
+                    output.push(SchemaColumn {
+                        name: name.clone(),
+                        data_type: scalar_type(schema, &expr),
+                    });
+                    exprs.push(expr);
+                }
+                schema.replace_columns(output);
+                Ok(vec![TableOp::Map(exprs)])
+            }
+            TransactionOp::Aggregate {
+                func,
+                column,
+                alias,
+            } => {
+                let (aggregate, output) =
+                    self.compile_aggregate(schema, func, column.as_deref(), alias)?;
+                schema.columns = vec![output];
+                Ok(vec![TableOp::Aggregate(aggregate)])
+            }
+            TransactionOp::GroupBy { keys, aggregates } => {
+                let keys = keys
+                    .iter()
+                    .map(|key| schema.resolve_index(key))
+                    .collect::<QueryResult<Vec<_>>>()?;
+                let mut output = schema.project(&keys);
+                let mut compiled = vec![];
+                for aggregate in aggregates {
+                    let (aggregate, column) = self.compile_aggregate(
+                        schema,
+                        &aggregate.func,
+                        aggregate.column.as_deref(),
+                        &aggregate.alias,
+                    )?;
+                    compiled.push(aggregate);
+                    output.columns.push(column);
+                }
+                *schema = output;
+                Ok(vec![TableOp::GroupBy {
+                    keys,
+                    aggregates: compiled,
+                }])
+            }
+        }
+    }
+
+    fn evaluate_condition(&self, cond: &QueryExpr) -> QueryResult<bool> {
+        match cond {
+            QueryExpr::Literal(Value::Boolean(value)) => Ok(*value),
+            QueryExpr::Reference(name) => {
+                let info = self
+                    .lookup_symbol(name)
+                    .ok_or_else(|| QueryError::SymbolNotFound(name.clone()))?;
+                self.evaluate_condition(info)
+            }
+            _ => Err(QueryError::NonConstantCondition),
         }
     }
 
+    fn compile_aggregate(
+        &self,
+        schema: &Schema,
+        func: &str,
+        column: Option<&str>,
+        alias: &str,
+    ) -> QueryResult<(AggregateCall, SchemaColumn)> {
+        let function = AggregateFunction::from_name(func)
+            .ok_or_else(|| QueryError::UnknownAggregate(func.to_string()))?;
+        let (column_index, input_type) = match column {
+            Some(column) => {
+                let (index, info) = schema.resolve(column)?;
+                if !function.accepts(&info.data_type) {
+                    return Err(QueryError::InvalidAggregateColumn {
+                        func: func.to_string(),
+                        column: column.to_string(),
+                        data_type: info.data_type.clone(),
+                    });
+                }
+                (Some(index), Some(&info.data_type))
+            }
+            None => (None, None),
+        };
+        let output = SchemaColumn {
+            name: alias.to_string(),
+            data_type: function.result_type(input_type),
+        };
+        Ok((
+            AggregateCall {
+                function,
+                column_index,
+                alias: alias.to_string(),
+            },
+            output,
+        ))
+    }
+
+    fn catalog(&self) -> &TableCatalog {
+        self.catalog_guard
+            .as_deref()
+            .expect("the catalog is only looked at while a query is being compiled")
+    }
+
+    fn table_schema(&self, table: &str) -> QueryResult<Schema> {
+        let physical = self
+            .catalog()
+            .get_table(table)
+            .ok_or_else(|| QueryError::TableNotFound(table.to_string()))?;
+        Ok(Schema::from_table(table, &physical.info))
+    }
+
+    // Only filters that run before anything reshapes the rows still index into the table's own
+    // columns. The value has to have the column's exact type too, since the index compares
+    // encodings rather than promoting numbers.
+    fn index_lookup(&self, table: &str, ops: &[TableOp]) -> Option<(u32, Value)> {
+        let physical = self.catalog().get_table(table)?;
+        ops.iter()
+            .take_while(|op| matches!(op, TableOp::Filter { .. } | TableOp::PredicativeFilter(_)))
+            .find_map(|op| match op {
+                TableOp::Filter {
+                    column_index,
+                    operator: ComparisonOperator::Eq,
+                    value,
+                } => {
+                    let column_id = *column_index as u32;
+                    let column = physical
+                        .info
+                        .columns
+                        .values()
+                        .find(|col| col.id == column_id)?;
+                    (physical.indexes.contains_key(&column_id)
+                        && column.data_type.id() == value.data_type().id())
+                    .then(|| (column_id, value.clone()))
+                }
+                _ => None,
+            })
+    }
+
     fn resolve_column_index(&self, table: &str, column: &str) -> QueryResult<usize> {
-        self.table_catalog
+        self.catalog()
             .get_table(table)
             .ok_or_else(|| QueryError::TableNotFound(table.to_string()))?
             .info
@@ -167,14 +631,101 @@ impl PlanCompiler {
             .ok_or_else(|| QueryError::ColumnNotFound(column.to_string(), table.to_string()))
     }
 
-    // TODO: implement
-    fn create_predicate_function(
-        &self,
-        _predicate: &PredicateExpr,
-    ) -> QueryResult<Arc<dyn Fn(&Tuple) -> bool + Send + Sync>> {
-        let filter_fn = Arc::new(move |_tuple: &Tuple| -> bool { true });
+    fn compile_predicate(
+        &mut self,
+        schema: &Schema,
+        predicate: &PredicateExpr,
+    ) -> QueryResult<CompiledPredicate> {
+        match predicate {
+            PredicateExpr::Comparison { left, op, right } => {
+                let left = self.compile_scalar(schema, left)?;
+                let right = self.compile_scalar(schema, right)?;
+                self.expect_parameter(&left, &scalar_type(schema, &right));
+                self.expect_parameter(&right, &scalar_type(schema, &left));
+                Ok(CompiledPredicate::Comparison {
+                    left,
+                    op: op.clone(),
+                    right,
+                })
+            }
+            PredicateExpr::And(left, right) => Ok(CompiledPredicate::And(
+                Box::new(self.compile_predicate(schema, left)?),
+                Box::new(self.compile_predicate(schema, right)?),
+            )),
+            PredicateExpr::Or(left, right) => Ok(CompiledPredicate::Or(
+                Box::new(self.compile_predicate(schema, left)?),
+                Box::new(self.compile_predicate(schema, right)?),
+            )),
+            PredicateExpr::Not(inner) => Ok(CompiledPredicate::Not(Box::new(
+                self.compile_predicate(schema, inner)?,
+            ))),
+            PredicateExpr::IsNull(expr) => Ok(CompiledPredicate::IsNull(
+                self.compile_scalar(schema, expr)?,
+            )),
+            PredicateExpr::IsNotNull(expr) => Ok(CompiledPredicate::IsNotNull(
+                self.compile_scalar(schema, expr)?,
+            )),
+            PredicateExpr::In(expr, candidates) => {
+                let expr = self.compile_scalar(schema, expr)?;
+                let candidates = candidates
+                    .iter()
+                    .map(|candidate| self.compile_scalar(schema, candidate))
+                    .collect::<QueryResult<Vec<_>>>()?;
+                let expr_type = scalar_type(schema, &expr);
+                for candidate in &candidates {
+                    self.expect_parameter(candidate, &expr_type);
+                }
+                Ok(CompiledPredicate::In(expr, candidates))
+            }
+            PredicateExpr::Constant(value) => Ok(CompiledPredicate::Constant(*value)),
+            PredicateExpr::Exists(_) => Err(QueryError::UnsupportedFilterExpression(
+                "exists".to_string(),
+            )),
+        }
+    }
+
+    fn compile_scalar(&mut self, schema: &Schema, expr: &QueryExpr) -> QueryResult<ScalarExpr> {
+        match expr {
+            QueryExpr::Column(name) => Ok(ScalarExpr::Column(schema.resolve_index(name)?)),
+            QueryExpr::Literal(value) => Ok(ScalarExpr::Literal(value.clone())),
+            QueryExpr::Parameter(index) => {
+                self.declare_parameter(*index);
+                Ok(ScalarExpr::Parameter(*index))
+            }
+            QueryExpr::BinaryOp { left, op, right } => Ok(ScalarExpr::BinaryOp {
+                left: Box::new(self.compile_scalar(schema, left)?),
+                op: op.clone(),
+                right: Box::new(self.compile_scalar(schema, right)?),
+            }),
+            QueryExpr::Reference(name) => {
+                let info = self
+                    .lookup_symbol(name)
+                    .cloned()
+                    .ok_or_else(|| QueryError::SymbolNotFound(name.clone()))?;
+                self.compile_scalar(schema, &info)
+            }
+            other => Err(QueryError::UnsupportedFilterExpression(format!(
+                "{:?}",
+                other
+            ))),
+        }
+    }
 
-        Ok(filter_fn)
+    fn declare_parameter(&mut self, index: usize) {
+        if self.parameters.len() <= index {
+            self.parameters.resize(index + 1, None);
+        }
+    }
+
+    // The first use that pins a parameter down decides its type; any other use has to
+    // accept a value of that type anyway or the query couldn't mean anything.
+    fn expect_parameter(&mut self, expr: &ScalarExpr, data_type: &DataType) {
+        if let ScalarExpr::Parameter(index) = expr
+            && self.parameters[*index].is_none()
+            && !matches!(data_type, DataType::Null)
+        {
+            self.parameters[*index] = Some(data_type.clone());
+        }
     }
 
     fn push_scope(&mut self) {
@@ -204,14 +755,61 @@ impl PlanCompiler {
 
     fn build_ops(
         &mut self,
-        table: &str,
+        schema: &mut Schema,
         operations: &[TransactionOp],
     ) -> QueryResult<Vec<TableOp>> {
         let mut ops = vec![];
         for op in operations {
-            let compiled_op = self.compile_transaction_ops(table, op)?;
+            let compiled_op = self.compile_transaction_ops(schema, op)?;
             ops.extend(compiled_op);
         }
         Ok(ops)
     }
 }
+
+fn creates_table(steps: &[Transaction], table: &str) -> bool {
+    steps.iter().any(
+        |step| matches!(step, Transaction::CreateTable { table: created, .. } if created == table),
+    )
+}
+
+// Whether two rows of an insert set the same columns, in whatever order.
+fn sets_same_columns(left: &[(u32, ScalarExpr)], right: &[(u32, ScalarExpr)]) -> bool {
+    let columns = |values: &[(u32, ScalarExpr)]| {
+        let mut columns: Vec<u32> = values.iter().map(|(index, _)| *index).collect();
+        columns.sort_unstable();
+        columns
+    };
+    columns(left) == columns(right)
+}
+
+// Follows the promotions `apply_arithmetic` makes: two ints stay an int, other integers widen
+// to a long, decimals stay exact with integers except through a division or a power, and
+// anything else ends up a double.
+fn scalar_type(schema: &Schema, expr: &ScalarExpr) -> DataType {
+    match expr {
+        ScalarExpr::Column(index) => schema.columns[*index].data_type.clone(),
+        ScalarExpr::Literal(value) => value.data_type(),
+        ScalarExpr::Parameter(_) => DataType::Null,
+        ScalarExpr::BinaryOp { left, op, right } => {
+            match (scalar_type(schema, left), scalar_type(schema, right)) {
+                (DataType::Null, other) | (other, DataType::Null) => other,
+                (DataType::Int, DataType::Int) => DataType::Int,
+                (
+                    DataType::Byte | DataType::Int | DataType::Long,
+                    DataType::Byte | DataType::Int | DataType::Long,
+                ) => DataType::Long,
+                (
+                    DataType::Decimal,
+                    DataType::Decimal | DataType::Byte | DataType::Int | DataType::Long,
+                )
+                | (DataType::Byte | DataType::Int | DataType::Long, DataType::Decimal)
+                    if !matches!(op, BinaryOperator::Divide | BinaryOperator::Power) =>
+                {
+                    DataType::Decimal
+                }
+                _ => DataType::Double,
+            }
+        }
+    }
+}
@@ -1,5 +1,8 @@
 use crate::frontend::ast::Expr;
 use crate::frontend::lexer::TokenKind;
+use crate::page::err::DbInternalError;
+use crate::page::tuple::{DataType, Value};
+use crate::query::BinaryOperator;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -10,10 +13,16 @@ pub enum TransformError {
     UndefinedReference(String),
     #[error("Unsupported operator: {0}")]
     UnsupportedOperator(TokenKind),
-    #[error("Invalid field access")]
-    InvalidFieldAccess,
+    #[error("Cannot read `{field}` from `{path}`, which is neither the row nor an instance")]
+    InvalidFieldAccess { path: String, field: String },
+    #[error("`{path}` has no field `{field}`")]
+    UnknownField { path: String, field: String },
+    #[error("Column `{path}` holds a plain value, so it has no field `{field}`")]
+    ColumnHasNoFields { path: String, field: String },
     #[error("Invalid number")]
     InvalidNumber,
+    #[error("Character literal `{0}` is not ASCII")]
+    NonAsciiChar(char),
     #[error("Unsupported expression: {0:?}")]
     UnsupportedExpression(Expr),
     #[error("Invalid number of lambda parameters")]
@@ -36,6 +45,18 @@ pub enum TransformError {
     InvalidColumnName,
     #[error("Expected number")]
     ExpectedNumber,
+    #[error("`{name}` takes a count of rows, but found {count}")]
+    NegativeLimit { name: String, count: i32 },
+    #[error("Expected a list like `[1, 2, 3]` after `in`")]
+    ExpectedList,
+    #[error("Cannot apply {0:?} to `null`")]
+    NullOperand(BinaryOperator),
+    #[error("Invalid {kind} literal \"{text}\"")]
+    InvalidLiteral { kind: String, text: String },
+    #[error("Unknown column type `{0}`")]
+    UnknownType(String),
+    #[error("Column `{0}` is declared twice")]
+    DuplicateColumn(String),
 }
 
 pub type QueryResult<T> = Result<T, QueryError>;
@@ -46,12 +67,78 @@ pub enum QueryError {
     TableNotFound(String),
     #[error("Column '{0}' not found in table '{1}'")]
     ColumnNotFound(String, String),
+    #[error("Column {0} of '{1}' has no index")]
+    IndexNotFound(u32, String),
+    #[error("Column '{0}' of '{1}' was dropped by an earlier projection")]
+    ColumnProjectedOut(String, String),
     #[error("The query is not a transaction")]
     NotATransaction,
+    #[error(
+        "Table '{0}' is created in the same `do` block, so it can only be used by later queries"
+    )]
+    TableCreatedInBlock(String),
     #[error("Unknown reference '{0}'")]
     SymbolNotFound(String),
     #[error("Only rows can be inserted into a table")]
     ExpectedRow,
+    #[error("Row {row} of the insert sets other columns than the first one")]
+    MismatchedInsertRow { row: usize },
     #[error("Expected a value, but found a row")]
     RowCannotBeEmbeddedIntoAnotherRow,
+    #[error("Expression `{0}` cannot be used as a value")]
+    UnsupportedValueExpression(String),
+    #[error("The condition of an `if` must be a constant boolean for now")]
+    NonConstantCondition,
+    #[error("Expression `{0}` cannot be used inside a filter")]
+    UnsupportedFilterExpression(String),
+    #[error("Column index {0} is out of bounds")]
+    ColumnIndexOutOfBounds(usize),
+    #[error("Division by zero")]
+    DivisionByZero,
+    #[error("Arithmetic overflow")]
+    ArithmeticOverflow,
+    #[error("Operator {0:?} is not supported yet")]
+    UnsupportedOperation(BinaryOperator),
+    #[error("Cannot apply {0:?} to {1:?} and {2:?}")]
+    InvalidOperands(BinaryOperator, Value, Value),
+    #[error("Column '{0}' is ambiguous in '{1}'")]
+    AmbiguousColumn(String, String),
+    #[error("{0} are not implemented yet")]
+    NotImplemented(String),
+    #[error("Only filters can come before an update, but found {0}")]
+    UnsupportedUpdateOp(String),
+    #[error("Unknown aggregate function '{0}'")]
+    UnknownAggregate(String),
+    #[error("Cannot apply `{func}` to column '{column}' of type {data_type:?}")]
+    InvalidAggregateColumn {
+        func: String,
+        column: String,
+        data_type: DataType,
+    },
+    #[error("Column '{0}' appears more than once in a projection")]
+    DuplicateColumn(String),
+    #[error("Parameter ${0} has no value")]
+    UnboundParameter(usize),
+    #[error("The query has parameters, so it has to be prepared and run with their values")]
+    UnboundParameters,
+    #[error("Expected {expected} parameters, but found {found}")]
+    ParameterCount { expected: usize, found: usize },
+    #[error("Parameter ${index} is used as {expected:?}, but the value given is {value:?}")]
+    ParameterType {
+        index: usize,
+        expected: DataType,
+        value: Value,
+    },
+    #[error("Column '{0}' cannot be null")]
+    NullConstraintViolation(String),
+    #[error("Missing value for column without defaults '{0}'")]
+    MissingValue(String),
+    #[error("Duplicate value {value} for primary key '{column}' of table '{table}'")]
+    DuplicateKey {
+        table: String,
+        column: String,
+        value: Value,
+    },
+    #[error(transparent)]
+    Storage(#[from] DbInternalError),
 }
@@ -0,0 +1,498 @@
+use crate::page::decimal::Decimal;
+use crate::page::tuple::{Tuple, Value};
+use crate::query::err::{QueryError, QueryResult};
+use crate::query::{BinaryOperator, ComparisonOperator};
+use std::cmp::Ordering;
+
+#[derive(Debug, Clone)]
+pub enum ScalarExpr {
+    Column(usize),
+    Literal(Value),
+    Parameter(usize),
+    BinaryOp {
+        left: Box<ScalarExpr>,
+        op: BinaryOperator,
+        right: Box<ScalarExpr>,
+    },
+}
+
+impl ScalarExpr {
+    pub fn evaluate(&self, tuple: &Tuple) -> QueryResult<Value> {
+        match self {
+            ScalarExpr::Column(index) => tuple
+                .0
+                .get(*index)
+                .cloned()
+                .ok_or(QueryError::ColumnIndexOutOfBounds(*index)),
+            ScalarExpr::Literal(value) => Ok(value.clone()),
+            ScalarExpr::Parameter(index) => Err(QueryError::UnboundParameter(*index + 1)),
+            ScalarExpr::BinaryOp { left, op, right } => {
+                apply_arithmetic(left.evaluate(tuple)?, op, right.evaluate(tuple)?)
+            }
+        }
+    }
+
+    pub fn bind(&self, params: &[Value]) -> ScalarExpr {
+        match self {
+            ScalarExpr::Parameter(index) => ScalarExpr::Literal(params[*index].clone()),
+            ScalarExpr::BinaryOp { left, op, right } => ScalarExpr::BinaryOp {
+                left: Box::new(left.bind(params)),
+                op: op.clone(),
+                right: Box::new(right.bind(params)),
+            },
+            other => other.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum CompiledPredicate {
+    Comparison {
+        left: ScalarExpr,
+        op: ComparisonOperator,
+        right: ScalarExpr,
+    },
+    And(Box<CompiledPredicate>, Box<CompiledPredicate>),
+    Or(Box<CompiledPredicate>, Box<CompiledPredicate>),
+    Not(Box<CompiledPredicate>),
+    IsNull(ScalarExpr),
+    IsNotNull(ScalarExpr),
+    In(ScalarExpr, Vec<ScalarExpr>),
+    Constant(bool),
+}
+
+impl CompiledPredicate {
+    /// Rows whose predicate fails to evaluate (e.g. a division by zero) are treated as not
+    /// matching, since a filter has no way to surface the error mid-stream.
+    pub fn matches(&self, tuple: &Tuple) -> bool {
+        self.evaluate(tuple).unwrap_or(false)
+    }
+
+    pub fn bind(&self, params: &[Value]) -> CompiledPredicate {
+        let bind = |predicate: &CompiledPredicate| Box::new(predicate.bind(params));
+        match self {
+            CompiledPredicate::Comparison { left, op, right } => CompiledPredicate::Comparison {
+                left: left.bind(params),
+                op: op.clone(),
+                right: right.bind(params),
+            },
+            CompiledPredicate::And(left, right) => CompiledPredicate::And(bind(left), bind(right)),
+            CompiledPredicate::Or(left, right) => CompiledPredicate::Or(bind(left), bind(right)),
+            CompiledPredicate::Not(inner) => CompiledPredicate::Not(bind(inner)),
+            CompiledPredicate::IsNull(expr) => CompiledPredicate::IsNull(expr.bind(params)),
+            CompiledPredicate::IsNotNull(expr) => CompiledPredicate::IsNotNull(expr.bind(params)),
+            CompiledPredicate::In(expr, candidates) => CompiledPredicate::In(
+                expr.bind(params),
+                candidates
+                    .iter()
+                    .map(|candidate| candidate.bind(params))
+                    .collect(),
+            ),
+            CompiledPredicate::Constant(value) => CompiledPredicate::Constant(*value),
+        }
+    }
+
+    fn evaluate(&self, tuple: &Tuple) -> QueryResult<bool> {
+        match self {
+            CompiledPredicate::Comparison { left, op, right } => Ok(compare_values(
+                &left.evaluate(tuple)?,
+                op,
+                &right.evaluate(tuple)?,
+            )),
+            // The right side only runs when the left doesn't settle the result, so it can rely
+            // on the left as a guard: `u.n != 0 && 10 / u.n > 1` never divides by zero.
+            CompiledPredicate::And(left, right) => {
+                if !left.evaluate(tuple)? {
+                    return Ok(false);
+                }
+                right.evaluate(tuple)
+            }
+            CompiledPredicate::Or(left, right) => {
+                if left.evaluate(tuple)? {
+                    return Ok(true);
+                }
+                right.evaluate(tuple)
+            }
+            CompiledPredicate::Not(inner) => Ok(!inner.evaluate(tuple)?),
+            CompiledPredicate::IsNull(expr) => Ok(expr.evaluate(tuple)? == Value::Null),
+            CompiledPredicate::IsNotNull(expr) => Ok(expr.evaluate(tuple)? != Value::Null),
+            CompiledPredicate::Constant(value) => Ok(*value),
+            CompiledPredicate::In(expr, candidates) => {
+                let value = expr.evaluate(tuple)?;
+                for candidate in candidates {
+                    if compare_values(&value, &ComparisonOperator::Eq, &candidate.evaluate(tuple)?)
+                    {
+                        return Ok(true);
+                    }
+                }
+                Ok(false)
+            }
+        }
+    }
+}
+
+pub fn compare_values(left: &Value, op: &ComparisonOperator, right: &Value) -> bool {
+    match (left, op, right) {
+        (a, ComparisonOperator::Eq, b) => a.compare(b) == Some(Ordering::Equal),
+        (a, ComparisonOperator::Neq, b) => {
+            matches!(a.compare(b), Some(Ordering::Less | Ordering::Greater))
+        }
+        (a, ComparisonOperator::Gt, b) => a.compare(b) == Some(Ordering::Greater),
+        (a, ComparisonOperator::GtEq, b) => {
+            matches!(a.compare(b), Some(Ordering::Greater | Ordering::Equal))
+        }
+        (a, ComparisonOperator::Lt, b) => a.compare(b) == Some(Ordering::Less),
+        (a, ComparisonOperator::LtEq, b) => {
+            matches!(a.compare(b), Some(Ordering::Less | Ordering::Equal))
+        }
+        (a, ComparisonOperator::Like, b) => {
+            if let (Value::Text(a), Value::Text(b)) = (a, b) {
+                like_match(a, b)
+            } else {
+                false
+            }
+        }
+        (a, ComparisonOperator::NotLike, b) => {
+            if let (Value::Text(a), Value::Text(b)) = (a, b) {
+                !like_match(a, b)
+            } else {
+                false
+            }
+        }
+    }
+}
+
+// SQL `LIKE`: `%` matches any run of characters, `_` exactly one, and `\` makes whatever
+// follows it literal.
+pub fn like_match(text: &str, pattern: &str) -> bool {
+    if !pattern.contains(['_', '\\']) {
+        if !pattern.contains('%') {
+            return text == pattern;
+        }
+        if let Some(middle) = pattern.strip_prefix('%').and_then(|p| p.strip_suffix('%'))
+            && !middle.contains('%')
+        {
+            return text.contains(middle);
+        }
+        if let Some(suffix) = pattern.strip_prefix('%')
+            && !suffix.contains('%')
+        {
+            return text.ends_with(suffix);
+        }
+        if let Some(prefix) = pattern.strip_suffix('%')
+            && !prefix.contains('%')
+        {
+            return text.starts_with(prefix);
+        }
+    }
+
+    // Byte offsets into both strings, plus where to resume after the last `%` if what
+    // followed it stops matching: one character further into the text.
+    let (mut t, mut p) = (0, 0);
+    let mut resume: Option<(usize, usize)> = None;
+    loop {
+        if let Some(pc) = pattern[p..].chars().next() {
+            if pc == '%' {
+                p += 1;
+                resume = Some((p, t));
+                continue;
+            }
+            if let Some(tc) = text[t..].chars().next() {
+                let (expected, width) = match pc {
+                    '_' => (None, 1),
+                    '\\' => match pattern[p + 1..].chars().next() {
+                        Some(escaped) => (Some(escaped), 1 + escaped.len_utf8()),
+                        None => (Some('\\'), 1),
+                    },
+                    pc => (Some(pc), pc.len_utf8()),
+                };
+                if expected.is_none_or(|c| c == tc) {
+                    p += width;
+                    t += tc.len_utf8();
+                    continue;
+                }
+            }
+        } else if t == text.len() {
+            return true;
+        }
+
+        match resume {
+            Some((after_wildcard, start)) if start < text.len() => {
+                let next = start + text[start..].chars().next().map_or(1, char::len_utf8);
+                resume = Some((after_wildcard, next));
+                p = after_wildcard;
+                t = next;
+            }
+            _ => return false,
+        }
+    }
+}
+
+pub fn apply_arithmetic(left: Value, op: &BinaryOperator, right: Value) -> QueryResult<Value> {
+    match (left, right) {
+        (Value::Null, _) | (_, Value::Null) => Ok(Value::Null),
+        (Value::Text(a), Value::Text(b)) if *op == BinaryOperator::Concat => {
+            Ok(Value::Text(a + &b))
+        }
+        (left, right) if *op == BinaryOperator::Concat => {
+            Err(QueryError::InvalidOperands(op.clone(), left, right))
+        }
+        (Value::Int(a), Value::Int(b)) => {
+            let result = match op {
+                BinaryOperator::Add => a.checked_add(b),
+                BinaryOperator::Subtract => a.checked_sub(b),
+                BinaryOperator::Multiply => a.checked_mul(b),
+                BinaryOperator::Divide if b == 0 => return Err(QueryError::DivisionByZero),
+                BinaryOperator::Divide => a.checked_div(b),
+                BinaryOperator::Modulus if b == 0 => return Err(QueryError::DivisionByZero),
+                BinaryOperator::Modulus => a.checked_rem(b),
+                // A negative exponent leaves the integers.
+                BinaryOperator::Power if b < 0 => return Ok(Value::Double((a as f64).powi(b))),
+                BinaryOperator::Power => a.checked_pow(b as u32),
+                _ => return Err(QueryError::UnsupportedOperation(op.clone())),
+            };
+            result.map(Value::Int).ok_or(QueryError::ArithmeticOverflow)
+        }
+        (left @ Value::Decimal(_), right) | (left, right @ Value::Decimal(_)) => {
+            decimal_arithmetic(left, op, right)
+        }
+        (left, right) => match (left.as_i64(), right.as_i64()) {
+            (Some(a), Some(b)) => {
+                let result = match op {
+                    BinaryOperator::Add => a.checked_add(b),
+                    BinaryOperator::Subtract => a.checked_sub(b),
+                    BinaryOperator::Multiply => a.checked_mul(b),
+                    BinaryOperator::Divide if b == 0 => return Err(QueryError::DivisionByZero),
+                    BinaryOperator::Divide => a.checked_div(b),
+                    BinaryOperator::Modulus if b == 0 => return Err(QueryError::DivisionByZero),
+                    BinaryOperator::Modulus => a.checked_rem(b),
+                    BinaryOperator::Power if b < 0 => {
+                        return Ok(Value::Double((a as f64).powf(b as f64)));
+                    }
+                    BinaryOperator::Power => u32::try_from(b).ok().and_then(|b| a.checked_pow(b)),
+                    _ => return Err(QueryError::UnsupportedOperation(op.clone())),
+                };
+                result
+                    .map(Value::Long)
+                    .ok_or(QueryError::ArithmeticOverflow)
+            }
+            _ => match (left.as_f64(), right.as_f64()) {
+                (Some(a), Some(b)) => float_arithmetic(a, op, b),
+                _ => Err(QueryError::InvalidOperands(op.clone(), left, right)),
+            },
+        },
+    }
+}
+
+// Integers join a decimal exactly. A division or a power can't stay exact, and neither can
+// anything with a float on the other side, so those are worked out in doubles.
+fn decimal_arithmetic(left: Value, op: &BinaryOperator, right: Value) -> QueryResult<Value> {
+    let exact = |value: &Value| match value {
+        Value::Decimal(d) => Some(*d),
+        other => other.as_i64().map(Decimal::from_integer),
+    };
+    let result = match (exact(&left), exact(&right), op) {
+        (Some(_), Some(b), BinaryOperator::Divide | BinaryOperator::Modulus) if b.is_zero() => {
+            return Err(QueryError::DivisionByZero);
+        }
+        (Some(a), Some(b), BinaryOperator::Add) => a.checked_add(&b),
+        (Some(a), Some(b), BinaryOperator::Subtract) => a.checked_sub(&b),
+        (Some(a), Some(b), BinaryOperator::Multiply) => a.checked_mul(&b),
+        (Some(a), Some(b), BinaryOperator::Modulus) => a.checked_rem(&b),
+        _ => {
+            let approximate = |value: &Value| match value {
+                Value::Decimal(d) => Some(d.to_f64()),
+                other => other.as_f64(),
+            };
+            return match (approximate(&left), approximate(&right)) {
+                (Some(a), Some(b)) => float_arithmetic(a, op, b),
+                _ => Err(QueryError::InvalidOperands(op.clone(), left, right)),
+            };
+        }
+    };
+    result
+        .map(Value::Decimal)
+        .ok_or(QueryError::ArithmeticOverflow)
+}
+
+fn float_arithmetic(a: f64, op: &BinaryOperator, b: f64) -> QueryResult<Value> {
+    let result = match op {
+        BinaryOperator::Add => a + b,
+        BinaryOperator::Subtract => a - b,
+        BinaryOperator::Multiply => a * b,
+        BinaryOperator::Divide => a / b,
+        BinaryOperator::Modulus => a % b,
+        BinaryOperator::Power => a.powf(b),
+        _ => return Err(QueryError::UnsupportedOperation(op.clone())),
+    };
+    Ok(Value::Double(result))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn like_wildcards() {
+        assert!(like_match("barfoo", "%foo"));
+        assert!(!like_match("foobar", "%foo"));
+        assert!(like_match("foobar", "foo%"));
+        assert!(!like_match("barfoo", "foo%"));
+        assert!(like_match("a foo b", "%foo%"));
+        assert!(like_match("foo", "f_o"));
+        assert!(!like_match("fo", "f_o"));
+        assert!(!like_match("fooo", "f_o"));
+        assert!(like_match("", "%"));
+        assert!(like_match("f🦀o", "f_o"));
+        assert!(like_match("abcabd", "%ab_"));
+        assert!(like_match("axbyc", "a%b%c"));
+        assert!(!like_match("axbyd", "a%b%c"));
+    }
+
+    #[test]
+    fn escaped_wildcards_are_literal() {
+        assert!(like_match("50%", r"50\%"));
+        assert!(!like_match("500", r"50\%"));
+        assert!(like_match("100% sure", r"%\%%"));
+        assert!(!like_match("100 sure", r"%\%%"));
+        assert!(like_match("a_b", r"a\_b"));
+        assert!(!like_match("axb", r"a\_b"));
+        assert!(like_match(r"a\", r"a\"));
+    }
+
+    // Reading past the end of the tuple fails, so these only pass if it's never read.
+    fn out_of_range() -> CompiledPredicate {
+        CompiledPredicate::IsNull(ScalarExpr::Column(5))
+    }
+
+    #[test]
+    fn and_and_or_skip_their_right_side() {
+        let tuple = Tuple(vec![Value::Int(1)]);
+        assert!(out_of_range().evaluate(&tuple).is_err());
+
+        let and = CompiledPredicate::And(
+            Box::new(CompiledPredicate::Constant(false)),
+            Box::new(out_of_range()),
+        );
+        assert!(matches!(and.evaluate(&tuple), Ok(false)));
+        let or = CompiledPredicate::Or(
+            Box::new(CompiledPredicate::Constant(true)),
+            Box::new(out_of_range()),
+        );
+        assert!(matches!(or.evaluate(&tuple), Ok(true)));
+    }
+
+    #[test]
+    fn integer_powers_stay_integers_until_they_overflow() {
+        let power = |left, right| apply_arithmetic(left, &BinaryOperator::Power, right);
+        assert!(matches!(
+            power(Value::Int(3), Value::Int(4)),
+            Ok(Value::Int(81))
+        ));
+        assert!(matches!(
+            power(Value::Long(2), Value::Int(40)),
+            Ok(Value::Long(1_099_511_627_776))
+        ));
+        assert!(matches!(
+            power(Value::Int(2), Value::Int(40)),
+            Err(QueryError::ArithmeticOverflow)
+        ));
+    }
+
+    #[test]
+    fn modulus_by_zero_is_an_error() {
+        let modulus = |left, right| apply_arithmetic(left, &BinaryOperator::Modulus, right);
+        assert!(matches!(
+            modulus(Value::Int(10), Value::Int(3)),
+            Ok(Value::Int(1))
+        ));
+        assert!(matches!(
+            modulus(Value::Int(10), Value::Int(0)),
+            Err(QueryError::DivisionByZero)
+        ));
+        assert!(matches!(
+            modulus(Value::Long(10), Value::Int(0)),
+            Err(QueryError::DivisionByZero)
+        ));
+    }
+
+    #[test]
+    fn concat_only_joins_text() {
+        let concat = |left, right| apply_arithmetic(left, &BinaryOperator::Concat, right);
+        assert!(matches!(
+            concat(Value::Text("a".to_string()), Value::Text("b".to_string())),
+            Ok(Value::Text(ref text)) if text == "ab"
+        ));
+        assert!(matches!(
+            concat(Value::Int(1), Value::Text("x".to_string())),
+            Err(QueryError::InvalidOperands(
+                BinaryOperator::Concat,
+                Value::Int(1),
+                _
+            ))
+        ));
+        assert!(matches!(
+            concat(Value::Text("x".to_string()), Value::Null),
+            Ok(Value::Null)
+        ));
+    }
+
+    #[test]
+    fn decimals_stay_exact_with_integers() {
+        let price = Value::Decimal(Decimal::new(1999, 2));
+        let arithmetic = |left: &Value, op, right| apply_arithmetic(left.clone(), &op, right);
+        let exact = |result: QueryResult<Value>| match result {
+            Ok(Value::Decimal(d)) => (d.mantissa, d.scale),
+            other => panic!("expected a decimal, got {:?}", other),
+        };
+
+        assert_eq!(
+            exact(arithmetic(&price, BinaryOperator::Multiply, Value::Int(2))),
+            (3998, 2)
+        );
+        assert_eq!(
+            exact(arithmetic(
+                &price,
+                BinaryOperator::Add,
+                Value::Decimal(Decimal::new(1, 3))
+            )),
+            (19991, 3)
+        );
+        assert_eq!(
+            exact(apply_arithmetic(
+                Value::Long(20),
+                &BinaryOperator::Subtract,
+                price.clone()
+            )),
+            (1, 2)
+        );
+        assert_eq!(
+            exact(arithmetic(&price, BinaryOperator::Modulus, Value::Int(7))),
+            (599, 2)
+        );
+        assert!(matches!(
+            arithmetic(&price, BinaryOperator::Divide, Value::Int(2)),
+            Ok(Value::Double(half)) if (half - 9.995).abs() < 1e-9
+        ));
+        assert!(matches!(
+            arithmetic(&price, BinaryOperator::Add, Value::Double(0.01)),
+            Ok(Value::Double(sum)) if (sum - 20.0).abs() < 1e-9
+        ));
+        assert!(matches!(
+            arithmetic(&price, BinaryOperator::Divide, Value::Int(0)),
+            Err(QueryError::DivisionByZero)
+        ));
+        assert!(matches!(
+            arithmetic(
+                &Value::Decimal(Decimal::new(i128::MAX, 0)),
+                BinaryOperator::Add,
+                Value::Int(1)
+            ),
+            Err(QueryError::ArithmeticOverflow)
+        ));
+        assert!(matches!(
+            arithmetic(&price, BinaryOperator::Add, Value::Text("1".to_string())),
+            Err(QueryError::InvalidOperands(..))
+        ));
+    }
+}
@@ -1,18 +1,30 @@
-use crate::page::tuple::{Tuple, Value};
-use crate::query::Transaction;
+use crate::page::err::{DbInternalError, DbResult};
+use crate::page::tuple::{DataType, Tuple, Value};
+use crate::query::aggregate::AggregateFunction;
+use crate::query::err::{QueryError, QueryResult};
+use crate::query::eval::ScalarExpr;
 use crate::query::op::TableOp;
-use crate::query::stream::apply_ops;
+use crate::query::stream::{apply_ops, hash_join, matches_filters};
+use crate::query::{PreparedStatement, Transaction};
 use crate::table::heap::scan_table;
-use crate::table::{ColumnInfo, TableCatalog, TableInfo};
+use crate::table::{ColumnInfo, PhysicalTable, SharedCatalog, TableInfo, key_bytes};
 use futures::Stream;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::pin::Pin;
 use std::sync::Arc;
 
-pub type TupleStream = Pin<Box<dyn Stream<Item = Tuple> + Send + 'static>>;
+// A row that can't be produced, say because its page fails the checksum, comes out as an
+// error item, and nothing follows it.
+pub type TupleStream = Pin<Box<dyn Stream<Item = QueryResult<Tuple>> + Send + 'static>>;
+
+// The rows of a query along with the name and type of each of their columns, in tuple order.
+pub struct ResultSet {
+    pub columns: Vec<(String, DataType)>,
+    pub stream: TupleStream,
+}
 
 pub struct QueryExecutor {
-    catalog: Arc<TableCatalog>,
+    catalog: SharedCatalog,
 }
 
 pub enum PlanResult {
@@ -24,74 +36,1884 @@ pub enum PlanResult {
 }
 
 impl QueryExecutor {
-    pub fn new(catalog: Arc<TableCatalog>) -> Self {
+    pub fn new(catalog: SharedCatalog) -> Self {
         Self { catalog }
     }
 
-    pub async fn execute(
+    // Waits for the statements still holding the catalog to finish, and keeps new ones out
+    // until the table exists.
+    pub async fn create_table(&self, name: String, info: TableInfo) -> DbResult<()> {
+        self.catalog.write().await.create_table(name, info).await
+    }
+
+    pub async fn drop_table(&self, name: &str) -> DbResult<()> {
+        self.catalog.write().await.drop_table(name).await
+    }
+
+    pub async fn create_index(&self, table: &str, column: &str) -> DbResult<()> {
+        self.catalog.write().await.create_index(table, column).await
+    }
+
+    // Checks the values against what the statement expects of them before running it. The
+    // statement itself is left untouched, so it can be run again with other values.
+    pub async fn execute_prepared(
+        &self,
+        statement: &PreparedStatement,
+        params: Vec<Value>,
+    ) -> QueryResult<TupleStream> {
+        if params.len() != statement.parameters.len() {
+            return Err(QueryError::ParameterCount {
+                expected: statement.parameters.len(),
+                found: params.len(),
+            });
+        }
+        for (index, (expected, value)) in statement.parameters.iter().zip(&params).enumerate() {
+            if let Some(expected) = expected
+                && *value != Value::Null
+                && value.data_type().id() != expected.id()
+            {
+                return Err(QueryError::ParameterType {
+                    index: index + 1,
+                    expected: expected.clone(),
+                    value: value.clone(),
+                });
+            }
+        }
+        self.execute(statement.transaction.bind(&params)).await
+    }
+
+    // The columns are the ones the compiler worked out for the same transaction.
+    pub async fn execute_with_schema(
         &self,
         transaction: Transaction,
-    ) -> Result<Pin<Box<dyn Stream<Item = Tuple> + Send>>, String> {
+        columns: Vec<(String, DataType)>,
+    ) -> QueryResult<ResultSet> {
+        let stream = self.execute(transaction).await?;
+        Ok(ResultSet { columns, stream })
+    }
+
+    pub async fn execute(&self, transaction: Transaction) -> QueryResult<TupleStream> {
         match transaction {
-            Transaction::Select { table, ops } => {
-                let physical_table = self
-                    .catalog
+            Transaction::Select {
+                table,
+                filter,
+                limit,
+                ops,
+            } => {
+                let catalog = self.catalog.read().await;
+                let physical_table = catalog
                     .get_table(&table)
-                    .ok_or_else(|| format!("Table '{}' not found", table))?;
+                    .ok_or_else(|| QueryError::TableNotFound(table.clone()))?;
                 let heap = physical_table.heap.clone();
-                let base_stream = scan_table(heap).await;
+                // A bare count only needs the slot headers, not the rows.
+                if filter.is_empty()
+                    && limit.is_none()
+                    && let Some(TableOp::Aggregate(call)) = ops.first()
+                    && call.function == AggregateFunction::Count
+                    && call.column_index.is_none()
+                {
+                    let count = heap.count_rows().await?;
+                    let rows = vec![Ok(Tuple(vec![Value::Long(count as i64)]))];
+                    return Ok(apply_ops(
+                        Box::pin(futures::stream::iter(rows)),
+                        ops[1..].to_vec(),
+                    ));
+                }
+                let mut base_stream = scan_table::<QueryError>(heap).await;
+                if !filter.is_empty() {
+                    base_stream = base_stream
+                        .with_filter(Arc::new(move |tuple| matches_filters(&filter, tuple)));
+                }
+                if let Some(limit) = limit {
+                    base_stream = base_stream.with_limit(limit);
+                }
                 Ok(apply_ops(base_stream, ops))
             }
+            Transaction::IndexScan {
+                table,
+                column_id,
+                value,
+                ops,
+            } => {
+                let catalog = self.catalog.read().await;
+                let physical_table = catalog
+                    .get_table(&table)
+                    .ok_or_else(|| QueryError::TableNotFound(table.clone()))?;
+                let index = physical_table
+                    .indexes
+                    .get(&column_id)
+                    .ok_or_else(|| QueryError::IndexNotFound(column_id, table.clone()))?;
+                let heap = physical_table.heap.clone();
+
+                let locations = index.lookup(&heap, &value).await?;
+                let mut rows = Vec::with_capacity(locations.len());
+                for (page_id, slot) in locations {
+                    if let Some(tuple) = heap.get_tuple(page_id, slot).await? {
+                        rows.push(Ok(tuple));
+                    }
+                }
+                Ok(apply_ops(Box::pin(futures::stream::iter(rows)), ops))
+            }
+            Transaction::Update {
+                table,
+                assignments,
+                ops,
+            } => {
+                let catalog = self.catalog.read().await;
+                let physical_table = catalog
+                    .get_table(&table)
+                    .ok_or_else(|| QueryError::TableNotFound(table.clone()))?;
+                let heap = physical_table.heap.clone();
+
+                // Every row is worked out, and the keys checked, before the first one is
+                // written.
+                let _writes = physical_table.writes.lock().await;
+                let rows = heap.scan_with_locations().await?;
+                let types = physical_table.info.column_types();
+                let mut changes = vec![];
+                for (row, (_, _, tuple)) in rows.iter().enumerate() {
+                    if !matches_filters(&ops, tuple)? {
+                        continue;
+                    }
+
+                    let mut new_values = tuple.0.clone();
+                    for (index, expr) in &assignments {
+                        new_values[*index as usize] =
+                            expr.evaluate(tuple)?.for_column(&types[*index as usize]);
+                    }
+                    changes.push((row, Tuple(new_values)));
+                }
+                Self::check_updated_keys(physical_table, &rows, &changes)?;
+
+                for (row, tuple) in &changes {
+                    let (page_id, slot, _) = rows[*row];
+                    heap.update_tuple(page_id, slot, tuple)
+                        .await
+                        .map_err(DbInternalError::UpdateFailed)?;
+                }
+                if !changes.is_empty() {
+                    physical_table.invalidate_indexes().await;
+                }
+
+                Ok(Box::pin(futures::stream::iter(vec![Ok(Tuple(vec![
+                    Value::Long(changes.len() as i64),
+                ]))])))
+            }
+            Transaction::Join {
+                left,
+                right,
+                left_key,
+                right_key,
+                ops,
+            } => {
+                let left_stream = Box::pin(self.execute(*left)).await?;
+                let right_stream = Box::pin(self.execute(*right)).await?;
+                let joined = hash_join(left_stream, right_stream, left_key, right_key);
+                Ok(apply_ops(joined, ops))
+            }
+            // Every row is built and checked before the first one is written.
             Transaction::Insert {
                 table,
-                values,
+                rows,
                 ops,
                 returning,
             } => {
-                let physical_table = self
-                    .catalog
+                let catalog = self.catalog.read().await;
+                let physical_table = catalog
                     .get_table(&table)
-                    .ok_or_else(|| format!("Table '{}' not found", table))?;
-                let mut tuple = Self::build_tuple(&physical_table.info, values)?;
-                let heap = physical_table.heap.clone();
-                heap.insert_tuple(&tuple)
-                    .await
-                    .map_err(|e| format!("Insert failed: {}", e))?;
-
-                if let Some(returning_columns) = returning {
-                    let tuple_values: Vec<Value> = returning_columns
-                        .iter()
-                        .map(|idx| std::mem::replace(&mut tuple.0[*idx], Value::Null))
-                        .collect();
-                    let base_stream = Box::pin(futures::stream::iter(vec![Tuple(tuple_values)]));
-                    Ok(apply_ops(base_stream, ops))
-                } else {
-                    Ok(Box::pin(futures::stream::iter(vec![])))
+                    .ok_or_else(|| QueryError::TableNotFound(table.clone()))?;
+                let tuples = rows
+                    .into_iter()
+                    .map(|values| Self::build_tuple(&physical_table.info, values))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let writes = physical_table.writes.lock().await;
+                Self::check_primary_key(physical_table, &tuples).await?;
+                let inserted = physical_table.heap.insert_many(&tuples).await;
+                drop(writes);
+                if !tuples.is_empty() {
+                    physical_table.invalidate_indexes().await;
+                }
+                inserted.map_err(|(_, e)| DbInternalError::InsertFailed(e))?;
+
+                let output: Vec<Tuple> = match returning {
+                    Some(returning_columns) => tuples
+                        .into_iter()
+                        .map(|mut tuple| {
+                            Tuple(
+                                returning_columns
+                                    .iter()
+                                    .map(|idx| std::mem::replace(&mut tuple.0[*idx], Value::Null))
+                                    .collect(),
+                            )
+                        })
+                        .collect(),
+                    None => vec![Tuple(vec![Value::Long(tuples.len() as i64)])],
+                };
+                Ok(apply_ops(
+                    Box::pin(futures::stream::iter(output.into_iter().map(Ok))),
+                    ops,
+                ))
+            }
+            Transaction::CreateTable { table, columns } => {
+                let columns = columns
+                    .into_iter()
+                    .enumerate()
+                    .map(|(id, (name, data_type))| {
+                        let column = ColumnInfo {
+                            id: id as u32,
+                            name: name.clone(),
+                            data_type,
+                            nullable: false,
+                            default: None,
+                        };
+                        (name, column)
+                    })
+                    .collect();
+                let info = TableInfo {
+                    columns,
+                    primary_key: None,
+                };
+                self.create_table(table, info).await?;
+                Ok(Box::pin(futures::stream::iter(vec![])))
+            }
+            Transaction::CreateIndex { table, column } => {
+                self.create_index(&table, &column).await?;
+                Ok(Box::pin(futures::stream::iter(vec![])))
+            }
+            // Scans are lazy, so only what the last step yields has to be kept. Inserts and
+            // updates are done by the time their stream comes back.
+            Transaction::Block(steps) => {
+                let mut rows: TupleStream = Box::pin(futures::stream::iter(vec![]));
+                for step in steps {
+                    rows = Box::pin(self.execute(step)).await?;
                 }
+                Ok(rows)
+            }
+        }
+    }
+
+    // The keys have to be new to the table and to each other. The caller holds the table's
+    // write lock until the rows are in.
+    async fn check_primary_key(table: &PhysicalTable, tuples: &[Tuple]) -> QueryResult<()> {
+        let Some((index, mut keys)) = table.primary_keys().await? else {
+            return Ok(());
+        };
+        for tuple in tuples {
+            if !keys.insert(key_bytes(&tuple.0[index])) {
+                return Err(Self::duplicate_key(table, &tuple.0[index]));
+            }
+        }
+        Ok(())
+    }
+
+    // Each changed row gives up its old key, so keys can be moved between rows as long as no
+    // two end up with the same one.
+    fn check_updated_keys(
+        table: &PhysicalTable,
+        rows: &[(u32, usize, Tuple)],
+        changes: &[(usize, Tuple)],
+    ) -> QueryResult<()> {
+        let Some(key) = table.info.primary_key else {
+            return Ok(());
+        };
+        let index = key as usize;
+        let changed: HashSet<usize> = changes.iter().map(|(row, _)| *row).collect();
+        let mut keys: HashSet<Vec<u8>> = rows
+            .iter()
+            .enumerate()
+            .filter(|(row, _)| !changed.contains(row))
+            .map(|(_, (_, _, tuple))| key_bytes(&tuple.0[index]))
+            .collect();
+        for (_, tuple) in changes {
+            if !keys.insert(key_bytes(&tuple.0[index])) {
+                return Err(Self::duplicate_key(table, &tuple.0[index]));
             }
         }
+        Ok(())
     }
 
-    fn build_tuple(table_info: &TableInfo, values: Vec<(u32, Value)>) -> Result<Tuple, String> {
-        let mut value_map: HashMap<u32, Value> = values.into_iter().collect();
-        let mut columns: Vec<&ColumnInfo> = table_info.columns.values().collect();
-        columns.sort_by_key(|col| col.id);
+    fn duplicate_key(table: &PhysicalTable, value: &Value) -> QueryError {
+        QueryError::DuplicateKey {
+            table: table.name.clone(),
+            column: table.primary_key_name().unwrap_or_default().to_string(),
+            value: value.clone(),
+        }
+    }
 
+    fn build_tuple(table_info: &TableInfo, values: Vec<(u32, ScalarExpr)>) -> QueryResult<Tuple> {
+        let mut value_map: HashMap<u32, ScalarExpr> = values.into_iter().collect();
         let mut tuple_values = Vec::new();
-        for col in columns.drain(..) {
-            if let Some(val) = value_map.remove(&col.id) {
-                tuple_values.push(Value::from(val));
+        for col in table_info.ordered_columns() {
+            let value = if let Some(expr) = value_map.remove(&col.id) {
+                expr.evaluate(&Tuple(vec![]))?.for_column(&col.data_type)
             } else if let Some(default) = &col.default {
-                tuple_values.push(default.clone());
+                default.clone()
             } else if col.nullable {
-                tuple_values.push(Value::Null);
+                Value::Null
             } else {
-                return Err(format!(
-                    "Missing value for column without defaults '{}'",
-                    col.name
-                ));
+                return Err(QueryError::MissingValue(col.name.clone()));
+            };
+            if matches!(value, Value::Null) && !col.nullable {
+                return Err(QueryError::NullConstraintViolation(col.name.clone()));
             }
+            tuple_values.push(value);
         }
         Ok(Tuple(tuple_values))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::frontend::ast::Arena;
+    use crate::frontend::lexer::Lexer;
+    use crate::frontend::parser::parse_expression_all;
+    use crate::page::decimal::Decimal;
+    use crate::page::err::DbInternalError;
+    use crate::page::tuple::{DataType, Tuple, Value};
+    use crate::query::compiler::PlanCompiler;
+    use crate::query::err::{QueryError, TransformError};
+    use crate::query::exec::QueryExecutor;
+    use crate::query::op::TableOp;
+    use crate::query::optimizer::ConstantFoldingOptimizer;
+    use crate::query::transformer::AstToQueryTransformer;
+    use crate::query::{PreparedStatement, Transaction};
+    use crate::table::TableInfo;
+    use crate::testing::{TempDir, column, rows, users_database};
+    use crate::{Database, DatabaseError};
+    use futures::TryStreamExt;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    // `accounts { id Int, owner Text }`, keyed by id.
+    async fn accounts_database(dir: &TempDir) -> Database {
+        let database = Database::open(dir.path()).await.unwrap();
+        let columns = HashMap::from([
+            column(0, "id", DataType::Int, false),
+            column(1, "owner", DataType::Text, false),
+        ]);
+        database
+            .catalog()
+            .write()
+            .await
+            .create_table(
+                "accounts".to_string(),
+                TableInfo {
+                    columns,
+                    primary_key: Some(0),
+                },
+            )
+            .await
+            .unwrap();
+        database
+    }
+
+    fn is_duplicate_key(result: &Result<Vec<Tuple>, DatabaseError>, key: i32) -> bool {
+        matches!(
+            result,
+            Err(DatabaseError::Query(QueryError::DuplicateKey { column, value, .. }))
+                if column == "id" && *value == Value::Int(key)
+        )
+    }
+
+    #[tokio::test]
+    async fn explicit_null_in_a_required_column_is_rejected() {
+        let dir = TempDir::new();
+        let mut database = users_database(&dir).await;
+        let result = database
+            .execute_str(r#"insert_ users { name = "ana", age = null }"#)
+            .await;
+        assert!(matches!(
+            result,
+            Err(DatabaseError::Query(QueryError::NullConstraintViolation(column))) if column == "age"
+        ));
+        assert!(rows(&mut database, "scan users").await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn missing_value_for_a_required_column_is_rejected() {
+        let dir = TempDir::new();
+        let mut database = users_database(&dir).await;
+        let result = database
+            .execute_str(r#"insert_ users { name = "ana" }"#)
+            .await;
+        assert!(matches!(
+            result,
+            Err(DatabaseError::Query(QueryError::MissingValue(column))) if column == "age"
+        ));
+        assert!(rows(&mut database, "scan users").await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn missing_nullable_column_is_null() {
+        let dir = TempDir::new();
+        let mut database = users_database(&dir).await;
+        rows(&mut database, r#"insert_ users { name = "ana", age = 30 }"#).await;
+        let users = rows(&mut database, "scan users").await;
+        assert_eq!(users[0].0[2], Value::Null);
+    }
+
+    #[tokio::test]
+    async fn distinct_keys_are_accepted() {
+        let dir = TempDir::new();
+        let mut database = accounts_database(&dir).await;
+        rows(
+            &mut database,
+            r#"insert_ accounts { id = 1, owner = "ana" }"#,
+        )
+        .await;
+        rows(
+            &mut database,
+            r#"insert_ accounts { id = 2, owner = "bia" }"#,
+        )
+        .await;
+        assert_eq!(rows(&mut database, "scan accounts").await.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn duplicate_key_is_rejected() {
+        let dir = TempDir::new();
+        let mut database = accounts_database(&dir).await;
+        rows(
+            &mut database,
+            r#"insert_ accounts { id = 1, owner = "ana" }"#,
+        )
+        .await;
+        let result = database
+            .execute_str(r#"insert_ accounts { id = 1, owner = "bia" }"#)
+            .await;
+        assert!(is_duplicate_key(&result, 1));
+        assert_eq!(rows(&mut database, "scan accounts").await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn duplicate_keys_within_one_insert_are_rejected() {
+        let dir = TempDir::new();
+        let mut database = accounts_database(&dir).await;
+        let result = database
+            .execute_str(
+                r#"insert_ accounts [{ id = 3, owner = "ana" }, { id = 3, owner = "bia" }]"#,
+            )
+            .await;
+        assert!(is_duplicate_key(&result, 3));
+        assert!(rows(&mut database, "scan accounts").await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn one_insert_writes_every_row() {
+        let dir = TempDir::new();
+        let mut database = users_database(&dir).await;
+        rows(
+            &mut database,
+            r#"insert_ users [{ name = "ana", age = 20 }, { age = 35, name = "bia" }, { name = "caio", age = 50 }]"#,
+        )
+        .await;
+        let mut users: Vec<(String, i32)> = rows(&mut database, "scan users")
+            .await
+            .into_iter()
+            .map(|row| (row.0[0].as_string().unwrap(), row.0[1].as_int().unwrap()))
+            .collect();
+        users.sort();
+        assert_eq!(
+            users,
+            [
+                ("ana".to_string(), 20),
+                ("bia".to_string(), 35),
+                ("caio".to_string(), 50)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn insert_rows_setting_other_columns_are_rejected() {
+        let dir = TempDir::new();
+        let mut database = users_database(&dir).await;
+        let result = database
+            .execute_str(r#"insert_ users [{ name = "ana", age = 20 }, { name = "bia" }]"#)
+            .await;
+        assert!(matches!(
+            result,
+            Err(DatabaseError::Query(QueryError::MismatchedInsertRow {
+                row: 2
+            }))
+        ));
+        assert!(rows(&mut database, "scan users").await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn functions_cannot_be_inserted() {
+        let dir = TempDir::new();
+        let mut database = users_database(&dir).await;
+        let result = database
+            .execute_str(r#"insert_ users { name = (\x -> x), age = 20 }"#)
+            .await;
+        assert!(matches!(
+            result,
+            Err(DatabaseError::Query(
+                QueryError::UnsupportedValueExpression(_)
+            ))
+        ));
+    }
+
+    #[tokio::test]
+    async fn block_runs_its_statements_in_order() {
+        let dir = TempDir::new();
+        let mut database = users_database(&dir).await;
+        let block = rows(
+            &mut database,
+            r#"do
+  insert_ users { name = "ana", age = 20 }
+  insert_ users { name = "bia", age = 35 }
+  scan users"#,
+        )
+        .await;
+        let mut names: Vec<String> = block
+            .into_iter()
+            .filter_map(|row| row.0[0].as_string())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["ana", "bia"]);
+    }
+
+    #[tokio::test]
+    async fn block_cannot_use_a_table_it_creates() {
+        let dir = TempDir::new();
+        let mut database = users_database(&dir).await;
+        let result = database
+            .execute_str(
+                r#"do
+  create_table products { name = Text, price = Int }
+  insert_ products { name = "pen", price = 2 }"#,
+            )
+            .await;
+        assert!(matches!(
+            result,
+            Err(DatabaseError::Query(QueryError::TableCreatedInBlock(ref table))) if table == "products"
+        ));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_inserts_of_one_key_keep_one_row() {
+        let dir = TempDir::new();
+        let mut database = accounts_database(&dir).await;
+        let mut tasks = vec![];
+        for id in 0..20 {
+            for owner in ["ana", "bia", "caio", "duda"] {
+                let query = format!(r#"insert_ accounts {{ id = {}, owner = "{}" }}"#, id, owner);
+                let (transaction, _) = database.compile(&query).await.unwrap();
+                let executor = QueryExecutor::new(database.catalog().clone());
+                tasks.push(tokio::spawn(async move {
+                    executor.execute(transaction).await.is_ok()
+                }));
+            }
+        }
+        let mut inserted = 0;
+        for task in tasks {
+            inserted += task.await.unwrap() as usize;
+        }
+        assert_eq!(inserted, 20);
+        assert_eq!(rows(&mut database, "scan accounts").await.len(), 20);
+    }
+
+    #[tokio::test]
+    async fn update_onto_an_existing_key_is_rejected() {
+        let dir = TempDir::new();
+        let mut database = accounts_database(&dir).await;
+        rows(
+            &mut database,
+            r#"insert_ accounts { id = 1, owner = "ana" }"#,
+        )
+        .await;
+        rows(
+            &mut database,
+            r#"insert_ accounts { id = 2, owner = "bia" }"#,
+        )
+        .await;
+        let result = database
+            .execute_str(r#"scan accounts |> filter (\a -> a.id == 2) |> update { id = 1 }"#)
+            .await;
+        assert!(is_duplicate_key(&result, 1));
+
+        rows(
+            &mut database,
+            r#"scan accounts |> filter (\a -> a.id == 2) |> update { id = 5 }"#,
+        )
+        .await;
+        let ids: Vec<Value> = rows(&mut database, "scan accounts")
+            .await
+            .into_iter()
+            .map(|row| row.0[0].clone())
+            .collect();
+        assert!(ids.contains(&Value::Int(1)) && ids.contains(&Value::Int(5)));
+    }
+
+    async fn prepare(database: &Database, query: &str) -> PreparedStatement {
+        let tokens = Lexer::new(query).tokenize().unwrap();
+        let mut arena = Arena::with_capacity(100, 100);
+        let root = parse_expression_all(&tokens, &mut arena).unwrap();
+        let expr = AstToQueryTransformer::new(&arena, Box::new(ConstantFoldingOptimizer))
+            .transform(root)
+            .unwrap();
+        let mut compiler = PlanCompiler::new(Arc::clone(database.catalog()));
+        compiler.prepare(&expr).await.unwrap()
+    }
+
+    async fn run_prepared(
+        executor: &QueryExecutor,
+        statement: &PreparedStatement,
+        params: Vec<Value>,
+    ) -> Vec<String> {
+        let tuples: Vec<Tuple> = executor
+            .execute_prepared(statement, params)
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        let mut names: Vec<String> = tuples
+            .into_iter()
+            .filter_map(|tuple| tuple.0[0].as_string())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[tokio::test]
+    async fn prepared_statement_runs_with_different_bindings() {
+        let dir = TempDir::new();
+        let mut database = users_database(&dir).await;
+        for (name, age) in [("ana", 20), ("bia", 35), ("caio", 50)] {
+            let query = format!(r#"insert_ users {{ name = "{}", age = {} }}"#, name, age);
+            rows(&mut database, &query).await;
+        }
+
+        let statement = prepare(&database, r#"scan users |> filter (\u -> u.age > $1)"#).await;
+        assert!(matches!(statement.parameters[..], [Some(DataType::Int)]));
+
+        let executor = QueryExecutor::new(Arc::clone(database.catalog()));
+        assert_eq!(
+            run_prepared(&executor, &statement, vec![Value::Int(30)]).await,
+            ["bia", "caio"]
+        );
+        assert_eq!(
+            run_prepared(&executor, &statement, vec![Value::Int(10)]).await,
+            ["ana", "bia", "caio"]
+        );
+        assert!(
+            run_prepared(&executor, &statement, vec![Value::Int(60)])
+                .await
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn prepared_statement_checks_its_bindings() {
+        let dir = TempDir::new();
+        let database = users_database(&dir).await;
+        let statement = prepare(&database, r#"scan users |> filter (\u -> u.age > $1)"#).await;
+        let executor = QueryExecutor::new(Arc::clone(database.catalog()));
+
+        let missing = executor.execute_prepared(&statement, vec![]).await;
+        assert!(matches!(
+            missing,
+            Err(QueryError::ParameterCount {
+                expected: 1,
+                found: 0
+            })
+        ));
+        let mistyped = executor
+            .execute_prepared(&statement, vec![Value::Text("old".to_string())])
+            .await;
+        assert!(matches!(
+            mistyped,
+            Err(QueryError::ParameterType { index: 1, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn constant_if_runs_one_branch() {
+        let dir = TempDir::new();
+        let mut database = users_database(&dir).await;
+        rows(
+            &mut database,
+            r#"if true then insert_ users { name = "ana", age = 30 } else insert_ missing { id = 1 }"#,
+        )
+        .await;
+        let users = rows(&mut database, "scan users").await;
+        assert_eq!(users.len(), 1);
+        assert_eq!(users[0].0[1], Value::Int(30));
+    }
+
+    #[tokio::test]
+    async fn if_on_a_query_is_rejected() {
+        let dir = TempDir::new();
+        let mut database = users_database(&dir).await;
+        let result = database
+            .execute_str("let everyone = scan users in if everyone then everyone else everyone")
+            .await;
+        assert!(matches!(
+            result,
+            Err(DatabaseError::Query(QueryError::NonConstantCondition))
+        ));
+    }
+
+    // ana 30 (no retirement), bia 61 retiring at 65, caio 70 retiring at 65.
+    async fn people(dir: &TempDir) -> Database {
+        let mut database = users_database(dir).await;
+        rows(
+            &mut database,
+            concat!(
+                r#"insert_ users [{ name = "ana", age = 30, retirement = null }, "#,
+                r#"{ name = "bia", age = 61, retirement = 65 }, "#,
+                r#"{ name = "caio", age = 70, retirement = 65 }]"#
+            ),
+        )
+        .await;
+        database
+    }
+
+    async fn names(database: &mut Database, query: &str) -> Vec<String> {
+        rows(database, query)
+            .await
+            .into_iter()
+            .map(|row| match &row.0[0] {
+                Value::Text(name) => name.clone(),
+                other => panic!("expected a name, got {:?}", other),
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn filter_compares_arithmetic_on_two_columns() {
+        let dir = TempDir::new();
+        let mut database = people(&dir).await;
+        assert_eq!(
+            names(
+                &mut database,
+                "scan users |> filter (\\u -> u.age + 5 >= u.retirement)"
+            )
+            .await,
+            ["bia", "caio"]
+        );
+    }
+
+    #[tokio::test]
+    async fn filter_combines_predicates() {
+        let dir = TempDir::new();
+        let mut database = people(&dir).await;
+        assert_eq!(
+            names(
+                &mut database,
+                "scan users |> filter (\\u -> u.age > 40 && u.age < 65)"
+            )
+            .await,
+            ["bia"]
+        );
+        assert_eq!(
+            names(
+                &mut database,
+                "scan users |> filter (\\u -> u.age < 40 || u.age > 65)"
+            )
+            .await,
+            ["ana", "caio"]
+        );
+        assert_eq!(
+            names(
+                &mut database,
+                "scan users |> filter (\\u -> !(u.age in [30, 70]))"
+            )
+            .await,
+            ["bia"]
+        );
+        assert_eq!(
+            names(
+                &mut database,
+                "scan users |> filter (\\u -> u.retirement is null)"
+            )
+            .await,
+            ["ana"]
+        );
+    }
+
+    #[tokio::test]
+    async fn filter_compares_two_columns() {
+        let dir = TempDir::new();
+        let mut database = people(&dir).await;
+        assert_eq!(
+            names(
+                &mut database,
+                "scan users |> filter (\\u -> u.age > u.retirement)"
+            )
+            .await,
+            ["caio"]
+        );
+    }
+
+    #[tokio::test]
+    async fn filter_with_the_literal_first_is_flipped() {
+        let dir = TempDir::new();
+        let mut database = people(&dir).await;
+        assert_eq!(
+            names(&mut database, "scan users |> filter (\\u -> 61 <= u.age)").await,
+            ["bia", "caio"]
+        );
+    }
+
+    #[tokio::test]
+    async fn sort_orders_by_a_column() {
+        let dir = TempDir::new();
+        let mut database = people(&dir).await;
+        rows(
+            &mut database,
+            r#"insert_ users { name = "dani", age = 45 }"#,
+        )
+        .await;
+        assert_eq!(
+            names(&mut database, "scan users |> sort_desc (\\u -> u.age)").await,
+            ["caio", "bia", "dani", "ana"]
+        );
+        assert_eq!(
+            names(&mut database, "scan users |> sort age").await,
+            ["ana", "dani", "bia", "caio"]
+        );
+    }
+
+    #[tokio::test]
+    async fn sort_keeps_ties_in_scan_order_and_breaks_them_by_later_keys() {
+        let dir = TempDir::new();
+        let mut database = people(&dir).await;
+        assert_eq!(
+            names(&mut database, "scan users |> sort retirement").await,
+            ["ana", "bia", "caio"]
+        );
+        assert_eq!(
+            names(&mut database, "scan users |> sort_desc (retirement, age)").await,
+            ["caio", "bia", "ana"]
+        );
+    }
+
+    #[tokio::test]
+    async fn distinct_collapses_duplicate_rows() {
+        let dir = TempDir::new();
+        let mut database = people(&dir).await;
+        rows(
+            &mut database,
+            r#"insert_ users [{ name = "bia", age = 61, retirement = 65 }, { name = "ana", age = 30, retirement = null }]"#,
+        )
+        .await;
+        assert_eq!(
+            names(&mut database, "scan users |> distinct").await,
+            ["ana", "bia", "caio"]
+        );
+        assert_eq!(
+            rows(
+                &mut database,
+                "scan users |> project (retirement) |> distinct"
+            )
+            .await
+            .len(),
+            2
+        );
+    }
+
+    async fn single_value(database: &mut Database, query: &str) -> Value {
+        match rows(database, query).await.as_slice() {
+            [row] => match row.0.as_slice() {
+                [value] => value.clone(),
+                other => panic!("expected a single column, got {:?}", other),
+            },
+            other => panic!("expected a single row, got {} rows", other.len()),
+        }
+    }
+
+    #[tokio::test]
+    async fn aggregates_fold_every_row_into_one() {
+        let dir = TempDir::new();
+        let mut database = people(&dir).await;
+        assert_eq!(
+            single_value(&mut database, "scan users |> count").await,
+            Value::Long(3)
+        );
+        assert_eq!(
+            single_value(&mut database, "scan users |> sum age").await,
+            Value::Long(161)
+        );
+        assert_eq!(
+            single_value(&mut database, "scan users |> avg age").await,
+            Value::Double(161.0 / 3.0)
+        );
+        assert_eq!(
+            single_value(&mut database, "scan users |> min retirement").await,
+            Value::Int(65)
+        );
+        assert_eq!(
+            single_value(&mut database, "scan users |> max name").await,
+            Value::Text("caio".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn aggregates_over_no_rows() {
+        let dir = TempDir::new();
+        let mut database = users_database(&dir).await;
+        assert_eq!(
+            single_value(&mut database, "scan users |> count").await,
+            Value::Long(0)
+        );
+        assert_eq!(
+            single_value(&mut database, "scan users |> avg age").await,
+            Value::Null
+        );
+    }
+
+    #[tokio::test]
+    async fn sum_of_text_is_rejected() {
+        let dir = TempDir::new();
+        let mut database = people(&dir).await;
+        assert!(matches!(
+            database.execute_str("scan users |> sum name").await,
+            Err(DatabaseError::Query(QueryError::InvalidAggregateColumn { func, column, .. }))
+                if func == "sum" && column == "name"
+        ));
+    }
+
+    #[tokio::test]
+    async fn group_by_sums_each_group() {
+        let dir = TempDir::new();
+        let mut database = people(&dir).await;
+        let groups = rows(
+            &mut database,
+            "scan users |> group_by retirement (\\u -> { total = sum u.age, people = count u })",
+        )
+        .await;
+        let groups = groups.into_iter().map(|row| row.0).collect::<Vec<_>>();
+        assert_eq!(
+            groups,
+            [
+                vec![Value::Null, Value::Long(30), Value::Long(1)],
+                vec![Value::Int(65), Value::Long(131), Value::Long(2)],
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn group_by_a_double_column() {
+        let dir = TempDir::new();
+        let mut database = Database::open(dir.path()).await.unwrap();
+        rows(
+            &mut database,
+            "create_table prices { item = Text, price = Double }",
+        )
+        .await;
+        rows(
+            &mut database,
+            r#"insert_ prices [{ item = "a", price = 1.5 }, { item = "b", price = 2.5 }, { item = "c", price = 1.5 }]"#,
+        )
+        .await;
+        let groups = rows(
+            &mut database,
+            "scan prices |> group_by price (\\p -> { items = count p })",
+        )
+        .await;
+        let groups = groups.into_iter().map(|row| row.0).collect::<Vec<_>>();
+        assert_eq!(
+            groups,
+            [
+                vec![Value::Double(1.5), Value::Long(2)],
+                vec![Value::Double(2.5), Value::Long(1)],
+            ]
+        );
+    }
+
+    // ana (1) has two orders, bia (2) none, and one order belongs to no account.
+    async fn accounts_with_orders(dir: &TempDir) -> Database {
+        let mut database = accounts_database(dir).await;
+        rows(
+            &mut database,
+            "create_table orders { account = Int, total = Int }",
+        )
+        .await;
+        rows(
+            &mut database,
+            r#"insert_ accounts [{ id = 1, owner = "ana" }, { id = 2, owner = "bia" }]"#,
+        )
+        .await;
+        rows(
+            &mut database,
+            "insert_ orders [{ account = 1, total = 10 }, { account = 1, total = 20 }, { account = 3, total = 5 }]",
+        )
+        .await;
+        database
+    }
+
+    #[tokio::test]
+    async fn join_matches_rows_on_an_int_key() {
+        let dir = TempDir::new();
+        let mut database = accounts_with_orders(&dir).await;
+        let joined = rows(
+            &mut database,
+            "scan accounts |> join (scan orders) (\\a o -> a.id == o.account) |> project (owner, total)",
+        )
+        .await;
+        let joined = joined.into_iter().map(|row| row.0).collect::<Vec<_>>();
+        assert_eq!(
+            joined,
+            [
+                vec![Value::Text("ana".to_string()), Value::Int(10)],
+                vec![Value::Text("ana".to_string()), Value::Int(20)],
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn joined_columns_can_be_filtered_on() {
+        let dir = TempDir::new();
+        let mut database = accounts_with_orders(&dir).await;
+        let joined = rows(
+            &mut database,
+            "scan accounts |> join (scan orders) (id, account) |> filter (\\r -> r.total > 10)",
+        )
+        .await;
+        assert_eq!(joined.len(), 1);
+        assert_eq!(
+            joined[0].0,
+            [
+                Value::Int(1),
+                Value::Text("ana".to_string()),
+                Value::Int(1),
+                Value::Int(20)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn outer_joins_are_not_implemented() {
+        let dir = TempDir::new();
+        let mut database = accounts_with_orders(&dir).await;
+        assert!(matches!(
+            database
+                .execute_str("scan accounts |> left_join (scan orders) (id, account)")
+                .await,
+            Err(DatabaseError::Query(QueryError::NotImplemented(_)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn update_changes_matching_rows_and_counts_them() {
+        let dir = TempDir::new();
+        let mut database = people(&dir).await;
+        assert_eq!(
+            single_value(
+                &mut database,
+                "scan users |> filter (\\u -> u.age > 40) |> update (\\u -> { age = u.age + 1 })",
+            )
+            .await,
+            Value::Long(2)
+        );
+        let ages = rows(&mut database, "scan users |> project (age)").await;
+        let ages = ages
+            .into_iter()
+            .map(|row| row.0[0].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(ages, [Value::Int(30), Value::Int(62), Value::Int(71)]);
+    }
+
+    #[tokio::test]
+    async fn update_that_outgrows_its_slot_keeps_the_row() {
+        let dir = TempDir::new();
+        let mut database = users_database(&dir).await;
+        let users = (0..200)
+            .map(|i| format!(r#"{{ name = "user{}", age = {} }}"#, i, i))
+            .collect::<Vec<_>>();
+        rows(
+            &mut database,
+            &format!("insert_ users [{}]", users.join(", ")),
+        )
+        .await;
+
+        let long_name = "a".repeat(3000);
+        assert_eq!(
+            single_value(
+                &mut database,
+                &format!(
+                    r#"scan users |> filter (\u -> u.age == 0) |> update {{ name = "{}" }}"#,
+                    long_name
+                ),
+            )
+            .await,
+            Value::Long(1)
+        );
+
+        let users = rows(&mut database, "scan users").await;
+        assert_eq!(users.len(), 200);
+        let updated = rows(&mut database, "scan users |> filter (\\u -> u.age == 0)").await;
+        assert_eq!(updated[0].0[0], Value::Text(long_name));
+    }
+
+    #[tokio::test]
+    async fn decimal_column_filters_against_literals() {
+        let dir = TempDir::new();
+        let mut database = Database::open(dir.path()).await.unwrap();
+        rows(
+            &mut database,
+            "create_table prices { item = Text, price = Decimal }",
+        )
+        .await;
+        rows(
+            &mut database,
+            r#"insert_ prices [{ item = "a", price = 19.99 }, { item = "b", price = 9 }]"#,
+        )
+        .await;
+
+        let prices = rows(&mut database, "scan prices |> project (price)").await;
+        let prices = prices
+            .into_iter()
+            .map(|row| row.0[0].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            prices,
+            [
+                Value::Decimal(Decimal::new(1999, 2)),
+                Value::Decimal(Decimal::from_integer(9))
+            ]
+        );
+
+        let cheap = rows(&mut database, "scan prices |> filter (\\p -> p.price < 10)").await;
+        assert_eq!(cheap.len(), 1);
+        assert_eq!(cheap[0].0[0], Value::Text("b".to_string()));
+        let exact = rows(
+            &mut database,
+            "scan prices |> filter (\\p -> p.price >= 19.99)",
+        )
+        .await;
+        assert_eq!(exact.len(), 1);
+        assert_eq!(exact[0].0[0], Value::Text("a".to_string()));
+    }
+
+    #[tokio::test]
+    async fn long_column_filters_against_an_int_literal() {
+        let dir = TempDir::new();
+        let mut database = Database::open(dir.path()).await.unwrap();
+        rows(
+            &mut database,
+            "create_table people { name = Text, age = Long }",
+        )
+        .await;
+        rows(
+            &mut database,
+            r#"insert_ people [{ name = "ana", age = 17 }, { name = "bia", age = 3_000_000_000 }]"#,
+        )
+        .await;
+        assert_eq!(
+            names(&mut database, "scan people |> filter (\\u -> u.age > 18)").await,
+            ["bia"]
+        );
+        assert_eq!(
+            names(&mut database, "scan people |> filter (\\u -> u.age < 17.5)").await,
+            ["ana"]
+        );
+    }
+
+    #[tokio::test]
+    async fn project_reorders_columns() {
+        let dir = TempDir::new();
+        let mut database = people(&dir).await;
+        let projected = rows(&mut database, "scan users |> project (age, name)").await;
+        assert_eq!(
+            projected[0].0,
+            [Value::Int(30), Value::Text("ana".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn project_renames_columns_for_later_ops() {
+        let dir = TempDir::new();
+        let mut database = people(&dir).await;
+        let result = database
+            .execute_with_schema(
+                "scan users |> project { years = age, who = name } |> filter (\\u -> u.years > 65)",
+            )
+            .await
+            .unwrap();
+        let columns = result
+            .columns
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(columns, ["years", "who"]);
+        let projected = result.stream.try_collect::<Vec<_>>().await.unwrap();
+        assert_eq!(projected.len(), 1);
+        assert_eq!(
+            projected[0].0,
+            [Value::Int(70), Value::Text("caio".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn projecting_a_misspelled_column_is_rejected() {
+        let dir = TempDir::new();
+        let mut database = people(&dir).await;
+        assert!(matches!(
+            database.execute_str("scan users |> project (name, agee)").await,
+            Err(DatabaseError::Query(QueryError::ColumnNotFound(column, table)))
+                if column == "agee" && table == "users"
+        ));
+    }
+
+    #[tokio::test]
+    async fn offset_and_limit_count_filtered_rows() {
+        let dir = TempDir::new();
+        let mut database = people(&dir).await;
+        rows(
+            &mut database,
+            r#"insert_ users { name = "dani", age = 80 }"#,
+        )
+        .await;
+        assert_eq!(
+            names(
+                &mut database,
+                "scan users |> filter (\\u -> u.age > 40) |> offset 1"
+            )
+            .await,
+            ["caio", "dani"]
+        );
+        assert_eq!(
+            names(
+                &mut database,
+                "scan users |> filter (\\u -> u.age > 40) |> offset 1 |> limit 1"
+            )
+            .await,
+            ["caio"]
+        );
+        // Taken before the filter, the first two rows leave only one that passes it.
+        assert_eq!(
+            names(
+                &mut database,
+                "scan users |> limit 2 |> filter (\\u -> u.age > 40)"
+            )
+            .await,
+            ["bia"]
+        );
+    }
+
+    #[tokio::test]
+    async fn filter_on_a_projected_out_column_is_rejected() {
+        let dir = TempDir::new();
+        let mut database = people(&dir).await;
+        assert!(matches!(
+            database
+                .execute_str("scan users |> project (name) |> filter (\\u -> u.age > 40)")
+                .await,
+            Err(DatabaseError::Query(QueryError::ColumnProjectedOut(column, _))) if column == "age"
+        ));
+        assert_eq!(
+            names(
+                &mut database,
+                "scan users |> project (name, age) |> filter (\\u -> u.age > 65) |> project (name)"
+            )
+            .await,
+            ["caio"]
+        );
+    }
+
+    #[tokio::test]
+    async fn map_computes_new_columns() {
+        let dir = TempDir::new();
+        let mut database = people(&dir).await;
+        let result = database
+            .execute_with_schema(
+                "scan users |> map (\\u -> { name = u.name, next_age = u.age + 1 })",
+            )
+            .await
+            .unwrap();
+        let columns = result
+            .columns
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(columns, ["name", "next_age"]);
+        let mapped = result.stream.try_collect::<Vec<_>>().await.unwrap();
+        let mapped = mapped.into_iter().map(|row| row.0).collect::<Vec<_>>();
+        assert_eq!(
+            mapped,
+            [
+                vec![Value::Text("ana".to_string()), Value::Int(31)],
+                vec![Value::Text("bia".to_string()), Value::Int(62)],
+                vec![Value::Text("caio".to_string()), Value::Int(71)],
+            ]
+        );
+        assert_eq!(
+            names(
+                &mut database,
+                "scan users |> map (\\u -> { name = u.name, next_age = u.age + 1 }) |> filter (\\u -> u.next_age > 70)"
+            )
+            .await,
+            ["caio"]
+        );
+    }
+
+    #[tokio::test]
+    async fn filter_keeps_rows_in_a_list() {
+        let dir = TempDir::new();
+        let mut database = people(&dir).await;
+        assert_eq!(
+            names(
+                &mut database,
+                r#"scan users |> filter (\u -> u.name in ["caio", "ana", "zeca"])"#
+            )
+            .await,
+            ["ana", "caio"]
+        );
+        assert!(
+            names(&mut database, r"scan users |> filter (\u -> u.age in [])")
+                .await
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn filter_on_null_and_not_null() {
+        let dir = TempDir::new();
+        let mut database = people(&dir).await;
+        assert_eq!(
+            names(
+                &mut database,
+                r"scan users |> filter (\u -> u.retirement is null)"
+            )
+            .await,
+            ["ana"]
+        );
+        assert_eq!(
+            names(
+                &mut database,
+                r"scan users |> filter (\u -> u.retirement is not null)"
+            )
+            .await,
+            ["bia", "caio"]
+        );
+    }
+
+    #[tokio::test]
+    async fn leading_filters_are_pushed_into_the_scan() {
+        let dir = TempDir::new();
+        let mut database = people(&dir).await;
+        let (compiled, _) = database
+            .compile(
+                r#"scan users |> filter (\u -> u.age > 40) |> filter (\u -> u.age > u.retirement) |> project (name) |> filter (\u -> u.name != "x")"#,
+            )
+            .await
+            .unwrap();
+        let Transaction::Select { filter, ops, .. } = &compiled else {
+            panic!("expected a select, got {:?}", compiled);
+        };
+        assert!(matches!(
+            filter.as_slice(),
+            [TableOp::Filter { .. }, TableOp::PredicativeFilter(_)]
+        ));
+        assert!(matches!(
+            ops.as_slice(),
+            [TableOp::Project(_), TableOp::Filter { .. }]
+        ));
+        assert_eq!(
+            names(
+                &mut database,
+                r"scan users |> filter (\u -> u.age > 40) |> filter (\u -> u.age > u.retirement)"
+            )
+            .await,
+            ["caio"]
+        );
+    }
+
+    #[tokio::test]
+    async fn or_skips_its_right_side() {
+        let dir = TempDir::new();
+        let mut database = people(&dir).await;
+        rows(
+            &mut database,
+            r#"insert_ users { name = "newborn", age = 0 }"#,
+        )
+        .await;
+        // Dividing by the newborn's age would fail the whole predicate for that row.
+        assert_eq!(
+            names(
+                &mut database,
+                r"scan users |> filter (\u -> u.age == 0 || 100 / u.age > 2)"
+            )
+            .await,
+            ["ana", "newborn"]
+        );
+    }
+
+    #[tokio::test]
+    async fn join_takes_a_lambda_with_comma_separated_params() {
+        let dir = TempDir::new();
+        let mut database = accounts_with_orders(&dir).await;
+        let joined = rows(
+            &mut database,
+            r"scan accounts |> join (scan orders) (\a, o -> a.id == o.account)",
+        )
+        .await;
+        assert_eq!(joined.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn table_created_through_a_shared_catalog_is_queryable_at_once() {
+        let dir = TempDir::new();
+        let mut database = Database::open(dir.path()).await.unwrap();
+        let executor = QueryExecutor::new(Arc::clone(database.catalog()));
+        let reader = database.catalog().read().await;
+        let create = tokio::spawn(async move {
+            let columns = HashMap::from([column(0, "name", DataType::Text, false)]);
+            let info = TableInfo {
+                columns,
+                primary_key: None,
+            };
+            executor.create_table("pets".to_string(), info).await
+        });
+        // The table isn't created while a statement still reads the catalog.
+        tokio::task::yield_now().await;
+        assert!(!create.is_finished());
+        assert!(reader.get_table("pets").is_none());
+        drop(reader);
+        create.await.unwrap().unwrap();
+
+        rows(&mut database, r#"insert_ pets { name = "rex" }"#).await;
+        let pets = rows(&mut database, "scan pets").await;
+        assert_eq!(pets.len(), 1);
+        assert_eq!(pets[0].0, [Value::Text("rex".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn created_table_takes_inserts() {
+        let dir = TempDir::new();
+        let mut database = Database::open(dir.path()).await.unwrap();
+        rows(
+            &mut database,
+            "create_table products { name = Text, price = Double, stock = Long }",
+        )
+        .await;
+        rows(
+            &mut database,
+            r#"insert_ products { name = "pen", price = 1.5, stock = 10 }"#,
+        )
+        .await;
+        let products = rows(&mut database, "scan products").await;
+        assert_eq!(products.len(), 1);
+        assert_eq!(
+            products[0].0,
+            [
+                Value::Text("pen".to_string()),
+                Value::Double(1.5),
+                Value::Long(10)
+            ]
+        );
+
+        assert!(matches!(
+            database.execute_str("create_table products { name = Text }").await,
+            Err(DatabaseError::Storage(DbInternalError::TableAlreadyExists(ref table))) if table == "products"
+        ));
+        assert!(matches!(
+            database.execute_str("create_table sizes { size = Integer }").await,
+            Err(DatabaseError::Transform(TransformError::UnknownType(ref name))) if name == "Integer"
+        ));
+    }
+
+    #[tokio::test]
+    async fn insert_returning_all_streams_back_defaulted_columns() {
+        let dir = TempDir::new();
+        let mut database = Database::open(dir.path()).await.unwrap();
+        let (_, mut level) = column(1, "level", DataType::Int, false);
+        level.default = Some(Value::Int(1));
+        let columns = HashMap::from([
+            column(0, "name", DataType::Text, false),
+            ("level".to_string(), level),
+            column(2, "nick", DataType::Text, true),
+        ]);
+        database
+            .catalog()
+            .write()
+            .await
+            .create_table(
+                "players".to_string(),
+                TableInfo {
+                    columns,
+                    primary_key: None,
+                },
+            )
+            .await
+            .unwrap();
+
+        let inserted = rows(&mut database, r#"insert players { name = "ana" } all"#).await;
+        assert_eq!(inserted.len(), 1);
+        assert_eq!(
+            inserted[0].0,
+            [Value::Text("ana".to_string()), Value::Int(1), Value::Null]
+        );
+        let named = rows(&mut database, r#"insert players { name = "bia" } name"#).await;
+        assert_eq!(named[0].0, [Value::Text("bia".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn filter_over_a_date_column() {
+        let dir = TempDir::new();
+        let mut database = Database::open(dir.path()).await.unwrap();
+        rows(
+            &mut database,
+            "create_table events { name = Text, day = Date }",
+        )
+        .await;
+        rows(
+            &mut database,
+            concat!(
+                r#"insert_ events [{ name = "launch", day = date "2023-12-31" }, "#,
+                r#"{ name = "party", day = date "2024-01-01" }, "#,
+                r#"{ name = "review", day = date "2024-03-15" }]"#
+            ),
+        )
+        .await;
+        assert_eq!(
+            names(
+                &mut database,
+                r#"scan events |> filter (\e -> e.day >= date "2024-01-01")"#
+            )
+            .await,
+            ["party", "review"]
+        );
+    }
+
+    #[tokio::test]
+    async fn limit_zero_yields_no_rows() {
+        let dir = TempDir::new();
+        let mut database = people(&dir).await;
+        assert!(
+            names(&mut database, "scan users |> limit 0")
+                .await
+                .is_empty()
+        );
+        assert!(
+            names(&mut database, "scan users |> offset 1 |> limit 0")
+                .await
+                .is_empty()
+        );
+        assert_eq!(
+            names(&mut database, "scan users |> offset 0").await.len(),
+            3
+        );
+    }
+
+    #[tokio::test]
+    async fn take_and_drop_match_limit_and_offset() {
+        let dir = TempDir::new();
+        let mut database = people(&dir).await;
+        for (aliased, spelled_out) in [
+            (
+                "scan users |> drop 1 |> take 1",
+                "scan users |> offset 1 |> limit 1",
+            ),
+            ("scan users |> take 2", "scan users |> limit 2"),
+            (
+                "scan users |> sort age |> drop 2",
+                "scan users |> sort age |> offset 2",
+            ),
+        ] {
+            assert_eq!(
+                names(&mut database, aliased).await,
+                names(&mut database, spelled_out).await
+            );
+        }
+        assert_eq!(
+            names(&mut database, "scan users |> drop 1 |> take 1").await,
+            ["bia"]
+        );
+        assert!(matches!(
+            database.execute_str("scan users |> take -1").await,
+            Err(DatabaseError::Transform(TransformError::NegativeLimit { ref name, .. })) if name == "take"
+        ));
+    }
+
+    #[tokio::test]
+    async fn pipes_pass_the_left_side_as_the_last_argument() {
+        let dir = TempDir::new();
+        let mut database = people(&dir).await;
+        let piped = names(
+            &mut database,
+            r"scan users |> filter (\u -> u.age > 40) |> sort_desc age |> take 1",
+        )
+        .await;
+        let nested = names(
+            &mut database,
+            r"take 1 (sort_desc age (filter (\u -> u.age > 40) (scan users)))",
+        )
+        .await;
+        assert_eq!(piped, ["caio"]);
+        assert_eq!(piped, nested);
+
+        let inserted = rows(
+            &mut database,
+            r#"(name, age) |> insert users { name = "dan", age = 1, retirement = null }"#,
+        )
+        .await;
+        assert_eq!(
+            inserted[0].0,
+            [Value::Text("dan".to_string()), Value::Int(1)]
+        );
+        assert_eq!(names(&mut database, "scan users").await.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn modulus_of_a_column() {
+        let dir = TempDir::new();
+        let mut database = people(&dir).await;
+        let remainders: Vec<Value> = rows(
+            &mut database,
+            r"scan users |> sort age |> map (\u -> { rest = u.age % 7 })",
+        )
+        .await
+        .into_iter()
+        .map(|tuple| tuple.0[0].clone())
+        .collect();
+        assert_eq!(remainders, [Value::Int(2), Value::Int(5), Value::Int(0)]);
+        assert!(matches!(
+            database
+                .execute_str(r"scan users |> map (\u -> { rest = u.age % 0 })")
+                .await,
+            Err(DatabaseError::Query(QueryError::DivisionByZero))
+        ));
+    }
+
+    #[tokio::test]
+    async fn map_concatenates_text_columns() {
+        let dir = TempDir::new();
+        let mut database = people(&dir).await;
+        let greetings: Vec<Value> = rows(
+            &mut database,
+            r#"scan users |> sort age |> map (\u -> { greeting = "hi " ++ u.name })"#,
+        )
+        .await
+        .into_iter()
+        .map(|tuple| tuple.0[0].clone())
+        .collect();
+        assert_eq!(
+            greetings,
+            [
+                Value::Text("hi ana".to_string()),
+                Value::Text("hi bia".to_string()),
+                Value::Text("hi caio".to_string())
+            ]
+        );
+        assert!(matches!(
+            database
+                .execute_str(r"scan users |> map (\u -> { oops = u.age ++ u.name })")
+                .await,
+            Err(DatabaseError::Query(QueryError::InvalidOperands(..)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn filter_compares_against_a_nested_field() {
+        let dir = TempDir::new();
+        let mut database = people(&dir).await;
+        assert_eq!(
+            names(
+                &mut database,
+                concat!(
+                    "let limits = { age = { max = 65 } } in ",
+                    r"scan users |> filter (\u -> u.age > limits.age.max)"
+                )
+            )
+            .await,
+            ["caio"]
+        );
+    }
+
+    #[tokio::test]
+    async fn filter_on_a_missing_column_names_it_and_its_table() {
+        let dir = TempDir::new();
+        let mut database = people(&dir).await;
+        let result = database
+            .execute_str(r"scan users |> filter (\u -> u.nope > 1)")
+            .await;
+        assert!(matches!(
+            result,
+            Err(DatabaseError::Query(QueryError::ColumnNotFound(ref column, ref table)))
+                if column == "nope" && table == "users"
+        ));
+        assert!(matches!(
+            database
+                .execute_str(r"scan users |> filter (\u -> u.age > 1 && u.nope == 2)")
+                .await,
+            Err(DatabaseError::Query(QueryError::ColumnNotFound(..)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn result_columns_follow_projections_and_aggregates() {
+        let dir = TempDir::new();
+        let mut database = people(&dir).await;
+        let mut columns = async |query: &str| {
+            let result = database.execute_with_schema(query).await.unwrap();
+            result
+                .columns
+                .into_iter()
+                .map(|(name, data_type)| (name, data_type.id()))
+                .collect::<Vec<_>>()
+        };
+        let typed = |name: &str, data_type: DataType| (name.to_string(), data_type.id());
+
+        assert_eq!(
+            columns("scan users").await,
+            [
+                typed("name", DataType::Text),
+                typed("age", DataType::Int),
+                typed("retirement", DataType::Int)
+            ]
+        );
+        assert_eq!(
+            columns("scan users |> project (retirement, name)").await,
+            [
+                typed("retirement", DataType::Int),
+                typed("name", DataType::Text)
+            ]
+        );
+        assert_eq!(
+            columns("scan users |> project { years = age }").await,
+            [typed("years", DataType::Int)]
+        );
+        assert_eq!(
+            columns(r"scan users |> map (\u -> { half = u.age / 2.0 })").await,
+            [typed("half", DataType::Double)]
+        );
+        assert_eq!(
+            columns("scan users |> count").await,
+            [typed("count", DataType::Long)]
+        );
+    }
+
+    #[tokio::test]
+    async fn bare_count_matches_counting_the_scanned_rows() {
+        let dir = TempDir::new();
+        let mut database = users_database(&dir).await;
+        let inserts = (0..300)
+            .map(|i| {
+                format!(
+                    r#"{{ name = "{}", age = {}, retirement = null }}"#,
+                    "n".repeat(60),
+                    i
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        rows(&mut database, &format!("insert_ users [{}]", inserts)).await;
+
+        let heap = database
+            .catalog()
+            .read()
+            .await
+            .get_table("users")
+            .unwrap()
+            .heap
+            .clone();
+        assert!(heap.page_ids.lock().await.len() > 1);
+        for (page_id, slot, _) in heap
+            .scan_with_locations()
+            .await
+            .unwrap()
+            .into_iter()
+            .step_by(7)
+        {
+            heap.delete_tuple(page_id, slot).await.unwrap();
+        }
+
+        let counted = single_value(&mut database, "scan users |> count").await;
+        let iterated = single_value(
+            &mut database,
+            r"scan users |> filter (\u -> u.age >= 0) |> count",
+        )
+        .await;
+        assert_eq!(counted, Value::Long(300 - 43));
+        assert_eq!(counted, iterated);
+    }
+
+    #[tokio::test]
+    async fn leading_limit_stops_the_scan_after_enough_pages() {
+        let dir = TempDir::new();
+        let mut database = users_database(&dir).await;
+        // Long enough names that every row takes a page of its own.
+        let inserts = (0..40)
+            .map(|i| {
+                format!(
+                    r#"{{ name = "{}", age = {}, retirement = null }}"#,
+                    "n".repeat(2500),
+                    i
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        rows(&mut database, &format!("insert_ users [{}]", inserts)).await;
+
+        let (transaction, _) = database.compile("scan users |> limit 3").await.unwrap();
+        assert!(matches!(
+            transaction,
+            Transaction::Select { limit: Some(3), ref ops, .. } if ops.is_empty()
+        ));
+
+        let pool = database.catalog().read().await.buffer_pool.clone();
+        let fetches = || {
+            let stats = pool.stats();
+            stats.hits + stats.misses
+        };
+        let before = fetches();
+        assert_eq!(rows(&mut database, "scan users |> limit 3").await.len(), 3);
+        assert!(fetches() - before <= 4, "{} fetches", fetches() - before);
+
+        let before = fetches();
+        assert_eq!(rows(&mut database, "scan users").await.len(), 40);
+        assert!(fetches() - before >= 40);
+    }
+
+    #[tokio::test]
+    async fn decimal_columns_take_arithmetic_and_sums() {
+        let dir = TempDir::new();
+        let mut database = Database::open(dir.path()).await.unwrap();
+        rows(&mut database, "create_table prices { price = Decimal }").await;
+        rows(
+            &mut database,
+            "insert_ prices [{ price = 19.99 }, { price = 0.01 }, { price = 5 }]",
+        )
+        .await;
+
+        let doubled: Vec<Value> = rows(
+            &mut database,
+            r"scan prices |> map (\p -> { twice = p.price * 2 })",
+        )
+        .await
+        .into_iter()
+        .map(|row| row.0[0].clone())
+        .collect();
+        assert!(
+            doubled
+                .iter()
+                .all(|value| matches!(value, Value::Decimal(_))),
+            "{:?}",
+            doubled
+        );
+        assert_eq!(
+            doubled,
+            [
+                Value::Decimal(Decimal::new(3998, 2)),
+                Value::Decimal(Decimal::new(2, 2)),
+                Value::Decimal(Decimal::from_integer(10))
+            ]
+        );
+
+        let total = single_value(&mut database, "scan prices |> sum price").await;
+        assert!(matches!(total, Value::Decimal(d) if d == Decimal::from_integer(25)));
+        let average = single_value(&mut database, "scan prices |> avg price").await;
+        assert!(matches!(average, Value::Double(a) if (a - 25.0 / 3.0).abs() < 1e-9));
+
+        let result = database
+            .execute_with_schema(r"scan prices |> map (\p -> { twice = p.price * 2 })")
+            .await
+            .unwrap();
+        assert!(matches!(result.columns[0].1, DataType::Decimal));
+    }
+}
@@ -0,0 +1,275 @@
+use crate::query::Transaction;
+use crate::query::op::TableOp;
+use crate::table::TableCatalog;
+
+const INDENT: &str = "  ";
+
+// Renders a compiled transaction as an indented tree, one op per line. Column positions are
+// followed by the names they resolve to at that point, since projections and aggregates keep
+// reshaping the tuples.
+pub fn explain(transaction: &Transaction, catalog: &TableCatalog) -> String {
+    let mut out = String::new();
+    write_transaction(&mut out, "", transaction, catalog, 0);
+    out
+}
+
+fn write_transaction(
+    out: &mut String,
+    label: &str,
+    transaction: &Transaction,
+    catalog: &TableCatalog,
+    depth: usize,
+) -> Vec<String> {
+    let pad = format!("{}{}", INDENT.repeat(depth), label);
+    match transaction {
+        Transaction::Select {
+            table,
+            filter,
+            limit,
+            ops,
+        } => {
+            out.push_str(&format!("{}Select {}\n", pad, table));
+            let columns = table_columns(catalog, table);
+            if !filter.is_empty() {
+                out.push_str(&format!("{}Scan filter\n", INDENT.repeat(depth + 1)));
+                write_ops(out, filter, columns.clone(), depth + 2);
+            }
+            if let Some(limit) = limit {
+                out.push_str(&format!(
+                    "{}Scan limit {}\n",
+                    INDENT.repeat(depth + 1),
+                    limit
+                ));
+            }
+            write_ops(out, ops, columns, depth + 1)
+        }
+        Transaction::IndexScan {
+            table,
+            column_id,
+            value,
+            ops,
+        } => {
+            let columns = table_columns(catalog, table);
+            out.push_str(&format!(
+                "{}IndexScan {} where {} = {:?}\n",
+                pad,
+                table,
+                name(&columns, *column_id as usize),
+                value
+            ));
+            write_ops(out, ops, columns, depth + 1)
+        }
+        Transaction::Update {
+            table,
+            assignments,
+            ops,
+        } => {
+            let columns = table_columns(catalog, table);
+            let assigned: Vec<String> = assignments
+                .iter()
+                .map(|(index, _)| name(&columns, *index as usize))
+                .collect();
+            out.push_str(&format!(
+                "{}Update {} set {}\n",
+                pad,
+                table,
+                assigned.join(", ")
+            ));
+            write_ops(out, ops, columns, depth + 1);
+            vec!["updated".to_string()]
+        }
+        Transaction::Insert {
+            table,
+            rows,
+            ops,
+            returning,
+        } => {
+            let columns = table_columns(catalog, table);
+            let inserted: Vec<String> = rows
+                .first()
+                .into_iter()
+                .flatten()
+                .map(|(index, _)| name(&columns, *index as usize))
+                .collect();
+            let count = match rows.len() {
+                1 => String::new(),
+                count => format!("{} rows ", count),
+            };
+            out.push_str(&format!(
+                "{}Insert {}into {} ({})\n",
+                pad,
+                count,
+                table,
+                inserted.join(", ")
+            ));
+            let returned = returning
+                .as_deref()
+                .map(|indices| project(&columns, indices))
+                .unwrap_or_else(|| vec!["inserted".to_string()]);
+            write_ops(out, ops, returned, depth + 1)
+        }
+        Transaction::Join {
+            left,
+            right,
+            left_key,
+            right_key,
+            ops,
+        } => {
+            let mut inputs = String::new();
+            let mut columns = write_transaction(&mut inputs, "left: ", left, catalog, depth + 1);
+            let right_columns =
+                write_transaction(&mut inputs, "right: ", right, catalog, depth + 1);
+            out.push_str(&format!(
+                "{}Join on {} = {}\n",
+                pad,
+                name(&columns, *left_key),
+                name(&right_columns, *right_key)
+            ));
+            columns.extend(right_columns);
+            let columns = write_ops(out, ops, columns, depth + 1);
+            out.push_str(&inputs);
+            columns
+        }
+        Transaction::CreateTable { table, columns } => {
+            let declared: Vec<String> = columns
+                .iter()
+                .map(|(name, data_type)| format!("{} {:?}", name, data_type))
+                .collect();
+            out.push_str(&format!(
+                "{}CreateTable {} ({})\n",
+                pad,
+                table,
+                declared.join(", ")
+            ));
+            vec![]
+        }
+        Transaction::CreateIndex { table, column } => {
+            out.push_str(&format!("{}CreateIndex {} ({})\n", pad, table, column));
+            vec![]
+        }
+        Transaction::Block(steps) => {
+            out.push_str(&format!("{}Block\n", pad));
+            let mut columns = vec![];
+            for step in steps {
+                columns = write_transaction(out, "", step, catalog, depth + 1);
+            }
+            columns
+        }
+    }
+}
+
+// Writes each op with the names of the columns it touches and returns the columns left once
+// all of them ran.
+fn write_ops(
+    out: &mut String,
+    ops: &[TableOp],
+    mut columns: Vec<String>,
+    depth: usize,
+) -> Vec<String> {
+    let pad = INDENT.repeat(depth);
+    for op in ops {
+        let touched = match op {
+            TableOp::Filter { column_index, .. } => vec![name(&columns, *column_index)],
+            TableOp::Project(indices) => {
+                columns = project(&columns, indices);
+                columns.clone()
+            }
+            TableOp::Sort(keys) => keys
+                .iter()
+                .map(|(index, _)| name(&columns, *index))
+                .collect(),
+            TableOp::Aggregate(call) => {
+                let touched = call
+                    .column_index
+                    .map(|index| vec![name(&columns, index)])
+                    .unwrap_or_default();
+                columns = vec![call.alias.clone()];
+                touched
+            }
+            TableOp::GroupBy { keys, aggregates } => {
+                let key_names = project(&columns, keys);
+                let touched = key_names
+                    .iter()
+                    .cloned()
+                    .chain(
+                        aggregates
+                            .iter()
+                            .filter_map(|call| call.column_index)
+                            .map(|index| name(&columns, index)),
+                    )
+                    .collect();
+                columns = key_names
+                    .into_iter()
+                    .chain(aggregates.iter().map(|call| call.alias.clone()))
+                    .collect();
+                touched
+            }
+            // The closure doesn't keep the names it gives its output.
+            TableOp::Map(_) => {
+                columns.clear();
+                vec![]
+            }
+            _ => vec![],
+        };
+
+        if touched.is_empty() {
+            out.push_str(&format!("{}{:?}\n", pad, op));
+        } else {
+            out.push_str(&format!("{}{:?} -- {}\n", pad, op, touched.join(", ")));
+        }
+    }
+    columns
+}
+
+fn table_columns(catalog: &TableCatalog, table: &str) -> Vec<String> {
+    catalog
+        .get_table(table)
+        .map(|physical| {
+            physical
+                .info
+                .ordered_columns()
+                .into_iter()
+                .map(|col| col.name.clone())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn project(columns: &[String], indices: &[usize]) -> Vec<String> {
+    indices.iter().map(|&index| name(columns, index)).collect()
+}
+
+fn name(columns: &[String], index: usize) -> String {
+    columns
+        .get(index)
+        .cloned()
+        .unwrap_or_else(|| format!("#{}", index))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{TempDir, users_database};
+
+    #[tokio::test]
+    async fn filtered_projection_lists_its_ops_in_order() {
+        let dir = TempDir::new();
+        let mut database = users_database(&dir).await;
+        let (compiled, _) = database
+            .compile(r"scan users |> filter (\u -> u.age > 34) |> project (name) |> limit 3")
+            .await
+            .unwrap();
+        assert_eq!(
+            explain(&compiled, &*database.catalog().read().await),
+            [
+                "Select users",
+                "  Scan filter",
+                "    Filter(column_index: 1, operator: Gt, value: Int(34)) -- age",
+                "  Project(indices: [0]) -- name",
+                "  Limit(3)",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+}
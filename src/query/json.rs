@@ -0,0 +1,162 @@
+use crate::page::tuple::{DataType, Tuple, Value};
+use crate::query::err::QueryResult;
+use crate::query::exec::ResultSet;
+use futures::StreamExt;
+use std::fmt::Write;
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+// Renders the rows as an array of objects keyed by column name, one row per line.
+pub async fn rows_to_json(mut result: ResultSet) -> QueryResult<String> {
+    let mut out = String::from("[");
+    let mut first = true;
+    while let Some(tuple) = result.stream.next().await {
+        let tuple = tuple?;
+        out.push_str(if first { "\n  " } else { ",\n  " });
+        first = false;
+        write_row(&mut out, &tuple, &result.columns);
+    }
+    out.push_str(if first { "]" } else { "\n]" });
+    Ok(out)
+}
+
+fn write_row(out: &mut String, tuple: &Tuple, columns: &[(String, DataType)]) {
+    out.push('{');
+    for (index, value) in tuple.0.iter().enumerate() {
+        if index > 0 {
+            out.push_str(", ");
+        }
+        // A row can be wider than the names known for it, e.g. after a map.
+        match columns.get(index) {
+            Some((name, _)) => write_string(out, name),
+            None => write_string(out, &format!("#{}", index)),
+        }
+        out.push_str(": ");
+        write_value(out, value);
+    }
+    out.push('}');
+}
+
+fn write_value(out: &mut String, value: &Value) {
+    match value {
+        Value::Null => out.push_str("null"),
+        Value::Int(i) => write!(out, "{}", i).unwrap(),
+        Value::Long(l) => write!(out, "{}", l).unwrap(),
+        Value::Byte(b) => write!(out, "{}", b).unwrap(),
+        Value::Decimal(d) => write!(out, "{}", d).unwrap(),
+        // JSON has no way to spell NaN or the infinities.
+        Value::Float(f) if f.is_finite() => write!(out, "{}", f).unwrap(),
+        Value::Double(d) if d.is_finite() => write!(out, "{}", d).unwrap(),
+        Value::Float(_) | Value::Double(_) => out.push_str("null"),
+        Value::Text(text) => write_string(out, text),
+        Value::Boolean(b) => write!(out, "{}", b).unwrap(),
+        Value::Date(date) => write_string(out, &date.format("%Y-%m-%d").to_string()),
+        Value::DateTime(datetime) => {
+            write_string(out, &datetime.format("%Y-%m-%dT%H:%M:%S%.f").to_string())
+        }
+        Value::Blob(bytes) => write_string(out, &base64(bytes)),
+    }
+}
+
+fn write_string(out: &mut String, text: &str) {
+    out.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+fn base64(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (i, &byte)| {
+            group | (byte as u32) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                let sextet = (group >> (18 - 6 * i)) & 0x3f;
+                encoded.push(BASE64_ALPHABET[sextet as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result_set(columns: &[(&str, DataType)], rows: Vec<Vec<Value>>) -> ResultSet {
+        ResultSet {
+            columns: columns
+                .iter()
+                .map(|(name, data_type)| (name.to_string(), data_type.clone()))
+                .collect(),
+            stream: Box::pin(futures::stream::iter(
+                rows.into_iter().map(|row| Ok(Tuple(row))),
+            )),
+        }
+    }
+
+    #[tokio::test]
+    async fn mixed_row_maps_each_value_to_its_json_type() {
+        let date = chrono::NaiveDate::from_ymd_opt(2024, 2, 29).unwrap();
+        let result = result_set(
+            &[
+                ("id", DataType::Int),
+                ("name", DataType::Text),
+                ("score", DataType::Double),
+                ("active", DataType::Boolean),
+                ("retired", DataType::Null),
+                ("born", DataType::Date),
+                ("seen", DataType::DateTime),
+                ("avatar", DataType::Blob),
+            ],
+            vec![vec![
+                Value::Int(1),
+                Value::Text("say \"hi\"\n".to_string()),
+                Value::Double(1.5),
+                Value::Boolean(true),
+                Value::Null,
+                Value::Date(date),
+                Value::DateTime(date.and_hms_opt(13, 5, 0).unwrap()),
+                Value::Blob(b"akasha".to_vec()),
+            ]],
+        );
+        assert_eq!(
+            rows_to_json(result).await.unwrap(),
+            concat!(
+                "[\n",
+                r#"  {"id": 1, "name": "say \"hi\"\n", "score": 1.5, "active": true, "#,
+                r#""retired": null, "born": "2024-02-29", "seen": "2024-02-29T13:05:00", "#,
+                r#""avatar": "YWthc2hh"}"#,
+                "\n]",
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn no_rows_is_an_empty_array() {
+        let result = result_set(&[("id", DataType::Int)], vec![]);
+        assert_eq!(rows_to_json(result).await.unwrap(), "[]");
+    }
+
+    #[test]
+    fn base64_pads_partial_groups() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"a"), "YQ==");
+        assert_eq!(base64(b"ab"), "YWI=");
+        assert_eq!(base64(b"abc"), "YWJj");
+    }
+}
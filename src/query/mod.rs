@@ -1,29 +1,160 @@
+mod aggregate;
 mod builtins;
 pub mod compiler;
 pub mod err;
+mod eval;
 pub mod exec;
+pub mod explain;
+pub mod json;
 pub mod op;
 pub mod optimizer;
+mod schema;
 mod stream;
 pub mod transformer;
 
 use crate::frontend::ast::NodeId;
-use crate::page::tuple::Value;
+use crate::page::tuple::{DataType, Value};
+use crate::query::eval::ScalarExpr;
 use crate::query::op::TableOp;
 use std::rc::Rc;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Transaction {
+    // Every row sets the same columns.
     Insert {
         table: String,
-        values: Vec<(u32, Value)>,
+        rows: Vec<Vec<(u32, ScalarExpr)>>,
         ops: Vec<TableOp>,
         returning: Option<Vec<usize>>,
     },
+    // `filter` holds the filters that came first in the pipeline, which the scan itself
+    // applies; all of them have to pass. A `limit` right after them stops the scan too.
     Select {
         table: String,
+        filter: Vec<TableOp>,
+        limit: Option<usize>,
+        ops: Vec<TableOp>,
+    },
+    // `ops` keeps the equality filter the index answers, so every row it finds is rechecked.
+    IndexScan {
+        table: String,
+        column_id: u32,
+        value: Value,
+        ops: Vec<TableOp>,
+    },
+    Update {
+        table: String,
+        assignments: Vec<(u32, ScalarExpr)>,
         ops: Vec<TableOp>,
     },
+    Join {
+        left: Box<Transaction>,
+        right: Box<Transaction>,
+        left_key: usize,
+        right_key: usize,
+        ops: Vec<TableOp>,
+    },
+    // The columns in declaration order, which is also the order of their ids.
+    CreateTable {
+        table: String,
+        columns: Vec<(String, DataType)>,
+    },
+    CreateIndex {
+        table: String,
+        column: String,
+    },
+    // Runs the transactions in order and yields the rows of the last one.
+    Block(Vec<Transaction>),
+}
+
+impl Transaction {
+    // Fills every parameter placeholder in the plan with its value. The values have already
+    // been checked against the statement, so an index out of range can't happen here.
+    pub fn bind(&self, params: &[Value]) -> Transaction {
+        let bind_ops = |ops: &[TableOp]| ops.iter().map(|op| op.bind(params)).collect();
+        match self {
+            Transaction::Insert {
+                table,
+                rows,
+                ops,
+                returning,
+            } => Transaction::Insert {
+                table: table.clone(),
+                rows: rows
+                    .iter()
+                    .map(|values| {
+                        values
+                            .iter()
+                            .map(|(index, value)| (*index, value.bind(params)))
+                            .collect()
+                    })
+                    .collect(),
+                ops: bind_ops(ops),
+                returning: returning.clone(),
+            },
+            Transaction::Select {
+                table,
+                filter,
+                limit,
+                ops,
+            } => Transaction::Select {
+                table: table.clone(),
+                filter: bind_ops(filter),
+                limit: *limit,
+                ops: bind_ops(ops),
+            },
+            Transaction::IndexScan {
+                table,
+                column_id,
+                value,
+                ops,
+            } => Transaction::IndexScan {
+                table: table.clone(),
+                column_id: *column_id,
+                value: value.clone(),
+                ops: bind_ops(ops),
+            },
+            Transaction::Update {
+                table,
+                assignments,
+                ops,
+            } => Transaction::Update {
+                table: table.clone(),
+                assignments: assignments
+                    .iter()
+                    .map(|(index, value)| (*index, value.bind(params)))
+                    .collect(),
+                ops: bind_ops(ops),
+            },
+            Transaction::Join {
+                left,
+                right,
+                left_key,
+                right_key,
+                ops,
+            } => Transaction::Join {
+                left: Box::new(left.bind(params)),
+                right: Box::new(right.bind(params)),
+                left_key: *left_key,
+                right_key: *right_key,
+                ops: bind_ops(ops),
+            },
+            Transaction::CreateTable { .. } | Transaction::CreateIndex { .. } => self.clone(),
+            Transaction::Block(steps) => {
+                Transaction::Block(steps.iter().map(|step| step.bind(params)).collect())
+            }
+        }
+    }
+}
+
+// A plan compiled once and run any number of times with different parameter values. Each
+// parameter has the type it was used with, when that could be told from the query, and
+// `columns` names the values of each row the plan produces.
+#[derive(Debug, Clone)]
+pub struct PreparedStatement {
+    pub transaction: Transaction,
+    pub parameters: Vec<Option<DataType>>,
+    pub columns: Vec<(String, DataType)>,
 }
 
 #[derive(Debug, Clone)]
@@ -33,11 +164,6 @@ pub enum QueryExpr {
         operations: Vec<TransactionOp>,
     },
 
-    Bind {
-        input: Rc<QueryExpr>,
-        func: Rc<QueryExpr>,
-    },
-
     Lambda {
         params: Vec<String>,
         body: NodeId,
@@ -45,6 +171,8 @@ pub enum QueryExpr {
 
     Reference(String),
     Literal(Value),
+    // A value supplied when a prepared statement runs, zero-based.
+    Parameter(usize),
     Column(String),
 
     BinaryOp {
@@ -64,8 +192,18 @@ pub enum QueryExpr {
         body: Rc<QueryExpr>,
     },
 
+    If {
+        cond: Rc<QueryExpr>,
+        then_branch: Rc<QueryExpr>,
+        else_branch: Rc<QueryExpr>,
+    },
+
+    // The statements of a `do`, in order. Its value is the value of the last one.
+    Block(Vec<QueryExpr>),
+
     Predicate(Rc<PredicateExpr>),
     Instance(Vec<(String, QueryExpr)>),
+    List(Vec<QueryExpr>),
     Tuple(Vec<String>),
 
     BuiltInFunction {
@@ -81,16 +219,75 @@ pub enum TransactionType {
     Insert {
         table_name: String,
         value: Rc<QueryExpr>,
-        returning: Option<Vec<String>>,
+        returning: Option<Returning>,
+    },
+    Update {
+        table_name: String,
+        assignments: Vec<(String, QueryExpr)>,
+    },
+    Join {
+        left: Rc<QueryExpr>,
+        right: Rc<QueryExpr>,
+        left_key: String,
+        right_key: String,
+        join_type: JoinType,
+    },
+    CreateTable {
+        table_name: String,
+        columns: Vec<(String, DataType)>,
+    },
+    CreateIndex {
+        table_name: String,
+        column: String,
     },
 }
 
 #[derive(Debug, Clone)]
 pub enum TransactionOp {
-    Filter { predicate: Rc<PredicateExpr> },
-    Limit { count: i32 },
-    Project { columns: Vec<String> },
-    Offset { offset: i32 },
+    Filter {
+        predicate: Rc<PredicateExpr>,
+    },
+    Limit {
+        count: usize,
+    },
+    // Each column with the name it's given in the output, if not its own.
+    Project {
+        columns: Vec<(String, Option<String>)>,
+    },
+    Offset {
+        offset: usize,
+    },
+    Sort {
+        keys: Vec<(String, SortDirection)>,
+    },
+    Distinct,
+    // Replaces each row with the named values computed from it.
+    Map {
+        fields: Vec<(String, QueryExpr)>,
+    },
+    Aggregate {
+        func: String,
+        column: Option<String>,
+        alias: String,
+    },
+    GroupBy {
+        keys: Vec<String>,
+        aggregates: Vec<AggregateExpr>,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct AggregateExpr {
+    pub func: String,
+    pub column: Option<String>,
+    pub alias: String,
+}
+
+// The columns an insert streams back; `All` comes from the bare identifier `all`.
+#[derive(Debug, Clone)]
+pub enum Returning {
+    Columns(Vec<String>),
+    All,
 }
 
 #[derive(Debug, Clone)]
@@ -121,6 +318,7 @@ pub enum PredicateExpr {
     IsNotNull(QueryExpr),
     In(QueryExpr, Vec<QueryExpr>),
     Exists(Rc<QueryExpr>),
+    Constant(bool),
 }
 
 type SymbolInfo = Rc<QueryExpr>;
@@ -149,3 +347,17 @@ pub enum ComparisonOperator {
     Like,
     NotLike,
 }
+
+impl ComparisonOperator {
+    pub fn flipped(&self) -> Option<ComparisonOperator> {
+        match self {
+            ComparisonOperator::Eq => Some(ComparisonOperator::Eq),
+            ComparisonOperator::Neq => Some(ComparisonOperator::Neq),
+            ComparisonOperator::Gt => Some(ComparisonOperator::Lt),
+            ComparisonOperator::GtEq => Some(ComparisonOperator::LtEq),
+            ComparisonOperator::Lt => Some(ComparisonOperator::Gt),
+            ComparisonOperator::LtEq => Some(ComparisonOperator::GtEq),
+            ComparisonOperator::Like | ComparisonOperator::NotLike => None,
+        }
+    }
+}
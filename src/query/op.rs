@@ -1,8 +1,10 @@
-use crate::page::tuple::{Tuple, Value};
-use crate::query::ComparisonOperator;
+use crate::page::tuple::Value;
+use crate::query::aggregate::AggregateFunction;
+use crate::query::eval::{CompiledPredicate, ScalarExpr};
+use crate::query::{ComparisonOperator, SortDirection};
 use std::fmt::{Debug, Formatter};
-use std::sync::Arc;
 
+#[derive(Clone)]
 pub enum TableOp {
     Filter {
         column_index: usize,
@@ -10,10 +12,41 @@ pub enum TableOp {
         value: Value,
     },
     Project(Vec<usize>),
-    Limit(i32),
-    Offset(i32),
-    PredicativeFilter(Arc<dyn Fn(&Tuple) -> bool + Send + Sync>),
-    Map(Arc<dyn Fn(&Tuple) -> Tuple + Send + Sync>),
+    Limit(usize),
+    Offset(usize),
+    PredicativeFilter(CompiledPredicate),
+    // Replaces each row with the values of these expressions.
+    Map(Vec<ScalarExpr>),
+    Sort(Vec<(usize, SortDirection)>),
+    // Remembers the encoding of every row it lets through, so memory grows with the number
+    // of distinct rows.
+    Distinct,
+    Aggregate(AggregateCall),
+    GroupBy {
+        keys: Vec<usize>,
+        aggregates: Vec<AggregateCall>,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct AggregateCall {
+    pub function: AggregateFunction,
+    pub column_index: Option<usize>,
+    pub alias: String,
+}
+
+impl TableOp {
+    pub fn bind(&self, params: &[Value]) -> TableOp {
+        match self {
+            TableOp::PredicativeFilter(predicate) => {
+                TableOp::PredicativeFilter(predicate.bind(params))
+            }
+            TableOp::Map(exprs) => {
+                TableOp::Map(exprs.iter().map(|expr| expr.bind(params)).collect())
+            }
+            other => other.clone(),
+        }
+    }
 }
 
 impl Debug for TableOp {
@@ -45,6 +78,18 @@ impl Debug for TableOp {
             TableOp::Map(_) => {
                 write!(f, "Map")
             }
+            TableOp::Sort(keys) => {
+                write!(f, "Sort(keys: {:?})", keys)
+            }
+            TableOp::Distinct => {
+                write!(f, "Distinct")
+            }
+            TableOp::Aggregate(aggregate) => {
+                write!(f, "Aggregate({:?})", aggregate)
+            }
+            TableOp::GroupBy { keys, aggregates } => {
+                write!(f, "GroupBy(keys: {:?}, aggregates: {:?})", keys, aggregates)
+            }
         }
     }
 }
@@ -1,4 +1,7 @@
-use crate::query::QueryExpr;
+use crate::page::tuple::Value;
+use crate::query::eval::{apply_arithmetic, compare_values};
+use crate::query::{PredicateExpr, QueryExpr, TransactionOp, TransactionType};
+use std::rc::Rc;
 
 pub trait QueryOptimizer {
     fn optimize(&self, plan: QueryExpr) -> QueryExpr;
@@ -11,3 +14,189 @@ impl QueryOptimizer for IdentityOptimizer {
         plan
     }
 }
+
+// Evaluates whatever only involves literals ahead of time: arithmetic on two literals becomes
+// its result, and predicates that come out constant collapse into `true` or `false`, which
+// then absorb or drop out of the `and`s and `or`s around them. A filter that's always true
+// is removed outright. Anything that fails to evaluate, like a division by zero, is left in
+// place so running the query still reports it.
+pub struct ConstantFoldingOptimizer;
+
+impl QueryOptimizer for ConstantFoldingOptimizer {
+    fn optimize(&self, plan: QueryExpr) -> QueryExpr {
+        fold_expr(plan)
+    }
+}
+
+fn fold_rc(expr: Rc<QueryExpr>) -> Rc<QueryExpr> {
+    Rc::new(fold_expr(Rc::unwrap_or_clone(expr)))
+}
+
+fn fold_expr(expr: QueryExpr) -> QueryExpr {
+    match expr {
+        QueryExpr::BinaryOp { left, op, right } => {
+            let left = fold_expr(Rc::unwrap_or_clone(left));
+            let right = fold_expr(Rc::unwrap_or_clone(right));
+            if let (QueryExpr::Literal(a), QueryExpr::Literal(b)) = (&left, &right)
+                && let Ok(value) = apply_arithmetic(a.clone(), &op, b.clone())
+            {
+                return QueryExpr::Literal(value);
+            }
+            QueryExpr::BinaryOp {
+                left: Rc::new(left),
+                op,
+                right: Rc::new(right),
+            }
+        }
+        QueryExpr::Transaction { typ, operations } => QueryExpr::Transaction {
+            typ: fold_transaction_type(typ),
+            operations: operations.into_iter().filter_map(fold_op).collect(),
+        },
+        QueryExpr::Apply { func, args } => QueryExpr::Apply {
+            func: fold_rc(func),
+            args: args.into_iter().map(fold_expr).collect(),
+        },
+        QueryExpr::Binding { name, value, body } => QueryExpr::Binding {
+            name,
+            value: fold_rc(value),
+            body: fold_rc(body),
+        },
+        QueryExpr::If {
+            cond,
+            then_branch,
+            else_branch,
+        } => QueryExpr::If {
+            cond: fold_rc(cond),
+            then_branch: fold_rc(then_branch),
+            else_branch: fold_rc(else_branch),
+        },
+        QueryExpr::Block(statements) => {
+            QueryExpr::Block(statements.into_iter().map(fold_expr).collect())
+        }
+        QueryExpr::Predicate(predicate) => {
+            QueryExpr::Predicate(Rc::new(fold_predicate(Rc::unwrap_or_clone(predicate))))
+        }
+        QueryExpr::Instance(fields) => QueryExpr::Instance(fold_fields(fields)),
+        QueryExpr::List(items) => QueryExpr::List(items.into_iter().map(fold_expr).collect()),
+        other => other,
+    }
+}
+
+fn fold_fields(fields: Vec<(String, QueryExpr)>) -> Vec<(String, QueryExpr)> {
+    fields
+        .into_iter()
+        .map(|(name, value)| (name, fold_expr(value)))
+        .collect()
+}
+
+fn fold_transaction_type(typ: TransactionType) -> TransactionType {
+    match typ {
+        TransactionType::Insert {
+            table_name,
+            value,
+            returning,
+        } => TransactionType::Insert {
+            table_name,
+            value: fold_rc(value),
+            returning,
+        },
+        TransactionType::Update {
+            table_name,
+            assignments,
+        } => TransactionType::Update {
+            table_name,
+            assignments: fold_fields(assignments),
+        },
+        TransactionType::Join {
+            left,
+            right,
+            left_key,
+            right_key,
+            join_type,
+        } => TransactionType::Join {
+            left: fold_rc(left),
+            right: fold_rc(right),
+            left_key,
+            right_key,
+            join_type,
+        },
+        other @ (TransactionType::Scan { .. }
+        | TransactionType::CreateTable { .. }
+        | TransactionType::CreateIndex { .. }) => other,
+    }
+}
+
+fn fold_op(op: TransactionOp) -> Option<TransactionOp> {
+    match op {
+        TransactionOp::Filter { predicate } => {
+            match fold_predicate(Rc::unwrap_or_clone(predicate)) {
+                PredicateExpr::Constant(true) => None,
+                predicate => Some(TransactionOp::Filter {
+                    predicate: Rc::new(predicate),
+                }),
+            }
+        }
+        TransactionOp::Map { fields } => Some(TransactionOp::Map {
+            fields: fold_fields(fields),
+        }),
+        op => Some(op),
+    }
+}
+
+fn fold_predicate(predicate: PredicateExpr) -> PredicateExpr {
+    match predicate {
+        PredicateExpr::Comparison { left, op, right } => {
+            let left = fold_expr(left);
+            let right = fold_expr(right);
+            match (&left, &right) {
+                (QueryExpr::Literal(a), QueryExpr::Literal(b)) => {
+                    PredicateExpr::Constant(compare_values(a, &op, b))
+                }
+                _ => PredicateExpr::Comparison { left, op, right },
+            }
+        }
+        PredicateExpr::And(left, right) => {
+            let left = fold_predicate(Rc::unwrap_or_clone(left));
+            let right = fold_predicate(Rc::unwrap_or_clone(right));
+            match (left, right) {
+                (PredicateExpr::Constant(true), other) | (other, PredicateExpr::Constant(true)) => {
+                    other
+                }
+                (PredicateExpr::Constant(false), _) | (_, PredicateExpr::Constant(false)) => {
+                    PredicateExpr::Constant(false)
+                }
+                (left, right) => PredicateExpr::And(Rc::new(left), Rc::new(right)),
+            }
+        }
+        PredicateExpr::Or(left, right) => {
+            let left = fold_predicate(Rc::unwrap_or_clone(left));
+            let right = fold_predicate(Rc::unwrap_or_clone(right));
+            match (left, right) {
+                (PredicateExpr::Constant(false), other)
+                | (other, PredicateExpr::Constant(false)) => other,
+                (PredicateExpr::Constant(true), _) | (_, PredicateExpr::Constant(true)) => {
+                    PredicateExpr::Constant(true)
+                }
+                (left, right) => PredicateExpr::Or(Rc::new(left), Rc::new(right)),
+            }
+        }
+        PredicateExpr::Not(inner) => match fold_predicate(Rc::unwrap_or_clone(inner)) {
+            PredicateExpr::Constant(value) => PredicateExpr::Constant(!value),
+            inner => PredicateExpr::Not(Rc::new(inner)),
+        },
+        PredicateExpr::IsNull(expr) => match fold_expr(expr) {
+            QueryExpr::Literal(value) => PredicateExpr::Constant(matches!(value, Value::Null)),
+            expr => PredicateExpr::IsNull(expr),
+        },
+        PredicateExpr::IsNotNull(expr) => match fold_expr(expr) {
+            QueryExpr::Literal(value) => PredicateExpr::Constant(!matches!(value, Value::Null)),
+            expr => PredicateExpr::IsNotNull(expr),
+        },
+        PredicateExpr::In(expr, candidates) => PredicateExpr::In(
+            fold_expr(expr),
+            candidates.into_iter().map(fold_expr).collect(),
+        ),
+        PredicateExpr::Exists(query) => PredicateExpr::Exists(fold_rc(query)),
+        constant @ PredicateExpr::Constant(_) => constant,
+    }
+}
@@ -0,0 +1,101 @@
+use crate::page::tuple::DataType;
+use crate::query::err::{QueryError, QueryResult};
+use crate::table::TableInfo;
+
+#[derive(Debug, Clone)]
+pub struct SchemaColumn {
+    pub name: String,
+    pub data_type: DataType,
+}
+
+// The shape of the tuples flowing out of a transaction at some point of its ops, so names
+// used by later ops resolve to positions in the tuples they actually see.
+#[derive(Debug, Clone)]
+pub struct Schema {
+    pub source: String,
+    pub columns: Vec<SchemaColumn>,
+    // Names an earlier projection dropped, so using one reads as such rather than as a typo.
+    pub projected_out: Vec<String>,
+}
+
+impl Schema {
+    pub fn from_table(name: &str, info: &TableInfo) -> Self {
+        Self {
+            source: name.to_string(),
+            columns: info
+                .ordered_columns()
+                .into_iter()
+                .map(|col| SchemaColumn {
+                    name: col.name.clone(),
+                    data_type: col.data_type.clone(),
+                })
+                .collect(),
+            projected_out: vec![],
+        }
+    }
+
+    pub fn join(left: &Schema, right: &Schema) -> Self {
+        Self {
+            source: format!("{} join {}", left.source, right.source),
+            columns: left.columns.iter().chain(&right.columns).cloned().collect(),
+            projected_out: left
+                .projected_out
+                .iter()
+                .chain(&right.projected_out)
+                .cloned()
+                .collect(),
+        }
+    }
+
+    pub fn resolve(&self, name: &str) -> QueryResult<(usize, &SchemaColumn)> {
+        let mut matches = self
+            .columns
+            .iter()
+            .enumerate()
+            .filter(|(_, col)| col.name == name);
+
+        match (matches.next(), matches.next()) {
+            (Some(found), None) => Ok(found),
+            (Some(_), Some(_)) => Err(QueryError::AmbiguousColumn(
+                name.to_string(),
+                self.source.clone(),
+            )),
+            (None, _) if self.projected_out.iter().any(|col| col == name) => Err(
+                QueryError::ColumnProjectedOut(name.to_string(), self.source.clone()),
+            ),
+            (None, _) => Err(QueryError::ColumnNotFound(
+                name.to_string(),
+                self.source.clone(),
+            )),
+        }
+    }
+
+    pub fn resolve_index(&self, name: &str) -> QueryResult<usize> {
+        self.resolve(name).map(|(index, _)| index)
+    }
+
+    pub fn project(&self, indices: &[usize]) -> Self {
+        let columns: Vec<SchemaColumn> = indices.iter().map(|&i| self.columns[i].clone()).collect();
+        Self {
+            source: self.source.clone(),
+            projected_out: self.dropped_by(&columns),
+            columns,
+        }
+    }
+
+    // Sets what a projection keeps, remembering whatever it leaves behind.
+    pub fn replace_columns(&mut self, columns: Vec<SchemaColumn>) {
+        self.projected_out = self.dropped_by(&columns);
+        self.columns = columns;
+    }
+
+    fn dropped_by(&self, kept: &[SchemaColumn]) -> Vec<String> {
+        self.columns
+            .iter()
+            .map(|col| &col.name)
+            .chain(&self.projected_out)
+            .filter(|name| !kept.iter().any(|col| col.name == **name))
+            .cloned()
+            .collect()
+    }
+}
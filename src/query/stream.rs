@@ -1,45 +1,52 @@
 use crate::page::tuple::{Tuple, Value};
-use crate::query::ComparisonOperator;
-use crate::query::op::TableOp;
+use crate::query::SortDirection;
+use crate::query::aggregate::Accumulator;
+use crate::query::err::QueryResult;
+use crate::query::eval::compare_values;
+use crate::query::exec::TupleStream;
+use crate::query::op::{AggregateCall, TableOp};
+use crate::table::key_bytes;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
 use std::pin::Pin;
 use std::task::{Context, Poll};
 use tokio_stream::Stream;
 
+// Offsets and limits count the tuples that reach them, so they act on whatever the ops before
+// them let through rather than on the raw input.
 struct CombinedOpsStream<S> {
     inner: Pin<Box<S>>,
     ops: Vec<TableOp>,
-    offset_remaining: usize,
-    taken: usize,
-    limit: Option<usize>,
+    // By op position: how many tuples an offset has skipped or a limit has let through.
+    counts: Vec<usize>,
+    exhausted: bool,
+    seen: Vec<HashSet<Vec<u8>>>,
 }
 
 impl<S> CombinedOpsStream<S>
 where
-    S: Stream<Item = Tuple> + Send,
+    S: Stream<Item = QueryResult<Tuple>> + Send,
 {
     fn new(stream: S, ops: Vec<TableOp>) -> Self {
-        let (offset, limit) = ops
+        let exhausted = ops.iter().any(|op| matches!(op, TableOp::Limit(0)));
+        let distinct_count = ops
             .iter()
-            .fold((0, None), |(acc_offset, acc_limit), op| match op {
-                TableOp::Limit(count) => (
-                    acc_offset,
-                    Some(acc_limit.unwrap_or(usize::MAX).min(*count as usize)),
-                ),
-                TableOp::Offset(offset_value) => (acc_offset + *offset_value as usize, acc_limit),
-                _ => (acc_offset, acc_limit),
-            });
+            .filter(|op| matches!(op, TableOp::Distinct))
+            .count();
+        let seen = vec![HashSet::new(); distinct_count];
 
         Self {
             inner: Box::pin(stream),
+            counts: vec![0; ops.len()],
             ops,
-            offset_remaining: offset,
-            taken: 0,
-            limit,
+            exhausted,
+            seen,
         }
     }
 
-    fn apply_ops_to_tuple(&self, mut tuple: Tuple) -> Option<Tuple> {
-        for op in &self.ops {
+    fn apply_ops_to_tuple(&mut self, mut tuple: Tuple) -> QueryResult<Option<Tuple>> {
+        let mut distinct_index = 0;
+        for (position, op) in self.ops.iter().enumerate() {
             match op {
                 TableOp::Filter {
                     column_index,
@@ -48,94 +55,385 @@ where
                 } => {
                     let Tuple(ref tuple_values) = tuple;
                     let column_value = &tuple_values[*column_index];
-                    let matches = match (column_value, operator, value) {
-                        (a, ComparisonOperator::Eq, b) => a == b,
-                        (a, ComparisonOperator::Neq, b) => a != b,
-                        (a, ComparisonOperator::Gt, b) => a > b,
-                        (a, ComparisonOperator::GtEq, b) => a >= b,
-                        (a, ComparisonOperator::Lt, b) => a < b,
-                        (a, ComparisonOperator::LtEq, b) => a <= b,
-                        (a, ComparisonOperator::Like, b) => {
-                            if let (Value::Text(a), Value::Text(b)) = (a, b) {
-                                a.contains(b)
-                            } else {
-                                false
-                            }
-                        }
-                        (a, ComparisonOperator::NotLike, b) => {
-                            if let (Value::Text(a), Value::Text(b)) = (a, b) {
-                                !a.contains(b)
-                            } else {
-                                false
-                            }
-                        }
-                    };
+                    let matches = compare_values(column_value, operator, value);
                     if !matches {
-                        return None;
+                        return Ok(None);
                     }
                 }
-                TableOp::PredicativeFilter(filter_fn) => {
-                    if !filter_fn(&tuple) {
-                        return None;
+                TableOp::PredicativeFilter(predicate) => {
+                    if !predicate.matches(&tuple) {
+                        return Ok(None);
                     }
                 }
                 TableOp::Project(indices) => {
-                    let Tuple(mut tuple_values) = tuple;
-                    let projected_values = indices
-                        .iter()
-                        .map(|&idx| std::mem::replace(&mut tuple_values[idx], Value::Null))
-                        .collect();
+                    // Cloned rather than taken, since a column can be projected twice.
+                    let projected_values =
+                        indices.iter().map(|&idx| tuple.0[idx].clone()).collect();
                     tuple = Tuple(projected_values);
                 }
-                TableOp::Map(map_fn) => {
-                    tuple = map_fn(&tuple);
+                // A value that doesn't evaluate (a division by zero, say) fails the stream.
+                TableOp::Map(exprs) => {
+                    tuple = Tuple(
+                        exprs
+                            .iter()
+                            .map(|expr| expr.evaluate(&tuple))
+                            .collect::<QueryResult<_>>()?,
+                    );
+                }
+                TableOp::Offset(offset) => {
+                    if self.counts[position] < *offset {
+                        self.counts[position] += 1;
+                        return Ok(None);
+                    }
+                }
+                TableOp::Limit(count) => {
+                    self.counts[position] += 1;
+                    // Any later tuple would be stopped here, so there's no point reading on.
+                    if self.counts[position] >= *count {
+                        self.exhausted = true;
+                    }
+                }
+                TableOp::Sort(_) => {}
+                TableOp::Aggregate(_) => {}
+                TableOp::GroupBy { .. } => {}
+                TableOp::Distinct => {
+                    let seen = &mut self.seen[distinct_index];
+                    distinct_index += 1;
+                    if !seen.insert(tuple.to_bytes()) {
+                        return Ok(None);
+                    }
                 }
-                TableOp::Limit { .. } => {}
-                TableOp::Offset { .. } => {}
             }
         }
-        Some(tuple)
+        Ok(Some(tuple))
     }
 }
 
 impl<S> Stream for CombinedOpsStream<S>
 where
-    S: Stream<Item = Tuple> + Send,
+    S: Stream<Item = QueryResult<Tuple>> + Send,
 {
-    type Item = Tuple;
+    type Item = QueryResult<Tuple>;
 
+    // The first error ends the stream, whether it came from the input or from an op.
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
         loop {
-            if let Some(limit) = self.limit {
-                if self.taken >= limit {
-                    return Poll::Ready(None);
+            if self.exhausted {
+                return Poll::Ready(None);
+            }
+
+            let processed = match futures::ready!(self.inner.as_mut().poll_next(cx)) {
+                Some(Ok(tuple)) => self.apply_ops_to_tuple(tuple),
+                Some(Err(err)) => Err(err),
+                None => return Poll::Ready(None),
+            };
+            match processed {
+                Ok(Some(tuple)) => return Poll::Ready(Some(Ok(tuple))),
+                Ok(None) => {}
+                Err(err) => {
+                    self.exhausted = true;
+                    return Poll::Ready(Some(Err(err)));
                 }
             }
+        }
+    }
+}
 
-            match futures::ready!(self.inner.as_mut().poll_next(cx)) {
-                Some(tuple) => {
-                    if self.offset_remaining > 0 {
-                        self.offset_remaining -= 1;
-                        continue;
+enum SortState<S> {
+    Collecting(Pin<Box<S>>, Vec<Tuple>),
+    Draining(std::vec::IntoIter<Tuple>),
+}
+
+struct SortStream<S> {
+    state: SortState<S>,
+    keys: Vec<(usize, SortDirection)>,
+}
+
+impl<S> SortStream<S>
+where
+    S: Stream<Item = QueryResult<Tuple>> + Send,
+{
+    fn new(stream: S, keys: Vec<(usize, SortDirection)>) -> Self {
+        Self {
+            state: SortState::Collecting(Box::pin(stream), vec![]),
+            keys,
+        }
+    }
+
+    fn compare(&self, a: &Tuple, b: &Tuple) -> Ordering {
+        for (index, direction) in &self.keys {
+            let ordering = a.0[*index]
+                .partial_cmp(&b.0[*index])
+                .unwrap_or(Ordering::Equal);
+            let ordering = match direction {
+                SortDirection::Ascending => ordering,
+                SortDirection::Descending => ordering.reverse(),
+            };
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+        Ordering::Equal
+    }
+}
+
+impl<S> Stream for SortStream<S>
+where
+    S: Stream<Item = QueryResult<Tuple>> + Send,
+{
+    type Item = QueryResult<Tuple>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            match &mut self.state {
+                SortState::Collecting(inner, buffer) => match inner.as_mut().poll_next(cx) {
+                    Poll::Ready(Some(Ok(tuple))) => buffer.push(tuple),
+                    Poll::Ready(Some(Err(err))) => {
+                        self.state = SortState::Draining(vec![].into_iter());
+                        return Poll::Ready(Some(Err(err)));
+                    }
+                    Poll::Ready(None) => {
+                        let mut buffer = std::mem::take(buffer);
+                        buffer.sort_by(|a, b| self.compare(a, b));
+                        self.state = SortState::Draining(buffer.into_iter());
+                    }
+                    Poll::Pending => return Poll::Pending,
+                },
+                SortState::Draining(iter) => return Poll::Ready(iter.next().map(Ok)),
+            }
+        }
+    }
+}
+
+pub fn matches_filters(ops: &[TableOp], tuple: &Tuple) -> QueryResult<bool> {
+    Ok(ops.iter().all(|op| match op {
+        TableOp::Filter {
+            column_index,
+            operator,
+            value,
+        } => compare_values(&tuple.0[*column_index], operator, value),
+        TableOp::PredicativeFilter(predicate) => predicate.matches(tuple),
+        _ => true,
+    }))
+}
+
+type Group = (Vec<Value>, Vec<Accumulator>);
+
+enum GroupByState<S> {
+    Collecting(Pin<Box<S>>, HashMap<Vec<u8>, Group>, Vec<Vec<u8>>),
+    Draining(std::vec::IntoIter<Tuple>),
+}
+
+// Also backs the plain aggregates, which are a grouping without keys. That case folds into
+// a single row even when the input is empty, as `count` is expected to say 0 rather than
+// return nothing.
+struct GroupByStream<S> {
+    state: GroupByState<S>,
+    keys: Vec<usize>,
+    aggregates: Vec<AggregateCall>,
+}
+
+impl<S> GroupByStream<S>
+where
+    S: Stream<Item = QueryResult<Tuple>> + Send,
+{
+    fn new(stream: S, keys: Vec<usize>, aggregates: Vec<AggregateCall>) -> Self {
+        Self {
+            state: GroupByState::Collecting(Box::pin(stream), HashMap::new(), vec![]),
+            keys,
+            aggregates,
+        }
+    }
+}
+
+fn new_accumulators(aggregates: &[AggregateCall]) -> Vec<Accumulator> {
+    aggregates
+        .iter()
+        .map(|aggregate| Accumulator::new(aggregate.function))
+        .collect()
+}
+
+impl<S> Stream for GroupByStream<S>
+where
+    S: Stream<Item = QueryResult<Tuple>> + Send,
+{
+    type Item = QueryResult<Tuple>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        loop {
+            match &mut this.state {
+                GroupByState::Collecting(inner, groups, order) => {
+                    match inner.as_mut().poll_next(cx) {
+                        Poll::Ready(Some(Err(err))) => {
+                            this.state = GroupByState::Draining(vec![].into_iter());
+                            return Poll::Ready(Some(Err(err)));
+                        }
+                        Poll::Ready(Some(Ok(tuple))) => {
+                            let key =
+                                Tuple(this.keys.iter().map(|&i| tuple.0[i].clone()).collect());
+                            let key_bytes = key.to_bytes();
+                            let (_, accumulators) = match groups.get_mut(&key_bytes) {
+                                Some(group) => group,
+                                None => {
+                                    order.push(key_bytes.clone());
+                                    groups
+                                        .entry(key_bytes)
+                                        .or_insert((key.0, new_accumulators(&this.aggregates)))
+                                }
+                            };
+                            for (accumulator, aggregate) in
+                                accumulators.iter_mut().zip(&this.aggregates)
+                            {
+                                let value = aggregate.column_index.map(|index| &tuple.0[index]);
+                                accumulator.update(value);
+                            }
+                        }
+                        Poll::Ready(None) => {
+                            let mut groups = std::mem::take(groups);
+                            let mut order = std::mem::take(order);
+                            if this.keys.is_empty() && order.is_empty() {
+                                order.push(vec![]);
+                                groups.insert(vec![], (vec![], new_accumulators(&this.aggregates)));
+                            }
+
+                            let rows = order
+                                .into_iter()
+                                .filter_map(|key| groups.remove(&key))
+                                .map(|(mut values, accumulators)| {
+                                    values.extend(accumulators.iter().map(Accumulator::finish));
+                                    Tuple(values)
+                                })
+                                .collect::<Vec<_>>();
+                            this.state = GroupByState::Draining(rows.into_iter());
+                        }
+                        Poll::Pending => return Poll::Pending,
                     }
+                }
+                GroupByState::Draining(iter) => return Poll::Ready(iter.next().map(Ok)),
+            }
+        }
+    }
+}
+
+// Builds a hash table over the right side, then probes it with the left one. Keys are
+// hashed by their encoding and null keys never match, like in SQL.
+struct HashJoinStream {
+    build: Option<TupleStream>,
+    table: HashMap<Vec<u8>, Vec<Tuple>>,
+    probe: TupleStream,
+    left_key: usize,
+    right_key: usize,
+    matches: std::vec::IntoIter<Tuple>,
+}
+
+fn join_key(value: &Value) -> Option<Vec<u8>> {
+    match value {
+        Value::Null => None,
+        value => Some(key_bytes(value)),
+    }
+}
 
-                    if let Some(processed_tuple) = self.apply_ops_to_tuple(tuple) {
-                        self.taken += 1;
-                        return Poll::Ready(Some(processed_tuple));
+impl Stream for HashJoinStream {
+    type Item = QueryResult<Tuple>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        while let Some(build) = this.build.as_mut() {
+            match build.as_mut().poll_next(cx) {
+                Poll::Ready(Some(Err(err))) => {
+                    this.build = None;
+                    this.probe = Box::pin(futures::stream::empty());
+                    return Poll::Ready(Some(Err(err)));
+                }
+                Poll::Ready(Some(Ok(tuple))) => {
+                    if let Some(key) = join_key(&tuple.0[this.right_key]) {
+                        this.table.entry(key).or_default().push(tuple);
                     }
                 }
-                None => return Poll::Ready(None),
+                Poll::Ready(None) => this.build = None,
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+
+        loop {
+            if let Some(tuple) = this.matches.next() {
+                return Poll::Ready(Some(Ok(tuple)));
+            }
+
+            match this.probe.as_mut().poll_next(cx) {
+                Poll::Ready(Some(Err(err))) => return Poll::Ready(Some(Err(err))),
+                Poll::Ready(Some(Ok(left))) => {
+                    let Some(rows) =
+                        join_key(&left.0[this.left_key]).and_then(|key| this.table.get(&key))
+                    else {
+                        continue;
+                    };
+                    let joined: Vec<Tuple> = rows
+                        .iter()
+                        .map(|right| Tuple(left.0.iter().chain(&right.0).cloned().collect()))
+                        .collect();
+                    this.matches = joined.into_iter();
+                }
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
             }
         }
     }
 }
 
-pub fn apply_ops<S>(
-    stream: S,
-    ops: Vec<TableOp>,
-) -> Pin<Box<dyn Stream<Item = Tuple> + Send + 'static>>
+pub fn hash_join(
+    left: TupleStream,
+    right: TupleStream,
+    left_key: usize,
+    right_key: usize,
+) -> TupleStream {
+    Box::pin(HashJoinStream {
+        build: Some(right),
+        table: HashMap::new(),
+        probe: left,
+        left_key,
+        right_key,
+        matches: vec![].into_iter(),
+    })
+}
+
+// Sorting and aggregating have to see every tuple before yielding one, so the ops are split
+// at each of them: everything before runs per tuple, then the stage buffers, then the rest
+// runs on its output.
+pub fn apply_ops<S>(stream: S, ops: Vec<TableOp>) -> TupleStream
 where
-    S: Stream<Item = Tuple> + Send + 'static,
+    S: Stream<Item = QueryResult<Tuple>> + Send + 'static,
 {
-    Box::pin(CombinedOpsStream::new(stream, ops))
+    let mut stream: TupleStream = Box::pin(stream);
+    let mut pending = vec![];
+    for op in ops {
+        match op {
+            TableOp::Sort(keys) => {
+                if !pending.is_empty() {
+                    stream = Box::pin(CombinedOpsStream::new(stream, std::mem::take(&mut pending)));
+                }
+                stream = Box::pin(SortStream::new(stream, keys));
+            }
+            TableOp::Aggregate(aggregate) => {
+                if !pending.is_empty() {
+                    stream = Box::pin(CombinedOpsStream::new(stream, std::mem::take(&mut pending)));
+                }
+                stream = Box::pin(GroupByStream::new(stream, vec![], vec![aggregate]));
+            }
+            TableOp::GroupBy { keys, aggregates } => {
+                if !pending.is_empty() {
+                    stream = Box::pin(CombinedOpsStream::new(stream, std::mem::take(&mut pending)));
+                }
+                stream = Box::pin(GroupByStream::new(stream, keys, aggregates));
+            }
+            op => pending.push(op),
+        }
+    }
+
+    if pending.is_empty() {
+        stream
+    } else {
+        Box::pin(CombinedOpsStream::new(stream, pending))
+    }
 }
@@ -4,8 +4,8 @@ use crate::page::tuple::Value;
 use crate::query::builtins::BuiltInTransactionFunction;
 use crate::query::err::TransformError;
 use crate::query::optimizer::QueryOptimizer;
+use crate::query::{AggregateExpr, PredicateExpr, QueryExpr};
 use crate::query::{BinaryOperator, ComparisonOperator};
-use crate::query::{PredicateExpr, QueryExpr};
 use std::collections::HashMap;
 use std::rc::Rc;
 
@@ -50,6 +50,27 @@ impl<'a> AstToQueryTransformer<'a> {
         builtin("project", 2, crate::query::builtins::project_impl);
         builtin("limit", 2, crate::query::builtins::limit_impl);
         builtin("offset", 2, crate::query::builtins::offset_impl);
+        builtin("take", 2, crate::query::builtins::take_impl);
+        builtin("drop", 2, crate::query::builtins::drop_impl);
+        builtin("sort", 2, crate::query::builtins::sort_impl);
+        builtin("sort_desc", 2, crate::query::builtins::sort_desc_impl);
+        builtin("distinct", 1, crate::query::builtins::distinct_impl);
+        builtin("map", 2, crate::query::builtins::map_impl);
+        builtin("count", 1, crate::query::builtins::count_impl);
+        builtin("sum", 2, crate::query::builtins::sum_impl);
+        builtin("avg", 2, crate::query::builtins::avg_impl);
+        builtin("min", 2, crate::query::builtins::min_impl);
+        builtin("max", 2, crate::query::builtins::max_impl);
+        builtin("group_by", 3, crate::query::builtins::group_by_impl);
+        builtin("update", 2, crate::query::builtins::update_impl);
+        builtin("join", 3, crate::query::builtins::join_impl);
+        builtin("left_join", 3, crate::query::builtins::left_join_impl);
+        builtin("right_join", 3, crate::query::builtins::right_join_impl);
+        builtin("full_join", 3, crate::query::builtins::full_join_impl);
+        builtin("create_table", 2, crate::query::builtins::create_table_impl);
+        builtin("create_index", 2, crate::query::builtins::create_index_impl);
+        builtin("date", 1, crate::query::builtins::date_impl);
+        builtin("datetime", 1, crate::query::builtins::datetime_impl);
 
         Self {
             arena,
@@ -67,7 +88,7 @@ impl<'a> AstToQueryTransformer<'a> {
         Ok(self.optimizer.optimize(unoptimized))
     }
 
-    fn transform_node(&mut self, node_id: NodeId) -> Result<QueryExpr, TransformError> {
+    pub(crate) fn transform_node(&mut self, node_id: NodeId) -> Result<QueryExpr, TransformError> {
         match self.arena.get(node_id) {
             Expr::Reference(name_id) => {
                 let name = self.arena.resolve_str(*name_id).to_string();
@@ -87,15 +108,6 @@ impl<'a> AstToQueryTransformer<'a> {
                 let func_expr = self.transform_node(*func)?;
                 let arg_exprs = self.transform_args(args.to_vec())?;
 
-                if let QueryExpr::Reference(op_name) = &func_expr {
-                    if op_name == "|>" && arg_exprs.len() == 2 {
-                        return Ok(QueryExpr::Bind {
-                            input: Rc::new(arg_exprs[0].clone()),
-                            func: Rc::new(arg_exprs[1].clone()),
-                        });
-                    }
-                }
-
                 match func_expr {
                     QueryExpr::BuiltInFunction { name } => {
                         if let Some(built_in) = self.built_in_functions.get(&name) {
@@ -131,6 +143,30 @@ impl<'a> AstToQueryTransformer<'a> {
                     body: Rc::new(body_expr),
                 })
             }
+            Expr::Block(statements) => {
+                let statements = statements.to_vec();
+                self.push_scope();
+                let transformed = statements
+                    .into_iter()
+                    .map(|statement| self.transform_node(statement))
+                    .collect::<Result<Vec<_>, _>>();
+                self.pop_scope();
+                let mut transformed = transformed?;
+                match transformed.len() {
+                    0 => Err(TransformError::EmptyBlock),
+                    1 => Ok(transformed.remove(0)),
+                    _ => Ok(QueryExpr::Block(transformed)),
+                }
+            }
+            Expr::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => Ok(QueryExpr::If {
+                cond: Rc::new(self.transform_node(*cond)?),
+                then_branch: Rc::new(self.transform_node(*then_branch)?),
+                else_branch: Rc::new(self.transform_node(*else_branch)?),
+            }),
             Expr::BinaryOp { op, left, right } => {
                 let left_expr = self.transform_node(*left)?;
                 let right_expr = self.transform_node(*right)?;
@@ -141,8 +177,16 @@ impl<'a> AstToQueryTransformer<'a> {
                     TokenKind::Asterisk => BinaryOperator::Multiply,
                     TokenKind::Slash => BinaryOperator::Divide,
                     TokenKind::Percent => BinaryOperator::Modulus,
-                    _ => return Err(TransformError::UnsupportedOperator(op.clone())),
+                    TokenKind::Caret => BinaryOperator::Power,
+                    TokenKind::PlusPlus => BinaryOperator::Concat,
+                    _ => return Err(TransformError::UnsupportedOperator(*op)),
                 };
+                // A null column still propagates through arithmetic, but a literal one can only
+                // be a mistake.
+                let is_null = |expr: &QueryExpr| matches!(expr, QueryExpr::Literal(Value::Null));
+                if is_null(&left_expr) || is_null(&right_expr) {
+                    return Err(TransformError::NullOperand(operator));
+                }
 
                 Ok(QueryExpr::BinaryOp {
                     left: Rc::new(left_expr),
@@ -151,9 +195,11 @@ impl<'a> AstToQueryTransformer<'a> {
                 })
             }
             Expr::Number(num_str) => {
-                let num_str = self.arena.resolve_str(*num_str);
+                let num_str = self.arena.resolve_str(*num_str).replace('_', "");
                 if let Ok(n) = num_str.parse::<i32>() {
                     Ok(QueryExpr::Literal(Value::Int(n)))
+                } else if let Ok(n) = num_str.parse::<i64>() {
+                    Ok(QueryExpr::Literal(Value::Long(n)))
                 } else if let Ok(f) = num_str.parse::<f64>() {
                     Ok(QueryExpr::Literal(Value::Double(f)))
                 } else {
@@ -164,7 +210,16 @@ impl<'a> AstToQueryTransformer<'a> {
                 let string_value = self.arena.resolve_str(*str_id).to_string();
                 Ok(QueryExpr::Literal(Value::Text(string_value)))
             }
+            Expr::CharLit(c) => {
+                if c.is_ascii() {
+                    Ok(QueryExpr::Literal(Value::Byte(*c as u8)))
+                } else {
+                    Err(TransformError::NonAsciiChar(*c))
+                }
+            }
             Expr::Bool(value) => Ok(QueryExpr::Literal(Value::Boolean(*value))),
+            Expr::Null => Ok(QueryExpr::Literal(Value::Null)),
+            Expr::Parameter(index) => Ok(QueryExpr::Parameter(*index)),
             Expr::Instance(values) => {
                 let mut fields = Vec::new();
                 for (name_id, value) in values {
@@ -174,21 +229,42 @@ impl<'a> AstToQueryTransformer<'a> {
                 }
                 Ok(QueryExpr::Instance(fields))
             }
+            Expr::Array(items) => {
+                let items = items.to_vec();
+                let values = items
+                    .into_iter()
+                    .map(|item| self.transform_node(item))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(QueryExpr::List(values))
+            }
             Expr::FieldAccess { base, field } => {
-                let base_name = match self.arena.get(*base) {
-                    Expr::Reference(name_id) => self.arena.resolve_str(*name_id).to_string(),
-                    _ => return Err(TransformError::InvalidFieldAccess),
-                };
+                let field_name = self.arena.resolve_str(*field).to_string();
+                if let Expr::Reference(name_id) = self.arena.get(*base)
+                    && self.current_row_variable.as_deref()
+                        == Some(self.arena.resolve_str(*name_id))
+                {
+                    return Ok(QueryExpr::Column(field_name));
+                }
 
-                if let Some(row_var) = &self.current_row_variable {
-                    if base_name == *row_var {
-                        let field_name = self.arena.resolve_str(*field).to_string();
-                        Ok(QueryExpr::Column(field_name))
-                    } else {
-                        Err(TransformError::InvalidFieldAccess)
-                    }
-                } else {
-                    Err(TransformError::InvalidFieldAccess)
+                // Anything past the row variable has to be an instance known right here, like
+                // one bound by a `let`, since columns can't hold structs.
+                match self.transform_node(*base)? {
+                    QueryExpr::Instance(fields) => fields
+                        .into_iter()
+                        .find(|(name, _)| *name == field_name)
+                        .map(|(_, value)| value)
+                        .ok_or_else(|| TransformError::UnknownField {
+                            path: self.access_path(*base),
+                            field: field_name,
+                        }),
+                    QueryExpr::Column(_) => Err(TransformError::ColumnHasNoFields {
+                        path: self.access_path(*base),
+                        field: field_name,
+                    }),
+                    _ => Err(TransformError::InvalidFieldAccess {
+                        path: self.access_path(*base),
+                        field: field_name,
+                    }),
                 }
             }
             Expr::Tuple(tuple) => {
@@ -210,6 +286,21 @@ impl<'a> AstToQueryTransformer<'a> {
         }
     }
 
+    // The access as it was written, for errors.
+    fn access_path(&self, node_id: NodeId) -> String {
+        match self.arena.get(node_id) {
+            Expr::Reference(name_id) => self.arena.resolve_str(*name_id).to_string(),
+            Expr::FieldAccess { base, field } => {
+                format!(
+                    "{}.{}",
+                    self.access_path(*base),
+                    self.arena.resolve_str(*field)
+                )
+            }
+            _ => "(...)".to_string(),
+        }
+    }
+
     fn transform_args(
         &mut self,
         args_nodes: Vec<NodeId>,
@@ -246,28 +337,52 @@ impl<'a> AstToQueryTransformer<'a> {
         node_id: NodeId,
     ) -> Result<PredicateExpr, TransformError> {
         match self.arena.get(node_id) {
+            Expr::Bool(value) => Ok(PredicateExpr::Constant(*value)),
+            Expr::BinaryOp {
+                op: TokenKind::Is,
+                left,
+                ..
+            } => Ok(PredicateExpr::IsNull(self.transform_node(*left)?)),
+            Expr::BinaryOp {
+                op: TokenKind::In,
+                left,
+                right,
+            } => {
+                let Expr::Array(items) = self.arena.get(*right) else {
+                    return Err(TransformError::ExpectedList);
+                };
+                let candidates = items
+                    .iter()
+                    .map(|item| self.transform_node(*item))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(PredicateExpr::In(self.transform_node(*left)?, candidates))
+            }
+            // The operands are predicates themselves, so they can't go through `transform_node`.
+            Expr::BinaryOp {
+                op: op @ (TokenKind::And | TokenKind::Or),
+                left,
+                right,
+            } => {
+                let left_pred = Rc::new(self.transform_to_predicate(*left)?);
+                let right_pred = Rc::new(self.transform_to_predicate(*right)?);
+                if *op == TokenKind::And {
+                    Ok(PredicateExpr::And(left_pred, right_pred))
+                } else {
+                    Ok(PredicateExpr::Or(left_pred, right_pred))
+                }
+            }
             Expr::BinaryOp { op, left, right } => {
                 let left_expr = self.transform_node(*left)?;
                 let right_expr = self.transform_node(*right)?;
 
                 let operator = match op {
-                    TokenKind::Equals => ComparisonOperator::Eq,
+                    TokenKind::EqualsEquals => ComparisonOperator::Eq,
                     TokenKind::NotEquals => ComparisonOperator::Neq,
                     TokenKind::GreaterThan => ComparisonOperator::Gt,
                     TokenKind::GreaterThanEquals => ComparisonOperator::GtEq,
                     TokenKind::LessThan => ComparisonOperator::Lt,
                     TokenKind::LessThanEquals => ComparisonOperator::LtEq,
-                    TokenKind::And => {
-                        let left_pred = self.transform_to_predicate(*left)?;
-                        let right_pred = self.transform_to_predicate(*right)?;
-                        return Ok(PredicateExpr::And(Rc::new(left_pred), Rc::new(right_pred)));
-                    }
-                    TokenKind::Or => {
-                        let left_pred = self.transform_to_predicate(*left)?;
-                        let right_pred = self.transform_to_predicate(*right)?;
-                        return Ok(PredicateExpr::Or(Rc::new(left_pred), Rc::new(right_pred)));
-                    }
-                    _ => return Err(TransformError::UnsupportedOperator(op.clone())),
+                    _ => return Err(TransformError::UnsupportedOperator(*op)),
                 };
 
                 Ok(PredicateExpr::Comparison {
@@ -278,16 +393,107 @@ impl<'a> AstToQueryTransformer<'a> {
             }
             Expr::UnaryOp { op, operand } => {
                 if *op == TokenKind::Not {
-                    let pred = self.transform_to_predicate(*operand)?;
-                    Ok(PredicateExpr::Not(Rc::new(pred)))
+                    match self.transform_to_predicate(*operand)? {
+                        PredicateExpr::IsNull(expr) => Ok(PredicateExpr::IsNotNull(expr)),
+                        pred => Ok(PredicateExpr::Not(Rc::new(pred))),
+                    }
                 } else {
-                    Err(TransformError::UnsupportedOperator(op.clone()))
+                    Err(TransformError::UnsupportedOperator(*op))
                 }
             }
             u => Err(TransformError::UnsupportedExpression(u.clone())),
         }
     }
 
+    pub(crate) fn transform_to_aggregates(
+        &mut self,
+        node_id: NodeId,
+    ) -> Result<Vec<AggregateExpr>, TransformError> {
+        let Expr::Instance(fields) = self.arena.get(node_id) else {
+            return Err(TransformError::InvalidArgument("group_by".to_string()));
+        };
+
+        let mut aggregates = Vec::new();
+        for (alias_id, value) in fields {
+            let alias = self.arena.resolve_str(*alias_id).to_string();
+            let Some((func, args)) = self.arena.extract_function_call(*value) else {
+                return Err(TransformError::InvalidArgument("group_by".to_string()));
+            };
+            let func = match self.arena.get(func) {
+                Expr::Reference(name_id) => self.arena.resolve_str(*name_id).to_string(),
+                _ => return Err(TransformError::InvalidArgument("group_by".to_string())),
+            };
+            let column = match args.as_slice() {
+                [arg] => match self.arena.get(*arg) {
+                    Expr::Reference(name_id)
+                        if self.current_row_variable.as_deref()
+                            == Some(self.arena.resolve_str(*name_id)) =>
+                    {
+                        None
+                    }
+                    _ => match self.transform_node(*arg)? {
+                        QueryExpr::Column(name) => Some(name),
+                        _ => return Err(TransformError::InvalidArgument(func)),
+                    },
+                },
+                _ => return Err(TransformError::InvalidArgument(func)),
+            };
+            aggregates.push(AggregateExpr {
+                func,
+                column,
+                alias,
+            });
+        }
+        Ok(aggregates)
+    }
+
+    pub(crate) fn transform_to_join_keys(
+        &mut self,
+        params: &[String],
+        node_id: NodeId,
+    ) -> Result<(String, String), TransformError> {
+        let invalid = || TransformError::InvalidArgument("join".to_string());
+        let [left_param, right_param] = params else {
+            return Err(TransformError::InvalidLambdaParams);
+        };
+        let Expr::BinaryOp {
+            op: TokenKind::EqualsEquals,
+            left,
+            right,
+        } = self.arena.get(node_id)
+        else {
+            return Err(invalid());
+        };
+
+        let field = |node: NodeId| -> Result<(String, String), TransformError> {
+            match self.arena.get(node) {
+                Expr::FieldAccess { base, field } => match self.arena.get(*base) {
+                    Expr::Reference(name_id) => Ok((
+                        self.arena.resolve_str(*name_id).to_string(),
+                        self.arena.resolve_str(*field).to_string(),
+                    )),
+                    _ => Err(TransformError::InvalidFieldAccess {
+                        path: self.access_path(*base),
+                        field: self.arena.resolve_str(*field).to_string(),
+                    }),
+                },
+                _ => Err(invalid()),
+            }
+        };
+        let first = field(*left)?;
+        let second = field(*right)?;
+
+        match (first, second) {
+            ((a, left_key), (b, right_key)) if a == *left_param && b == *right_param => {
+                Ok((left_key, right_key))
+            }
+            ((a, right_key), (b, left_key)) if a == *right_param && b == *left_param => {
+                Ok((left_key, right_key))
+            }
+            _ => Err(invalid()),
+        }
+    }
+
     pub(crate) fn push_scope(&mut self) {
         self.current_scope.push(SymbolTable {
             symbols: HashMap::new(),
@@ -312,3 +518,262 @@ impl<'a> AstToQueryTransformer<'a> {
         self.current_row_variable = None;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frontend::lexer::Lexer;
+    use crate::frontend::parser::parse_expression_all;
+    use crate::query::TransactionOp;
+    use crate::query::optimizer::ConstantFoldingOptimizer;
+
+    fn transform(source: &str) -> Result<QueryExpr, TransformError> {
+        let tokens = Lexer::new(source).tokenize().unwrap();
+        let mut arena = Arena::with_capacity(100, 100);
+        let root = parse_expression_all(&tokens, &mut arena).unwrap();
+        AstToQueryTransformer::new(&arena, Box::new(ConstantFoldingOptimizer)).transform(root)
+    }
+
+    fn literal(source: &str) -> Value {
+        match transform(source).unwrap() {
+            QueryExpr::Literal(value) => value,
+            other => panic!("{} isn't a literal: {:?}", source, other),
+        }
+    }
+
+    #[test]
+    fn exponents_make_doubles() {
+        assert_eq!(literal("1e10"), Value::Double(1e10));
+        assert_eq!(literal("2.5e-3"), Value::Double(0.0025));
+        assert_eq!(literal("1.0E+5"), Value::Double(100_000.0));
+    }
+
+    #[test]
+    fn ascii_chars_are_bytes() {
+        assert_eq!(literal("'a'"), Value::Byte(b'a'));
+        assert_eq!(literal(r"'\n'"), Value::Byte(b'\n'));
+        assert!(matches!(transform("'é'"), Err(TransformError::NonAsciiChar('é'))));
+    }
+
+    #[test]
+    fn digit_separators_are_dropped() {
+        assert_eq!(literal("1_000"), Value::Int(1000));
+        assert_eq!(literal("3_000_000_000"), Value::Long(3_000_000_000));
+        assert_eq!(literal("1_000.5"), Value::Double(1000.5));
+    }
+
+    #[test]
+    fn if_branches_are_folded() {
+        let expr = transform("if true then 2 + 3 else 4 * 5").unwrap();
+        let QueryExpr::If {
+            cond,
+            then_branch,
+            else_branch,
+        } = expr
+        else {
+            panic!("expected an if, got {:?}", expr);
+        };
+        assert!(matches!(*cond, QueryExpr::Literal(Value::Boolean(true))));
+        assert!(matches!(*then_branch, QueryExpr::Literal(Value::Int(5))));
+        assert!(matches!(*else_branch, QueryExpr::Literal(Value::Int(20))));
+    }
+
+    #[test]
+    fn integers_past_i32_become_longs() {
+        assert_eq!(literal("2147483647"), Value::Int(i32::MAX));
+        assert_eq!(literal("2147483648"), Value::Long(2_147_483_648));
+        assert_eq!(literal("9223372036854775807"), Value::Long(i64::MAX));
+        assert_eq!(literal("9223372036854775808"), Value::Double(9.223372036854776e18));
+        assert_eq!(literal("-2147483649"), Value::Long(-2_147_483_649));
+    }
+
+    fn filter_predicate(source: &str) -> PredicateExpr {
+        match transform(source).unwrap() {
+            QueryExpr::Transaction { operations, .. } => match operations.as_slice() {
+                [TransactionOp::Filter { predicate }] => (**predicate).clone(),
+                other => panic!("expected a single filter, got {:?}", other),
+            },
+            other => panic!("expected a transaction, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn null_tests_become_their_own_predicates() {
+        assert!(matches!(
+            filter_predicate(r"scan users |> filter (\u -> u.retirement is null)"),
+            PredicateExpr::IsNull(QueryExpr::Column(column)) if column == "retirement"
+        ));
+        assert!(matches!(
+            filter_predicate(r"scan users |> filter (\u -> u.retirement is not null)"),
+            PredicateExpr::IsNotNull(QueryExpr::Column(column)) if column == "retirement"
+        ));
+    }
+
+    #[test]
+    fn literal_arithmetic_folds() {
+        assert_eq!(literal("2 + 3 * 4"), Value::Int(14));
+        assert_eq!(literal("(2 + 3) * 4"), Value::Int(20));
+        assert_eq!(literal("1 + 0.5"), Value::Double(1.5));
+    }
+
+    #[test]
+    fn constant_sides_of_a_predicate_fold_away() {
+        assert!(matches!(
+            filter_predicate(r"scan users |> filter (\u -> true && u.age > 1 + 1)"),
+            PredicateExpr::Comparison {
+                left: QueryExpr::Column(_),
+                op: ComparisonOperator::Gt,
+                right: QueryExpr::Literal(Value::Int(2)),
+            }
+        ));
+        // A filter that always passes isn't kept at all.
+        assert!(matches!(
+            transform(r"scan users |> filter (\u -> 1 < 2 || u.age > 1)"),
+            Ok(QueryExpr::Transaction { operations, .. }) if operations.is_empty()
+        ));
+        assert!(matches!(
+            filter_predicate(r"scan users |> filter (\u -> u.age > 1 && !(1 < 2))"),
+            PredicateExpr::Constant(false)
+        ));
+    }
+
+    #[test]
+    fn and_or_mix_keeps_precedence() {
+        let predicate = filter_predicate(
+            r"scan users |> filter (\u -> u.age < 18 || u.age > 65 && u.retirement is null)",
+        );
+        let PredicateExpr::Or(left, right) = predicate else {
+            panic!("expected an or, got {:?}", predicate);
+        };
+        assert!(matches!(&*left, PredicateExpr::Comparison { op: ComparisonOperator::Lt, .. }));
+        assert!(matches!(
+            &*right,
+            PredicateExpr::And(gt, null)
+                if matches!(**gt, PredicateExpr::Comparison { op: ComparisonOperator::Gt, .. })
+                    && matches!(**null, PredicateExpr::IsNull(_))
+        ));
+    }
+
+    #[test]
+    fn null_is_a_literal_that_arithmetic_rejects() {
+        assert_eq!(literal("null"), Value::Null);
+        assert!(matches!(
+            transform(r#"insert_ users { name = "x", retirement = null }"#),
+            Ok(QueryExpr::Transaction { .. })
+        ));
+        assert!(matches!(
+            transform("1 + null"),
+            Err(TransformError::NullOperand(BinaryOperator::Add))
+        ));
+        assert!(matches!(
+            transform(r"scan users |> filter (\u -> u.age * null > 1)"),
+            Err(TransformError::NullOperand(BinaryOperator::Multiply))
+        ));
+    }
+
+    #[test]
+    fn date_and_datetime_literals() {
+        let day = chrono::NaiveDate::from_ymd_opt(2024, 2, 29).unwrap();
+        assert_eq!(literal(r#"date "2024-02-29""#), Value::Date(day));
+        assert_eq!(
+            literal(r#"datetime "2024-02-29T12:30:05""#),
+            Value::DateTime(day.and_hms_opt(12, 30, 5).unwrap())
+        );
+        assert!(matches!(
+            transform(r#"date "2023-02-29""#),
+            Err(TransformError::InvalidLiteral { ref kind, ref text })
+                if kind == "date" && text == "2023-02-29"
+        ));
+        assert!(matches!(
+            transform(r#"datetime "2024-02-29""#),
+            Err(TransformError::InvalidLiteral { ref kind, .. }) if kind == "datetime"
+        ));
+        assert!(matches!(
+            transform("date 20240229"),
+            Err(TransformError::InvalidArgument(ref name)) if name == "date"
+        ));
+    }
+
+    #[test]
+    fn negative_row_counts_are_rejected() {
+        assert!(matches!(
+            transform("scan users |> limit -1"),
+            Err(TransformError::NegativeLimit { ref name, count: -1 }) if name == "limit"
+        ));
+        assert!(matches!(
+            transform("scan users |> offset -2"),
+            Err(TransformError::NegativeLimit { ref name, count: -2 }) if name == "offset"
+        ));
+        assert!(matches!(
+            transform("scan users |> limit 0"),
+            Ok(QueryExpr::Transaction { ref operations, .. })
+                if matches!(operations[..], [TransactionOp::Limit { count: 0 }])
+        ));
+    }
+
+    #[test]
+    fn powers_fold() {
+        assert_eq!(literal("2 ^ 3 ^ 2"), Value::Int(512));
+        assert_eq!(literal("(2 ^ 3) ^ 2"), Value::Int(64));
+        assert_eq!(literal("2.0 ^ 3"), Value::Double(8.0));
+        assert_eq!(literal("1.5 ^ 2"), Value::Double(2.25));
+        assert_eq!(literal("2 ^ -1"), Value::Double(0.5));
+    }
+
+    #[test]
+    fn modulus_folds_unless_it_divides_by_zero() {
+        assert_eq!(literal("10 % 3"), Value::Int(1));
+        assert_eq!(literal("-7 % 3"), Value::Int(-1));
+        assert_eq!(literal("7.5 % 2"), Value::Double(1.5));
+        assert!(matches!(
+            transform("10 % 0"),
+            Ok(QueryExpr::BinaryOp {
+                op: BinaryOperator::Modulus,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn text_concatenation_folds() {
+        assert_eq!(literal(r#""a" ++ "b""#), Value::Text("ab".to_string()));
+        assert_eq!(literal(r#""a" ++ "b" ++ "c""#), Value::Text("abc".to_string()));
+        assert!(matches!(
+            transform(r#"1 ++ "x""#),
+            Ok(QueryExpr::BinaryOp {
+                op: BinaryOperator::Concat,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn fields_of_nested_instances_resolve() {
+        let home = r#"let home = { address = { city = "rio" } } in "#;
+        let QueryExpr::Binding { body, .. } = transform(&format!("{home}home.address.city")).unwrap()
+        else {
+            panic!("expected a binding");
+        };
+        assert!(matches!(*body, QueryExpr::Literal(Value::Text(ref city)) if city == "rio"));
+
+        assert!(matches!(
+            transform(&format!("{home}home.address.zip")),
+            Err(TransformError::UnknownField { ref path, ref field })
+                if path == "home.address" && field == "zip"
+        ));
+        assert!(matches!(
+            transform(r#"scan users |> filter (\u -> u.name.first == "a")"#),
+            Err(TransformError::ColumnHasNoFields { ref path, ref field })
+                if path == "u.name" && field == "first"
+        ));
+    }
+
+    #[test]
+    fn field_access_on_an_unknown_variable_names_the_path() {
+        assert!(matches!(
+            transform(r#"scan users |> filter (\u -> v.name == "a")"#),
+            Err(TransformError::InvalidFieldAccess { ref path, ref field })
+                if path == "v" && field == "name"
+        ));
+    }
+}
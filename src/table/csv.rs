@@ -0,0 +1,201 @@
+use crate::page::err::{DbInternalError, DbResult};
+use crate::page::tuple::{Tuple, Value};
+use crate::table::{TableCatalog, key_bytes};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+#[derive(Debug)]
+pub struct ImportSummary {
+    pub rows: usize,
+    pub columns: usize,
+}
+
+struct Field {
+    text: String,
+    quoted: bool,
+}
+
+struct Record {
+    line: usize,
+    fields: Vec<Field>,
+}
+
+// The first record names the columns each field goes to; columns it leaves out take their
+// default. Every row is parsed before the first one is written, so a bad file inserts nothing.
+pub async fn import_csv(
+    catalog: &TableCatalog,
+    table: &str,
+    mut reader: impl AsyncRead + Unpin,
+) -> DbResult<ImportSummary> {
+    let physical = catalog
+        .get_table(table)
+        .ok_or_else(|| DbInternalError::TableNotFound(table.to_string()))?;
+    let mut text = String::new();
+    reader.read_to_string(&mut text).await?;
+
+    let mut records = parse_records(&text)?.into_iter();
+    let Some(header) = records.next() else {
+        return Ok(ImportSummary {
+            rows: 0,
+            columns: 0,
+        });
+    };
+
+    // Per table column, in tuple order: the position of its field in each record, if any.
+    let columns = physical.info.ordered_columns();
+    let mut positions = vec![None; columns.len()];
+    for (position, field) in header.fields.iter().enumerate() {
+        let name = field.text.trim();
+        let index = columns
+            .iter()
+            .position(|col| col.name == name)
+            .ok_or_else(|| DbInternalError::ColumnNotFound {
+                table: table.to_string(),
+                column: name.to_string(),
+            })?;
+        if positions[index].replace(position).is_some() {
+            return Err(invalid(
+                header.line,
+                format!("column '{}' appears twice", name),
+            ));
+        }
+    }
+
+    let mut tuples = vec![];
+    for record in records {
+        if record.fields.len() != header.fields.len() {
+            return Err(invalid(
+                record.line,
+                format!(
+                    "expected {} fields, found {}",
+                    header.fields.len(),
+                    record.fields.len()
+                ),
+            ));
+        }
+        let mut values = Vec::with_capacity(columns.len());
+        for (col, position) in columns.iter().zip(&positions) {
+            let value = match position.map(|position| &record.fields[position]) {
+                Some(field) if field.text.is_empty() && !field.quoted && col.nullable => {
+                    Value::Null
+                }
+                Some(field) => Value::parse_as(&field.text, &col.data_type)
+                    .map_err(|err| invalid(record.line, err.to_string()))?,
+                None => match &col.default {
+                    Some(default) => default.clone(),
+                    None if col.nullable => Value::Null,
+                    None => {
+                        return Err(invalid(
+                            record.line,
+                            format!("no value for column '{}'", col.name),
+                        ));
+                    }
+                },
+            };
+            values.push(value);
+        }
+        tuples.push((record.line, Tuple(values)));
+    }
+
+    let _writes = physical.writes.lock().await;
+    if let Some((index, mut seen)) = physical.primary_keys().await? {
+        for (line, tuple) in &tuples {
+            if !seen.insert(key_bytes(&tuple.0[index])) {
+                return Err(invalid(
+                    *line,
+                    format!("duplicate value {} for the primary key", tuple.0[index]),
+                ));
+            }
+        }
+    }
+
+    let (lines, tuples): (Vec<usize>, Vec<Tuple>) = tuples.into_iter().unzip();
+    let inserted = physical.heap.insert_many(&tuples).await;
+    if !tuples.is_empty() {
+        physical.invalidate_indexes().await;
+    }
+    inserted.map_err(|(index, err)| match lines.get(index) {
+        Some(&line) => invalid(line, err),
+        None => DbInternalError::InsertFailed(err),
+    })?;
+
+    Ok(ImportSummary {
+        rows: tuples.len(),
+        columns: header.fields.len(),
+    })
+}
+
+fn invalid(line: usize, reason: String) -> DbInternalError {
+    DbInternalError::InvalidCsv { line, reason }
+}
+
+// RFC 4180: fields are split by commas, and a quoted field can hold commas, line breaks and
+// doubled quotes. Empty lines are skipped.
+fn parse_records(text: &str) -> DbResult<Vec<Record>> {
+    let mut records = vec![];
+    let mut chars = text.chars().peekable();
+    let mut line = 1;
+
+    while chars.peek().is_some() {
+        let start_line = line;
+        let mut fields = vec![];
+        let mut field = Field {
+            text: String::new(),
+            quoted: false,
+        };
+        loop {
+            match chars.next() {
+                Some('"') if field.text.is_empty() && !field.quoted => {
+                    field.quoted = true;
+                    loop {
+                        match chars.next() {
+                            Some('"') if chars.peek() == Some(&'"') => {
+                                chars.next();
+                                field.text.push('"');
+                            }
+                            Some('"') => break,
+                            Some(c) => {
+                                if c == '\n' {
+                                    line += 1;
+                                }
+                                field.text.push(c);
+                            }
+                            None => {
+                                return Err(invalid(
+                                    start_line,
+                                    "a quoted field is never closed".to_string(),
+                                ));
+                            }
+                        }
+                    }
+                }
+                Some(',') => {
+                    fields.push(std::mem::replace(&mut field, Field {
+                        text: String::new(),
+                        quoted: false,
+                    }));
+                }
+                Some('\r') if chars.peek() == Some(&'\n') => {}
+                Some('\n') | None => {
+                    line += 1;
+                    break;
+                }
+                Some(_) if field.quoted => {
+                    return Err(invalid(
+                        line,
+                        "unexpected text after a quoted field".to_string(),
+                    ));
+                }
+                Some(c) => field.text.push(c),
+            }
+        }
+        if fields.is_empty() && field.text.is_empty() && !field.quoted {
+            continue;
+        }
+        fields.push(field);
+        records.push(Record {
+            line: start_line,
+            fields,
+        });
+    }
+    Ok(records)
+}
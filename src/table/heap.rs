@@ -1,29 +1,68 @@
-use crate::page::Page;
-use crate::page::err::DbResult;
+use crate::page::err::{DbInternalError, DbResult};
 use crate::page::io::IoManager;
 use crate::page::pool::BufferPool;
-use crate::page::tuple::Tuple;
+use crate::page::tuple::{DataType, Tuple};
+use crate::page::{
+    MAX_INLINE_RECORD_SIZE, OVERFLOW_CHUNK_SIZE, OverflowPointer, PAGE_SIZE, Page, Record,
+    record_fits,
+};
 use futures::{
     Future, Stream,
     task::{Context, Poll},
 };
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::fmt::Debug;
 use std::pin::Pin;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex as SyncMutex};
 use tokio::sync::Mutex;
 
 pub struct TableHeap {
     pub file_id: u32,
     pub buffer_pool: Arc<BufferPool>,
     pub page_ids: Mutex<Vec<u32>>,
+    // By page id, the contiguous room each page had when last touched, so inserts can pass
+    // over full pages without fetching them. Pages not touched since the table was loaded
+    // are `None` and get fetched once to find out. Room freed by deletes only counts once a
+    // compaction reclaims it, so a page can hold a little more than this says.
+    free_space: SyncMutex<Vec<Option<u16>>>,
+    scans: SyncMutex<ScanRegistry>,
+    // Pages of reclaimed overflow chains, left as empty slotted pages. The next chain written
+    // takes them before adding pages to the table, unless an insert has filled them since.
+    free_pages: SyncMutex<Vec<u32>>,
+    // The column types rows are packed against, in column id order. The catalog's own tables
+    // have none and stay self-describing, since they're read before any schema is known.
+    schema: Option<Vec<DataType>>,
+}
+
+// Scans read pages as they go, so a row inserted into a page they haven't reached yet (or
+// into a slot of the one they're on) would show up halfway through. Each running scan
+// collects the locations written since it started and skips them. Updates done in place
+// and deletes are still seen as they happen.
+//
+// The overflow chains of rows deleted or rewritten since wait in `retired`: a scan may have
+// read the pointer to one before it went away, so a chain is only reclaimed once nothing is
+// registered.
+#[derive(Default)]
+struct ScanRegistry {
+    next_id: u64,
+    hidden: HashMap<u64, HashSet<(u32, usize)>>,
+    retired: Vec<OverflowPointer>,
 }
 
 impl TableHeap {
-    pub fn new(file_id: u32, buffer_pool: Arc<BufferPool>) -> Arc<Self> {
+    pub fn new(
+        file_id: u32,
+        buffer_pool: Arc<BufferPool>,
+        schema: Option<Vec<DataType>>,
+    ) -> Arc<Self> {
         Arc::new(TableHeap {
             file_id,
             buffer_pool,
             page_ids: Mutex::new(vec![0]),
+            free_space: SyncMutex::new(vec![]),
+            scans: SyncMutex::default(),
+            free_pages: SyncMutex::default(),
+            schema,
         })
     }
 
@@ -31,6 +70,7 @@ impl TableHeap {
         file_id: u32,
         buffer_pool: Arc<BufferPool>,
         io: Arc<IoManager>,
+        schema: Option<Vec<DataType>>,
     ) -> DbResult<Arc<Self>> {
         let page_count = io.try_get_page_count(file_id).await?;
         let page_ids = (0..page_count).collect::<Vec<u32>>();
@@ -38,114 +78,744 @@ impl TableHeap {
             file_id,
             buffer_pool,
             page_ids: Mutex::new(page_ids),
+            free_space: SyncMutex::new(vec![]),
+            scans: SyncMutex::default(),
+            free_pages: SyncMutex::default(),
+            schema,
         }))
     }
 
-    pub async fn init(&self) {
-        let ptr = self.buffer_pool.get_page_ptr(self.file_id, 0).await;
+    fn schema(&self) -> Option<&[DataType]> {
+        self.schema.as_deref()
+    }
+
+    // A row holding a value of another type than its column, which nothing checks on the way
+    // in, is written self-describing instead of packed. Both read back the same.
+    fn encode(&self, tuple: &Tuple) -> Vec<u8> {
+        self.schema()
+            .and_then(|columns| tuple.to_bytes_with_schema(columns).ok())
+            .unwrap_or_else(|| tuple.to_bytes())
+    }
+
+    fn decode(&self, bytes: &[u8]) -> DbResult<Tuple> {
+        Ok(match self.schema() {
+            Some(columns) => Tuple::from_bytes_with_schema(bytes, columns)?,
+            None => Tuple::from_bytes(bytes)?,
+        })
+    }
+
+    pub async fn init(&self) -> DbResult<()> {
+        let ptr = self.buffer_pool.get_page_ptr(self.file_id, 0).await?;
         let mut page = unsafe { Page::from_raw(0, ptr) };
-        page.init_new();
+        self.under_scans_lock(|| page.init_new());
+        self.record_free_space(&page);
         self.buffer_pool
             .unpin_and_flush(self.file_id, 0, true)
-            .await;
+            .await?;
+        Ok(())
+    }
+
+    fn record_free_space(&self, page: &Page) {
+        let mut free_space = self.free_space.lock().unwrap();
+        let index = page.index as usize;
+        if free_space.len() <= index {
+            free_space.resize(index + 1, None);
+        }
+        free_space[index] = Some(page.available_space() as u16);
+    }
+
+    // Scans call it with `page_ids` locked, which every insert holds too, so no row can land
+    // between taking the snapshot and registering the scan. Lookups register as well, only so
+    // the chain of the row they read isn't reclaimed under them.
+    fn begin_scan(&self) -> u64 {
+        let mut scans = self.scans.lock().unwrap();
+        let id = scans.next_id;
+        scans.next_id += 1;
+        scans.hidden.insert(id, HashSet::new());
+        id
+    }
+
+    fn end_scan(&self, id: u64) {
+        self.scans.lock().unwrap().hidden.remove(&id);
+    }
+
+    // The scans lock is held across the write, and scans copy pages under it too, so a copy
+    // either misses the new row or already knows to skip it.
+    fn insert_unseen(&self, page: &mut Page, record: &[u8]) -> Result<usize, String> {
+        let mut scans = self.scans.lock().unwrap();
+        let slot = page.insert_record(record)?;
+        for hidden in scans.hidden.values_mut() {
+            hidden.insert((page.index, slot));
+        }
+        Ok(slot)
+    }
+
+    fn copy_page(&self, ptr: *mut u8) -> Box<[u8; PAGE_SIZE]> {
+        let _scans = self.scans.lock().unwrap();
+        Box::new(unsafe { *(ptr as *const [u8; PAGE_SIZE]) })
+    }
+
+    // Every change to a frame that doesn't also update the registry goes through here, so a
+    // scan copying the page sees it either whole or not at all. Reads straight out of a frame
+    // take the lock too.
+    fn under_scans_lock<R>(&self, access: impl FnOnce() -> R) -> R {
+        let _scans = self.scans.lock().unwrap();
+        access()
+    }
+
+    fn is_hidden(&self, id: u64, page_id: u32, slot: usize) -> bool {
+        self.scans
+            .lock()
+            .unwrap()
+            .hidden
+            .get(&id)
+            .is_some_and(|hidden| hidden.contains(&(page_id, slot)))
+    }
+
+    fn may_fit(&self, page_id: u32, len: usize) -> bool {
+        let free_space = self.free_space.lock().unwrap();
+        match free_space.get(page_id as usize) {
+            Some(Some(available)) => record_fits(*available as usize, len),
+            _ => true,
+        }
     }
 
-    pub async fn get_tuple(&self, page_id: u32, slot_id: usize) -> Option<Tuple> {
-        let page = self.buffer_pool.get_page_raw(self.file_id, page_id).await;
-        page.get_tuple(slot_id)
+    pub async fn get_tuple(&self, page_id: u32, slot_id: usize) -> DbResult<Option<Tuple>> {
+        let reader = self.begin_scan();
+        let tuple = self.read_tuple(page_id, slot_id).await;
+        self.end_scan(reader);
+        tuple
+    }
+
+    async fn read_tuple(&self, page_id: u32, slot_id: usize) -> DbResult<Option<Tuple>> {
+        let ptr = self.buffer_pool.get_page_ptr(self.file_id, page_id).await?;
+        let page = unsafe { Page::from_raw(page_id, ptr) };
+        let record = self.under_scans_lock(|| page.get_record(slot_id, self.schema()));
+        self.buffer_pool.unpin(self.file_id, page_id, false);
+
+        match record? {
+            Some(Record::Inline(tuple)) => Ok(Some(tuple)),
+            Some(Record::Overflow(pointer)) => self.read_overflow(pointer).await.map(Some),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn read_overflow(&self, pointer: OverflowPointer) -> DbResult<Tuple> {
+        let length = pointer.length as usize;
+        let mut bytes = Vec::with_capacity(length);
+        let mut next = Some(pointer.page_id);
+
+        while let Some(pid) = next {
+            let ptr = self.buffer_pool.get_page_ptr(pointer.file_id, pid).await?;
+            let page = unsafe { Page::from_raw(pid, ptr) };
+            let chunk = page.overflow_chunk().map(|(chunk, next)| {
+                bytes.extend_from_slice(chunk);
+                next
+            });
+            self.buffer_pool.unpin(pointer.file_id, pid, false);
+
+            next = chunk.ok_or(DbInternalError::BrokenOverflowChain {
+                file_id: pointer.file_id,
+                page_id: pid,
+            })?;
+            if bytes.len() > length {
+                break;
+            }
+        }
+
+        if bytes.len() != length {
+            return Err(DbInternalError::BrokenOverflowChain {
+                file_id: pointer.file_id,
+                page_id: pointer.page_id,
+            });
+        }
+        self.decode(&bytes)
+    }
+
+    // Chains go into pages freed by earlier ones first, and only add pages to the table once
+    // those run out.
+    async fn write_overflow(
+        &self,
+        pages: &mut Vec<u32>,
+        bytes: &[u8],
+    ) -> DbResult<OverflowPointer> {
+        self.reclaim_retired().await?;
+        let chunks: Vec<&[u8]> = bytes.chunks(OVERFLOW_CHUNK_SIZE).collect();
+        let first_new = next_page_id(pages);
+        let mut next_new = first_new;
+        let chain: Vec<u32> = chunks
+            .iter()
+            .map(|_| {
+                self.take_free_page().unwrap_or_else(|| {
+                    next_new += 1;
+                    next_new - 1
+                })
+            })
+            .collect();
+
+        for (i, chunk) in chunks.iter().enumerate() {
+            let pid = chain[i];
+            let next = chain.get(i + 1).copied();
+            let ptr = self.buffer_pool.get_page_ptr(self.file_id, pid).await?;
+            let mut page = unsafe { Page::from_raw(pid, ptr) };
+            self.under_scans_lock(|| page.init_overflow(chunk, next));
+            self.record_free_space(&page);
+            if pid >= first_new {
+                pages.push(pid);
+            }
+            self.buffer_pool
+                .unpin_and_flush(self.file_id, pid, true)
+                .await?;
+        }
+
+        Ok(OverflowPointer {
+            file_id: self.file_id,
+            page_id: chain[0],
+            length: bytes.len() as u32,
+        })
+    }
+
+    // A page of a reclaimed chain that's still empty. Inserts record the room they leave, so
+    // one that took a row since no longer shows the room of an empty page.
+    fn take_free_page(&self) -> Option<u32> {
+        let mut free_pages = self.free_pages.lock().unwrap();
+        let free_space = self.free_space.lock().unwrap();
+        let empty = Some(MAX_INLINE_RECORD_SIZE as u16);
+        std::iter::from_fn(|| free_pages.pop())
+            .find(|&pid| free_space.get(pid as usize).copied().flatten() == empty)
+    }
+
+    // Turns the retired chains back into empty slotted pages, as long as no scan or lookup is
+    // running that could still follow one. Called with `page_ids` locked. A chain retired
+    // before the table was last closed is never reclaimed.
+    async fn reclaim_retired(&self) -> DbResult<()> {
+        let retired = {
+            let mut scans = self.scans.lock().unwrap();
+            if !scans.hidden.is_empty() {
+                return Ok(());
+            }
+            std::mem::take(&mut scans.retired)
+        };
+
+        for pointer in retired {
+            let mut next = Some(pointer.page_id);
+            while let Some(pid) = next {
+                let ptr = self.buffer_pool.get_page_ptr(self.file_id, pid).await?;
+                let mut page = unsafe { Page::from_raw(pid, ptr) };
+                let freed = self.under_scans_lock(|| {
+                    let (_, next) = page.overflow_chunk()?;
+                    page.init_new();
+                    Some(next)
+                });
+                self.record_free_space(&page);
+                self.buffer_pool
+                    .unpin_and_flush(self.file_id, pid, freed.is_some())
+                    .await?;
+                if freed.is_some() {
+                    self.free_pages.lock().unwrap().push(pid);
+                }
+                next = freed.flatten();
+            }
+        }
+        Ok(())
     }
 
     pub async fn insert_tuple(&self, tuple: &Tuple) -> Result<(), String> {
         let mut pages_guard = self.page_ids.lock().await;
+        let (record, spilled) = self.encode_spilling(&mut pages_guard, tuple).await?;
+        let placed = self.place_record(&mut pages_guard, &record).await;
+        if placed.is_err() {
+            self.retire(spilled);
+        }
+        placed
+    }
 
-        for &pid in pages_guard.iter() {
-            let ptr = self.buffer_pool.get_page_ptr(self.file_id, pid).await;
+    // A record too big to sit in a page goes to an overflow chain, and the page only keeps
+    // the pointer to it.
+    async fn encode_spilling(
+        &self,
+        pages: &mut Vec<u32>,
+        tuple: &Tuple,
+    ) -> Result<(Vec<u8>, Option<OverflowPointer>), String> {
+        let record = self.encode(tuple);
+        if record.len() <= MAX_INLINE_RECORD_SIZE {
+            return Ok((record, None));
+        }
+        let pointer = self
+            .write_overflow(pages, &record)
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok((pointer.to_bytes(), Some(pointer)))
+    }
+
+    // A chain written for a row that then found no slot has nothing pointing at it, so it's
+    // reclaimed the same way as a deleted row's.
+    fn retire(&self, spilled: Option<OverflowPointer>) {
+        self.scans.lock().unwrap().retired.extend(spilled);
+    }
+
+    async fn place_record(&self, pages: &mut Vec<u32>, record: &[u8]) -> Result<(), String> {
+        for &pid in pages.iter() {
+            if !self.may_fit(pid, record.len()) {
+                continue;
+            }
+            let ptr = self
+                .buffer_pool
+                .get_page_ptr(self.file_id, pid)
+                .await
+                .map_err(|e| e.to_string())?;
             let mut page = unsafe { Page::from_raw(pid, ptr) };
+            if !page.can_fit(record.len()) {
+                self.record_free_space(&page);
+                self.buffer_pool.unpin(self.file_id, pid, false);
+                continue;
+            }
 
-            if page.insert_tuple(tuple).is_ok() {
+            let inserted = self.insert_unseen(&mut page, record);
+            self.record_free_space(&page);
+            if inserted.is_ok() {
                 self.buffer_pool
                     .unpin_and_flush(self.file_id, pid, true)
-                    .await;
+                    .await
+                    .map_err(|e| e.to_string())?;
                 return Ok(());
             } else {
                 self.buffer_pool.unpin(self.file_id, pid, false);
             }
         }
 
-        let new_pid = pages_guard.len() as u32;
-        let ptr = self.buffer_pool.get_page_ptr(self.file_id, new_pid).await;
+        let new_pid = next_page_id(pages);
+        let ptr = self
+            .buffer_pool
+            .get_page_ptr(self.file_id, new_pid)
+            .await
+            .map_err(|e| e.to_string())?;
         let mut page = unsafe { Page::from_raw(new_pid, ptr) };
 
-        page.init_new();
-        page.insert_tuple(tuple)?;
-        pages_guard.push(new_pid);
+        self.under_scans_lock(|| page.init_new());
+        if let Err(e) = self.insert_unseen(&mut page, record) {
+            self.buffer_pool.unpin(self.file_id, new_pid, false);
+            return Err(e);
+        }
+        self.record_free_space(&page);
+        pages.push(new_pid);
 
         self.buffer_pool
             .unpin_and_flush(self.file_id, new_pid, true)
-            .await;
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    // Fills pages in order and never goes back to one it has moved past, so the whole batch
+    // walks the page list once. Touched pages are only flushed at the end, once each. If a
+    // tuple fails, its position in the batch comes with the error and the ones before it
+    // stay inserted; a flush that fails is reported with the length of the batch instead.
+    pub async fn insert_many(&self, tuples: &[Tuple]) -> Result<(), (usize, String)> {
+        let mut pages_guard = self.page_ids.lock().await;
+        let mut touched = BTreeSet::new();
+        let mut cursor = 0;
+
+        let mut result = Ok(());
+        for (index, tuple) in tuples.iter().enumerate() {
+            if let Err(e) = self
+                .insert_packed(&mut pages_guard, &mut cursor, &mut touched, tuple)
+                .await
+            {
+                result = Err((index, e));
+                break;
+            }
+        }
+
+        for pid in touched {
+            if let Err(e) = self.buffer_pool.flush_page(self.file_id, pid).await {
+                result = result.and(Err((tuples.len(), e.to_string())));
+            }
+        }
+        result
+    }
+
+    async fn insert_packed(
+        &self,
+        pages: &mut Vec<u32>,
+        cursor: &mut usize,
+        touched: &mut BTreeSet<u32>,
+        tuple: &Tuple,
+    ) -> Result<(), String> {
+        let (record, spilled) = self.encode_spilling(pages, tuple).await?;
+        let packed = self.pack_record(pages, cursor, touched, &record).await;
+        if packed.is_err() {
+            self.retire(spilled);
+        }
+        packed
+    }
+
+    async fn pack_record(
+        &self,
+        pages: &mut Vec<u32>,
+        cursor: &mut usize,
+        touched: &mut BTreeSet<u32>,
+        record: &[u8],
+    ) -> Result<(), String> {
+        loop {
+            let fresh = *cursor == pages.len();
+            let pid = if fresh {
+                next_page_id(pages)
+            } else {
+                pages[*cursor]
+            };
+            if !fresh && !self.may_fit(pid, record.len()) {
+                *cursor += 1;
+                continue;
+            }
+            let ptr = self
+                .buffer_pool
+                .get_page_ptr(self.file_id, pid)
+                .await
+                .map_err(|e| e.to_string())?;
+            let mut page = unsafe { Page::from_raw(pid, ptr) };
+            if fresh {
+                self.under_scans_lock(|| page.init_new());
+                pages.push(pid);
+                touched.insert(pid);
+            }
+
+            let inserted = self.insert_unseen(&mut page, record);
+            self.record_free_space(&page);
+            match inserted {
+                Ok(_) => {
+                    self.buffer_pool.unpin(self.file_id, pid, true);
+                    touched.insert(pid);
+                    return Ok(());
+                }
+                Err(e) => {
+                    self.buffer_pool.unpin(self.file_id, pid, fresh);
+                    if fresh {
+                        return Err(e);
+                    }
+                    *cursor += 1;
+                }
+            }
+        }
+    }
+
+    pub async fn vacuum(&self) -> DbResult<()> {
+        let pages_guard = self.page_ids.lock().await;
+        self.reclaim_retired().await?;
+
+        for &pid in pages_guard.iter() {
+            let ptr = self.buffer_pool.get_page_ptr(self.file_id, pid).await?;
+            let mut page = unsafe { Page::from_raw(pid, ptr) };
+            self.under_scans_lock(|| page.compact());
+            self.record_free_space(&page);
+            self.buffer_pool
+                .unpin_and_flush(self.file_id, pid, true)
+                .await?;
+        }
         Ok(())
     }
+
+    // Counts the rows without copying pages or decoding a single tuple. The scans lock keeps
+    // inserts out of each page while its slots are counted.
+    pub async fn count_rows(&self) -> DbResult<usize> {
+        let snapshot = self.page_ids.lock().await.clone();
+        let mut count = 0;
+        for pid in snapshot {
+            let ptr = self.buffer_pool.get_page_ptr(self.file_id, pid).await?;
+            count += {
+                let _scans = self.scans.lock().unwrap();
+                unsafe { Page::from_raw(pid, ptr) }.live_slot_count()
+            };
+            self.buffer_pool.unpin(self.file_id, pid, false);
+        }
+        Ok(count)
+    }
+
+    pub async fn scan_with_locations(&self) -> DbResult<Vec<(u32, usize, Tuple)>> {
+        let (snapshot, scan_id) = {
+            let page_ids = self.page_ids.lock().await;
+            (page_ids.clone(), self.begin_scan())
+        };
+        let rows = self.read_snapshot(&snapshot, scan_id).await;
+        self.end_scan(scan_id);
+        rows
+    }
+
+    async fn read_snapshot(
+        &self,
+        snapshot: &[u32],
+        scan_id: u64,
+    ) -> DbResult<Vec<(u32, usize, Tuple)>> {
+        let mut rows = vec![];
+        let mut overflowed = vec![];
+        for &pid in snapshot {
+            let ptr = self.buffer_pool.get_page_ptr(self.file_id, pid).await?;
+            let mut data = self.copy_page(ptr);
+            self.buffer_pool.unpin(self.file_id, pid, false);
+            let page = Page {
+                index: pid,
+                data: &mut data,
+            };
+            for slot in 0..page.slot_count() {
+                if self.is_hidden(scan_id, pid, slot) {
+                    continue;
+                }
+                match page.get_record(slot, self.schema())? {
+                    Some(Record::Inline(tuple)) => rows.push((pid, slot, tuple)),
+                    Some(Record::Overflow(pointer)) => overflowed.push((pid, slot, pointer)),
+                    None => {}
+                }
+            }
+        }
+
+        for (pid, slot, pointer) in overflowed {
+            rows.push((pid, slot, self.read_overflow(pointer).await?));
+        }
+        Ok(rows)
+    }
+
+    pub async fn delete_tuple(&self, page_id: u32, slot: usize) -> Result<(), String> {
+        let ptr = self
+            .buffer_pool
+            .get_page_ptr(self.file_id, page_id)
+            .await
+            .map_err(|e| e.to_string())?;
+        let mut page = unsafe { Page::from_raw(page_id, ptr) };
+
+        let deleted = self.delete_slot(&mut page, slot);
+        self.buffer_pool
+            .unpin_and_flush(self.file_id, page_id, deleted.is_ok())
+            .await
+            .map_err(|e| e.to_string())?;
+        deleted
+    }
+
+    // The chain the row spilled to, if any, is retired along with it.
+    fn delete_slot(&self, page: &mut Page, slot: usize) -> Result<(), String> {
+        let mut scans = self.scans.lock().unwrap();
+        let spilled = page.overflow_pointer(slot);
+        page.delete_tuple(slot)?;
+        scans.retired.extend(spilled);
+        Ok(())
+    }
+
+    pub async fn update_tuple(
+        &self,
+        page_id: u32,
+        slot: usize,
+        new_tuple: &Tuple,
+    ) -> Result<(), String> {
+        let ptr = self
+            .buffer_pool
+            .get_page_ptr(self.file_id, page_id)
+            .await
+            .map_err(|e| e.to_string())?;
+        let mut page = unsafe { Page::from_raw(page_id, ptr) };
+
+        let record = self.encode(new_tuple);
+        let updated = {
+            let mut scans = self.scans.lock().unwrap();
+            let spilled = page.overflow_pointer(slot);
+            let updated = page.update_record(slot, &record).is_ok();
+            if updated {
+                scans.retired.extend(spilled);
+            }
+            updated
+        };
+        self.record_free_space(&page);
+        if updated {
+            self.buffer_pool
+                .unpin_and_flush(self.file_id, page_id, true)
+                .await
+                .map_err(|e| e.to_string())?;
+            return Ok(());
+        }
+
+        // The new version doesn't fit in this page anymore, so it moves elsewhere.
+        let deleted = self.delete_slot(&mut page, slot);
+        self.buffer_pool
+            .unpin_and_flush(self.file_id, page_id, true)
+            .await
+            .map_err(|e| e.to_string())?;
+        deleted?;
+        self.insert_tuple(new_tuple).await
+    }
 }
 
+type PageFetchFuture =
+    Pin<Box<dyn Future<Output = DbResult<Option<(Box<[u8; PAGE_SIZE]>, u32)>>> + Send>>;
+type OverflowReadFuture = Pin<Box<dyn Future<Output = DbResult<Tuple>> + Send>>;
+
+// Each page is copied out of the buffer pool and unpinned as soon as it's fetched, so the
+// scan never holds a frame across an await, nor points into one the pool might evict.
 enum OptimizedTableIteratorState {
     ReadyToFetchNextPage,
     FetchingPage {
-        future: Pin<Box<dyn Future<Output = Option<(*mut u8, u32)>> + Send>>,
+        future: PageFetchFuture,
     },
     IteratingPage {
         page_id: u32,
-        page_ptr: *mut u8,
+        page: Box<[u8; PAGE_SIZE]>,
         current_slot_idx: usize,
     },
+    ReadingOverflow {
+        page_id: u32,
+        page: Box<[u8; PAGE_SIZE]>,
+        next_slot_idx: usize,
+        future: OverflowReadFuture,
+    },
     Finished,
 }
 
-pub struct OptimizedTableIterator {
+pub type ScanFilter<E> = Arc<dyn Fn(&Tuple) -> Result<bool, E> + Send + Sync>;
+
+// Yields the error of a page that can't be read instead of the rows on it, and stops there.
+// The filter can fail too, so the error type is whatever the caller's filter returns.
+pub struct OptimizedTableIterator<E = DbInternalError> {
     heap: Arc<TableHeap>,
+    scan_id: u64,
     page_ids_snapshot: Vec<u32>,
     current_page_idx_in_snapshot: usize,
     state: OptimizedTableIteratorState,
+    filter: Option<ScanFilter<E>>,
+    // Rows left to yield before the scan stops reading pages.
+    remaining: Option<usize>,
 }
 
-impl OptimizedTableIterator {
-    fn new(heap: Arc<TableHeap>, page_ids_snapshot: Vec<u32>) -> Self {
+impl<E> OptimizedTableIterator<E> {
+    fn new(heap: Arc<TableHeap>, scan_id: u64, page_ids_snapshot: Vec<u32>) -> Self {
         OptimizedTableIterator {
             heap,
+            scan_id,
             page_ids_snapshot,
             current_page_idx_in_snapshot: 0,
             state: OptimizedTableIteratorState::ReadyToFetchNextPage,
+            filter: None,
+            remaining: None,
+        }
+    }
+
+    // Rows the filter rejects are skipped while still on their page instead of being yielded.
+    pub fn with_filter(mut self, filter: ScanFilter<E>) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    // Counts the rows the filter accepts, so no page past the one holding the last of them
+    // is fetched.
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.remaining = Some(limit);
+        self
+    }
+
+    fn take_page(&mut self) -> Box<[u8; PAGE_SIZE]> {
+        match std::mem::replace(&mut self.state, OptimizedTableIteratorState::Finished) {
+            OptimizedTableIteratorState::IteratingPage { page, .. }
+            | OptimizedTableIteratorState::ReadingOverflow { page, .. } => page,
+            _ => unreachable!("only called while on a page"),
         }
     }
 }
 
-impl Stream for OptimizedTableIterator {
-    type Item = Tuple;
+impl<E: From<DbInternalError>> Stream for OptimizedTableIterator<E> {
+    type Item = Result<Tuple, E>;
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
         let this = self.get_mut();
+        if this.remaining == Some(0) {
+            return Poll::Ready(None);
+        }
+        let polled = this.poll_row(cx);
+        match (&polled, &mut this.remaining) {
+            (Poll::Ready(Some(Ok(_))), Some(remaining)) => *remaining -= 1,
+            (Poll::Ready(Some(Err(_))), _) => this.state = OptimizedTableIteratorState::Finished,
+            _ => {}
+        }
+        polled
+    }
+}
+
+impl<E: From<DbInternalError>> OptimizedTableIterator<E> {
+    fn poll_row(&mut self, cx: &mut Context<'_>) -> Poll<Option<Result<Tuple, E>>> {
+        let this = self;
 
         loop {
             match &mut this.state {
                 OptimizedTableIteratorState::IteratingPage {
                     page_id,
-                    page_ptr,
+                    page,
                     current_slot_idx,
                 } => {
-                    let page = unsafe { Page::from_raw(*page_id, *page_ptr) };
+                    let page_id = *page_id;
+                    let page = Page {
+                        index: page_id,
+                        data: page,
+                    };
 
-                    if let Some(tuple) = page.get_tuple(*current_slot_idx) {
+                    if *current_slot_idx < page.slot_count() {
+                        let slot = *current_slot_idx;
                         *current_slot_idx += 1;
-                        return Poll::Ready(Some(tuple));
+                        if this.heap.is_hidden(this.scan_id, page_id, slot) {
+                            continue;
+                        }
+                        match page.get_record(slot, this.heap.schema()) {
+                            Ok(Some(Record::Inline(tuple))) => {
+                                match accepts(&this.filter, &tuple) {
+                                    Ok(true) => return Poll::Ready(Some(Ok(tuple))),
+                                    Ok(false) => continue,
+                                    Err(err) => return Poll::Ready(Some(Err(err))),
+                                }
+                            }
+                            Ok(Some(Record::Overflow(pointer))) => {
+                                let heap = this.heap.clone();
+                                let page = this.take_page();
+                                this.state = OptimizedTableIteratorState::ReadingOverflow {
+                                    page_id,
+                                    page,
+                                    next_slot_idx: slot + 1,
+                                    future: Box::pin(
+                                        async move { heap.read_overflow(pointer).await },
+                                    ),
+                                };
+                                continue;
+                            }
+                            Ok(None) => continue,
+                            Err(err) => {
+                                return Poll::Ready(Some(Err(DbInternalError::from(err).into())));
+                            }
+                        }
                     } else {
-                        this.heap
-                            .buffer_pool
-                            .unpin(this.heap.file_id, *page_id, false);
-
                         this.state = OptimizedTableIteratorState::ReadyToFetchNextPage;
                         continue;
                     }
                 }
 
+                OptimizedTableIteratorState::ReadingOverflow {
+                    page_id,
+                    next_slot_idx,
+                    future,
+                    ..
+                } => match future.as_mut().poll(cx) {
+                    Poll::Ready(result) => {
+                        let (page_id, next_slot_idx) = (*page_id, *next_slot_idx);
+                        let page = this.take_page();
+                        this.state = OptimizedTableIteratorState::IteratingPage {
+                            page_id,
+                            page,
+                            current_slot_idx: next_slot_idx,
+                        };
+                        match result {
+                            Ok(tuple) => match accepts(&this.filter, &tuple) {
+                                Ok(true) => return Poll::Ready(Some(Ok(tuple))),
+                                Ok(false) => continue,
+                                Err(err) => return Poll::Ready(Some(Err(err))),
+                            },
+                            Err(err) => return Poll::Ready(Some(Err(err.into()))),
+                        }
+                    }
+                    Poll::Pending => return Poll::Pending,
+                },
+
                 OptimizedTableIteratorState::ReadyToFetchNextPage => {
                     if this.current_page_idx_in_snapshot >= this.page_ids_snapshot.len() {
                         this.state = OptimizedTableIteratorState::Finished;
@@ -156,14 +826,21 @@ impl Stream for OptimizedTableIterator {
                     let heap_clone = this.heap.clone();
 
                     let fetch_future = async move {
-                        let page_ptr = heap_clone
+                        let file_id = heap_clone.file_id;
+                        match heap_clone
                             .buffer_pool
-                            .get_page_ptr(heap_clone.file_id, pid_to_fetch)
-                            .await;
-                        if page_ptr.is_null() {
-                            None
-                        } else {
-                            Some((page_ptr, pid_to_fetch))
+                            .get_page_ptr(file_id, pid_to_fetch)
+                            .await
+                        {
+                            Ok(page_ptr) if !page_ptr.is_null() => {
+                                // The frame stays pinned until the unpin below, so it can't be
+                                // evicted or reused while it's copied.
+                                let page = heap_clone.copy_page(page_ptr);
+                                heap_clone.buffer_pool.unpin(file_id, pid_to_fetch, false);
+                                Ok(Some((page, pid_to_fetch)))
+                            }
+                            Ok(_) => Ok(None),
+                            Err(err) => Err(err),
                         }
                     };
 
@@ -175,20 +852,21 @@ impl Stream for OptimizedTableIterator {
 
                 OptimizedTableIteratorState::FetchingPage { future } => {
                     match future.as_mut().poll(cx) {
-                        Poll::Ready(Some((ptr, pid))) => {
+                        Poll::Ready(Ok(Some((page, pid)))) => {
                             this.state = OptimizedTableIteratorState::IteratingPage {
                                 page_id: pid,
-                                page_ptr: ptr,
+                                page,
                                 current_slot_idx: 0,
                             };
                             this.current_page_idx_in_snapshot += 1;
                             continue;
                         }
-                        Poll::Ready(None) => {
+                        Poll::Ready(Ok(None)) => {
                             this.current_page_idx_in_snapshot += 1;
                             this.state = OptimizedTableIteratorState::ReadyToFetchNextPage;
                             continue;
                         }
+                        Poll::Ready(Err(err)) => return Poll::Ready(Some(Err(err.into()))),
                         Poll::Pending => {
                             return Poll::Pending;
                         }
@@ -203,18 +881,506 @@ impl Stream for OptimizedTableIterator {
     }
 }
 
-pub async fn scan_table(table_ref: Arc<TableHeap>) -> OptimizedTableIterator {
-    let snapshot = {
+// Ids go past the highest one in use rather than the count, so a page list with gaps in it
+// can't hand out an id that's taken. Callers hold the `page_ids` lock until the new page
+// is pushed.
+fn next_page_id(pages: &[u32]) -> u32 {
+    pages.iter().max().map_or(0, |&pid| pid + 1)
+}
+
+fn accepts<E>(filter: &Option<ScanFilter<E>>, tuple: &Tuple) -> Result<bool, E> {
+    filter.as_ref().map_or(Ok(true), |filter| filter(tuple))
+}
+
+// Yields the rows the table held when the scan started: pages added later aren't read at all,
+// and rows inserted into the pages it does read are skipped.
+pub async fn scan_table<E>(table_ref: Arc<TableHeap>) -> OptimizedTableIterator<E> {
+    let (snapshot, scan_id) = {
         let page_ids_guard = table_ref.page_ids.lock().await;
-        page_ids_guard.clone()
+        (page_ids_guard.clone(), table_ref.begin_scan())
     };
-    OptimizedTableIterator::new(table_ref, snapshot)
+    OptimizedTableIterator::new(table_ref, scan_id, snapshot)
 }
 
-unsafe impl Send for OptimizedTableIterator {}
+impl<E> Drop for OptimizedTableIterator<E> {
+    fn drop(&mut self) {
+        self.heap.end_scan(self.scan_id);
+    }
+}
 
 impl Debug for TableHeap {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "TableHeap(file_id: {})", self.file_id)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{TableHeap, next_page_id, scan_table};
+    use crate::DatabaseError;
+    use crate::page::err::{DbInternalError, TupleDecodeError};
+    use crate::page::io::{FileSystemManager, IoManager};
+    use crate::page::pool::{BufferPool, BufferPoolConfig};
+    use crate::page::tuple::{PACKED_RECORD_TAG, Tuple, Value};
+    use crate::page::{PAGE_SIZE, Page};
+    use crate::testing::{TempDir, rows, users_database};
+    use futures::TryStreamExt;
+    use std::collections::HashSet;
+    use std::sync::Arc;
+
+    async fn blob_heap(dir: &TempDir) -> (Arc<IoManager>, Arc<TableHeap>) {
+        let fs = Arc::new(FileSystemManager::new(dir.path().to_string()));
+        fs.create_home().await.unwrap();
+        let io = Arc::new(IoManager::new(fs));
+        let heap = TableHeap::new(7, BufferPool::new(Arc::clone(&io)), None);
+        heap.init().await.unwrap();
+        (io, heap)
+    }
+
+    // Ten kilobytes that no shifted copy of itself matches, so a misplaced chunk shows.
+    fn blob(seed: u8) -> Vec<u8> {
+        (0..10 * 1024)
+            .map(|i: usize| (i * 31 + i / 251) as u8 ^ seed)
+            .collect()
+    }
+
+    async fn only_row(heap: &TableHeap) -> (u32, usize, Tuple) {
+        let mut rows = heap.scan_with_locations().await.unwrap();
+        assert_eq!(rows.len(), 1);
+        rows.remove(0)
+    }
+
+    async fn page_count(heap: &TableHeap) -> usize {
+        heap.page_ids.lock().await.len()
+    }
+
+    #[tokio::test]
+    async fn blob_round_trips_through_overflow_pages() {
+        let dir = TempDir::new();
+        let (io, heap) = blob_heap(&dir).await;
+        let row = Tuple(vec![Value::Int(1), Value::Blob(blob(0))]);
+        heap.insert_tuple(&row).await.unwrap();
+        assert!(page_count(&heap).await > 3);
+
+        let (page_id, slot, scanned) = only_row(&heap).await;
+        assert_eq!(scanned.0, row.0);
+        let fetched = heap.get_tuple(page_id, slot).await.unwrap().unwrap();
+        assert_eq!(fetched.0, row.0);
+
+        heap.buffer_pool.close().await.unwrap();
+        let pool = BufferPool::new(Arc::clone(&io));
+        let reopened = TableHeap::from_existing(7, pool, io, None).await.unwrap();
+        assert_eq!(only_row(&reopened).await.2.0, row.0);
+    }
+
+    #[tokio::test]
+    async fn deleted_chain_is_reused() {
+        let dir = TempDir::new();
+        let (_io, heap) = blob_heap(&dir).await;
+        heap.insert_tuple(&Tuple(vec![Value::Blob(blob(0))]))
+            .await
+            .unwrap();
+        let pages = page_count(&heap).await;
+
+        let (page_id, slot, _) = only_row(&heap).await;
+        heap.delete_tuple(page_id, slot).await.unwrap();
+        let row = Tuple(vec![Value::Blob(blob(1))]);
+        heap.insert_tuple(&row).await.unwrap();
+        assert_eq!(page_count(&heap).await, pages);
+        assert_eq!(only_row(&heap).await.2.0, row.0);
+    }
+
+    #[tokio::test]
+    async fn rewritten_chain_is_reused() {
+        let dir = TempDir::new();
+        let (_io, heap) = blob_heap(&dir).await;
+        heap.insert_tuple(&Tuple(vec![Value::Blob(blob(0))]))
+            .await
+            .unwrap();
+        let pages = page_count(&heap).await;
+
+        for seed in 1..6 {
+            let (page_id, slot, _) = only_row(&heap).await;
+            let row = Tuple(vec![Value::Blob(blob(seed))]);
+            heap.update_tuple(page_id, slot, &row).await.unwrap();
+            assert_eq!(only_row(&heap).await.2.0, row.0);
+        }
+        assert_eq!(page_count(&heap).await, pages);
+
+        let (page_id, slot, _) = only_row(&heap).await;
+        let small = Tuple(vec![Value::Blob(vec![1, 2, 3])]);
+        heap.update_tuple(page_id, slot, &small).await.unwrap();
+        let pages = page_count(&heap).await;
+        heap.insert_tuple(&Tuple(vec![Value::Blob(blob(9))]))
+            .await
+            .unwrap();
+        assert_eq!(page_count(&heap).await, pages);
+    }
+
+    #[tokio::test]
+    async fn chain_is_kept_while_a_scan_is_running() {
+        let dir = TempDir::new();
+        let (_io, heap) = blob_heap(&dir).await;
+        heap.insert_tuple(&Tuple(vec![Value::Blob(blob(0))]))
+            .await
+            .unwrap();
+        let (page_id, slot, _) = only_row(&heap).await;
+        let pages = page_count(&heap).await;
+
+        let scan = scan_table::<DbInternalError>(Arc::clone(&heap)).await;
+        heap.delete_tuple(page_id, slot).await.unwrap();
+        heap.insert_tuple(&Tuple(vec![Value::Blob(blob(1))]))
+            .await
+            .unwrap();
+        let grown = page_count(&heap).await;
+        assert!(grown > pages);
+        drop(scan);
+
+        let (page_id, slot, _) = only_row(&heap).await;
+        heap.delete_tuple(page_id, slot).await.unwrap();
+        heap.insert_tuple(&Tuple(vec![Value::Blob(blob(2))]))
+            .await
+            .unwrap();
+        assert_eq!(page_count(&heap).await, grown);
+    }
+
+    #[tokio::test]
+    async fn corrupt_page_fails_the_scan() {
+        let dir = TempDir::new();
+        let mut database = users_database(&dir).await;
+        rows(&mut database, r#"insert_ users { name = "ana", age = 30 }"#).await;
+        let file_id = database
+            .catalog()
+            .read()
+            .await
+            .get_table("users")
+            .unwrap()
+            .heap
+            .file_id;
+        database.close().await.unwrap();
+
+        let path = dir.file(&format!("ak{}.record", file_id));
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[PAGE_SIZE / 2] ^= 0xFF;
+        std::fs::write(&path, bytes).unwrap();
+
+        let mut database = crate::Database::open(dir.path()).await.unwrap();
+        assert!(matches!(
+            database.execute_str("scan users").await,
+            Err(DatabaseError::Storage(DbInternalError::ChecksumMismatch {
+                page_id: 0,
+                ..
+            }))
+        ));
+    }
+
+    #[tokio::test]
+    async fn undecodable_slot_fails_the_scan() {
+        let dir = TempDir::new();
+        let mut database = users_database(&dir).await;
+        rows(&mut database, r#"insert_ users { name = "ana", age = 30 }"#).await;
+        {
+            let catalog = database.catalog().read().await;
+            let heap = &catalog.get_table("users").unwrap().heap;
+            let ptr = heap
+                .buffer_pool
+                .get_page_ptr(heap.file_id, 0)
+                .await
+                .unwrap();
+            let mut page = unsafe { Page::from_raw(0, ptr) };
+            page.insert_record(&[0xFE, 1, 2, 3]).unwrap();
+            heap.buffer_pool.unpin(heap.file_id, 0, true);
+        }
+
+        assert!(matches!(
+            database.execute_str("scan users").await,
+            Err(DatabaseError::Storage(DbInternalError::CorruptTuple(
+                TupleDecodeError::UnknownType(0xFE)
+            )))
+        ));
+    }
+
+    #[tokio::test]
+    async fn packed_rows_with_nulls_survive_reopening() {
+        let dir = TempDir::new();
+        let mut database = users_database(&dir).await;
+        rows(&mut database, r#"insert_ users { name = "ana", age = 30 }"#).await;
+        rows(
+            &mut database,
+            r#"insert_ users { name = "bia", age = 61, retirement = 65 }"#,
+        )
+        .await;
+        database.close().await.unwrap();
+
+        let mut database = crate::Database::open(dir.path()).await.unwrap();
+        let users = rows(&mut database, "scan users").await;
+        assert_eq!(users.len(), 2);
+        assert_eq!(users[0].0[2], Value::Null);
+        assert_eq!(users[1].0[2], Value::Int(65));
+    }
+
+    #[tokio::test]
+    async fn rows_are_packed_against_the_schema() {
+        let dir = TempDir::new();
+        let mut database = users_database(&dir).await;
+        rows(&mut database, r#"insert_ users { name = "ana", age = 30 }"#).await;
+        let catalog = database.catalog().read().await;
+        let heap = &catalog.get_table("users").unwrap().heap;
+        let ptr = heap
+            .buffer_pool
+            .get_page_ptr(heap.file_id, 0)
+            .await
+            .unwrap();
+        let page = unsafe { Page::from_raw(0, ptr) };
+        let packed = page.get_record(0, None);
+        heap.buffer_pool.unpin(heap.file_id, 0, false);
+        assert!(matches!(
+            packed,
+            Err(TupleDecodeError::UnknownType(PACKED_RECORD_TAG))
+        ));
+    }
+
+    // Sixteen frames under a table of about fifty pages, so every scan evicts as it goes
+    // while other tasks update, move, delete and compact rows on the pages it copies.
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn scans_of_a_table_larger_than_the_pool_see_whole_rows() {
+        let dir = TempDir::new();
+        let fs = Arc::new(FileSystemManager::new(dir.path().to_string()));
+        fs.create_home().await.unwrap();
+        let io = Arc::new(IoManager::new(fs));
+        let pool = BufferPool::with_config(
+            io,
+            BufferPoolConfig {
+                slots_per_shard: 4,
+                ..BufferPoolConfig::default()
+            },
+        );
+        let heap = TableHeap::new(7, pool, None);
+        heap.init().await.unwrap();
+        let tuples: Vec<Tuple> = (0..6000)
+            .map(|i| Tuple(vec![Value::Int(i), Value::Text("a".repeat(20))]))
+            .collect();
+        heap.insert_many(&tuples).await.unwrap();
+        assert!(heap.page_ids.lock().await.len() > 16);
+
+        let writer = {
+            let heap = Arc::clone(&heap);
+            tokio::spawn(async move {
+                for round in 0..4 {
+                    let rows = heap.scan_with_locations().await.unwrap();
+                    for (page_id, slot, tuple) in rows.into_iter().step_by(7) {
+                        let id = tuple.0[0].as_int().unwrap();
+                        if id % 5 == 0 {
+                            let _ = heap.delete_tuple(page_id, slot).await;
+                        } else {
+                            // Every other round the text doubles, so some rows move.
+                            let text = "b".repeat(20 << (round % 2));
+                            let moved = Tuple(vec![Value::Int(id), Value::Text(text)]);
+                            heap.update_tuple(page_id, slot, &moved).await.unwrap();
+                        }
+                    }
+                    heap.vacuum().await.unwrap();
+                }
+            })
+        };
+
+        let mut scanners = vec![];
+        for _ in 0..3 {
+            let heap = Arc::clone(&heap);
+            scanners.push(tokio::spawn(async move {
+                for _ in 0..6 {
+                    let rows: Vec<Tuple> = scan_table::<DbInternalError>(Arc::clone(&heap))
+                        .await
+                        .try_collect()
+                        .await
+                        .unwrap();
+                    let mut ids = HashSet::new();
+                    for row in &rows {
+                        let [Value::Int(id), Value::Text(text)] = &row.0[..] else {
+                            panic!("torn row {:?}", row);
+                        };
+                        assert!(ids.insert(*id), "row {} seen twice", id);
+                        assert!(
+                            matches!(text.len(), 20 | 40)
+                                && text.chars().all(|c| c == text.as_bytes()[0] as char),
+                            "torn text {:?}",
+                            text
+                        );
+                    }
+                }
+            }));
+        }
+
+        writer.await.unwrap();
+        for scanner in scanners {
+            scanner.await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn insert_many_packs_rows_into_few_pages() {
+        let dir = TempDir::new();
+        let (_io, heap) = blob_heap(&dir).await;
+        let tuples: Vec<Tuple> = (0..1000)
+            .map(|i| Tuple(vec![Value::Int(i), Value::Text(format!("row {}", i))]))
+            .collect();
+        heap.insert_many(&tuples).await.unwrap();
+
+        assert!(page_count(&heap).await < 20);
+        let mut ids: Vec<i32> = heap
+            .scan_with_locations()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|(_, _, tuple)| match (&tuple.0[0], &tuple.0[1]) {
+                (Value::Int(i), Value::Text(text)) if *text == format!("row {}", i) => *i,
+                _ => panic!("unexpected row {:?}", tuple.0),
+            })
+            .collect();
+        ids.sort();
+        assert_eq!(ids, (0..1000).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn insert_skips_pages_without_room() {
+        let dir = TempDir::new();
+        let (_io, heap) = blob_heap(&dir).await;
+        // Each row takes most of a page, so no two share one.
+        let row = |i: i32| Tuple(vec![Value::Int(i), Value::Text("x".repeat(2500))]);
+        for i in 0..30 {
+            heap.insert_tuple(&row(i)).await.unwrap();
+        }
+        assert_eq!(page_count(&heap).await, 30);
+
+        let fetches = |heap: &TableHeap| {
+            let stats = heap.buffer_pool.stats();
+            stats.hits + stats.misses
+        };
+        let before = fetches(&heap);
+        heap.insert_tuple(&row(30)).await.unwrap();
+        assert!(fetches(&heap) - before <= 2);
+        assert_eq!(page_count(&heap).await, 31);
+
+        let (page_id, slot, _) = heap
+            .scan_with_locations()
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|(_, _, tuple)| matches!(tuple.0[0], Value::Int(10)))
+            .unwrap();
+        heap.delete_tuple(page_id, slot).await.unwrap();
+        heap.vacuum().await.unwrap();
+        heap.insert_tuple(&row(31)).await.unwrap();
+        assert_eq!(page_count(&heap).await, 31);
+    }
+
+    #[tokio::test]
+    async fn scan_sees_only_rows_inserted_before_it_started() {
+        let dir = TempDir::new();
+        let (_io, heap) = blob_heap(&dir).await;
+        let row = |i: i32| Tuple(vec![Value::Int(i), Value::Text("a".repeat(100))]);
+        for i in 0..60 {
+            heap.insert_tuple(&row(i)).await.unwrap();
+        }
+        let pages_before = page_count(&heap).await;
+
+        let mut scan = scan_table::<DbInternalError>(Arc::clone(&heap)).await;
+        let mut ids = vec![
+            scan.try_next().await.unwrap().unwrap().0[0]
+                .as_int()
+                .unwrap(),
+        ];
+        // The first lands in the room left on the last page, the rest on new pages.
+        for i in 60..200 {
+            heap.insert_tuple(&row(i)).await.unwrap();
+        }
+        assert!(page_count(&heap).await > pages_before);
+        while let Some(tuple) = scan.try_next().await.unwrap() {
+            ids.push(tuple.0[0].as_int().unwrap());
+        }
+        ids.sort();
+        assert_eq!(ids, (0..60).collect::<Vec<_>>());
+
+        let after: Vec<Tuple> = scan_table::<DbInternalError>(Arc::clone(&heap))
+            .await
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(after.len(), 200);
+    }
+
+    #[test]
+    fn new_pages_go_past_the_highest_id() {
+        assert_eq!(next_page_id(&[]), 0);
+        assert_eq!(next_page_id(&[0, 1, 2]), 3);
+        assert_eq!(next_page_id(&[0, 5, 2]), 6);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn interleaved_inserts_get_distinct_increasing_pages() {
+        let dir = TempDir::new();
+        let (_io, heap) = blob_heap(&dir).await;
+        let mut writers = vec![];
+        for writer in 0..4 {
+            let heap = Arc::clone(&heap);
+            writers.push(tokio::spawn(async move {
+                for i in 0..10 {
+                    // Every third row spills, so overflow chains take page ids as well.
+                    let text = "x".repeat(if i % 3 == 0 { 6000 } else { 2500 });
+                    let row = Tuple(vec![Value::Int(writer * 10 + i), Value::Text(text)]);
+                    heap.insert_tuple(&row).await.unwrap();
+                }
+            }));
+        }
+        for writer in writers {
+            writer.await.unwrap();
+        }
+
+        let pages = heap.page_ids.lock().await.clone();
+        assert!(
+            pages.windows(2).all(|pair| pair[0] < pair[1]),
+            "{:?}",
+            pages
+        );
+        let mut ids: Vec<i32> = heap
+            .scan_with_locations()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|(_, _, tuple)| tuple.0[0].as_int().unwrap())
+            .collect();
+        ids.sort();
+        assert_eq!(ids, (0..40).collect::<Vec<_>>());
+    }
+
+    // One frame per shard: with another page of its shard pinned, page 0 can't be loaded,
+    // so the row's chain gets written and then there's no slot to point at it from.
+    #[tokio::test]
+    async fn chain_of_a_row_that_found_no_slot_is_reclaimed() {
+        let dir = TempDir::new();
+        let fs = Arc::new(FileSystemManager::new(dir.path().to_string()));
+        fs.create_home().await.unwrap();
+        let io = Arc::new(IoManager::new(fs));
+        let pool = BufferPool::with_config(
+            io,
+            BufferPoolConfig {
+                slots_per_shard: 1,
+                max_eviction_sweeps: 2,
+            },
+        );
+        let heap = TableHeap::new(7, Arc::clone(&pool), None);
+        heap.init().await.unwrap();
+
+        pool.get_page_ptr(7, 4).await.unwrap();
+        let row = Tuple(vec![Value::Blob(blob(1))]);
+        assert!(heap.insert_tuple(&row).await.is_err());
+        assert_eq!(heap.scans.lock().unwrap().retired.len(), 1);
+        pool.unpin(7, 4, false);
+
+        heap.vacuum().await.unwrap();
+        assert!(heap.scans.lock().unwrap().retired.is_empty());
+        assert_eq!(heap.free_pages.lock().unwrap().len(), 3);
+        heap.insert_tuple(&row).await.unwrap();
+        assert_eq!(only_row(&heap).await.2.0, row.0);
+    }
+}
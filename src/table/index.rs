@@ -0,0 +1,52 @@
+use crate::page::err::DbResult;
+use crate::page::tuple::Value;
+use crate::table::heap::TableHeap;
+use crate::table::key_bytes;
+use std::collections::HashMap;
+use tokio::sync::Mutex;
+
+pub type RowLocation = (u32, usize);
+
+// Maps the encoding of a column value to the rows holding it. The entries only exist in
+// memory: they're built by the first lookup and dropped by any write to the table, so the
+// lookup after a write pays for a full scan again.
+#[derive(Debug)]
+pub struct HashIndex {
+    pub column_id: u32,
+    entries: Mutex<Option<HashMap<Vec<u8>, Vec<RowLocation>>>>,
+}
+
+impl HashIndex {
+    pub fn new(column_id: u32) -> Self {
+        Self {
+            column_id,
+            entries: Mutex::new(None),
+        }
+    }
+
+    pub async fn lookup(&self, heap: &TableHeap, value: &Value) -> DbResult<Vec<RowLocation>> {
+        let mut entries = self.entries.lock().await;
+        if entries.is_none() {
+            *entries = Some(self.build(heap).await?);
+        }
+        Ok(entries
+            .as_ref()
+            .and_then(|entries| entries.get(&key_bytes(value)).cloned())
+            .unwrap_or_default())
+    }
+
+    pub async fn invalidate(&self) {
+        *self.entries.lock().await = None;
+    }
+
+    async fn build(&self, heap: &TableHeap) -> DbResult<HashMap<Vec<u8>, Vec<RowLocation>>> {
+        let mut entries: HashMap<Vec<u8>, Vec<RowLocation>> = HashMap::new();
+        for (page_id, slot, tuple) in heap.scan_with_locations().await? {
+            entries
+                .entry(key_bytes(&tuple.0[self.column_id as usize]))
+                .or_default()
+                .push((page_id, slot));
+        }
+        Ok(entries)
+    }
+}
@@ -1,18 +1,21 @@
-use crate::page::err::DbResult;
+use crate::page::err::{DbInternalError, DbResult};
 use crate::page::io::IoManager;
 use crate::page::pool::BufferPool;
 use crate::page::tuple::{DataType, Tuple, Value};
-use crate::table::heap::{TableHeap, scan_table};
+use crate::table::heap::TableHeap;
 use crate::table::{ColumnInfo, PhysicalTable, TableInfo};
-use futures::StreamExt;
 use std::collections::HashMap;
 use std::sync::Arc;
+use tokio::sync::Mutex;
 
 pub const RELATIONS_TABLE_ID: u32 = 0;
 pub const COLUMNS_TABLE_ID: u32 = 1;
+pub const RELATIONS_TABLE_NAME: &str = "akasha.relations";
+pub const COLUMNS_TABLE_NAME: &str = "akasha.columns";
 
 pub const RELATIONS_TABLE_ID_INDEX: usize = 0;
 pub const RELATIONS_TABLE_NAME_INDEX: usize = 1;
+pub const RELATIONS_TABLE_PRIMARY_KEY_INDEX: usize = 2;
 
 pub const COLUMNS_TABLE_ID_INDEX: usize = 0;
 pub const COLUMNS_TABLE_TABLE_ID_INDEX: usize = 1;
@@ -21,6 +24,12 @@ pub const COLUMNS_TABLE_TYPE_INDEX: usize = 3;
 pub const COLUMNS_TABLE_NULLABLE_INDEX: usize = 4;
 pub const COLUMNS_TABLE_DEFAULT_INDEX: usize = 5;
 
+// Unlike the two tables above, this one is only created once the first index is, so it gets
+// an ordinary file id.
+pub const INDEXES_TABLE_NAME: &str = "akasha.indexes";
+pub const INDEXES_TABLE_TABLE_ID_INDEX: usize = 0;
+pub const INDEXES_TABLE_COLUMN_ID_INDEX: usize = 1;
+
 pub struct InternalTableInterface {
     pub pool: Arc<BufferPool>,
     pub io: Arc<IoManager>,
@@ -42,8 +51,8 @@ impl InternalTableInterface {
     }
 
     pub async fn init_internals(pool: Arc<BufferPool>, io: Arc<IoManager>) {
-        let relations_table = TableHeap::new(RELATIONS_TABLE_ID, pool.clone());
-        let columns_table = TableHeap::new(COLUMNS_TABLE_ID, pool.clone());
+        let relations_table = TableHeap::new(RELATIONS_TABLE_ID, pool.clone(), None);
+        let columns_table = TableHeap::new(COLUMNS_TABLE_ID, pool.clone(), None);
 
         let interface = InternalTableInterface {
             pool,
@@ -56,35 +65,58 @@ impl InternalTableInterface {
         interface
             .save_table(
                 relations_table,
-                "akasha.relations".to_string(),
-                relations_table_columns(),
+                RELATIONS_TABLE_NAME.to_string(),
+                TableInfo {
+                    columns: relations_table_columns(),
+                    primary_key: None,
+                },
             )
             .await
             .expect("Failed to save relations table");
         interface
-            .save_table(
-                columns_table,
-                "akasha.columns".to_string(),
-                columns_table_columns(),
-            )
+            .save_table(columns_table, COLUMNS_TABLE_NAME.to_string(), TableInfo {
+                columns: columns_table_columns(),
+                primary_key: None,
+            })
             .await
             .expect("Failed to save columns table");
     }
 
+    // Pages of the internal tables that can't be read fail the load instead of being skipped,
+    // since the tables they describe would silently go missing.
     pub async fn load_tables(&self) -> DbResult<HashMap<String, PhysicalTable>> {
-        let table_iterator = scan_table(self.relations_table.clone()).await;
-        let column_iterator = scan_table(self.columns_table.clone()).await;
-
-        let column_tuples: Vec<(u32, ColumnInfo)> = column_iterator
-            .filter_map(|tuple| async move {
-                let column_id = tuple.0[COLUMNS_TABLE_ID_INDEX].as_int().unwrap() as u32;
-                let table_id: u32 = tuple.0[COLUMNS_TABLE_TABLE_ID_INDEX].as_int().unwrap() as u32;
-                let name: String = tuple.0[COLUMNS_TABLE_NAME_INDEX].as_string().unwrap();
-                let data_type: DataType =
-                    DataType::from_id(tuple.0[COLUMNS_TABLE_TYPE_INDEX].as_byte().unwrap())
-                        .expect("Invalid data type");
-                let nullable: bool = tuple.0[COLUMNS_TABLE_NULLABLE_INDEX].as_boolean().unwrap();
-                let default = tuple.0.get(COLUMNS_TABLE_DEFAULT_INDEX).cloned();
+        let table_rows = self.relations_table.scan_with_locations().await?;
+        let column_rows = self.columns_table.scan_with_locations().await?;
+
+        let column_tuples: Vec<(u32, ColumnInfo)> = column_rows
+            .into_iter()
+            .map(|(_, _, tuple)| {
+                let column_id = catalog_field(COLUMNS_TABLE_NAME, &tuple, COLUMNS_TABLE_ID_INDEX)?;
+                let table_id =
+                    catalog_field(COLUMNS_TABLE_NAME, &tuple, COLUMNS_TABLE_TABLE_ID_INDEX)?;
+                let name = catalog_value(
+                    COLUMNS_TABLE_NAME,
+                    &tuple,
+                    COLUMNS_TABLE_NAME_INDEX,
+                    Value::as_string,
+                )?;
+                let data_type = catalog_value(
+                    COLUMNS_TABLE_NAME,
+                    &tuple,
+                    COLUMNS_TABLE_TYPE_INDEX,
+                    |value| value.as_byte().and_then(DataType::from_id),
+                )?;
+                let nullable = catalog_value(
+                    COLUMNS_TABLE_NAME,
+                    &tuple,
+                    COLUMNS_TABLE_NULLABLE_INDEX,
+                    Value::as_boolean,
+                )?;
+                // Rows written before defaults were stored stop at the nullable flag.
+                let default = match tuple.0.get(COLUMNS_TABLE_DEFAULT_INDEX) {
+                    None | Some(Value::Null) => None,
+                    Some(value) => Some(value.clone()),
+                };
 
                 let column_info = ColumnInfo {
                     id: column_id,
@@ -94,39 +126,58 @@ impl InternalTableInterface {
                     default,
                 };
 
-                Some((table_id, column_info))
+                Ok((table_id, column_info))
             })
-            .collect()
-            .await;
+            .collect::<DbResult<_>>()?;
 
         let mut columns: HashMap<u32, Vec<ColumnInfo>> = HashMap::new();
         for (table_id, column_info) in column_tuples {
-            columns
-                .entry(table_id)
-                .or_insert_with(Vec::new)
-                .push(column_info);
+            columns.entry(table_id).or_default().push(column_info);
         }
 
-        let table_tuples: Vec<Tuple> = table_iterator.collect().await;
-        let mut tables = HashMap::new();
-
-        for tuple in table_tuples {
-            let id: u32 = tuple.0[RELATIONS_TABLE_ID_INDEX].as_int().unwrap() as u32;
-            let name: String = tuple.0[RELATIONS_TABLE_NAME_INDEX].as_string().unwrap();
-            let heap = self.load_table_heap(id).await?;
+        // The relations table lists itself first thing, so a catalog without it lost its rows.
+        if !table_rows.iter().any(|(_, _, tuple)| {
+            tuple.0[RELATIONS_TABLE_ID_INDEX].as_int() == Some(RELATIONS_TABLE_ID as i32)
+        }) {
+            return Err(DbInternalError::MissingInternalTable(
+                RELATIONS_TABLE_NAME.to_string(),
+            ));
+        }
 
+        let mut tables = HashMap::new();
+        for (_, _, tuple) in table_rows {
+            let id = catalog_field(RELATIONS_TABLE_NAME, &tuple, RELATIONS_TABLE_ID_INDEX)?;
+            let name = catalog_value(
+                RELATIONS_TABLE_NAME,
+                &tuple,
+                RELATIONS_TABLE_NAME_INDEX,
+                Value::as_string,
+            )?;
+            let primary_key = tuple
+                .0
+                .get(RELATIONS_TABLE_PRIMARY_KEY_INDEX)
+                .and_then(|key| key.as_int())
+                .map(|key| key as u32);
             let columns_map: HashMap<String, ColumnInfo> = columns
                 .get_mut(&id)
                 .map(|cols| cols.drain(..).map(|col| (col.name.clone(), col)).collect())
                 .unwrap_or_default();
+            let info = TableInfo {
+                columns: columns_map,
+                primary_key,
+            };
+            let schema = (id != RELATIONS_TABLE_ID && id != COLUMNS_TABLE_ID)
+                .then(|| info.column_types());
+            let heap = TableHeap::from_existing(id, self.pool.clone(), self.io.clone(), schema)
+                .await?;
 
             let physical = PhysicalTable {
                 file_id: id,
                 name: name.clone(),
                 heap,
-                info: TableInfo {
-                    columns: columns_map,
-                },
+                info,
+                indexes: HashMap::new(),
+                writes: Mutex::new(()),
             };
 
             tables.insert(name, physical);
@@ -139,17 +190,18 @@ impl InternalTableInterface {
         &self,
         heap: Arc<TableHeap>,
         name: String,
-        columns: HashMap<String, ColumnInfo>,
+        info: TableInfo,
     ) -> DbResult<PhysicalTable> {
-        heap.init().await;
+        heap.init().await?;
         let mut column_rows: Vec<Tuple> = Vec::new();
-        for column in columns.values() {
+        for column in info.columns.values() {
             let tuple = Tuple(vec![
-                Value::Int(column.id as i32),       // COLUMNS_TABLE_ID_INDEX
-                Value::Int(heap.file_id as i32),    // COLUMNS_TABLE_TABLE_ID_INDEX
-                Value::Text(column.name.clone()),   // COLUMNS_TABLE_NAME_INDEX
-                Value::Byte(column.data_type.id()), // COLUMNS_TABLE_TYPE_INDEX
-                Value::Boolean(column.nullable),    // COLUMNS_TABLE_NULLABLE_INDEX
+                Value::Int(column.id as i32),                  // COLUMNS_TABLE_ID_INDEX
+                Value::Int(heap.file_id as i32),               // COLUMNS_TABLE_TABLE_ID_INDEX
+                Value::Text(column.name.clone()),              // COLUMNS_TABLE_NAME_INDEX
+                Value::Byte(column.data_type.id()),            // COLUMNS_TABLE_TYPE_INDEX
+                Value::Boolean(column.nullable),               // COLUMNS_TABLE_NULLABLE_INDEX
+                column.default.clone().unwrap_or(Value::Null), // COLUMNS_TABLE_DEFAULT_INDEX
             ]);
             column_rows.push(tuple);
         }
@@ -159,37 +211,84 @@ impl InternalTableInterface {
             column_heap
                 .insert_tuple(&tuple)
                 .await
-                .expect("Failed to insert column tuple");
+                .map_err(DbInternalError::InsertFailed)?;
         }
 
         let relation_tuple = Tuple(vec![
             Value::Int(heap.file_id as i32), // RELATIONS_TABLE_ID_INDEX
             Value::Text(name.clone()),       // RELATIONS_TABLE_NAME_INDEX
+            info.primary_key
+                .map_or(Value::Null, |key| Value::Int(key as i32)), // RELATIONS_TABLE_PRIMARY_KEY_INDEX
         ]);
         self.relations_table
             .insert_tuple(&relation_tuple)
             .await
-            .expect("Failed to insert relation tuple");
+            .map_err(DbInternalError::InsertFailed)?;
 
         Ok(PhysicalTable {
             file_id: heap.file_id,
             name,
             heap,
-            info: TableInfo { columns },
+            info,
+            indexes: HashMap::new(),
+            writes: Mutex::new(()),
         })
     }
 
-    async fn load_table_heap(&self, file_id: u32) -> DbResult<Arc<TableHeap>> {
-        load_table_heap(file_id, self.io.clone(), self.pool.clone()).await
+    pub async fn delete_table(&self, file_id: u32) -> DbResult<()> {
+        let owned_by =
+            |tuple: &Tuple, index: usize| tuple.0[index].as_int() == Some(file_id as i32);
+
+        for (page_id, slot, tuple) in self.columns_table.scan_with_locations().await? {
+            if owned_by(&tuple, COLUMNS_TABLE_TABLE_ID_INDEX) {
+                self.columns_table
+                    .delete_tuple(page_id, slot)
+                    .await
+                    .map_err(DbInternalError::DeleteFailed)?;
+            }
+        }
+
+        for (page_id, slot, tuple) in self.relations_table.scan_with_locations().await? {
+            if owned_by(&tuple, RELATIONS_TABLE_ID_INDEX) {
+                self.relations_table
+                    .delete_tuple(page_id, slot)
+                    .await
+                    .map_err(DbInternalError::DeleteFailed)?;
+            }
+        }
+        Ok(())
     }
 }
 
+// Reads a field out of a catalog row. One that's missing or of the wrong type means the row
+// is corrupt, which fails the load like an unreadable page would.
+pub fn catalog_value<T>(
+    table: &str,
+    tuple: &Tuple,
+    field: usize,
+    read: impl FnOnce(&Value) -> Option<T>,
+) -> DbResult<T> {
+    tuple
+        .0
+        .get(field)
+        .and_then(read)
+        .ok_or_else(|| DbInternalError::CorruptCatalog {
+            table: table.to_string(),
+            field,
+        })
+}
+
+// Ids are stored as ints.
+pub fn catalog_field(table: &str, tuple: &Tuple, field: usize) -> DbResult<u32> {
+    catalog_value(table, tuple, field, Value::as_int).map(|id| id as u32)
+}
+
 async fn load_table_heap(
     file_id: u32,
     io: Arc<IoManager>,
     buffer_pool: Arc<BufferPool>,
 ) -> DbResult<Arc<TableHeap>> {
-    TableHeap::from_existing(file_id, buffer_pool, io).await
+    TableHeap::from_existing(file_id, buffer_pool, io, None).await
 }
 
 fn relations_table_columns() -> HashMap<String, ColumnInfo> {
@@ -208,6 +307,32 @@ fn relations_table_columns() -> HashMap<String, ColumnInfo> {
             nullable: false,
             default: None,
         }),
+        ("primary_key".to_string(), ColumnInfo {
+            id: 2,
+            name: "primary_key".to_string(),
+            data_type: DataType::Int,
+            nullable: true,
+            default: None,
+        }),
+    ])
+}
+
+pub fn indexes_table_columns() -> HashMap<String, ColumnInfo> {
+    HashMap::from([
+        ("table_id".to_string(), ColumnInfo {
+            id: 0,
+            name: "table_id".to_string(),
+            data_type: DataType::Int,
+            nullable: false,
+            default: None,
+        }),
+        ("column_id".to_string(), ColumnInfo {
+            id: 1,
+            name: "column_id".to_string(),
+            data_type: DataType::Int,
+            nullable: false,
+            default: None,
+        }),
     ])
 }
 
@@ -248,5 +373,116 @@ fn columns_table_columns() -> HashMap<String, ColumnInfo> {
             nullable: false,
             default: None,
         }),
+        // Typed `Null` so it can hold a default of any type.
+        ("default".to_string(), ColumnInfo {
+            id: 5,
+            name: "default".to_string(),
+            data_type: DataType::Null,
+            nullable: true,
+            default: None,
+        }),
     ])
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Database;
+    use crate::testing::{TempDir, column, rows, users_database};
+
+    async fn load_with_column_row(row: Vec<Value>) -> DbResult<HashMap<String, PhysicalTable>> {
+        let dir = TempDir::new();
+        let database = users_database(&dir).await;
+        let catalog = database.catalog().read().await;
+        let internals = &catalog.internals;
+        internals.columns_table.insert_tuple(&Tuple(row)).await.unwrap();
+        internals.load_tables().await
+    }
+
+    #[tokio::test]
+    async fn column_with_an_unknown_type_fails_the_load() {
+        let loaded = load_with_column_row(vec![
+            Value::Int(9),
+            Value::Int(2),
+            Value::Text("broken".to_string()),
+            Value::Byte(0xEE),
+            Value::Boolean(false),
+            Value::Null,
+        ])
+        .await;
+        assert!(matches!(
+            loaded,
+            Err(DbInternalError::CorruptCatalog { ref table, field: COLUMNS_TABLE_TYPE_INDEX })
+                if table == COLUMNS_TABLE_NAME
+        ));
+    }
+
+    #[tokio::test]
+    async fn column_missing_its_name_fails_the_load() {
+        let loaded = load_with_column_row(vec![Value::Int(9), Value::Int(2)]).await;
+        assert!(matches!(
+            loaded,
+            Err(DbInternalError::CorruptCatalog { field: COLUMNS_TABLE_NAME_INDEX, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn defaults_and_nullability_survive_reopening() {
+        let dir = TempDir::new();
+        let database = Database::open(dir.path()).await.unwrap();
+        let (_, mut level) = column(1, "level", DataType::Int, false);
+        level.default = Some(Value::Int(1));
+        let columns = HashMap::from([
+            column(0, "name", DataType::Text, false),
+            ("level".to_string(), level),
+            column(2, "nick", DataType::Text, true),
+        ]);
+        database
+            .catalog()
+            .write()
+            .await
+            .create_table("players".to_string(), TableInfo {
+                columns,
+                primary_key: None,
+            })
+            .await
+            .unwrap();
+        database.close().await.unwrap();
+
+        let mut database = Database::open(dir.path()).await.unwrap();
+        {
+            let catalog = database.catalog().read().await;
+            let info = &catalog.get_table("players").unwrap().info;
+            assert_eq!(info.columns["level"].default, Some(Value::Int(1)));
+            assert!(!info.columns["level"].nullable);
+            assert_eq!(info.columns["nick"].default, None);
+            assert!(info.columns["nick"].nullable);
+        }
+        rows(&mut database, r#"insert_ players { name = "ana" }"#).await;
+        let players = rows(&mut database, "scan players").await;
+        assert_eq!(
+            players[0].0,
+            [Value::Text("ana".to_string()), Value::Int(1), Value::Null]
+        );
+    }
+
+    #[tokio::test]
+    async fn column_row_without_a_default_loads() {
+        let loaded = load_with_column_row(vec![
+            Value::Int(9),
+            Value::Int(2),
+            Value::Text("old".to_string()),
+            Value::Byte(DataType::Int.id()),
+            Value::Boolean(true),
+        ])
+        .await
+        .unwrap();
+        let column = loaded
+            .values()
+            .flat_map(|table| table.info.columns.values())
+            .find(|column| column.name == "old")
+            .unwrap();
+        assert!(column.nullable);
+        assert_eq!(column.default, None);
+    }
+}
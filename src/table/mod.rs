@@ -1,24 +1,48 @@
 use crate::page::err::{DbInternalError, DbResult};
-use crate::page::io::IoManager;
+use crate::page::io::{FileSystemManager, IoManager};
 use crate::page::pool::BufferPool;
-use crate::page::tuple::{DataType, Value};
+use crate::page::tuple::{DataType, Tuple, Value};
 use crate::table::heap::TableHeap;
-use crate::table::internal::InternalTableInterface;
-use std::collections::HashMap;
+use crate::table::index::HashIndex;
+use crate::table::internal::{
+    COLUMNS_TABLE_ID, INDEXES_TABLE_COLUMN_ID_INDEX, INDEXES_TABLE_NAME,
+    INDEXES_TABLE_TABLE_ID_INDEX, InternalTableInterface, RELATIONS_TABLE_ID, catalog_field,
+    indexes_table_columns,
+};
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
+use tokio::sync::{Mutex, RwLock};
 
+pub mod csv;
 pub mod heap;
+pub mod index;
 mod internal;
 
 #[derive(Debug)]
 pub struct TableInfo {
     pub columns: HashMap<String, ColumnInfo>,
+    pub primary_key: Option<u32>,
 }
 
 impl TableInfo {
     pub fn get_column_index(&self, name: &str) -> Option<usize> {
         self.columns.get(name).map(|col| col.id as usize)
     }
+
+    // Tuples lay their values out in column id order.
+    pub fn ordered_columns(&self) -> Vec<&ColumnInfo> {
+        let mut columns: Vec<&ColumnInfo> = self.columns.values().collect();
+        columns.sort_by_key(|col| col.id);
+        columns
+    }
+
+    // What rows of the table are packed against.
+    pub fn column_types(&self) -> Vec<DataType> {
+        self.ordered_columns()
+            .into_iter()
+            .map(|col| col.data_type.clone())
+            .collect()
+    }
 }
 
 #[derive(Debug)]
@@ -36,8 +60,56 @@ pub struct PhysicalTable {
     pub name: String,
     pub heap: Arc<TableHeap>,
     pub info: TableInfo,
+    // Keyed by column id.
+    pub indexes: HashMap<u32, HashIndex>,
+    // Held by every write from reading the keys already in the table until its own rows are
+    // in, so two statements can't both add the same primary key.
+    pub writes: Mutex<()>,
 }
 
+impl PhysicalTable {
+    pub async fn invalidate_indexes(&self) {
+        for index in self.indexes.values() {
+            index.invalidate().await;
+        }
+    }
+
+    // Where the primary key sits in a row, along with the key of every row already in the
+    // table. Tables without a key have nothing to check.
+    pub async fn primary_keys(&self) -> DbResult<Option<(usize, HashSet<Vec<u8>>)>> {
+        let Some(key) = self.info.primary_key else {
+            return Ok(None);
+        };
+        let index = key as usize;
+        let keys = self
+            .heap
+            .scan_with_locations()
+            .await?
+            .iter()
+            .map(|(_, _, row)| key_bytes(&row.0[index]))
+            .collect();
+        Ok(Some((index, keys)))
+    }
+
+    pub fn primary_key_name(&self) -> Option<&str> {
+        let key = self.info.primary_key?;
+        self.info
+            .columns
+            .values()
+            .find(|col| col.id == key)
+            .map(|col| col.name.as_str())
+    }
+}
+
+// Values of any type, floats included, encoded so they can be hashed and compared.
+pub fn key_bytes(value: &Value) -> Vec<u8> {
+    Tuple(vec![value.clone()]).to_bytes()
+}
+
+// How the compiler, the executor and the REPL share one catalog: lookups take the read lock,
+// creating and dropping tables the write lock.
+pub type SharedCatalog = Arc<RwLock<TableCatalog>>;
+
 pub struct TableCatalog {
     pub internals: InternalTableInterface,
     pub buffer_pool: Arc<BufferPool>,
@@ -53,6 +125,16 @@ impl TableCatalog {
         }
     }
 
+    pub fn shared(self) -> SharedCatalog {
+        Arc::new(RwLock::new(self))
+    }
+
+    // The relations table is the first thing a catalog writes, so a directory without it has
+    // never held one.
+    pub async fn exists(file_io: &FileSystemManager) -> DbResult<bool> {
+        Ok(file_io.has_page_file(RELATIONS_TABLE_ID).await?)
+    }
+
     pub async fn init_then_load(io: Arc<IoManager>, buffer_pool: Arc<BufferPool>) -> Self {
         InternalTableInterface::init_internals(buffer_pool.clone(), io.clone()).await;
         TableCatalog::load(io, buffer_pool)
@@ -64,26 +146,302 @@ impl TableCatalog {
         if self.tables.contains_key(&name) {
             return Err(DbInternalError::TableAlreadyExists(name));
         }
-        let file_id = self.tables.len() as u32;
-        let heap = TableHeap::new(file_id, self.buffer_pool.clone());
+        // Ids of dropped tables leave gaps, so the count of tables isn't necessarily free.
+        let file_id = self
+            .tables
+            .values()
+            .map(|table| table.file_id + 1)
+            .max()
+            .unwrap_or(0);
+        let heap = TableHeap::new(file_id, self.buffer_pool.clone(), Some(info.column_types()));
         let physical = self
             .internals
-            .save_table(Arc::clone(&heap), name.clone(), info.columns)
+            .save_table(Arc::clone(&heap), name.clone(), info)
             .await?;
 
         self.tables.insert(name, physical);
         Ok(())
     }
 
+    pub async fn drop_table(&mut self, name: &str) -> DbResult<()> {
+        let file_id = self
+            .tables
+            .get(name)
+            .ok_or_else(|| DbInternalError::TableNotFound(name.to_string()))?
+            .file_id;
+        if file_id == RELATIONS_TABLE_ID
+            || file_id == COLUMNS_TABLE_ID
+            || name == INDEXES_TABLE_NAME
+        {
+            return Err(DbInternalError::CannotDropInternalTable(name.to_string()));
+        }
+
+        self.internals.delete_table(file_id).await?;
+        if let Some(indexes) = self.tables.get(INDEXES_TABLE_NAME) {
+            for (page_id, slot, tuple) in indexes.heap.scan_with_locations().await? {
+                if tuple.0[INDEXES_TABLE_TABLE_ID_INDEX].as_int() == Some(file_id as i32) {
+                    indexes
+                        .heap
+                        .delete_tuple(page_id, slot)
+                        .await
+                        .map_err(DbInternalError::DeleteFailed)?;
+                }
+            }
+        }
+        // The checkpoint drains pending writes and empties the log, so neither the writer nor
+        // a later recovery can recreate the file once it's deleted.
+        self.buffer_pool.discard_file(file_id);
+        self.internals.io.checkpoint().await?;
+        self.internals.io.delete_file(file_id).await?;
+
+        self.tables.remove(name);
+        Ok(())
+    }
+
+    pub async fn create_index(&mut self, table: &str, column: &str) -> DbResult<()> {
+        let physical = self
+            .tables
+            .get(table)
+            .ok_or_else(|| DbInternalError::TableNotFound(table.to_string()))?;
+        let column_id = physical
+            .info
+            .columns
+            .get(column)
+            .ok_or_else(|| DbInternalError::ColumnNotFound {
+                table: table.to_string(),
+                column: column.to_string(),
+            })?
+            .id;
+        if physical.indexes.contains_key(&column_id) {
+            return Ok(());
+        }
+        let file_id = physical.file_id;
+
+        if !self.tables.contains_key(INDEXES_TABLE_NAME) {
+            let info = TableInfo {
+                columns: indexes_table_columns(),
+                primary_key: None,
+            };
+            self.create_table(INDEXES_TABLE_NAME.to_string(), info)
+                .await?;
+        }
+        self.tables[INDEXES_TABLE_NAME]
+            .heap
+            .insert_tuple(&Tuple(vec![
+                Value::Int(file_id as i32),   // INDEXES_TABLE_TABLE_ID_INDEX
+                Value::Int(column_id as i32), // INDEXES_TABLE_COLUMN_ID_INDEX
+            ]))
+            .await
+            .map_err(DbInternalError::InsertFailed)?;
+
+        if let Some(physical) = self.tables.get_mut(table) {
+            physical
+                .indexes
+                .insert(column_id, HashIndex::new(column_id));
+        }
+        Ok(())
+    }
+
+    async fn register_indexes(&mut self) -> DbResult<()> {
+        let Some(indexes) = self.tables.get(INDEXES_TABLE_NAME) else {
+            return Ok(());
+        };
+        let definitions = indexes.heap.scan_with_locations().await?;
+        for (_, _, tuple) in definitions {
+            let file_id = catalog_field(INDEXES_TABLE_NAME, &tuple, INDEXES_TABLE_TABLE_ID_INDEX)?;
+            let column_id =
+                catalog_field(INDEXES_TABLE_NAME, &tuple, INDEXES_TABLE_COLUMN_ID_INDEX)?;
+            if let Some(physical) = self
+                .tables
+                .values_mut()
+                .find(|table| table.file_id == file_id)
+            {
+                physical
+                    .indexes
+                    .insert(column_id, HashIndex::new(column_id));
+            }
+        }
+        Ok(())
+    }
+
     pub fn get_table(&self, name: &str) -> Option<&PhysicalTable> {
         self.tables.get(name)
     }
 
     pub async fn load(io: Arc<IoManager>, pool: Arc<BufferPool>) -> DbResult<Self> {
+        // Pages a previous run logged but never wrote have to be in place before anything
+        // is read back.
+        io.recover().await?;
         let internals = InternalTableInterface::from_disk(Arc::clone(&pool), io).await?;
         let tables = internals.load_tables().await?;
         let mut catalog = TableCatalog::new(internals, pool);
         catalog.tables = tables;
+        catalog.register_indexes().await?;
         Ok(catalog)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::Database;
+    use crate::page::err::DbInternalError;
+    use crate::page::file::EXTENSION;
+    use crate::page::tuple::{Tuple, Value};
+    use crate::testing::{TempDir, rows, users_database};
+
+    // Enough rows to spread the table over several pages, with `ages` different ages among them.
+    async fn many_users(dir: &TempDir, ages: usize) -> Database {
+        let mut database = users_database(dir).await;
+        for i in 0..600 {
+            let query = format!(r#"insert_ users {{ name = "user{}", age = {} }}"#, i, i % ages);
+            rows(&mut database, &query).await;
+        }
+        database
+    }
+
+    async fn page_fetches(database: &Database) -> u64 {
+        let stats = database.catalog().read().await.buffer_pool.stats();
+        stats.hits + stats.misses
+    }
+
+    fn sorted(tuples: Vec<Tuple>) -> Vec<Vec<Value>> {
+        let mut values: Vec<Vec<Value>> = tuples.into_iter().map(|tuple| tuple.0).collect();
+        values.sort_by_key(|row| format!("{:?}", row));
+        values
+    }
+
+    #[tokio::test]
+    async fn index_finds_the_rows_a_scan_does() {
+        let dir = TempDir::new();
+        let mut database = many_users(&dir, 50).await;
+        let query = r#"scan users |> filter (\u -> u.age == 7)"#;
+        let scanned = sorted(rows(&mut database, query).await);
+
+        rows(&mut database, "create_index users age").await;
+        let indexed = sorted(rows(&mut database, query).await);
+        assert_eq!(scanned.len(), 12);
+        assert_eq!(indexed, scanned);
+
+        rows(&mut database, r#"insert_ users { name = "late", age = 7 }"#).await;
+        assert_eq!(rows(&mut database, query).await.len(), 13);
+    }
+
+    #[tokio::test]
+    async fn index_fetches_fewer_pages_than_a_scan() {
+        let dir = TempDir::new();
+        let mut database = many_users(&dir, 600).await;
+        rows(&mut database, "create_index users age").await;
+        let query = r#"scan users |> filter (\u -> u.age == 7)"#;
+        // The first lookup builds the index, which reads the whole table.
+        rows(&mut database, query).await;
+
+        let before = page_fetches(&database).await;
+        rows(&mut database, query).await;
+        let indexed = page_fetches(&database).await - before;
+
+        let before = page_fetches(&database).await;
+        rows(&mut database, r#"scan users |> filter (\u -> u.name == "user7")"#).await;
+        let scanned = page_fetches(&database).await - before;
+
+        assert!(scanned > 1);
+        assert_eq!(indexed, 1);
+        assert!(indexed < scanned, "{} fetches with the index, {} without", indexed, scanned);
+    }
+
+    #[tokio::test]
+    async fn index_is_kept_across_reopening() {
+        let dir = TempDir::new();
+        let mut database = many_users(&dir, 50).await;
+        rows(&mut database, "create_index users age").await;
+        database.close().await.unwrap();
+
+        let database = Database::open(dir.path()).await.unwrap();
+        let catalog = database.catalog().read().await;
+        assert!(catalog.get_table("users").unwrap().indexes.contains_key(&1));
+    }
+
+    #[tokio::test]
+    async fn index_on_a_missing_column_is_rejected() {
+        let dir = TempDir::new();
+        let mut database = users_database(&dir).await;
+        assert!(database.execute_str("create_index users height").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn dropped_table_is_gone_after_reopening() {
+        let dir = TempDir::new();
+        let mut database = users_database(&dir).await;
+        rows(&mut database, r#"insert_ users { name = "ana", age = 30 }"#).await;
+        rows(&mut database, "create_index users age").await;
+        let file_id = {
+            let mut catalog = database.catalog().write().await;
+            let file_id = catalog.get_table("users").unwrap().file_id;
+            catalog.drop_table("users").await.unwrap();
+            assert!(matches!(
+                catalog.drop_table("users").await,
+                Err(DbInternalError::TableNotFound(name)) if name == "users"
+            ));
+            file_id
+        };
+        assert!(database.execute_str("scan users").await.is_err());
+        database.close().await.unwrap();
+
+        assert!(!std::path::Path::new(&dir.file(&format!("ak{}.{}", file_id, EXTENSION))).exists());
+        let database = Database::open(dir.path()).await.unwrap();
+        assert!(database.catalog().read().await.get_table("users").is_none());
+    }
+
+    #[tokio::test]
+    async fn internal_tables_cant_be_dropped() {
+        let dir = TempDir::new();
+        let database = users_database(&dir).await;
+        let mut catalog = database.catalog().write().await;
+        let internal = catalog
+            .tables
+            .keys()
+            .filter(|name| name.starts_with("akasha."))
+            .cloned()
+            .collect::<Vec<_>>();
+        assert!(!internal.is_empty());
+        for name in internal {
+            assert!(matches!(
+                catalog.drop_table(&name).await,
+                Err(DbInternalError::CannotDropInternalTable(_))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn tables_created_after_reopening_get_unused_file_ids() {
+        let dir = TempDir::new();
+        let mut database = Database::open(dir.path()).await.unwrap();
+        for table in ["a", "b", "c"] {
+            rows(&mut database, &format!("create_table {} {{ n = Int }}", table)).await;
+        }
+        rows(&mut database, "insert_ c { n = 7 }").await;
+        database.catalog().write().await.drop_table("b").await.unwrap();
+        database.close().await.unwrap();
+
+        let mut database = Database::open(dir.path()).await.unwrap();
+        for table in ["d", "e"] {
+            rows(&mut database, &format!("create_table {} {{ n = Int }}", table)).await;
+        }
+        rows(&mut database, "insert_ e { n = 9 }").await;
+
+        let mut file_ids = database
+            .catalog()
+            .read()
+            .await
+            .tables
+            .values()
+            .map(|table| table.file_id)
+            .collect::<Vec<_>>();
+        let count = file_ids.len();
+        file_ids.sort();
+        file_ids.dedup();
+        assert_eq!(file_ids.len(), count);
+        let values = |tuples: Vec<Tuple>| tuples.into_iter().map(|t| t.0).collect::<Vec<_>>();
+        assert_eq!(values(rows(&mut database, "scan c").await), [[Value::Int(7)]]);
+        assert_eq!(values(rows(&mut database, "scan e").await), [[Value::Int(9)]]);
+    }
+}
@@ -0,0 +1,78 @@
+use crate::Database;
+use crate::page::tuple::{DataType, Tuple};
+use crate::table::{ColumnInfo, TableInfo};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+// A directory of its own for every test, removed again once the test is done with it.
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new() -> Self {
+        let path = std::env::temp_dir().join(format!(
+            "akasha-test-{}-{}",
+            std::process::id(),
+            NEXT_DIR.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = std::fs::remove_dir_all(&path);
+        Self { path }
+    }
+
+    pub fn path(&self) -> &str {
+        self.path.to_str().unwrap()
+    }
+
+    pub fn file(&self, name: &str) -> String {
+        self.path.join(name).to_str().unwrap().to_string()
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+pub fn column(id: u32, name: &str, data_type: DataType, nullable: bool) -> (String, ColumnInfo) {
+    (name.to_string(), ColumnInfo {
+        id,
+        name: name.to_string(),
+        data_type,
+        default: None,
+        nullable,
+    })
+}
+
+// The table the REPL starts a fresh database with, plus a nullable column for tests that
+// need one: name Text, age Int, retirement Int?.
+pub async fn users_database(dir: &TempDir) -> Database {
+    let database = Database::open(dir.path()).await.unwrap();
+    let columns = HashMap::from([
+        column(0, "name", DataType::Text, false),
+        column(1, "age", DataType::Int, false),
+        column(2, "retirement", DataType::Int, true),
+    ]);
+    database
+        .catalog()
+        .write()
+        .await
+        .create_table("users".to_string(), TableInfo {
+            columns,
+            primary_key: None,
+        })
+        .await
+        .unwrap();
+    database
+}
+
+pub async fn rows(database: &mut Database, query: &str) -> Vec<Tuple> {
+    match database.execute_str(query).await {
+        Ok(rows) => rows,
+        Err(err) => panic!("`{}` failed: {}", query, err),
+    }
+}
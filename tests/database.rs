@@ -0,0 +1,105 @@
+use akasha::Database;
+use akasha::page::tuple::Value;
+use std::path::PathBuf;
+
+// A directory of its own for the test, removed again once it's done.
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("akasha-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        Self(path)
+    }
+
+    fn path(&self) -> &str {
+        self.0.to_str().unwrap()
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+#[tokio::test]
+async fn create_insert_and_select() {
+    let dir = TempDir::new("create-insert-select");
+    let mut database = Database::open(dir.path()).await.unwrap();
+    assert!(database.was_created());
+
+    database
+        .execute_str("create_table products { name = Text, price = Int }")
+        .await
+        .unwrap();
+    for (name, price) in [("pen", 2), ("lamp", 40), ("desk", 250)] {
+        let query = format!(
+            r#"insert_ products {{ name = "{}", price = {} }}"#,
+            name, price
+        );
+        database.execute_str(&query).await.unwrap();
+    }
+
+    let cheap = database
+        .execute_str(r#"scan products |> filter (\p -> p.price < 100)"#)
+        .await
+        .unwrap();
+    let names: Vec<&Value> = cheap.iter().map(|row| &row.0[0]).collect();
+    assert_eq!(
+        names,
+        [
+            &Value::Text("pen".to_string()),
+            &Value::Text("lamp".to_string())
+        ]
+    );
+    database.close().await.unwrap();
+
+    let mut database = Database::open(dir.path()).await.unwrap();
+    assert!(!database.was_created());
+    let all = database.execute_str("scan products").await.unwrap();
+    assert_eq!(all.len(), 3);
+}
+
+#[tokio::test]
+async fn compile_traced_returns_every_stage() {
+    let dir = TempDir::new("compile-traced");
+    let mut database = Database::open(dir.path()).await.unwrap();
+    database
+        .execute_str("create_table products { name = Text }")
+        .await
+        .unwrap();
+
+    let (_, columns, trace) = database.compile_traced("scan products").await.unwrap();
+    assert_eq!(columns.len(), 1);
+    for stage in [
+        "Lexical analysis",
+        "Parsing",
+        "AST transformation",
+        "Query compilation",
+    ] {
+        assert!(trace.contains(stage), "no {} in {}", stage, trace);
+    }
+}
+
+#[tokio::test]
+async fn rows_inserted_before_close_survive_without_the_log() {
+    let dir = TempDir::new("close-persists");
+    let mut database = Database::open(dir.path()).await.unwrap();
+    database
+        .execute_str("create_table products { name = Text }")
+        .await
+        .unwrap();
+    database
+        .execute_str(r#"insert_ products { name = "pen" }"#)
+        .await
+        .unwrap();
+    database.close().await.unwrap();
+
+    // Without the log nothing can be replayed, so the row has to be in the table's own file.
+    std::fs::remove_file(dir.0.join(akasha::page::wal::WAL_FILE_NAME)).unwrap();
+    let mut database = Database::open(dir.path()).await.unwrap();
+    let all = database.execute_str("scan products").await.unwrap();
+    assert_eq!(all.len(), 1);
+    assert_eq!(all[0].0[0], Value::Text("pen".to_string()));
+}